@@ -0,0 +1,52 @@
+//! Time per callback block for the common playback setups, on the `MockDevice` so no
+//! sound hardware is involved. `cargo bench` prints one line per case; compare runs on
+//! the same machine to spot regressions in the hot path.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use audio_lib3::{generators, AudioSpecInfo, Control, LimiterMode, MockDevice, PlayMode};
+
+const FRAMES: usize = 512;
+const RUN_FOR: Duration = Duration::from_millis(500);
+
+fn device(channels: u8, mode: PlayMode) -> MockDevice {
+    let mut device = MockDevice::new(48000 * channels as usize, AudioSpecInfo { freq: 48000, channels, samples: FRAMES as u16 });
+    device.set_ramp_samples(0);
+    device.set_volume(6);
+    device.set_mode(mode);
+    device.set_data(0, &generators::white_noise(48000 * channels as usize, 0x4000, 1));
+    device.resume();
+    device
+}
+
+fn bench(name: &str, mut device: MockDevice) {
+    let mut out = vec![0u16; FRAMES * device.spec().channels as usize];
+    // allocated up front, so the timed loop measures the callback and not the allocator
+    let refill = vec![0x9000u16; device.buf_samples()];
+    let mut blocks = 0u32;
+    let start = Instant::now();
+    while start.elapsed() < RUN_FOR {
+        if device.remain() < out.len() {
+            let free = device.write_available();
+            device.write(&refill[..free]);
+        }
+        device.drive_callback_into(black_box(&mut out));
+        blocks += 1;
+    }
+    let per_block = start.elapsed() / blocks;
+    // a block of FRAMES frames lasts 10.7 ms at 48 kHz
+    println!("{:<28} {:>8.2} us/block", name, per_block.as_secs_f64() * 1e6);
+}
+
+fn main() {
+    bench("stream stereo", device(2, PlayMode::Stream));
+    bench("loop stereo", device(2, PlayMode::Loop));
+    bench("loop mono", device(1, PlayMode::Loop));
+    let mut soft = device(2, PlayMode::Loop);
+    soft.set_limiter(LimiterMode::Soft);
+    bench("loop stereo, soft limiter", soft);
+    let mut fast = device(2, PlayMode::Loop);
+    fast.set_rate(1.5);
+    bench("loop stereo, rate 1.5", fast);
+}
@@ -0,0 +1,45 @@
+//! The block routines in `dsp` against their scalar fallbacks, and
+//! `Control::set_data_i16` built on them, in samples per microsecond. Run with
+//! `cargo bench --bench dsp`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use audio_lib3::{dsp, generators, AudioSpecInfo, Control, MockDevice};
+
+const LEN: usize = 1024;
+// 64 KiB of i16 samples
+const SET_DATA_LEN: usize = 32 * 1024;
+const RUN_FOR: Duration = Duration::from_millis(300);
+
+fn bench(name: &str, len: usize, mut f: impl FnMut()) -> f64 {
+    let mut runs = 0u32;
+    let start = Instant::now();
+    while start.elapsed() < RUN_FOR {
+        f();
+        runs += 1;
+    }
+    let rate = (len as f64 * runs as f64) / (start.elapsed().as_secs_f64() * 1e6);
+    println!("{:<24} {:>9.1} samples/us", name, rate);
+    rate
+}
+
+fn main() {
+    let src = generators::white_noise(LEN, 0x7fff, 1);
+    let signed: Vec<i16> = src.iter().map(|s| (*s ^ 0x8000) as i16).collect();
+    let mut dst = vec![0u16; LEN];
+    let mut signed_dst = vec![0i16; LEN];
+    let gain = 0x6000;
+    let simd = bench("scale_u16", LEN, || dsp::scale_u16(black_box(&mut dst), black_box(&src), gain));
+    let scalar = bench("scale_u16_scalar", LEN, || dsp::scale_u16_scalar(black_box(&mut dst), black_box(&src), gain));
+    println!("{:<24} {:>9.1}x", "speedup", simd / scalar);
+    let simd = bench("scale_i16", LEN, || dsp::scale_i16(black_box(&mut signed_dst), black_box(&signed), gain));
+    let scalar = bench("scale_i16_scalar", LEN, || dsp::scale_i16_scalar(black_box(&mut signed_dst), black_box(&signed), gain));
+    println!("{:<24} {:>9.1}x", "speedup", simd / scalar);
+    bench("i16_to_u16", LEN, || dsp::i16_to_u16(black_box(&mut dst), black_box(&signed)));
+    bench("u16_to_i16", LEN, || dsp::u16_to_i16(black_box(&mut signed_dst), black_box(&src)));
+
+    let block: Vec<i16> = generators::white_noise(SET_DATA_LEN, 0x7fff, 2).iter().map(|s| (*s ^ 0x8000) as i16).collect();
+    let mut device: MockDevice = MockDevice::new(SET_DATA_LEN, AudioSpecInfo { freq: 48000, channels: 2, samples: 512 });
+    bench("set_data_i16 64 KiB", SET_DATA_LEN, || device.set_data_i16(0, black_box(&block)));
+}
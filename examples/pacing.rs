@@ -0,0 +1,49 @@
+//! A 60 Hz game loop that refills exactly what the device consumed each tick, so the
+//! amount queued in front of the read cursor never drifts.
+//!
+//! Runs for two seconds on the default device; `SDL_AUDIODRIVER=dummy` works without
+//! sound hardware.
+
+use std::time::{Duration, Instant};
+
+use audio_lib3::{generators, AudioContext, Control};
+
+const TICK: Duration = Duration::from_micros(16_667);
+const PREFILL: usize = 4096;
+
+fn main() -> Result<(), String> {
+    let context = AudioContext::builder().freq(48000).channels(1).samples(512).build()?;
+    let mut device = context.device().buffer_len(PREFILL * 2).open()?;
+    device.set_volume(5);
+
+    // one whole period, so the tone joins up no matter how the writes are split
+    let tone = generators::sine(500.0, 48000, 96, 0x4000);
+    let mut phase = 0;
+    let mut next = |len: usize| -> Vec<u16> {
+        let chunk = (0..len).map(|i| tone[(phase + i) % tone.len()]).collect();
+        phase = (phase + len) % tone.len();
+        chunk
+    };
+
+    let mut written = device.write(&next(PREFILL));
+    device.resume();
+    let start = Instant::now();
+    let mut deadline = start;
+    while start.elapsed() < Duration::from_secs(2) {
+        let consumed = device.take_consumed();
+        written += device.write(&next(consumed));
+        deadline += TICK;
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+    device.pause();
+
+    // nothing was dropped and the fill level is back where it started
+    let status = device.playback_status();
+    let consumed = written - status.remain;
+    assert_eq!(status.remain + device.take_consumed(), PREFILL);
+    println!(
+        "wrote {} samples, {} consumed, {} queued, {} underruns",
+        written, consumed, status.remain, status.underruns
+    );
+    Ok(())
+}
@@ -0,0 +1,33 @@
+//! Streams a generated sine through a `QueueDevice` for two seconds, topping the queue
+//! up to about 50 ms whenever it runs low, with no callback or buffer bookkeeping.
+//!
+//! Runs on the default device; `SDL_AUDIODRIVER=dummy` works without sound hardware.
+
+use std::time::{Duration, Instant};
+
+use audio_lib3::{generators, AudioContext, QueuePlayer};
+
+const CHUNK_FRAMES: usize = 480;
+const TARGET_BYTES: u32 = 4800;
+
+fn main() -> Result<(), String> {
+    let context = AudioContext::builder().freq(48000).channels(1).samples(512).build()?;
+    let mut device = context.open_queue()?;
+    device.set_volume(5);
+
+    // one whole period per chunk, so consecutive chunks join up
+    let chunk = generators::sine(400.0, 48000, CHUNK_FRAMES, 0x4000);
+    device.resume();
+    let start = Instant::now();
+    let mut queued = 0;
+    while start.elapsed() < Duration::from_secs(2) {
+        while device.queued_bytes() < TARGET_BYTES {
+            device.queue(&chunk)?;
+            queued += chunk.len();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    device.pause();
+    println!("queued {} samples, {} bytes still waiting", queued, device.queued_bytes());
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::wav::load_wav;
+use crate::{resample, MixerControl, PlayOptions, TriggerError, VoiceHandle, WavError};
+
+/// The layout of a banked sound. `insert` stores the default: mono at an unknown rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SoundInfo {
+    /// 0 when unknown.
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Loop points in samples for the caller's `set_loop_region`, kept with the sound.
+    pub loop_region: Option<(usize, usize)>,
+}
+
+/// Sounds loaded up front and played by name, e.g. `bank.trigger("explosion", ...)`.
+///
+/// Keys are `String`s by default; any `Eq + Hash` type works, such as a game's own enum.
+/// Every trigger shares the stored data with the voice playing it (`play_shared`), so a
+/// sound takes the same memory however many voices play it at once.
+#[derive(Debug, Clone)]
+pub struct SoundBank<K = String> {
+    sounds: HashMap<K, (Arc<[u16]>, SoundInfo)>,
+}
+
+impl<K: Eq + Hash> Default for SoundBank<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash> SoundBank<K> {
+    pub fn new() -> Self {
+        Self { sounds: HashMap::new() }
+    }
+
+    /// Stores `data` under `key` and returns what was stored there before.
+    pub fn insert(&mut self, key: K, data: impl Into<Arc<[u16]>>) -> Option<Arc<[u16]>> {
+        self.insert_with_info(key, data, SoundInfo { channels: 1, ..SoundInfo::default() })
+    }
+
+    /// `insert` with the sound's layout, as given back by `info`.
+    pub fn insert_with_info(&mut self, key: K, data: impl Into<Arc<[u16]>>, info: SoundInfo) -> Option<Arc<[u16]>> {
+        self.sounds.insert(key, (data.into(), info)).map(|(data, _)| data)
+    }
+
+    /// Voices still playing the sound keep their reference to it.
+    pub fn remove<Q: Eq + Hash + ?Sized>(&mut self, key: &Q) -> Option<Arc<[u16]>>
+    where
+        K: Borrow<Q>,
+    {
+        self.sounds.remove(key).map(|(data, _)| data)
+    }
+
+    pub fn get<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> Option<&Arc<[u16]>>
+    where
+        K: Borrow<Q>,
+    {
+        self.sounds.get(key).map(|(data, _)| data)
+    }
+
+    pub fn info<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> Option<&SoundInfo>
+    where
+        K: Borrow<Q>,
+    {
+        self.sounds.get(key).map(|(_, info)| info)
+    }
+
+    pub fn contains<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.sounds.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sounds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sounds.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.sounds.keys()
+    }
+
+    /// Bytes of sample data held, counting each sound once.
+    pub fn memory_usage(&self) -> usize {
+        self.sounds.values().map(|(data, _)| std::mem::size_of_val(&**data)).sum()
+    }
+
+    /// Starts the sound stored under `key` on a voice of `device`, with the volume, pan
+    /// and looping of `options`.
+    pub fn trigger<Q>(
+        &self,
+        key: &Q,
+        device: &mut impl MixerControl,
+        options: PlayOptions,
+    ) -> Result<VoiceHandle, TriggerError<K>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ToOwned<Owned = K> + ?Sized,
+    {
+        let data = self.get(key).ok_or_else(|| TriggerError::UnknownKey(key.to_owned()))?;
+        device.play_with(data.clone(), options).ok_or(TriggerError::NoFreeVoice)
+    }
+}
+
+impl SoundBank<String> {
+    /// Loads every `.wav` file in `dir`, keyed by the file name without its extension,
+    /// and returns how many were loaded. Files at another rate are resampled to
+    /// `sample_rate`; channels are kept as stored, so they should match the device.
+    pub fn load_dir(&mut self, dir: &Path, sample_rate: u32) -> Result<usize, WavError> {
+        let mut loaded = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_wav = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+            let Some(key) = path.file_stem().and_then(|stem| stem.to_str()).filter(|_| is_wav) else {
+                continue;
+            };
+            let sound = load_wav(&path)?;
+            let data = if sound.sample_rate == sample_rate {
+                sound.data
+            } else {
+                resample(&sound.data, sound.sample_rate, sample_rate, sound.channels as u8)
+            };
+            let info = SoundInfo { sample_rate, channels: sound.channels, loop_region: None };
+            self.insert_with_info(key.to_string(), data, info);
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::encode_wav;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum Sfx {
+        Jump,
+        Coin,
+    }
+
+    #[test]
+    fn stores_and_reports_sounds_by_key() {
+        let mut bank = SoundBank::new();
+        assert_eq!(bank.insert(Sfx::Jump, vec![1u16; 100]), None);
+        bank.insert(Sfx::Coin, vec![2u16; 50]);
+        assert_eq!(bank.len(), 2);
+        assert_eq!(bank.memory_usage(), 300);
+        assert_eq!(bank.get(&Sfx::Coin).map(|data| data.len()), Some(50));
+        assert_eq!(bank.remove(&Sfx::Jump).map(|data| data.len()), Some(100));
+        assert!(!bank.contains(&Sfx::Jump));
+        assert_eq!(bank.memory_usage(), 100);
+    }
+
+    #[test]
+    fn load_dir_keys_wav_files_by_name() {
+        let dir = std::env::temp_dir().join(format!("audio-lib3-bank-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("blip.wav"), encode_wav(&[0x9000; 100], 24000, 1).unwrap()).unwrap();
+        std::fs::write(dir.join("boom.WAV"), encode_wav(&[0x7000; 10], 48000, 1).unwrap()).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a sound").unwrap();
+        let mut bank = SoundBank::new();
+        let loaded = bank.load_dir(&dir, 48000);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.unwrap(), 2);
+        // resampled to the device rate
+        assert_eq!(bank.get("blip").unwrap().len(), 200);
+        assert_eq!(bank.get("boom").unwrap().len(), 10);
+        assert_eq!(bank.info("boom").map(|info| (info.sample_rate, info.channels)), Some((48000, 1)));
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+/// The samples a `Sound` plays: its own, or data shared with other voices that is
+/// copied on the first write (copy-on-write), so shared data is never changed.
+pub(crate) enum SampleBuffer<T> {
+    Owned(Vec<T>),
+    Shared(Arc<[T]>),
+}
+
+impl<T: Copy> SampleBuffer<T> {
+    /// The samples as a `Vec`, copying them out of shared data.
+    pub(crate) fn into_vec(self) -> Vec<T> {
+        match self {
+            SampleBuffer::Owned(buffer) => buffer,
+            SampleBuffer::Shared(data) => data.to_vec(),
+        }
+    }
+}
+
+impl<T> Default for SampleBuffer<T> {
+    fn default() -> Self {
+        SampleBuffer::Owned(Vec::new())
+    }
+}
+
+impl<T> From<Vec<T>> for SampleBuffer<T> {
+    fn from(buffer: Vec<T>) -> Self {
+        SampleBuffer::Owned(buffer)
+    }
+}
+
+impl<T> Deref for SampleBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            SampleBuffer::Owned(buffer) => buffer,
+            SampleBuffer::Shared(data) => data,
+        }
+    }
+}
+
+impl<T: Copy> DerefMut for SampleBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        if let SampleBuffer::Shared(data) = self {
+            *self = SampleBuffer::Owned(data.to_vec());
+        }
+        match self {
+            SampleBuffer::Owned(buffer) => buffer,
+            SampleBuffer::Shared(_) => unreachable!(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SampleBuffer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq<[T; N]> for SampleBuffer<T> {
+    fn eq(&self, other: &[T; N]) -> bool {
+        **self == other[..]
+    }
+}
+
+impl<T: PartialEq> PartialEq<Vec<T>> for SampleBuffer<T> {
+    fn eq(&self, other: &Vec<T>) -> bool {
+        **self == other[..]
+    }
+}
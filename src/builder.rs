@@ -0,0 +1,194 @@
+use crate::effects::EffectChain;
+use crate::{duration_len, AudioContext, AudioError, AudioSpecInfo, Control, Sample, SoundDevice};
+
+/// Collects the desired spec for an `AudioContext`; every field left unset is up to SDL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AudioContextBuilder {
+    freq: Option<i32>,
+    channels: Option<u8>,
+    samples: Option<u16>,
+    start_paused: Option<bool>,
+}
+
+impl AudioContextBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn freq(mut self, freq: i32) -> Self {
+        self.freq = Some(freq);
+        self
+    }
+
+    pub fn channels(mut self, channels: u8) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    /// Callback buffer size in frames.
+    pub fn samples(mut self, samples: u16) -> Self {
+        self.samples = Some(samples);
+        self
+    }
+
+    /// Whether opened devices wait for `resume`; the default is `true`.
+    pub fn start_paused(mut self, start_paused: bool) -> Self {
+        self.start_paused = Some(start_paused);
+        self
+    }
+
+    fn validate(&self) -> Result<(), AudioError> {
+        if self.freq.is_some_and(|freq| freq <= 0) {
+            return Err(AudioError::invalid_param("freq", "must be positive"));
+        }
+        if self.channels == Some(0) {
+            return Err(AudioError::invalid_param("channels", "must be at least 1"));
+        }
+        if self.samples == Some(0) {
+            return Err(AudioError::invalid_param("samples", "must be at least 1"));
+        }
+        Ok(())
+    }
+
+    fn apply(self, mut context: AudioContext) -> AudioContext {
+        context.set_freq(self.freq);
+        context.set_channels(self.channels);
+        context.set_samples(self.samples);
+        context.set_auto_resume(!self.start_paused.unwrap_or(true));
+        context
+    }
+
+    pub fn build(self) -> Result<AudioContext, AudioError> {
+        self.validate()?;
+        Ok(self.apply(AudioContext::try_new()?))
+    }
+
+    /// `build` on an audio subsystem the application already initialized.
+    pub fn build_with_subsystem(self, audio_subsystem: sdl2::AudioSubsystem) -> Result<AudioContext, AudioError> {
+        self.validate()?;
+        Ok(self.apply(AudioContext::with_subsystem(audio_subsystem)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BufferLen {
+    Samples(usize),
+    Secs(f32),
+}
+
+impl BufferLen {
+    /// The buffer length in samples for the spec SDL actually opened.
+    fn resolve(self, spec: &AudioSpecInfo) -> usize {
+        match self {
+            BufferLen::Samples(len) => len,
+            BufferLen::Secs(secs) => duration_len(spec, secs as f64),
+        }
+    }
+}
+
+/// Opens one playback device from an `AudioContext`, see `AudioContext::device`.
+pub struct DeviceBuilder<'a> {
+    context: &'a AudioContext,
+    len: Option<BufferLen>,
+    device_name: Option<String>,
+    start_paused: Option<bool>,
+    effects: EffectChain,
+    label: Option<String>,
+}
+
+impl<'a> DeviceBuilder<'a> {
+    pub(crate) fn new(context: &'a AudioContext) -> Self {
+        Self {
+            context,
+            len: None,
+            device_name: None,
+            start_paused: None,
+            effects: Vec::new(),
+            label: None,
+        }
+    }
+
+    /// Buffer length in samples. Replaces an earlier `buffer_secs`.
+    pub fn buffer_len(mut self, len: usize) -> Self {
+        self.len = Some(BufferLen::Samples(len));
+        self
+    }
+
+    /// Buffer length in seconds at the obtained rate and channel count, which may differ
+    /// from the requested ones; at least one callback period. Replaces an earlier `buffer_len`.
+    pub fn buffer_secs(mut self, secs: f32) -> Self {
+        self.len = Some(BufferLen::Secs(secs));
+        self
+    }
+
+    /// One of `AudioContext::playback_devices`; the default device otherwise.
+    pub fn device_name(mut self, name: &str) -> Self {
+        self.device_name = Some(name.to_string());
+        self
+    }
+
+    /// Overrides the context's setting for this device only.
+    pub fn start_paused(mut self, start_paused: bool) -> Self {
+        self.start_paused = Some(start_paused);
+        self
+    }
+
+    pub fn effects(mut self, effects: EffectChain) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    /// See `Control::set_label`.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn open(self) -> Result<SoundDevice, AudioError> {
+        self.open_as::<u16>()
+    }
+
+    pub fn open_as<T: Sample>(self) -> Result<SoundDevice<T>, AudioError> {
+        let len = match self.len {
+            None => return Err(AudioError::invalid_param("buffer_len", "set buffer_len or buffer_secs")),
+            Some(BufferLen::Samples(0)) => return Err(AudioError::invalid_param("buffer_len", "must be at least 1")),
+            Some(BufferLen::Secs(secs)) if !(secs.is_finite() && secs > 0.0) => {
+                return Err(AudioError::invalid_param("buffer_secs", "must be a positive number of seconds"));
+            }
+            Some(len) => len,
+        };
+        let resume = !self.start_paused.unwrap_or(!self.context.auto_resume());
+        let mut device = self.context.open_playback(
+            self.device_name.as_deref(),
+            |spec| len.resolve(spec),
+            self.effects,
+            resume,
+        )?;
+        if let Some(label) = self.label {
+            device.set_label(&label);
+        }
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_builder_rejects_bad_specs() {
+        let err = AudioContextBuilder::new().freq(0).build().err();
+        assert_eq!(err, Some(AudioError::invalid_param("freq", "must be positive")));
+        assert!(AudioContextBuilder::new().channels(0).validate().is_err());
+        assert!(AudioContextBuilder::new().samples(0).validate().is_err());
+        assert!(AudioContextBuilder::new().freq(48000).channels(2).samples(1024).validate().is_ok());
+    }
+
+    #[test]
+    fn buffer_secs_resolves_against_the_obtained_spec() {
+        let spec = AudioSpecInfo { freq: 44100, channels: 2, samples: 512 };
+        assert_eq!(BufferLen::Secs(2.0).resolve(&spec), 176400);
+        assert_eq!(BufferLen::Secs(0.00001).resolve(&spec), 1024);
+        assert_eq!(BufferLen::Samples(1000).resolve(&spec), 1000);
+    }
+}
@@ -0,0 +1,166 @@
+use sdl2::audio::{AudioCallback, AudioDevice};
+
+use crate::{copy_wrapping, read_wrapping, AudioSpecInfo, Sample};
+
+/// Capture callback: keeps the most recent `buf_size` recorded samples in a ring.
+pub struct Recorder<T: Sample = u16> {
+    buffer: Vec<T>,
+    spec: AudioSpecInfo,
+    // oldest unread sample and how many follow it
+    read_pos: usize,
+    available: usize,
+    overruns: u64,
+    called: usize,
+}
+
+impl<T: Sample> Recorder<T> {
+    pub(crate) fn new(len: usize, spec: AudioSpecInfo) -> Self {
+        let channels = (spec.channels as usize).max(1);
+        let len = len.max(1).div_ceil(channels) * channels;
+        Self {
+            buffer: vec![T::SILENCE; len],
+            spec,
+            read_pos: 0,
+            available: 0,
+            overruns: 0,
+            called: 0,
+        }
+    }
+
+    fn record(&mut self, input: &[T]) {
+        let len = self.buffer.len();
+        copy_wrapping(&mut self.buffer, self.read_pos + self.available, input);
+        let total = self.available + input.len();
+        if total > len {
+            // the consumer fell behind: the oldest samples were overwritten
+            self.read_pos = (self.read_pos + total - len) % len;
+            self.available = len;
+            self.overruns += 1;
+        } else {
+            self.available = total;
+        }
+    }
+
+    fn read(&mut self, out: &mut [T]) -> usize {
+        let channels = (self.spec.channels as usize).max(1);
+        let n = out.len().min(self.available);
+        let n = n - n % channels;
+        read_wrapping(&self.buffer, self.read_pos, &mut out[..n]);
+        self.read_pos = (self.read_pos + n) % self.buffer.len();
+        self.available -= n;
+        n
+    }
+}
+
+impl<T: Sample> AudioCallback for Recorder<T> {
+    type Channel = T;
+
+    fn callback(&mut self, input: &mut [T]) {
+        self.record(input);
+        self.called += 1;
+    }
+}
+
+pub type CaptureDevice<T = u16> = AudioDevice<Recorder<T>>;
+
+pub trait CaptureControl<T: Sample = u16> {
+    /// Moves up to `out.len()` recorded samples, oldest first, into `out` and returns
+    /// how many were copied. Only whole frames are read.
+    fn read(&mut self, out: &mut [T]) -> usize;
+    /// Recorded samples waiting to be read.
+    fn available(&mut self) -> usize;
+    /// Callbacks that had to overwrite unread samples because `read` fell behind.
+    fn overruns(&mut self) -> u64;
+    fn reset_overruns(&mut self);
+    /// Drops everything recorded but not yet read.
+    fn discard(&mut self);
+    fn buf_size(&mut self) -> usize;
+    fn called(&mut self) -> usize;
+    fn spec(&mut self) -> AudioSpecInfo;
+}
+
+impl<T: Sample> CaptureControl<T> for CaptureDevice<T> {
+    fn read(&mut self, out: &mut [T]) -> usize {
+        let mut locked = self.lock();
+        locked.read(out)
+    }
+
+    fn available(&mut self) -> usize {
+        let locked = self.lock();
+        locked.available
+    }
+
+    fn overruns(&mut self) -> u64 {
+        let locked = self.lock();
+        locked.overruns
+    }
+
+    fn reset_overruns(&mut self) {
+        let mut locked = self.lock();
+        locked.overruns = 0;
+    }
+
+    fn discard(&mut self) {
+        let mut locked = self.lock();
+        locked.read_pos = 0;
+        locked.available = 0;
+    }
+
+    fn buf_size(&mut self) -> usize {
+        let locked = self.lock();
+        locked.buffer.len()
+    }
+
+    fn called(&mut self) -> usize {
+        let locked = self.lock();
+        locked.called
+    }
+
+    fn spec(&mut self) -> AudioSpecInfo {
+        let locked = self.lock();
+        locked.spec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder(len: usize, channels: u8) -> Recorder<u16> {
+        Recorder::new(len, AudioSpecInfo { freq: 8000, channels, samples: 4 })
+    }
+
+    #[test]
+    fn reads_back_in_order_across_the_wrap() {
+        let mut rec = recorder(6, 1);
+        rec.callback(&mut [1, 2, 3, 4]);
+        let mut out = [0u16; 3];
+        assert_eq!(rec.read(&mut out), 3);
+        assert_eq!(out, [1, 2, 3]);
+        rec.callback(&mut [5, 6, 7, 8]);
+        let mut out = [0u16; 8];
+        assert_eq!(rec.read(&mut out), 5);
+        assert_eq!(out[..5], [4, 5, 6, 7, 8]);
+        assert_eq!(rec.overruns, 0);
+    }
+
+    #[test]
+    fn overruns_keep_the_newest_samples() {
+        let mut rec = recorder(4, 1);
+        rec.callback(&mut [1, 2, 3]);
+        rec.callback(&mut [4, 5, 6]);
+        assert_eq!((rec.overruns, rec.available), (1, 4));
+        let mut out = [0u16; 4];
+        rec.read(&mut out);
+        assert_eq!(out, [3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn reads_whole_frames_only() {
+        let mut rec = recorder(8, 2);
+        rec.callback(&mut [1, 2, 3, 4]);
+        let mut out = [0u16; 3];
+        assert_eq!(rec.read(&mut out), 2);
+        assert_eq!(rec.available, 2);
+    }
+}
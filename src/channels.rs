@@ -0,0 +1,103 @@
+//! Converting between channel layouts of offset-binary data: separate channel vectors
+//! and interleaved frames, and mono to or from any number of channels.
+//!
+//! Lengths that don't match up are an error; nothing is truncated.
+
+use crate::{ChannelError, Sample, SoundData16};
+
+fn check_frames(data: &[u16], channels: u8) -> Result<usize, ChannelError> {
+    if channels == 0 {
+        return Err(ChannelError::ZeroChannels);
+    }
+    if !data.len().is_multiple_of(channels as usize) {
+        return Err(ChannelError::PartialFrame { len: data.len(), channels });
+    }
+    Ok(channels as usize)
+}
+
+/// Interleaves equally long channels into frames, the first channel first in each.
+pub fn interleave(channels: &[&[u16]]) -> Result<SoundData16, ChannelError> {
+    let expected = channels.first().ok_or(ChannelError::ZeroChannels)?.len();
+    if channels.len() > u8::MAX as usize {
+        return Err(ChannelError::TooManyChannels(channels.len()));
+    }
+    if let Some((channel, data)) = channels.iter().enumerate().find(|(_, data)| data.len() != expected) {
+        return Err(ChannelError::LengthMismatch { channel, len: data.len(), expected });
+    }
+    let mut data = Vec::with_capacity(expected * channels.len());
+    for frame in 0..expected {
+        data.extend(channels.iter().map(|channel| channel[frame]));
+    }
+    Ok(data)
+}
+
+/// Splits interleaved frames into one vector per channel.
+pub fn deinterleave(data: &[u16], channels: u8) -> Result<Vec<SoundData16>, ChannelError> {
+    let channels = check_frames(data, channels)?;
+    Ok((0..channels).map(|channel| data.iter().skip(channel).step_by(channels).copied().collect()).collect())
+}
+
+/// Averages each frame into one sample, in the signed domain so the midpoint stays put.
+pub fn downmix_to_mono(data: &[u16], channels: u8) -> Result<SoundData16, ChannelError> {
+    let channels = check_frames(data, channels)?;
+    Ok(data.chunks_exact(channels).map(|frame| {
+        let sum: i32 = frame.iter().map(|s| s.to_i32()).sum();
+        u16::from_i32((sum as f64 / channels as f64).round() as i32)
+    }).collect())
+}
+
+/// Copies each mono sample to every channel of a frame.
+pub fn upmix_mono_to(data: &[u16], channels: u8) -> Result<SoundData16, ChannelError> {
+    if channels == 0 {
+        return Err(ChannelError::ZeroChannels);
+    }
+    Ok(data.iter().flat_map(|s| std::iter::repeat_n(*s, channels as usize)).collect())
+}
+
+/// Turns `from`-channel data into `to` channels: unchanged when they match, otherwise
+/// through mono. Layouts that would need a channel map, e.g. stereo to 5.1, are an error.
+pub fn convert(data: &[u16], from: u8, to: u8) -> Result<SoundData16, ChannelError> {
+    check_frames(data, from)?;
+    match (from, to) {
+        _ if from == to => Ok(data.to_vec()),
+        (1, _) => upmix_mono_to(data, to),
+        (_, 1) => downmix_to_mono(data, from),
+        _ => Err(ChannelError::Unsupported { from, to }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MID: u16 = u16::MID;
+
+    #[test]
+    fn stereo_round_trips_through_interleaving() {
+        let (left, right) = ([1, 2, 3], [10, 20, 30]);
+        let data = interleave(&[&left, &right]).unwrap();
+        assert_eq!(data, [1, 10, 2, 20, 3, 30]);
+        assert_eq!(deinterleave(&data, 2).unwrap(), [left.to_vec(), right.to_vec()]);
+        assert_eq!(deinterleave(&data, 3).unwrap(), [vec![1, 20], vec![10, 3], vec![2, 30]]);
+    }
+
+    #[test]
+    fn mismatched_lengths_are_errors() {
+        let err = interleave(&[&[1, 2], &[3, 4], &[5]]).unwrap_err();
+        assert_eq!(err, ChannelError::LengthMismatch { channel: 2, len: 1, expected: 2 });
+        assert_eq!(interleave(&[]), Err(ChannelError::ZeroChannels));
+        assert_eq!(deinterleave(&[1, 2, 3], 2), Err(ChannelError::PartialFrame { len: 3, channels: 2 }));
+        assert_eq!(downmix_to_mono(&[1, 2], 0), Err(ChannelError::ZeroChannels));
+        assert_eq!(convert(&[1, 2], 2, 6), Err(ChannelError::Unsupported { from: 2, to: 6 }));
+    }
+
+    #[test]
+    fn downmix_averages_around_the_midpoint() {
+        let data = [MID + 1000, MID - 1000, u16::MAX, u16::MAX, 0, MID];
+        assert_eq!(downmix_to_mono(&data, 2).unwrap(), [MID, u16::MAX, MID / 2]);
+        assert_eq!(upmix_mono_to(&[1, 2], 3).unwrap(), [1, 1, 1, 2, 2, 2]);
+        assert_eq!(convert(&[MID + 2, MID], 2, 1).unwrap(), [MID + 1]);
+        assert_eq!(convert(&[7, 8], 1, 2).unwrap(), [7, 7, 8, 8]);
+        assert_eq!(convert(&[7, 8], 2, 2).unwrap(), [7, 8]);
+    }
+}
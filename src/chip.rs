@@ -0,0 +1,370 @@
+//! Programmable tone channels in the style of the NES and Game Boy sound chips: pulse
+//! waves with a selectable duty cycle and a noise channel fed by a linear-feedback
+//! shift register, each with a 4-bit volume and an optional length counter.
+//!
+//! `Chip::render` works on its own, e.g. inside an emulator's mixing, or the chip can
+//! run as a device from `AudioContext::open_chip_device`. There, register writes go
+//! through `ChipControl::with_chip`, which holds the device lock, so every write lands
+//! between two callback blocks.
+
+use sdl2::audio::{AudioCallback, AudioDevice};
+
+use crate::{AudioSpecInfo, Sample};
+
+/// The loudest channel volume, as on the original hardware.
+pub const MAX_CHIP_VOLUME: u8 = 15;
+/// How many pulse channels a `Chip` can have.
+pub const MAX_PULSES: usize = 4;
+/// The NES CPU clock that `PulseChannel::set_period` timer values count in.
+pub const NES_CPU_HZ: f64 = 1_789_773.0;
+
+// one volume step, so all channels at full volume just fit the signed 16-bit range
+const VOLUME_STEP: i32 = i16::MAX as i32 / ((MAX_PULSES as i32 + 1) * MAX_CHIP_VOLUME as i32);
+
+/// How much of each pulse period the output is high.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Duty {
+    /// 12.5%
+    Eighth,
+    /// 25%
+    Quarter,
+    /// 50%, a plain square wave.
+    #[default]
+    Half,
+    /// 75%, which sounds like 25% inverted.
+    ThreeQuarters,
+}
+
+impl Duty {
+    fn fraction(self) -> f64 {
+        match self {
+            Duty::Eighth => 0.125,
+            Duty::Quarter => 0.25,
+            Duty::Half => 0.5,
+            Duty::ThreeQuarters => 0.75,
+        }
+    }
+}
+
+/// Counts down once per rendered frame and silences the channel at zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Length(Option<u32>);
+
+impl Length {
+    /// Whether the channel may sound this frame, counting the frame off.
+    fn tick(&mut self) -> bool {
+        match &mut self.0 {
+            None => true,
+            Some(0) => false,
+            Some(left) => {
+                *left -= 1;
+                true
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PulseChannel {
+    frequency: f64,
+    duty: Duty,
+    volume: u8,
+    length: Length,
+    // position in the current period, 0.0..1.0
+    phase: f64,
+}
+
+impl PulseChannel {
+    /// 0 Hz (the default) is silent.
+    pub fn set_frequency(&mut self, hz: f64) {
+        self.frequency = hz.max(0.0);
+    }
+
+    /// Sets the frequency from an 11-bit NES APU timer value: `NES_CPU_HZ / (16 * (timer + 1))`.
+    pub fn set_period(&mut self, timer: u16) {
+        self.set_frequency(NES_CPU_HZ / (16.0 * (timer as f64 + 1.0)));
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    pub fn set_duty(&mut self, duty: Duty) {
+        self.duty = duty;
+    }
+
+    pub fn duty(&self) -> Duty {
+        self.duty
+    }
+
+    /// 0..=`MAX_CHIP_VOLUME`, larger values clamp.
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(MAX_CHIP_VOLUME);
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// Plays for `frames` more output frames and then falls silent; `None` plays until
+    /// the volume is set to 0.
+    pub fn set_length(&mut self, frames: Option<u32>) {
+        self.length = Length(frames);
+    }
+
+    /// Frames left on the length counter.
+    pub fn length(&self) -> Option<u32> {
+        self.length.0
+    }
+
+    /// Starts the next sample at the beginning of a period, like a hardware retrigger.
+    pub fn reset_phase(&mut self) {
+        self.phase = 0.0;
+    }
+
+    fn next(&mut self, sample_rate: f64) -> i32 {
+        let sounding = self.length.tick() && self.frequency > 0.0;
+        let high = self.phase < self.duty.fraction();
+        self.phase = (self.phase + self.frequency / sample_rate).fract();
+        match (sounding, high) {
+            (false, _) => 0,
+            (true, true) => self.volume as i32 * VOLUME_STEP,
+            (true, false) => -(self.volume as i32) * VOLUME_STEP,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseChannel {
+    frequency: f64,
+    // taps bit 6 instead of bit 1, for a 93-step metallic loop
+    short: bool,
+    volume: u8,
+    length: Length,
+    lfsr: u16,
+    phase: f64,
+}
+
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self { frequency: 0.0, short: false, volume: 0, length: Length::default(), lfsr: 1, phase: 0.0 }
+    }
+}
+
+impl NoiseChannel {
+    /// How often the shift register is clocked; 0 Hz (the default) is silent.
+    pub fn set_frequency(&mut self, hz: f64) {
+        self.frequency = hz.max(0.0);
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// The short mode repeats every 93 steps instead of 32767, for a buzzing tone.
+    pub fn set_short_mode(&mut self, short: bool) {
+        self.short = short;
+    }
+
+    pub fn short_mode(&self) -> bool {
+        self.short
+    }
+
+    /// 0..=`MAX_CHIP_VOLUME`, larger values clamp.
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(MAX_CHIP_VOLUME);
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// `PulseChannel::set_length` for the noise channel.
+    pub fn set_length(&mut self, frames: Option<u32>) {
+        self.length = Length(frames);
+    }
+
+    pub fn length(&self) -> Option<u32> {
+        self.length.0
+    }
+
+    fn clock(&mut self) {
+        let tap = if self.short { 6 } else { 1 };
+        let feedback = (self.lfsr ^ (self.lfsr >> tap)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+    }
+
+    fn next(&mut self, sample_rate: f64) -> i32 {
+        let sounding = self.length.tick() && self.frequency > 0.0;
+        // the output is high while bit 0 is clear
+        let high = self.lfsr & 1 == 0;
+        self.phase += self.frequency / sample_rate;
+        while self.phase >= 1.0 {
+            self.clock();
+            self.phase -= 1.0;
+        }
+        match (sounding, high) {
+            (false, _) => 0,
+            (true, true) => self.volume as i32 * VOLUME_STEP,
+            (true, false) => -(self.volume as i32) * VOLUME_STEP,
+        }
+    }
+}
+
+/// Up to `MAX_PULSES` pulse channels and a noise channel mixed into one mono signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chip {
+    pub pulses: Vec<PulseChannel>,
+    pub noise: NoiseChannel,
+}
+
+impl Chip {
+    /// A chip with `pulses` pulse channels, clamped to `1..=MAX_PULSES`, all silent.
+    pub fn new(pulses: usize) -> Self {
+        Self { pulses: vec![PulseChannel::default(); pulses.clamp(1, MAX_PULSES)], noise: NoiseChannel::default() }
+    }
+
+    /// Overwrites `out` with the next `out.len()` mono frames as signed 16-bit samples,
+    /// advancing every channel's phase and length counter.
+    pub fn render(&mut self, out: &mut [i32], sample_rate: u32) {
+        let sample_rate = sample_rate.max(1) as f64;
+        for sample in out.iter_mut() {
+            let pulses: i32 = self.pulses.iter_mut().map(|pulse| pulse.next(sample_rate)).sum();
+            *sample = pulses + self.noise.next(sample_rate);
+        }
+    }
+}
+
+/// Plays a `Chip` on a device, the same signal on every channel.
+pub struct ChipPlayer {
+    chip: Chip,
+    spec: AudioSpecInfo,
+    // one callback block of mono frames, so rendering never allocates
+    mono: Vec<i32>,
+    called: u64,
+}
+
+impl ChipPlayer {
+    pub(crate) fn new(chip: Chip, spec: AudioSpecInfo) -> Self {
+        Self { chip, spec, mono: vec![0; (spec.samples as usize).max(1)], called: 0 }
+    }
+}
+
+impl AudioCallback for ChipPlayer {
+    type Channel = u16;
+
+    fn callback(&mut self, out: &mut [u16]) {
+        let channels = (self.spec.channels as usize).max(1);
+        for chunk in out.chunks_mut(self.mono.len() * channels) {
+            let mono = &mut self.mono[..chunk.len().div_ceil(channels)];
+            self.chip.render(mono, self.spec.freq.max(1) as u32);
+            for (frame, sample) in chunk.chunks_mut(channels).zip(mono.iter()) {
+                frame.fill(u16::from_i32(*sample));
+            }
+        }
+        self.called += 1;
+    }
+}
+
+pub type ChipDevice = AudioDevice<ChipPlayer>;
+
+pub trait ChipControl {
+    /// Runs `f` on the chip while the callback is held off, so all register writes made
+    /// in it take effect together at the start of the next block.
+    fn with_chip<R>(&mut self, f: impl FnOnce(&mut Chip) -> R) -> R;
+    fn called(&mut self) -> u64;
+    fn spec(&mut self) -> AudioSpecInfo;
+}
+
+impl ChipControl for ChipDevice {
+    fn with_chip<R>(&mut self, f: impl FnOnce(&mut Chip) -> R) -> R {
+        let mut locked = self.lock();
+        f(&mut locked.chip)
+    }
+
+    fn called(&mut self) -> u64 {
+        let locked = self.lock();
+        locked.called
+    }
+
+    fn spec(&mut self) -> AudioSpecInfo {
+        let locked = self.lock();
+        locked.spec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SETUP_U16;
+
+    #[test]
+    fn pulse_duty_sets_the_high_share_of_each_period() {
+        for (duty, high) in [(Duty::Eighth, 13), (Duty::Quarter, 25), (Duty::Half, 50), (Duty::ThreeQuarters, 75)] {
+            let mut chip = Chip::new(2);
+            chip.pulses[0].set_frequency(480.0);
+            chip.pulses[0].set_duty(duty);
+            chip.pulses[0].set_volume(MAX_CHIP_VOLUME);
+            // a period of exactly 100 frames
+            let mut out = [0; 100];
+            chip.render(&mut out, 48000);
+            let peak = MAX_CHIP_VOLUME as i32 * VOLUME_STEP;
+            assert_eq!(out.iter().filter(|s| **s == peak).count(), high, "{:?}", duty);
+            assert!(out.iter().all(|s| s.abs() == peak));
+        }
+    }
+
+    #[test]
+    fn length_counter_silences_the_channel() {
+        let mut chip = Chip::new(1);
+        chip.pulses[0].set_frequency(1000.0);
+        chip.pulses[0].set_volume(8);
+        chip.pulses[0].set_length(Some(3));
+        let mut out = [0; 5];
+        chip.render(&mut out, 48000);
+        assert_eq!(out, [8 * VOLUME_STEP, 8 * VOLUME_STEP, 8 * VOLUME_STEP, 0, 0]);
+        assert_eq!(chip.pulses[0].length(), Some(0));
+    }
+
+    #[test]
+    fn noise_register_repeats_after_its_period() {
+        for (short, period) in [(false, 32767), (true, 93)] {
+            let mut noise = NoiseChannel { short, ..NoiseChannel::default() };
+            // from the power-on state, the register comes back to it after `period` clocks
+            let start = noise.lfsr;
+            let steps = (1..=32767).find(|_| {
+                noise.clock();
+                noise.lfsr == start
+            });
+            assert_eq!(steps, Some(period), "short mode {}", short);
+        }
+    }
+
+    #[test]
+    fn full_volume_on_every_channel_stays_in_range() {
+        let mut chip = Chip::new(MAX_PULSES);
+        for pulse in chip.pulses.iter_mut() {
+            pulse.set_frequency(440.0);
+            pulse.set_volume(MAX_CHIP_VOLUME);
+        }
+        chip.noise.set_frequency(4000.0);
+        chip.noise.set_volume(MAX_CHIP_VOLUME);
+        let mut out = [0; 256];
+        chip.render(&mut out, 44100);
+        assert!(out.iter().all(|s| (i16::MIN as i32..=i16::MAX as i32).contains(s)));
+    }
+
+    #[test]
+    fn player_fills_every_output_channel() {
+        let spec = AudioSpecInfo { freq: 48000, channels: 2, samples: 4 };
+        let mut player = ChipPlayer::new(Chip::new(2), spec);
+        player.chip.pulses[1].set_frequency(4800.0);
+        player.chip.pulses[1].set_volume(1);
+        let mut out = [0u16; 20];
+        player.callback(&mut out);
+        let (high, low) = ((SETUP_U16 + VOLUME_STEP) as u16, (SETUP_U16 - VOLUME_STEP) as u16);
+        assert_eq!(out[..10], [high, high, high, high, high, high, high, high, high, high]);
+        assert_eq!(out[10..], [low; 10]);
+        assert_eq!(player.called, 1);
+    }
+}
@@ -0,0 +1,175 @@
+//! Block routines the callback and `Control::set_data_i16` are built on, public so
+//! applications preparing their own data can use them too.
+//!
+//! `scale_u16` and `scale_i16` use AVX2 when the CPU has it (detected at run time) and
+//! NEON on aarch64, falling back to the `_scalar` versions everywhere else. Every
+//! version gives bit-identical results. The i16/u16 conversions are a single XOR per
+//! sample, which the compiler already vectorizes for the target.
+
+use crate::{apply_gain, GAIN_ONE};
+
+/// Applies a Q16 gain (`1 << 16` is unity) to offset-binary samples, like
+/// `Sample::scale` on each of them. Stops at the end of the shorter slice.
+pub fn scale_u16(dst: &mut [u16], src: &[u16], gain: u32) {
+    let done = simd_scale(dst, src, gain, 0x8000);
+    scale_u16_scalar(&mut dst[done..], &src[done..], gain);
+}
+
+pub fn scale_u16_scalar(dst: &mut [u16], src: &[u16], gain: u32) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = (apply_gain(*s as i32 - 0x8000, gain) + 0x8000) as u16;
+    }
+}
+
+/// `scale_u16` for signed samples.
+pub fn scale_i16(dst: &mut [i16], src: &[i16], gain: u32) {
+    let n = dst.len().min(src.len());
+    // the kernels only care about the bit patterns
+    // SAFETY: i16 and u16 have the same size and alignment and every bit pattern is
+    // valid for both, and `n` is within both slices. `dst_bits` reborrows `dst`
+    // exclusively and is last used before `dst` is touched again, and `src` can't
+    // overlap it, being a shared borrow alive at the same time as `&mut dst`.
+    let dst_bits = unsafe { std::slice::from_raw_parts_mut(dst.as_mut_ptr() as *mut u16, n) };
+    // SAFETY: as above; a shared view of `src` alongside the shared borrow it comes from.
+    let src_bits = unsafe { std::slice::from_raw_parts(src.as_ptr() as *const u16, n) };
+    let done = simd_scale(dst_bits, src_bits, gain, 0);
+    scale_i16_scalar(&mut dst[done..], &src[done..], gain);
+}
+
+pub fn scale_i16_scalar(dst: &mut [i16], src: &[i16], gain: u32) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = apply_gain(*s as i32, gain) as i16;
+    }
+}
+
+/// Signed samples to offset-binary, up to the end of the shorter slice.
+pub fn i16_to_u16(dst: &mut [u16], src: &[i16]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = *s as u16 ^ 0x8000;
+    }
+}
+
+/// Offset-binary samples to signed, up to the end of the shorter slice.
+pub fn u16_to_i16(dst: &mut [i16], src: &[u16]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = (*s ^ 0x8000) as i16;
+    }
+}
+
+/// Scales as many leading samples as the SIMD kernel handles and returns how many.
+/// `bias` (0x8000 or 0) turns the samples into signed ones and back.
+fn simd_scale(dst: &mut [u16], src: &[u16], gain: u32, bias: u16) -> usize {
+    // above unity the products no longer fit 32 bits and results need clamping
+    if gain > GAIN_ONE {
+        return 0;
+    }
+    let n = dst.len().min(src.len());
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 was detected, and the kernel stays within the first `n` samples
+        return unsafe { x86::scale_avx2(&mut dst[..n], &src[..n], gain, bias) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        // SAFETY: NEON is part of every aarch64 target, and the kernel stays within `n`
+        return unsafe { arm::scale_neon(&mut dst[..n], &src[..n], gain, bias) };
+    }
+    #[allow(unreachable_code)]
+    {
+        let _ = (n, bias);
+        0
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    /// 16 samples at a time; `dst` and `src` have the same length.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn scale_avx2(dst: &mut [u16], src: &[u16], gain: u32, bias: u16) -> usize {
+        let n = src.len() / 16 * 16;
+        let flip = _mm256_set1_epi16(bias as i16);
+        let gain = _mm256_set1_epi32(gain as i32);
+        let mut i = 0;
+        while i < n {
+            let v = _mm256_xor_si256(_mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i), flip);
+            // at most unity gain, so every product fits 32 bits and the shift can't overflow 16
+            let lo = _mm256_cvtepi16_epi32(_mm256_castsi256_si128(v));
+            let hi = _mm256_cvtepi16_epi32(_mm256_extracti128_si256::<1>(v));
+            let lo = _mm256_srai_epi32::<16>(_mm256_mullo_epi32(lo, gain));
+            let hi = _mm256_srai_epi32::<16>(_mm256_mullo_epi32(hi, gain));
+            // the pack works within 128-bit lanes, so put the quarters back in order
+            let packed = _mm256_permute4x64_epi64::<0b11_01_10_00>(_mm256_packs_epi32(lo, hi));
+            _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, _mm256_xor_si256(packed, flip));
+            i += 16;
+        }
+        n
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use std::arch::aarch64::*;
+
+    /// 8 samples at a time; `dst` and `src` have the same length.
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn scale_neon(dst: &mut [u16], src: &[u16], gain: u32, bias: u16) -> usize {
+        let n = src.len() / 8 * 8;
+        let flip = vdupq_n_u16(bias);
+        let gain = vdupq_n_s32(gain as i32);
+        let mut i = 0;
+        while i < n {
+            let v = vreinterpretq_s16_u16(veorq_u16(vld1q_u16(src.as_ptr().add(i)), flip));
+            let lo = vshrq_n_s32::<16>(vmulq_s32(vmovl_s16(vget_low_s16(v)), gain));
+            let hi = vshrq_n_s32::<16>(vmulq_s32(vmovl_high_s16(v), gain));
+            let packed = vreinterpretq_u16_s16(vcombine_s16(vqmovn_s32(lo), vqmovn_s32(hi)));
+            vst1q_u16(dst.as_mut_ptr().add(i), veorq_u16(packed, flip));
+            i += 8;
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators;
+
+    const GAINS: [u32; 7] = [0, 1, 0x4000, 0x8000, 0xffff, GAIN_ONE, GAIN_ONE * 3];
+
+    #[test]
+    fn simd_scaling_matches_scalar() {
+        for seed in 0..20 {
+            // odd lengths leave a tail for the scalar loop
+            let mut src = generators::white_noise(1000 + seed as usize, 0x8000, seed);
+            src.extend_from_slice(&[0, 0xffff, 0x8000, 0x7fff]);
+            for gain in GAINS {
+                let (mut simd, mut scalar) = (vec![0u16; src.len()], vec![0u16; src.len()]);
+                scale_u16(&mut simd, &src, gain);
+                scale_u16_scalar(&mut scalar, &src, gain);
+                assert_eq!(simd, scalar, "gain {:#x}", gain);
+
+                let signed: Vec<i16> = src.iter().map(|s| (*s ^ 0x8000) as i16).collect();
+                let (mut simd, mut scalar) = (vec![0i16; src.len()], vec![0i16; src.len()]);
+                scale_i16(&mut simd, &signed, gain);
+                scale_i16_scalar(&mut scalar, &signed, gain);
+                assert_eq!(simd, scalar, "gain {:#x}", gain);
+            }
+        }
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let signed = [i16::MIN, -1, 0, 1, i16::MAX];
+        let mut unsigned = [0u16; 5];
+        i16_to_u16(&mut unsigned, &signed);
+        assert_eq!(unsigned, [0, 0x7fff, 0x8000, 0x8001, 0xffff]);
+        let mut back = [0i16; 5];
+        u16_to_i16(&mut back, &unsigned);
+        assert_eq!(back, signed);
+    }
+}
@@ -0,0 +1,869 @@
+//! Effects run on signed 16-bit samples held in `i32`, so they can overshoot between
+//! stages; the callback clamps the result of the whole chain.
+
+use std::sync::atomic::{fence, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A DSP stage run on every callback block, after gain, pan and the limiter.
+///
+/// `samples` is interleaved by channel and at most one SDL callback block long.
+/// `process` is called from the audio thread and must not allocate or block.
+pub trait Effect {
+    fn process(&mut self, samples: &mut [i32]);
+}
+
+pub type EffectChain = Vec<Box<dyn Effect + Send>>;
+
+/// A fixed linear gain, 1.0 leaves the signal unchanged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gain {
+    pub factor: f32,
+}
+
+impl Gain {
+    pub fn new(factor: f32) -> Self {
+        Self { factor }
+    }
+}
+
+impl Effect for Gain {
+    fn process(&mut self, samples: &mut [i32]) {
+        for sample in samples.iter_mut() {
+            *sample = (*sample as f32 * self.factor).round() as i32;
+        }
+    }
+}
+
+/// One-pole low-pass: `y += a * (x - y)` per channel, 6 dB per octave above the cutoff.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnePoleLowPass {
+    coefficient: f32,
+    state: Vec<f32>,
+}
+
+impl OnePoleLowPass {
+    pub fn new(cutoff_hz: f32, sample_rate: u32, channels: usize) -> Self {
+        let omega = std::f32::consts::TAU * cutoff_hz.max(0.0) / sample_rate.max(1) as f32;
+        Self {
+            coefficient: 1.0 - (-omega).exp(),
+            state: vec![0.0; channels.max(1)],
+        }
+    }
+}
+
+impl Effect for OnePoleLowPass {
+    fn process(&mut self, samples: &mut [i32]) {
+        for frame in samples.chunks_mut(self.state.len()) {
+            for (sample, state) in frame.iter_mut().zip(self.state.iter_mut()) {
+                *state += self.coefficient * (*sample as f32 - *state);
+                *sample = state.round() as i32;
+            }
+        }
+    }
+}
+
+/// A filter cutoff in Hz shared with a running filter, so it can be moved while the
+/// filter sits in a device's effect chain.
+#[derive(Debug, Clone)]
+pub struct Cutoff(Arc<AtomicU32>);
+
+impl Cutoff {
+    fn new(hz: f32) -> Self {
+        Self(Arc::new(AtomicU32::new(hz.to_bits())))
+    }
+
+    /// NaN turns the filter off, as far as it goes: a low-pass moves up to just under
+    /// Nyquist and a high-pass down to 1 Hz.
+    pub fn set(&self, hz: f32) {
+        self.0.store(hz.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BiquadKind {
+    LowPass,
+    HighPass,
+}
+
+/// Cutoff changes by more than this factor clear the filter history.
+const RESET_RATIO: f32 = 2.0;
+
+/// Direct form I biquad with RBJ cookbook coefficients, one history per channel.
+#[derive(Debug, Clone)]
+struct Biquad {
+    kind: BiquadKind,
+    sample_rate: f32,
+    q: f32,
+    cutoff: Cutoff,
+    applied_cutoff: f32,
+    // b0, b1, b2, a1, a2, normalized by a0
+    coefficients: [f32; 5],
+    // x1, x2, y1, y2
+    history: Vec<[f32; 4]>,
+}
+
+impl Biquad {
+    fn new(kind: BiquadKind, cutoff_hz: f32, sample_rate: u32, channels: usize) -> Self {
+        let mut biquad = Self {
+            kind,
+            sample_rate: sample_rate.max(1) as f32,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+            cutoff: Cutoff::new(cutoff_hz),
+            applied_cutoff: cutoff_hz,
+            coefficients: [0.0; 5],
+            history: vec![[0.0; 4]; channels.max(1)],
+        };
+        biquad.update(cutoff_hz);
+        biquad
+    }
+
+    fn update(&mut self, requested_hz: f32) {
+        // keep clear of 0 Hz and Nyquist where the formulas degenerate
+        let (lowest, highest) = (1.0, 0.49 * self.sample_rate);
+        let off = match self.kind {
+            BiquadKind::LowPass => highest,
+            BiquadKind::HighPass => lowest,
+        };
+        let usable = |hz: f32| if hz.is_nan() { off } else { hz.clamp(lowest, highest) };
+        let (cutoff_hz, previous) = (usable(requested_hz), usable(self.applied_cutoff));
+        if cutoff_hz / previous > RESET_RATIO || previous / cutoff_hz > RESET_RATIO {
+            self.history.fill([0.0; 4]);
+        }
+        self.applied_cutoff = requested_hz;
+        let omega = std::f32::consts::TAU * cutoff_hz / self.sample_rate;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2.0 * self.q);
+        let (b0, b1) = match self.kind {
+            BiquadKind::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos),
+            BiquadKind::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos)),
+        };
+        let a0 = 1.0 + alpha;
+        self.coefficients = [b0 / a0, b1 / a0, b0 / a0, -2.0 * cos / a0, (1.0 - alpha) / a0];
+    }
+
+    fn process(&mut self, samples: &mut [i32]) {
+        let cutoff_hz = self.cutoff.get();
+        // by bits, so a NaN cutoff isn't recomputed every block
+        if cutoff_hz.to_bits() != self.applied_cutoff.to_bits() {
+            self.update(cutoff_hz);
+        }
+        let [b0, b1, b2, a1, a2] = self.coefficients;
+        for frame in samples.chunks_mut(self.history.len()) {
+            for (sample, [x1, x2, y1, y2]) in frame.iter_mut().zip(self.history.iter_mut()) {
+                let x = *sample as f32;
+                let y = b0 * x + b1 * *x1 + b2 * *x2 - a1 * *y1 - a2 * *y2;
+                (*x2, *x1, *y2, *y1) = (*x1, x, *y1, y);
+                *sample = y.round() as i32;
+            }
+        }
+    }
+}
+
+macro_rules! biquad_effect {
+    ($name:ident, $kind:expr) => {
+        #[derive(Debug, Clone)]
+        pub struct $name(Biquad);
+
+        impl $name {
+            pub fn new(cutoff_hz: f32, sample_rate: u32, channels: usize) -> Self {
+                Self(Biquad::new($kind, cutoff_hz, sample_rate, channels))
+            }
+
+            /// A handle that retunes this filter from any thread.
+            pub fn cutoff(&self) -> Cutoff {
+                self.0.cutoff.clone()
+            }
+
+            /// See `Cutoff::set`.
+            pub fn set_cutoff(&mut self, hz: f32) {
+                self.0.cutoff.set(hz);
+            }
+        }
+
+        impl Effect for $name {
+            fn process(&mut self, samples: &mut [i32]) {
+                self.0.process(samples);
+            }
+        }
+    };
+}
+
+biquad_effect!(LowPass, BiquadKind::LowPass);
+biquad_effect!(HighPass, BiquadKind::HighPass);
+
+/// Feedback is capped just under 1.0 so the echoes always die away.
+pub const MAX_FEEDBACK: f32 = 0.99;
+
+/// Feedback delay: each echo repeats the previous one `feedback` times quieter.
+/// `mix` blends from fully dry (0.0) to fully wet (1.0).
+#[derive(Debug, Clone)]
+pub struct Echo {
+    feedback: f32,
+    mix: f32,
+    channels: usize,
+    delay_frames: usize,
+    sample_rate: u32,
+    // interleaved, sized for the widest delay at construction
+    line: Vec<f32>,
+    pos: usize,
+}
+
+impl Echo {
+    pub fn new(delay_ms: u32, feedback: f32, mix: f32, sample_rate: u32, channels: usize) -> Self {
+        let delay_frames = Self::frames(delay_ms, sample_rate);
+        let channels = channels.max(1);
+        Self {
+            feedback: feedback.clamp(0.0, MAX_FEEDBACK),
+            mix: mix.clamp(0.0, 1.0),
+            channels,
+            delay_frames,
+            sample_rate,
+            line: vec![0.0; delay_frames * channels],
+            pos: 0,
+        }
+    }
+
+    fn frames(delay_ms: u32, sample_rate: u32) -> usize {
+        ((delay_ms as u64 * sample_rate as u64 / 1000) as usize).max(1)
+    }
+
+    /// Shortens or restores the delay, up to the one given to `new`; the delay line
+    /// is never reallocated.
+    pub fn set_delay_ms(&mut self, delay_ms: u32) {
+        let delay_frames = Self::frames(delay_ms, self.sample_rate).min(self.line.len() / self.channels);
+        if delay_frames != self.delay_frames {
+            self.delay_frames = delay_frames;
+            self.pos = 0;
+            self.line.fill(0.0);
+        }
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(0.0, MAX_FEEDBACK);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+}
+
+impl Effect for Echo {
+    fn process(&mut self, samples: &mut [i32]) {
+        let len = self.delay_frames * self.channels;
+        for sample in samples.iter_mut() {
+            let dry = *sample as f32;
+            let delayed = self.line[self.pos];
+            self.line[self.pos] = dry + delayed * self.feedback;
+            self.pos = if self.pos + 1 == len { 0 } else { self.pos + 1 };
+            *sample = (dry * (1.0 - self.mix) + delayed * self.mix).round() as i32;
+        }
+    }
+}
+
+/// The most an `Equalizer` band boosts or cuts, in dB.
+pub const MAX_EQ_DB: f32 = 12.0;
+/// Centers of the `Equalizer::new` bands: the shelves' corners and the mid peak.
+pub const EQ_LOW_HZ: f32 = 200.0;
+pub const EQ_MID_HZ: f32 = 1000.0;
+pub const EQ_HIGH_HZ: f32 = 4000.0;
+// width of the mid band; about 1.4 octaves between the -3 dB points of a full boost
+const EQ_MID_Q: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Band {
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+/// RBJ cookbook coefficients, `b0, b1, b2, a1, a2` normalized by `a0`. Shelves have a
+/// slope of 1; 0 dB gives the identity filter for every band.
+fn band_coefficients(band: Band, hz: f32, db: f32, sample_rate: f64) -> [f32; 5] {
+    let a = 10f64.powf(db as f64 / 40.0);
+    let omega = std::f64::consts::TAU * (hz as f64).clamp(1.0, 0.49 * sample_rate) / sample_rate;
+    let (sin, cos) = omega.sin_cos();
+    let (b, den) = match band {
+        Band::Peak => {
+            let alpha = sin / (2.0 * EQ_MID_Q);
+            ([1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a], [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a])
+        }
+        Band::LowShelf | Band::HighShelf => {
+            let two_sqrt_a_alpha = a.sqrt() * sin * std::f64::consts::SQRT_2;
+            // the high shelf is the low shelf with the sign of cos flipped in places
+            let flip = if band == Band::LowShelf { 1.0 } else { -1.0 };
+            (
+                [
+                    a * ((a + 1.0) - flip * (a - 1.0) * cos + two_sqrt_a_alpha),
+                    flip * 2.0 * a * ((a - 1.0) - flip * (a + 1.0) * cos),
+                    a * ((a + 1.0) - flip * (a - 1.0) * cos - two_sqrt_a_alpha),
+                ],
+                [
+                    (a + 1.0) + flip * (a - 1.0) * cos + two_sqrt_a_alpha,
+                    -flip * 2.0 * ((a - 1.0) + flip * (a + 1.0) * cos),
+                    (a + 1.0) + flip * (a - 1.0) * cos - two_sqrt_a_alpha,
+                ],
+            )
+        }
+    };
+    let a0 = den[0];
+    [b[0] / a0, b[1] / a0, b[2] / a0, den[1] / a0, den[2] / a0].map(|c| c as f32)
+}
+
+const BANDS: [Band; 3] = [Band::LowShelf, Band::Peak, Band::HighShelf];
+
+/// Parameters computed on a control thread for an effect running in the callback.
+///
+/// A seqlock: `version` is odd while `write` is storing new values, and a `read` that
+/// overlaps a write returns `None`, so the callback keeps its old values for one more
+/// block instead of waiting. Writers must take turns, e.g. under a mutex.
+///
+/// The version only has to tell writes apart, so it is a wrapping `usize`, atomic on
+/// every target; a reader would have to miss 2^31 writes to mistake one for another.
+#[derive(Debug)]
+struct ParamBlock<const N: usize> {
+    version: AtomicUsize,
+    values: [AtomicU32; N],
+}
+
+impl<const N: usize> ParamBlock<N> {
+    fn new() -> Self {
+        Self { version: AtomicUsize::new(0), values: std::array::from_fn(|_| AtomicU32::new(0)) }
+    }
+
+    fn write(&self, values: [f32; N]) {
+        let version = self.version.load(Ordering::Relaxed);
+        self.version.store(version.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (slot, value) in self.values.iter().zip(values) {
+            slot.store(value.to_bits(), Ordering::Relaxed);
+        }
+        self.version.store(version.wrapping_add(2), Ordering::Release);
+    }
+
+    /// The values and their version, or `None` while they are being written.
+    fn read(&self) -> Option<(usize, [f32; N])> {
+        let version = self.version.load(Ordering::Acquire);
+        if version % 2 == 1 {
+            return None;
+        }
+        let values = std::array::from_fn(|i| f32::from_bits(self.values[i].load(Ordering::Relaxed)));
+        fence(Ordering::Acquire);
+        (self.version.load(Ordering::Relaxed) == version).then_some((version, values))
+    }
+}
+
+/// The gains of a running `Equalizer` and the coefficients computed from them, which
+/// setters store under `gains`.
+#[derive(Debug)]
+struct EqShared {
+    sample_rate: f64,
+    centers: [f32; 3],
+    gains: Mutex<[f32; 3]>,
+    coefficients: ParamBlock<15>,
+}
+
+impl EqShared {
+    fn set(&self, band: usize, db: f32) {
+        let mut gains = self.gains.lock().unwrap_or_else(PoisonError::into_inner);
+        gains[band] = if db.is_nan() { 0.0 } else { db.clamp(-MAX_EQ_DB, MAX_EQ_DB) };
+        let bands: [[f32; 5]; 3] =
+            std::array::from_fn(|i| band_coefficients(BANDS[i], self.centers[i], gains[i], self.sample_rate));
+        self.coefficients.write(std::array::from_fn(|i| bands[i / 5][i % 5]));
+    }
+}
+
+/// Changes the gains of an `Equalizer` from any thread while it runs.
+#[derive(Debug, Clone)]
+pub struct EqControl(Arc<EqShared>);
+
+impl EqControl {
+    /// Each gain is clamped to ±`MAX_EQ_DB`.
+    pub fn set_low_db(&self, db: f32) {
+        self.0.set(0, db);
+    }
+
+    pub fn set_mid_db(&self, db: f32) {
+        self.0.set(1, db);
+    }
+
+    pub fn set_high_db(&self, db: f32) {
+        self.0.set(2, db);
+    }
+
+    /// Low, mid and high gains in dB.
+    pub fn gains(&self) -> [f32; 3] {
+        *self.0.gains.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Tone controls: a low shelf, a peaking mid band and a high shelf, as three cascaded
+/// biquads. All gains start at 0 dB, where the output matches the input to within
+/// rounding.
+///
+/// Gains are changed through `control`; the new coefficients are computed right there
+/// on the calling thread, so the callback only picks them up.
+#[derive(Debug, Clone)]
+pub struct Equalizer {
+    shared: Arc<EqShared>,
+    applied_version: usize,
+    coefficients: [[f32; 5]; 3],
+    // x1, x2, y1, y2 per band and channel
+    history: Vec<[[f32; 4]; 3]>,
+}
+
+impl Equalizer {
+    /// Bands centered on `EQ_LOW_HZ`, `EQ_MID_HZ` and `EQ_HIGH_HZ`.
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self::with_bands(EQ_LOW_HZ, EQ_MID_HZ, EQ_HIGH_HZ, sample_rate, channels)
+    }
+
+    pub fn with_bands(low_hz: f32, mid_hz: f32, high_hz: f32, sample_rate: u32, channels: usize) -> Self {
+        let shared = Arc::new(EqShared {
+            sample_rate: sample_rate.max(1) as f64,
+            centers: [low_hz, mid_hz, high_hz],
+            gains: Mutex::new([0.0; 3]),
+            coefficients: ParamBlock::new(),
+        });
+        shared.set(0, 0.0);
+        let mut equalizer = Self {
+            shared,
+            // odd, so never the version of a finished write
+            applied_version: usize::MAX,
+            coefficients: [[0.0; 5]; 3],
+            history: vec![[[0.0; 4]; 3]; channels.max(1)],
+        };
+        equalizer.refresh();
+        equalizer
+    }
+
+    /// A handle that adjusts this equalizer from any thread.
+    pub fn control(&self) -> EqControl {
+        EqControl(self.shared.clone())
+    }
+
+    fn refresh(&mut self) {
+        if let Some((version, coefficients)) = self.shared.coefficients.read() {
+            if version != self.applied_version {
+                for (band, chunk) in self.coefficients.iter_mut().zip(coefficients.chunks_exact(5)) {
+                    band.copy_from_slice(chunk);
+                }
+                self.applied_version = version;
+            }
+        }
+    }
+}
+
+impl Effect for Equalizer {
+    fn process(&mut self, samples: &mut [i32]) {
+        self.refresh();
+        for frame in samples.chunks_mut(self.history.len()) {
+            for (sample, bands) in frame.iter_mut().zip(self.history.iter_mut()) {
+                let mut x = *sample as f32;
+                for ([b0, b1, b2, a1, a2], [x1, x2, y1, y2]) in self.coefficients.iter().zip(bands.iter_mut()) {
+                    let y = b0 * x + b1 * *x1 + b2 * *x2 - a1 * *y1 - a2 * *y2;
+                    (*x2, *x1, *y2, *y1) = (*x1, x, *y1, y);
+                    x = y;
+                }
+                *sample = x.round() as i32;
+            }
+        }
+    }
+}
+
+/// How a `Compressor` reacts to the level, see `CompressorControl` for each field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorSettings {
+    /// Level in dBFS above which the gain comes down.
+    pub threshold_db: f32,
+    /// Input dB above the threshold per output dB, at least 1.0; infinity limits.
+    pub ratio: f32,
+    /// Time for the detected level to cover about 63% of a rise.
+    pub attack_ms: f32,
+    /// Same for a fall.
+    pub release_ms: f32,
+    /// Gain added after compression to bring the level back up.
+    pub makeup_db: f32,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        Self { threshold_db: -20.0, ratio: 4.0, attack_ms: 5.0, release_ms: 100.0, makeup_db: 0.0 }
+    }
+}
+
+impl CompressorSettings {
+    /// `threshold_db`, the slope `1 - 1 / ratio`, the attack and release smoothing
+    /// factors and the makeup gain, as the callback uses them.
+    fn derive(&self, sample_rate: f64) -> [f32; 5] {
+        // 0 ms follows the level instantly; also keeps NaN times from reaching exp()
+        let smoothing = |ms: f32| {
+            let frames = ms as f64 * sample_rate / 1000.0;
+            if frames > 0.0 { (-1.0 / frames).exp() as f32 } else { 0.0 }
+        };
+        [
+            self.threshold_db,
+            1.0 - 1.0 / self.ratio,
+            smoothing(self.attack_ms),
+            smoothing(self.release_ms),
+            self.makeup_db,
+        ]
+    }
+
+    fn sanitized(mut self) -> Self {
+        let or_zero = |value: f32| if value.is_nan() { 0.0 } else { value };
+        self.threshold_db = or_zero(self.threshold_db).min(0.0);
+        self.ratio = if self.ratio.is_nan() { 1.0 } else { self.ratio.max(1.0) };
+        self.attack_ms = or_zero(self.attack_ms).max(0.0);
+        self.release_ms = or_zero(self.release_ms).max(0.0);
+        self.makeup_db = or_zero(self.makeup_db).clamp(-MAX_MAKEUP_DB, MAX_MAKEUP_DB);
+        self
+    }
+}
+
+/// The most makeup gain a `Compressor` adds or takes away, in dB.
+pub const MAX_MAKEUP_DB: f32 = 24.0;
+// 0 dBFS in the signed 16-bit domain effects work in
+const SIGNED_FULL_SCALE: f32 = 32768.0;
+
+#[derive(Debug)]
+struct CompressorShared {
+    sample_rate: f64,
+    settings: Mutex<CompressorSettings>,
+    params: ParamBlock<5>,
+}
+
+impl CompressorShared {
+    fn update(&self, change: impl FnOnce(&mut CompressorSettings)) {
+        let mut settings = self.settings.lock().unwrap_or_else(PoisonError::into_inner);
+        change(&mut settings);
+        *settings = settings.sanitized();
+        self.params.write(settings.derive(self.sample_rate));
+    }
+}
+
+/// Changes the settings of a `Compressor` from any thread while it runs.
+#[derive(Debug, Clone)]
+pub struct CompressorControl(Arc<CompressorShared>);
+
+impl CompressorControl {
+    /// At most 0 dBFS.
+    pub fn set_threshold_db(&self, db: f32) {
+        self.0.update(|settings| settings.threshold_db = db);
+    }
+
+    /// Clamped to at least 1.0, which leaves the signal alone.
+    pub fn set_ratio(&self, ratio: f32) {
+        self.0.update(|settings| settings.ratio = ratio);
+    }
+
+    pub fn set_attack_ms(&self, ms: f32) {
+        self.0.update(|settings| settings.attack_ms = ms);
+    }
+
+    pub fn set_release_ms(&self, ms: f32) {
+        self.0.update(|settings| settings.release_ms = ms);
+    }
+
+    /// Clamped to ±`MAX_MAKEUP_DB`.
+    pub fn set_makeup_db(&self, db: f32) {
+        self.0.update(|settings| settings.makeup_db = db);
+    }
+
+    pub fn set(&self, settings: CompressorSettings) {
+        self.0.update(|current| *current = settings);
+    }
+
+    pub fn settings(&self) -> CompressorSettings {
+        *self.0.settings.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Feed-forward compressor with a hard knee. The detector follows the loudest channel
+/// of each frame, and every channel gets the same gain so the stereo image holds.
+///
+/// Settings change through `control`, like the `Equalizer`'s gains.
+#[derive(Debug, Clone)]
+pub struct Compressor {
+    shared: Arc<CompressorShared>,
+    applied_version: usize,
+    params: [f32; 5],
+    channels: usize,
+    // detected level as a fraction of full scale
+    envelope: f32,
+}
+
+impl Compressor {
+    pub fn new(settings: CompressorSettings, sample_rate: u32, channels: usize) -> Self {
+        let shared = Arc::new(CompressorShared {
+            sample_rate: sample_rate.max(1) as f64,
+            settings: Mutex::new(settings),
+            params: ParamBlock::new(),
+        });
+        shared.update(|_| ());
+        let mut compressor =
+            Self { shared, applied_version: usize::MAX, params: [0.0; 5], channels: channels.max(1), envelope: 0.0 };
+        compressor.refresh();
+        compressor
+    }
+
+    /// A handle that adjusts this compressor from any thread.
+    pub fn control(&self) -> CompressorControl {
+        CompressorControl(self.shared.clone())
+    }
+
+    fn refresh(&mut self) {
+        if let Some((version, params)) = self.shared.params.read() {
+            if version != self.applied_version {
+                self.params = params;
+                self.applied_version = version;
+            }
+        }
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, samples: &mut [i32]) {
+        self.refresh();
+        let [threshold_db, slope, attack, release, makeup_db] = self.params;
+        for frame in samples.chunks_mut(self.channels) {
+            let level = frame.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0) as f32 / SIGNED_FULL_SCALE;
+            let smoothing = if level > self.envelope { attack } else { release };
+            self.envelope = level + smoothing * (self.envelope - level);
+            let over_db = 20.0 * self.envelope.max(f32::MIN_POSITIVE).log10() - threshold_db;
+            let gain_db = makeup_db - over_db.max(0.0) * slope;
+            if gain_db == 0.0 {
+                continue;
+            }
+            let gain = 10f32.powf(gain_db / 20.0);
+            for sample in frame.iter_mut() {
+                *sample = (*sample as f32 * gain).round() as i32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain_scales_without_clamping() {
+        let mut samples = [1000, -1000, 30000];
+        Gain::new(2.0).process(&mut samples);
+        assert_eq!(samples, [2000, -2000, 60000]);
+    }
+
+    #[test]
+    fn low_pass_keeps_dc_and_damps_alternation() {
+        let mut filter = OnePoleLowPass::new(1000.0, 48000, 1);
+        let mut dc = [10000; 512];
+        filter.process(&mut dc);
+        assert!(dc[0] < 2000);
+        assert_eq!(dc[511], 10000);
+        let mut filter = OnePoleLowPass::new(1000.0, 48000, 1);
+        let mut nyquist: Vec<i32> = (0..512).map(|i| if i % 2 == 0 { 10000 } else { -10000 }).collect();
+        filter.process(&mut nyquist);
+        assert!(nyquist[256..].iter().all(|s| s.abs() < 1000));
+    }
+
+    #[test]
+    fn low_pass_filters_channels_independently() {
+        let mut filter = OnePoleLowPass::new(20000.0, 48000, 2);
+        let mut samples = [10000, -10000].repeat(64);
+        filter.process(&mut samples);
+        assert_eq!(&samples[126..], [10000, -10000]);
+    }
+
+    fn impulse(len: usize) -> Vec<i32> {
+        let mut samples = vec![0; len];
+        samples[0] = 10000;
+        samples
+    }
+
+    #[test]
+    fn low_pass_impulse_response_decays() {
+        let mut filter = LowPass::new(15000.0, 48000, 1);
+        let mut samples = impulse(256);
+        filter.process(&mut samples);
+        assert!(samples[0] > 1000);
+        assert!(samples[64..].iter().all(|s| *s == 0));
+        // unity gain at DC
+        let mut dc = [10000; 256];
+        filter.process(&mut dc);
+        assert_eq!(dc[255], 10000);
+    }
+
+    #[test]
+    fn high_pass_blocks_dc() {
+        let mut filter = HighPass::new(100.0, 48000, 1);
+        let mut dc = [10000; 4800];
+        filter.process(&mut dc);
+        assert!(dc[0] > 9000);
+        assert!(dc[4700..].iter().all(|s| s.abs() < 10));
+        let mut samples = impulse(4800);
+        filter.process(&mut samples);
+        assert!(samples[4000..].iter().all(|s| s.abs() < 10));
+    }
+
+    #[test]
+    fn cutoff_handle_retunes_and_resets() {
+        let mut filter = LowPass::new(100.0, 48000, 1);
+        let cutoff = filter.cutoff();
+        let mut dc = [10000; 64];
+        filter.process(&mut dc);
+        assert!(dc[63] < 5000);
+        cutoff.set(20000.0);
+        assert_eq!(filter.cutoff().get(), 20000.0);
+        // the jump discards the old history, so the output starts from scratch
+        let mut samples = impulse(64);
+        filter.process(&mut samples);
+        assert!(samples[0] > 5000);
+        assert!(samples[32..].iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn nan_cutoff_turns_the_filter_off() {
+        let mut low = LowPass::new(100.0, 48000, 1);
+        low.set_cutoff(f32::NAN);
+        let mut dc = [10000; 64];
+        low.process(&mut dc);
+        // a cutoff this close to Nyquist rings a little, but lets DC straight through
+        assert!(dc.iter().all(|s| (s - 10000).abs() < 500), "{:?}", dc);
+        let mut high = HighPass::new(f32::NAN, 48000, 1);
+        let mut dc = [10000; 64];
+        high.process(&mut dc);
+        // at 1 Hz DC takes seconds to fade
+        assert!(dc.iter().all(|s| *s > 9800), "{:?}", dc);
+    }
+
+    #[test]
+    fn echo_repeats_an_impulse_geometrically() {
+        // 1 ms at 8 kHz is 8 frames
+        let mut echo = Echo::new(1, 0.5, 0.5, 8000, 1);
+        let mut samples = impulse(40);
+        echo.process(&mut samples[..13]);
+        echo.process(&mut samples[13..]);
+        let taps: Vec<(usize, i32)> = samples.iter().copied().enumerate().filter(|(_, s)| *s != 0).collect();
+        assert_eq!(taps, [(0, 5000), (8, 5000), (16, 2500), (24, 1250), (32, 625)]);
+    }
+
+    #[test]
+    fn echo_keeps_channels_apart_and_clamps_feedback() {
+        let mut echo = Echo::new(1, 5.0, 1.0, 2000, 2);
+        let mut samples = vec![0; 8];
+        samples[1] = 1000;
+        echo.process(&mut samples);
+        assert_eq!(samples, [0, 0, 0, 0, 0, 1000, 0, 0]);
+        let mut tail = vec![0; 8000];
+        echo.process(&mut tail);
+        assert!(tail[7990..].iter().all(|s| s.abs() < 1000));
+        // larger delays can't grow the line
+        echo.set_delay_ms(1000);
+        assert_eq!(echo.delay_frames, 2);
+    }
+
+    const IMPULSE: i32 = 1 << 24;
+
+    /// The gain in dB at `hz` of a response to an impulse of `IMPULSE`.
+    fn magnitude_db(response: &[i32], hz: f64, sample_rate: f64) -> f64 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, sample) in response.iter().enumerate() {
+            let phase = std::f64::consts::TAU * hz * n as f64 / sample_rate;
+            re += *sample as f64 * phase.cos();
+            im -= *sample as f64 * phase.sin();
+        }
+        20.0 * (re.hypot(im) / IMPULSE as f64).log10()
+    }
+
+    #[test]
+    fn equalizer_matches_reference_magnitudes() {
+        let mut equalizer = Equalizer::new(48000, 1);
+        let control = equalizer.control();
+        control.set_low_db(12.0);
+        control.set_mid_db(-6.0);
+        control.set_high_db(9.0);
+        let mut response = vec![0; 8192];
+        response[0] = IMPULSE;
+        equalizer.process(&mut response);
+        // |H| of the three RBJ sections, evaluated exactly
+        for (hz, reference) in [(30.0, 11.99), (200.0, 5.73), (1000.0, -5.94), (4000.0, 4.09), (16000.0, 8.98)] {
+            let measured = magnitude_db(&response, hz, 48000.0);
+            assert!((measured - reference).abs() < 0.05, "{} Hz: {} dB", hz, measured);
+        }
+    }
+
+    #[test]
+    fn flat_equalizer_is_transparent() {
+        let mut equalizer = Equalizer::new(44100, 2);
+        let data: Vec<i32> = crate::generators::white_noise(4096, 0x7fff, 3).iter().map(|s| *s as i32 - 0x8000).collect();
+        let mut samples = data.clone();
+        equalizer.process(&mut samples);
+        assert!(samples.iter().zip(&data).all(|(out, input)| (out - input).abs() <= 1));
+    }
+
+    #[test]
+    fn eq_gains_clamp_and_reach_the_running_filter() {
+        let mut equalizer = Equalizer::new(48000, 1);
+        let control = equalizer.control();
+        control.set_low_db(40.0);
+        control.set_high_db(f32::NAN);
+        assert_eq!(control.gains(), [MAX_EQ_DB, 0.0, 0.0]);
+        let mut dc = [1000; 48000];
+        equalizer.process(&mut dc);
+        // a low shelf reaches its full gain at DC: +12 dB is about 3.98x
+        assert!((dc[47999] - 3981).abs() <= 1, "{}", dc[47999]);
+        control.set_low_db(0.0);
+        let mut dc = [1000; 48000];
+        equalizer.process(&mut dc);
+        assert_eq!(dc[47999], 1000);
+    }
+
+    fn dbfs(db: f32) -> i32 {
+        (SIGNED_FULL_SCALE * 10f32.powf(db / 20.0)).round() as i32
+    }
+
+    #[test]
+    fn compressor_step_settles_at_the_compressed_level() {
+        let mut compressor = Compressor::new(CompressorSettings::default(), 48000, 1);
+        // -30 dBFS is below the -20 dBFS threshold and passes untouched
+        let mut quiet = vec![dbfs(-30.0); 4800];
+        compressor.process(&mut quiet);
+        assert!(quiet.iter().all(|s| *s == dbfs(-30.0)));
+        // 0 dBFS is 20 dB over: at 4:1 that leaves 5 dB, so -15 dBFS once the attack is through
+        let mut loud = vec![dbfs(0.0).min(i16::MAX as i32); 4800];
+        compressor.process(&mut loud);
+        assert!(loud[0] > dbfs(-15.0) * 2, "the attack lets the edge through");
+        let settled = loud[4799];
+        assert!((settled - dbfs(-15.0)).abs() <= 2, "{} vs {}", settled, dbfs(-15.0));
+    }
+
+    #[test]
+    fn compressor_at_ratio_one_is_transparent() {
+        let settings = CompressorSettings { ratio: 1.0, threshold_db: -40.0, ..CompressorSettings::default() };
+        let mut compressor = Compressor::new(settings, 48000, 2);
+        let data: Vec<i32> = crate::generators::white_noise(4096, 0x7fff, 9).iter().map(|s| *s as i32 - 0x8000).collect();
+        let mut samples = data.clone();
+        compressor.process(&mut samples);
+        assert_eq!(samples, data);
+    }
+
+    #[test]
+    fn instant_attack_and_release_stay_finite() {
+        let mut compressor = Compressor::new(CompressorSettings::default(), 48000, 1);
+        let control = compressor.control();
+        control.set_attack_ms(0.0);
+        control.set_release_ms(f32::NAN);
+        control.set_ratio(f32::INFINITY);
+        let mut samples: Vec<i32> = (0..1000).map(|i| if i % 3 == 0 { 32767 } else { -5 * i }).collect();
+        samples.extend([0; 100]);
+        compressor.process(&mut samples);
+        // an instant limiter: every loud sample is pulled right down to the threshold
+        assert!(samples.iter().all(|s| s.abs() <= dbfs(-20.0) + 1), "{:?}", samples.iter().max());
+        assert!(samples[1000..].iter().all(|s| *s == 0));
+        assert_eq!(control.settings().release_ms, 0.0);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{Sample, GAIN_ONE};
+
+/// Attack, decay and release lengths are in samples (frames when a mixer voice runs it live);
+/// `sustain` is a level between 0.0 and 1.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Envelope {
+    pub attack: usize,
+    pub decay: usize,
+    pub sustain: f32,
+    pub release: usize,
+}
+
+impl Envelope {
+    pub fn new(attack: usize, decay: usize, sustain: f32, release: usize) -> Self {
+        Self { attack, decay, sustain: sustain.clamp(0.0, 1.0), release }
+    }
+
+    pub fn from_ms(attack_ms: u32, decay_ms: u32, sustain: f32, release_ms: u32, sample_rate: u32) -> Self {
+        let samples = |ms: u32| (ms as u64 * sample_rate as u64 / 1000) as usize;
+        Self::new(samples(attack_ms), samples(decay_ms), sustain, samples(release_ms))
+    }
+
+    /// Attack/decay/sustain level `pos` samples after the start.
+    fn level_at(&self, pos: usize) -> f32 {
+        if pos < self.attack {
+            pos as f32 / self.attack as f32
+        } else if pos < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (pos - self.attack) as f32 / self.decay as f32
+        } else {
+            self.sustain
+        }
+    }
+
+    /// Attack, decay and release shortened in proportion so together they fit in `len`.
+    fn fit(&self, len: usize) -> Self {
+        let total = self.attack.saturating_add(self.decay).saturating_add(self.release);
+        if total <= len {
+            return *self;
+        }
+        let scale = |part: usize| (part as u128 * len as u128 / total as u128) as usize;
+        Self { attack: scale(self.attack), decay: scale(self.decay), release: scale(self.release), ..*self }
+    }
+
+    /// Scales offset-binary samples around the midpoint. The release ends the buffer
+    /// at silence, starting from wherever attack and decay have got to. An envelope
+    /// longer than the buffer is squeezed to fit, each stage keeping its share.
+    pub fn apply(&self, data: &mut [u16]) {
+        let len = data.len();
+        let envelope = self.fit(len);
+        let release = envelope.release;
+        let release_start = len - release;
+        let release_level = envelope.level_at(release_start);
+        for (i, sample) in data.iter_mut().enumerate() {
+            let level = if i < release_start {
+                envelope.level_at(i)
+            } else {
+                release_level * (len - i - 1) as f32 / release as f32
+            };
+            *sample = u16::from_i32((sample.to_i32() as f32 * level).round() as i32);
+        }
+    }
+}
+
+/// A running envelope on a voice, advanced once per frame by the callback.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct EnvelopeState {
+    envelope: Envelope,
+    pos: usize,
+    /// Frames into the release and the level it started from.
+    release: Option<(usize, f32)>,
+}
+
+impl EnvelopeState {
+    pub(crate) fn new(envelope: Envelope) -> Self {
+        Self { envelope, pos: 0, release: None }
+    }
+
+    pub(crate) fn release(&mut self) {
+        if self.release.is_none() {
+            self.release = Some((0, self.envelope.level_at(self.pos)));
+        }
+    }
+
+    /// The Q16 factor for the next frame, `None` once the release has run out.
+    pub(crate) fn next(&mut self) -> Option<u32> {
+        let level = match self.release.as_mut() {
+            None => {
+                let level = self.envelope.level_at(self.pos);
+                self.pos = self.pos.saturating_add(1);
+                level
+            }
+            Some((done, start)) => {
+                if *done >= self.envelope.release {
+                    return None;
+                }
+                *done += 1;
+                *start * (self.envelope.release - *done) as f32 / self.envelope.release as f32
+            }
+        };
+        Some((level * GAIN_ONE as f32) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SETUP_U16;
+
+    fn signed(data: &[u16]) -> Vec<i32> {
+        data.iter().map(|s| *s as i32 - SETUP_U16).collect()
+    }
+
+    #[test]
+    fn apply_shapes_attack_decay_sustain_release() {
+        let mut data = vec![(SETUP_U16 + 1000) as u16; 12];
+        Envelope::new(2, 2, 0.5, 4).apply(&mut data);
+        assert_eq!(signed(&data), [0, 500, 1000, 750, 500, 500, 500, 500, 375, 250, 125, 0]);
+    }
+
+    #[test]
+    fn apply_squeezes_long_envelopes_into_short_buffers() {
+        // half attack, half release, so the middle still gets loud
+        let mut data = vec![(SETUP_U16 - 800) as u16; 4];
+        Envelope::new(100, 0, 1.0, 100).apply(&mut data);
+        assert_eq!(signed(&data), [0, -400, -400, 0]);
+        let mut data = vec![(SETUP_U16 + 1000) as u16; 8];
+        Envelope::new(20, 20, 0.5, 40).apply(&mut data);
+        assert_eq!(signed(&data), [0, 500, 1000, 750, 375, 250, 125, 0]);
+        let mut data = vec![(SETUP_U16 - 800) as u16; 4];
+        Envelope::new(0, 0, 1.0, 10).apply(&mut data);
+        assert_eq!(signed(&data), [-600, -400, -200, 0]);
+    }
+
+    #[test]
+    fn zero_sustain_silences_the_tail() {
+        let mut data = vec![(SETUP_U16 + 1000) as u16; 8];
+        Envelope::new(0, 2, 0.0, 2).apply(&mut data);
+        assert_eq!(signed(&data), [1000, 500, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn live_release_ramps_from_current_level() {
+        let mut state = EnvelopeState::new(Envelope::new(2, 0, 1.0, 2));
+        assert_eq!(state.next(), Some(0));
+        assert_eq!(state.next(), Some(GAIN_ONE / 2));
+        assert_eq!(state.next(), Some(GAIN_ONE));
+        state.release();
+        assert_eq!(state.next(), Some(GAIN_ONE / 2));
+        assert_eq!(state.next(), Some(0));
+        assert_eq!(state.next(), None);
+    }
+
+    #[test]
+    fn from_ms_converts_with_sample_rate() {
+        let env = Envelope::from_ms(10, 20, 0.5, 5, 48000);
+        assert_eq!((env.attack, env.decay, env.release), (480, 960, 240));
+    }
+}
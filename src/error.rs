@@ -0,0 +1,349 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioError {
+    /// `sdl2::init` failed.
+    SdlInit(String),
+    /// SDL came up but its audio subsystem did not, e.g. no usable audio driver.
+    NoAudioSubsystem(String),
+    /// `AudioContext::with_driver` was given a driver this SDL build doesn't have.
+    UnknownDriver(String),
+    /// The audio subsystem was already running on another driver, so the requested one
+    /// could not be selected.
+    DriverInUse { requested: String, current: String },
+    /// SDL refused to open the playback device, including a named device that has disappeared.
+    DeviceOpen(String),
+    /// SDL has as many devices open as it supports (16 in SDL 2), counting playback and
+    /// capture devices from every context. Close one before opening another.
+    TooManyDevices(String),
+    /// The driver only has a default device and it is already open. Many drivers work
+    /// like this, among them `dummy`, JACK and Android's; `disk` does not.
+    SingleDeviceDriver(String),
+    /// SDL could not list the available devices.
+    DeviceEnumeration(String),
+    /// A setting was rejected before SDL was asked for anything.
+    InvalidParam { name: &'static str, reason: String },
+    /// SDL refused samples for a `QueueDevice`.
+    Queue(String),
+}
+
+impl AudioError {
+    pub(crate) fn invalid_param(name: &'static str, reason: &str) -> Self {
+        AudioError::InvalidParam { name, reason: reason.to_string() }
+    }
+
+    /// Sorts an SDL open failure into the limits above or `DeviceOpen`.
+    pub(crate) fn from_open(msg: String) -> Self {
+        if msg.contains("Too many open audio devices") {
+            AudioError::TooManyDevices(msg)
+        } else if msg.contains("Audio device already open") {
+            AudioError::SingleDeviceDriver(msg)
+        } else {
+            AudioError::DeviceOpen(msg)
+        }
+    }
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioError::SdlInit(msg) => write!(f, "SDL initialization failed: {}", msg),
+            AudioError::NoAudioSubsystem(msg) => write!(f, "SDL audio subsystem unavailable: {}", msg),
+            AudioError::UnknownDriver(name) => write!(f, "unknown audio driver: {}", name),
+            AudioError::DriverInUse { requested, current } => {
+                write!(f, "audio driver {} requested but {} is already running", requested, current)
+            }
+            AudioError::DeviceOpen(msg) => write!(f, "failed to open audio device: {}", msg),
+            AudioError::TooManyDevices(msg) => write!(f, "too many audio devices open: {}", msg),
+            AudioError::SingleDeviceDriver(msg) => {
+                write!(f, "the audio driver supports a single device: {}", msg)
+            }
+            AudioError::DeviceEnumeration(msg) => write!(f, "failed to enumerate audio devices: {}", msg),
+            AudioError::InvalidParam { name, reason } => write!(f, "invalid {}: {}", name, reason),
+            AudioError::Queue(msg) => write!(f, "failed to queue audio: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+/// For callers that still handle errors as strings, the `Display` text.
+impl From<AudioError> for String {
+    fn from(err: AudioError) -> Self {
+        err.to_string()
+    }
+}
+
+/// Returned by `Control::try_set_data` instead of wrapping a write around the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteError {
+    /// The write would start outside the buffer.
+    OffsetOutOfRange { offset: usize, buf_size: usize },
+    /// The data would wrap onto itself; only `fits` samples can be written at once.
+    TooLong { len: usize, fits: usize },
+}
+
+impl WriteError {
+    /// How many samples the rejected write could have held.
+    pub fn fits(&self) -> usize {
+        match self {
+            WriteError::OffsetOutOfRange { .. } => 0,
+            WriteError::TooLong { fits, .. } => *fits,
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::OffsetOutOfRange { offset, buf_size } => {
+                write!(f, "offset {} is outside the {}-sample buffer", offset, buf_size)
+            }
+            WriteError::TooLong { len, fits } => {
+                write!(f, "{} samples written but only {} fit in the buffer", len, fits)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+/// Returned by `Control::schedule_data` and `Control::notify_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// Playback is already past `at`.
+    InPast { at: u64, current: u64 },
+    /// `capacity` writes or markers are already waiting.
+    QueueFull { capacity: usize },
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::InPast { at, current } => {
+                write!(f, "position {} has already been played (now at {})", at, current)
+            }
+            ScheduleError::QueueFull { capacity } => {
+                write!(f, "all {} scheduled write slots are in use", capacity)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+/// `Control::wait_for_callback` gave up before the callback ran again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out waiting for the audio callback")
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Returned by `SoundBank::trigger`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerError<K> {
+    /// Nothing is stored under the key.
+    UnknownKey(K),
+    /// Every voice was busy and none could be stolen.
+    NoFreeVoice,
+}
+
+impl<K: fmt::Debug> fmt::Display for TriggerError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerError::UnknownKey(key) => write!(f, "no sound stored under {:?}", key),
+            TriggerError::NoFreeVoice => write!(f, "no voice free to play the sound"),
+        }
+    }
+}
+
+impl<K: fmt::Debug> std::error::Error for TriggerError<K> {}
+
+#[derive(Debug)]
+pub enum WavError {
+    Io(std::io::Error),
+    /// The file is not a RIFF/WAVE file, or a chunk runs past the end of it.
+    Malformed(&'static str),
+    /// A valid WAV file in an encoding other than 8-bit, 16-bit or 32-bit float PCM.
+    Unsupported { format_tag: u16, bits_per_sample: u16 },
+    /// Data to save isn't a whole number of frames, or has zero channels.
+    InvalidLayout { len: usize, channels: u8 },
+}
+
+impl From<std::io::Error> for WavError {
+    fn from(err: std::io::Error) -> Self {
+        WavError::Io(err)
+    }
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::Io(err) => write!(f, "WAV file I/O failed: {}", err),
+            WavError::Malformed(what) => write!(f, "malformed WAV file: {}", what),
+            WavError::Unsupported { format_tag, bits_per_sample } => {
+                write!(f, "unsupported WAV encoding: format tag {:#06x}, {} bits per sample", format_tag, bits_per_sample)
+            }
+            WavError::InvalidLayout { len, channels } => {
+                write!(f, "{} samples don't divide into frames of {} channels", len, channels)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WavError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WavError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Returned by `SoundBank::from_manifest` and `SoundBank::to_manifest`.
+#[derive(Debug)]
+pub enum BankError {
+    Io(std::io::Error),
+    /// The data is not a stored sound bank, is cut short, or points outside itself.
+    Malformed(&'static str),
+    /// A sound bank written by a newer version of the format.
+    UnsupportedVersion(u16),
+}
+
+impl From<std::io::Error> for BankError {
+    fn from(err: std::io::Error) -> Self {
+        BankError::Io(err)
+    }
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BankError::Io(err) => write!(f, "sound bank I/O failed: {}", err),
+            BankError::Malformed(what) => write!(f, "malformed sound bank: {}", what),
+            BankError::UnsupportedVersion(version) => write!(f, "unsupported sound bank version {}", version),
+        }
+    }
+}
+
+impl std::error::Error for BankError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BankError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmError {
+    /// The format has no channels.
+    ZeroChannels,
+    /// The bytes to load end partway through a frame of `frame_bytes`.
+    TrailingBytes { len: usize, frame_bytes: usize },
+    /// The samples to convert aren't a whole number of frames.
+    PartialFrame { len: usize, channels: u8 },
+}
+
+impl fmt::Display for PcmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcmError::ZeroChannels => write!(f, "PCM format has zero channels"),
+            PcmError::TrailingBytes { len, frame_bytes } => {
+                write!(f, "{} bytes of PCM end partway through a {}-byte frame", len, frame_bytes)
+            }
+            PcmError::PartialFrame { len, channels } => {
+                write!(f, "{} samples don't divide into frames of {} channels", len, channels)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PcmError {}
+
+/// Returned by the `channels` conversions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelError {
+    /// No channels were given, or a layout of zero channels was asked for.
+    ZeroChannels,
+    /// More channels than a frame can describe.
+    TooManyChannels(usize),
+    /// `channel` holds `len` samples where the first channel holds `expected`.
+    LengthMismatch { channel: usize, len: usize, expected: usize },
+    /// The interleaved samples end partway through a frame.
+    PartialFrame { len: usize, channels: u8 },
+    /// There is no single obvious mapping between the two layouts.
+    Unsupported { from: u8, to: u8 },
+}
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelError::ZeroChannels => write!(f, "zero channels"),
+            ChannelError::TooManyChannels(count) => write!(f, "{} channels are more than 255", count),
+            ChannelError::LengthMismatch { channel, len, expected } => {
+                write!(f, "channel {} has {} samples but channel 0 has {}", channel, len, expected)
+            }
+            ChannelError::PartialFrame { len, channels } => {
+                write!(f, "{} samples don't divide into frames of {} channels", len, channels)
+            }
+            ChannelError::Unsupported { from, to } => write!(f, "no channel mapping from {} to {} channels", from, to),
+        }
+    }
+}
+
+impl std::error::Error for ChannelError {}
+
+/// Returned by `notes::name_to_midi` and `notes::name_to_freq`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoteError {
+    Empty,
+    /// The name doesn't start with one of the letters A to G.
+    UnknownLetter(char),
+    /// The name has no octave number after its letter and accidentals.
+    MissingOctave(String),
+    /// The octave isn't a number from -1 to 9.
+    InvalidOctave(String),
+    /// The note lies outside MIDI's range, C-1 to G9.
+    OutOfRange(String),
+}
+
+impl fmt::Display for NoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteError::Empty => write!(f, "empty note name"),
+            NoteError::UnknownLetter(letter) => write!(f, "unknown note letter {:?}", letter),
+            NoteError::MissingOctave(name) => write!(f, "note {:?} has no octave", name),
+            NoteError::InvalidOctave(name) => write!(f, "note {:?} needs an octave from -1 to 9", name),
+            NoteError::OutOfRange(name) => write!(f, "note {:?} is outside the MIDI range C-1 to G9", name),
+        }
+    }
+}
+
+impl std::error::Error for NoteError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_names_the_failure() {
+        let err = AudioError::NoAudioSubsystem("no driver".to_string());
+        assert_eq!(err.to_string(), "SDL audio subsystem unavailable: no driver");
+        let err: Box<dyn std::error::Error> = Box::new(AudioError::DeviceOpen("busy".to_string()));
+        assert!(err.to_string().contains("busy"));
+    }
+
+    #[test]
+    fn converts_to_string_for_old_callers() {
+        fn open() -> Result<(), String> {
+            Err(AudioError::invalid_param("len", "the buffer needs at least one sample"))?
+        }
+        assert_eq!(open(), Err("invalid len: the buffer needs at least one sample".to_string()));
+    }
+}
@@ -0,0 +1,45 @@
+use crate::GAIN_ONE;
+
+/// Where the silent end of a fade sits on its decibel scale: quiet enough that the
+/// step to real silence on the last frame isn't heard.
+const FADE_FLOOR_DB: f64 = -60.0;
+
+/// A running `Control::fade_to`, advanced once per output frame by the callback. The
+/// gain moves in even decibel steps, so it sounds even rather than rushing at the
+/// quiet end the way a linear ramp does.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Fade {
+    from_db: f64,
+    to_db: f64,
+    to: u32,
+    frames: u64,
+    done: u64,
+}
+
+fn gain_db(gain: u32) -> f64 {
+    match gain {
+        0 => FADE_FLOOR_DB,
+        gain => (20.0 * (gain as f64 / GAIN_ONE as f64).log10()).max(FADE_FLOOR_DB),
+    }
+}
+
+impl Fade {
+    /// From the Q16 gain `from` to `to` over `frames` frames, at least one.
+    pub(crate) fn new(from: u32, to: u32, frames: u64) -> Self {
+        Self { from_db: gain_db(from), to_db: gain_db(to), to, frames: frames.max(1), done: 0 }
+    }
+
+    /// The gain for this frame, moving on to the next one; `to` on the last.
+    pub(crate) fn next(&mut self) -> u32 {
+        self.done += 1;
+        if self.done >= self.frames {
+            return self.to;
+        }
+        let db = self.from_db + (self.to_db - self.from_db) * (self.done as f64 / self.frames as f64);
+        (10f64.powf(db / 20.0) * GAIN_ONE as f64).round() as u32
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.done >= self.frames
+    }
+}
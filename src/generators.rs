@@ -0,0 +1,200 @@
+//! Offline waveform generators producing offset-binary data centered on `u16::MID`.
+//!
+//! The phase of sample `i` is computed directly from `i`, so a buffer whose length is a
+//! whole number of periods (see `loop_len`) loops without a seam. The noise generators
+//! take an explicit seed and always produce the same data for the same seed.
+
+use crate::{Sample, SoundData16};
+
+fn generate(freq_hz: f32, sample_rate: u32, len: usize, amplitude: u16, wave: impl Fn(f64) -> f64) -> SoundData16 {
+    let step = freq_hz as f64 / sample_rate.max(1) as f64;
+    (0..len)
+        .map(|i| {
+            let phase = (i as f64 * step).fract();
+            to_offset(amplitude, wave(phase))
+        })
+        .collect()
+}
+
+/// `amplitude` is the peak deviation from the midpoint, capped at `i16::MAX`.
+pub fn sine(freq_hz: f32, sample_rate: u32, len: usize, amplitude: u16) -> SoundData16 {
+    generate(freq_hz, sample_rate, len, amplitude, |phase| (phase * std::f64::consts::TAU).sin())
+}
+
+/// `duty` is the fraction of each period spent high, clamped to 0.0..=1.0.
+pub fn square(freq_hz: f32, sample_rate: u32, len: usize, amplitude: u16, duty: f32) -> SoundData16 {
+    let duty = duty.clamp(0.0, 1.0) as f64;
+    generate(freq_hz, sample_rate, len, amplitude, |phase| if phase < duty { 1.0 } else { -1.0 })
+}
+
+pub fn triangle(freq_hz: f32, sample_rate: u32, len: usize, amplitude: u16) -> SoundData16 {
+    generate(freq_hz, sample_rate, len, amplitude, |phase| match phase {
+        p if p < 0.25 => 4.0 * p,
+        p if p < 0.75 => 2.0 - 4.0 * p,
+        p => 4.0 * p - 4.0,
+    })
+}
+
+/// Rises from the midpoint, jumps from the top to the bottom halfway through each period.
+pub fn sawtooth(freq_hz: f32, sample_rate: u32, len: usize, amplitude: u16) -> SoundData16 {
+    generate(freq_hz, sample_rate, len, amplitude, |phase| 2.0 * (phase + 0.5).fract() - 1.0)
+}
+
+/// The buffer length closest to `approx_len` that holds a whole number of periods,
+/// ideally exactly, of `freq_hz` at `sample_rate`. At least one period long.
+///
+/// When no length near `approx_len` is an exact multiple, the nearest rounding of a
+/// whole number of periods is returned, leaving a seam smaller than one sample.
+pub fn loop_len(freq_hz: f32, sample_rate: u32, approx_len: usize) -> usize {
+    if freq_hz <= 0.0 || sample_rate == 0 {
+        return approx_len.max(1);
+    }
+    let period = sample_rate as f64 / freq_hz as f64;
+    let periods = (approx_len as f64 / period).round().max(1.0);
+    // prefer a nearby period count that lands on an integer length
+    let best = (0..8)
+        .flat_map(|d| [periods + d as f64, periods - d as f64])
+        .filter(|p| *p >= 1.0)
+        .find(|p| ((p * period) - (p * period).round()).abs() < 1e-6)
+        .unwrap_or(periods);
+    ((best * period).round() as usize).max(1)
+}
+
+const PINK_ROWS: usize = 8;
+
+/// SplitMix64: tiny, self-contained and good enough for audio noise.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in -1.0..1.0.
+    pub(crate) fn next_signed(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+fn to_offset(amplitude: u16, value: f64) -> u16 {
+    let amplitude = amplitude.min(i16::MAX as u16) as f64;
+    u16::from_i32((amplitude * value).round() as i32)
+}
+
+/// Uniformly distributed noise within `amplitude` of the midpoint.
+pub fn white_noise(len: usize, amplitude: u16, seed: u64) -> SoundData16 {
+    let mut rng = Rng(seed);
+    (0..len).map(|_| to_offset(amplitude, rng.next_signed())).collect()
+}
+
+/// Voss-McCartney pink noise: `PINK_ROWS` white sources, row `k` refreshed every `2^k`
+/// samples, summed with a fresh white sample and scaled so the peak stays within `amplitude`.
+pub fn pink_noise(len: usize, amplitude: u16, seed: u64) -> SoundData16 {
+    let mut rng = Rng(seed);
+    let mut rows = [0.0; PINK_ROWS];
+    for row in rows.iter_mut() {
+        *row = rng.next_signed();
+    }
+    let mut sum: f64 = rows.iter().sum();
+    (0..len)
+        .map(|i| {
+            // the lowest set bit of the counter picks the one row to refresh
+            let k = (i + 1).trailing_zeros() as usize;
+            if k < PINK_ROWS {
+                let fresh = rng.next_signed();
+                sum += fresh - rows[k];
+                rows[k] = fresh;
+            }
+            to_offset(amplitude, (sum + rng.next_signed()) / (PINK_ROWS + 1) as f64)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SETUP_U16;
+
+    fn signed(data: &[u16]) -> Vec<i32> {
+        data.iter().map(|s| *s as i32 - SETUP_U16).collect()
+    }
+
+    fn rising_crossings(data: &[u16]) -> usize {
+        signed(data).windows(2).filter(|w| w[0] < 0 && w[1] >= 0).count()
+    }
+
+    #[test]
+    fn sine_period_peak_and_center() {
+        let data = sine(480.0, 48000, 4800, 10000);
+        assert_eq!(data[0], SETUP_U16 as u16);
+        assert_eq!(data[25], (SETUP_U16 + 10000) as u16);
+        assert_eq!(data[75], (SETUP_U16 - 10000) as u16);
+        assert_eq!(data[100], data[0]);
+        assert_eq!(rising_crossings(&data), 47);
+        assert!(mean(&data).abs() < 0.5);
+    }
+
+    #[test]
+    fn square_duty_cycle() {
+        let data = square(1000.0, 8000, 8, 5000, 0.25);
+        assert_eq!(signed(&data), [5000, 5000, -5000, -5000, -5000, -5000, -5000, -5000]);
+    }
+
+    #[test]
+    fn triangle_and_sawtooth_shapes() {
+        assert_eq!(signed(&triangle(1000.0, 8000, 8, 4000)), [0, 2000, 4000, 2000, 0, -2000, -4000, -2000]);
+        assert_eq!(signed(&sawtooth(1000.0, 8000, 8, 4000)), [0, 1000, 2000, 3000, -4000, -3000, -2000, -1000]);
+    }
+
+    #[test]
+    fn amplitude_is_capped() {
+        let data = square(1.0, 8000, 4, u16::MAX, 1.0);
+        assert_eq!(data[0], u16::MAX);
+    }
+
+    #[test]
+    fn loop_len_is_whole_periods() {
+        assert_eq!(loop_len(480.0, 48000, 1000), 1000);
+        assert_eq!(loop_len(480.0, 48000, 1030), 1000);
+        // 441 Hz at 48 kHz has a 108.84-sample period; 147 periods are exactly 16000 samples
+        let len = loop_len(441.0, 48000, 16000);
+        assert_eq!(len, 16000);
+        let data = sine(441.0, 48000, len, 10000);
+        let next = sine(441.0, 48000, len + 1, 10000)[len];
+        assert_eq!(next, data[0]);
+        assert_eq!(loop_len(1000.0, 48000, 0), 48);
+    }
+
+    fn mean(data: &[u16]) -> f64 {
+        signed(data).iter().map(|s| *s as f64).sum::<f64>() / data.len() as f64
+    }
+
+    #[test]
+    fn white_noise_is_centered_and_bounded() {
+        let data = white_noise(1 << 16, 8000, 1);
+        assert!(mean(&data).abs() < 8000.0 / 100.0);
+        assert!(signed(&data).iter().all(|s| s.abs() <= 8000));
+        assert!(signed(&data).iter().any(|s| s.abs() > 7000));
+    }
+
+    #[test]
+    fn pink_noise_is_centered_and_bounded() {
+        let data = pink_noise(1 << 16, 8000, 7);
+        assert!(mean(&data).abs() < 8000.0 / 50.0);
+        assert!(signed(&data).iter().all(|s| s.abs() <= 8000));
+        // low-frequency weighting: neighbouring samples are strongly correlated, unlike white noise
+        let diff = |d: &[u16]| signed(d).windows(2).map(|w| ((w[1] - w[0]) as f64).abs()).sum::<f64>();
+        assert!(diff(&data) < diff(&white_noise(1 << 16, 8000, 7)) / 2.0);
+    }
+
+    #[test]
+    fn noise_is_deterministic_per_seed() {
+        assert_eq!(white_noise(64, 1000, 42), white_noise(64, 1000, 42));
+        assert_ne!(white_noise(64, 1000, 42), white_noise(64, 1000, 43));
+        assert_eq!(pink_noise(64, 1000, 0), pink_noise(64, 1000, 0));
+    }
+}
@@ -0,0 +1,105 @@
+use std::marker::PhantomData;
+
+use crate::{Control, Sample};
+
+/// Several devices driven as one, e.g. main output on the speakers and a cue mix on
+/// headphones opened from the same `AudioContext`. Every call goes to each device in
+/// the order they were added; per-device calls go through `get_mut`.
+pub struct DeviceGroup<D, T: Sample = u16> {
+    devices: Vec<D>,
+    _sample: PhantomData<fn(T)>,
+}
+
+impl<D: Control<T>, T: Sample> Default for DeviceGroup<D, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Control<T>, T: Sample> From<Vec<D>> for DeviceGroup<D, T> {
+    fn from(devices: Vec<D>) -> Self {
+        Self { devices, _sample: PhantomData }
+    }
+}
+
+impl<D: Control<T>, T: Sample> DeviceGroup<D, T> {
+    pub fn new() -> Self {
+        Self::from(Vec::new())
+    }
+
+    /// Adds a device and returns its index for `get_mut`.
+    pub fn push(&mut self, device: D) -> usize {
+        self.devices.push(device);
+        self.devices.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut D> {
+        self.devices.get_mut(index)
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, D> {
+        self.devices.iter_mut()
+    }
+
+    pub fn into_devices(self) -> Vec<D> {
+        self.devices
+    }
+
+    pub fn set_volume(&mut self, volume: u16) {
+        self.devices.iter_mut().for_each(|device| device.set_volume(volume));
+    }
+
+    pub fn set_master_volume(&mut self, volume: u16) {
+        self.devices.iter_mut().for_each(|device| device.set_master_volume(volume));
+    }
+
+    pub fn set_mute(&mut self, specifier: bool) {
+        self.devices.iter_mut().for_each(|device| device.set_mute(specifier));
+    }
+
+    /// Pauses each device in turn; they stop a few samples apart at most, not in sync.
+    pub fn pause(&mut self) {
+        self.devices.iter_mut().for_each(|device| device.pause());
+    }
+
+    pub fn resume(&mut self) {
+        self.devices.iter_mut().for_each(|device| device.resume());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AudioSpecInfo, MockDevice, PlayMode};
+
+    fn device() -> MockDevice {
+        let mut device = MockDevice::new(4, AudioSpecInfo { freq: 48000, channels: 1, samples: 4 });
+        device.set_ramp_samples(0);
+        device.set_mode(PlayMode::Loop);
+        device.fill(0xc000);
+        device
+    }
+
+    #[test]
+    fn calls_fan_out_to_every_device() {
+        let mut group = DeviceGroup::from(vec![device(), device()]);
+        assert_eq!(group.push(device()), 2);
+        group.set_volume(6);
+        group.resume();
+        assert!(group.iter_mut().all(|device| device.drive_callback(1) == [0xa000]));
+        group.set_mute(true);
+        group.get_mut(1).unwrap().set_mute(false);
+        let played: Vec<Vec<u16>> = group.iter_mut().map(|device| device.drive_callback(1)).collect();
+        assert_eq!(played, [[0x8000], [0xa000], [0x8000]]);
+        group.pause();
+        assert!(group.into_devices().iter_mut().all(|device| device.is_paused()));
+    }
+}
@@ -0,0 +1,58 @@
+use std::f64::consts::TAU;
+
+use crate::GAIN_ONE;
+
+/// What `Control::set_lfo` modulates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LfoTarget {
+    /// Tremolo: the gain dips from its set level to `1 - depth` of it and back.
+    Volume,
+    /// Vibrato: the playback rate swings up and down by `depth` times the set rate.
+    Rate,
+}
+
+/// A sine oscillator on a device, advanced once per output frame by the callback.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Lfo {
+    freq_hz: f32,
+    depth: f32,
+    // cycles per frame at the device's rate, and how far into the cycle it is
+    step: f64,
+    phase: f64,
+}
+
+impl Lfo {
+    /// `None` for a depth or frequency that would leave the sound untouched, so a
+    /// disabled LFO costs nothing and changes nothing.
+    pub(crate) fn new(freq_hz: f32, depth: f32, sample_rate: i32) -> Option<Self> {
+        if !(freq_hz > 0.0 && depth > 0.0 && freq_hz.is_finite()) {
+            return None;
+        }
+        let mut lfo = Self { freq_hz, depth: depth.min(1.0), step: 0.0, phase: 0.0 };
+        lfo.set_sample_rate(sample_rate);
+        Some(lfo)
+    }
+
+    /// Keeps the wobble at `freq_hz` when the device comes back at another rate.
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: i32) {
+        self.step = self.freq_hz as f64 / sample_rate.max(1) as f64;
+    }
+
+    /// The oscillator's value for this frame, -1.0..=1.0, moving on to the next one.
+    fn next(&mut self) -> f64 {
+        let value = (TAU * self.phase).sin();
+        self.phase = (self.phase + self.step).fract();
+        value
+    }
+
+    /// The Q16 factor to scale this frame's gain by.
+    pub(crate) fn next_gain(&mut self) -> u32 {
+        let level = 1.0 - self.depth as f64 * (1.0 - self.next()) * 0.5;
+        (level * GAIN_ONE as f64).round() as u32
+    }
+
+    /// `rate` modulated for this frame.
+    pub(crate) fn next_rate(&mut self, rate: f32) -> f64 {
+        rate as f64 * (1.0 + self.depth as f64 * self.next())
+    }
+}
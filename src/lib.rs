@@ -1,222 +1,4598 @@
-use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
+//! SDL2 audio playback around a ring buffer of samples.
+//!
+//! Start with `AudioContext::builder`, then open a device from the context:
+//!
+//! ```no_run
+//! use audio_lib3::{AudioContext, Control};
+//!
+//! let context = AudioContext::builder().freq(48000).channels(2).samples(1024).build()?;
+//! let mut device = context.device().buffer_secs(2.0).start_paused(false).open()?;
+//! device.set_volume(7);
+//! # Ok::<(), audio_lib3::AudioError>(())
+//! ```
+
+use std::any::Any;
+use std::ffi::CString;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use buffer::SampleBuffer;
+use sdl2::audio::{AudioCallback, AudioFormatNum, AudioSpec, AudioSpecDesired, AudioDevice, AudioStatus};
+use sdl2::sys::{SDL_HintPriority, SDL_SetHintWithPriority, SDL_bool};
+
+mod bank;
+mod buffer;
+mod builder;
+mod capture;
+pub mod channels;
+pub mod chip;
+pub mod effects;
+pub mod dsp;
+mod envelope;
+mod lfo;
+mod lifecycle;
+mod error;
+mod fade;
+pub mod generators;
+mod group;
+mod manifest;
+mod meter;
+mod mixer;
+mod mock;
+pub mod notes;
+mod observer;
+pub mod pcm;
+pub mod process;
+mod queue;
+mod queue_device;
+mod rate;
+mod recording;
+mod resample;
+mod state;
+mod streamer;
+mod units;
+pub mod wav;
+
+use effects::EffectChain;
+use envelope::EnvelopeState;
+use fade::Fade;
+use lfo::Lfo;
+use lifecycle::Lifecycle;
+use meter::LevelMeter;
+use observer::Observer;
+use queue::CommandReceiver;
+use rate::DriftEstimator;
+use recording::RecordingTap;
+pub use envelope::Envelope;
+pub use lfo::LfoTarget;
+pub use lifecycle::{DeviceEvent, DeviceEventKind};
+pub use bank::{SoundBank, SoundInfo};
+pub use builder::{AudioContextBuilder, DeviceBuilder};
+pub use capture::{CaptureControl, CaptureDevice, Recorder};
+pub use group::DeviceGroup;
+pub use error::{AudioError, BankError, ChannelError, NoteError, PcmError, ScheduleError, Timeout, TriggerError, WavError, WriteError};
+pub use manifest::{ManifestEntry, SampleBlob, SoundBankManifest, StoredSoundBank};
+pub use mixer::{Mixer, MixerControl, MixerDevice, PlayOptions, StealPolicy, VoiceHandle};
+pub use mock::{MockDevice, MockLock};
+pub use observer::CallbackInfo;
+pub use queue::{Commander, ControlHandle};
+pub use queue_device::{QueueDevice, QueuePlayer};
+pub use rate::{AvSync, RateController, StreamResampler, AV_MAX_DEVIATION};
+pub use recording::{RecordingSink, RecordingStats};
+pub use resample::resample;
+pub use state::{PlaybackState, SoundState};
+use state::Request;
+pub use streamer::{Pumped, Streamer};
+pub use units::{Channels, Frames, SampleRate, Samples};
 
 pub type SoundData16 = Vec<u16>;
+pub type SoundDataI16 = Vec<i16>;
+pub type SoundDataF32 = Vec<f32>;
+/// The bias of offset-binary `u16` data, which is also its silence; in generic code use
+/// `Sample::MID` and `to_i32`/`from_i32` instead.
 pub const SETUP_U16: i32 = 1 << 15;
+/// Unity gain on the linear volume scale used by `Control::set_gain`.
+pub const UNITY_GAIN: u16 = 256;
+pub const MAX_VOLUME: u16 = 7;
+pub const DEFAULT_DB_FLOOR: f32 = -60.0;
+pub const DEFAULT_RAMP_SAMPLES: usize = 128;
+/// How many `Control::schedule_data` writes can wait at once.
+pub const SCHEDULE_CAPACITY: usize = 16;
+/// How many `Control::notify_at` markers, and how many undelivered events, fit at once.
+pub const EVENT_CAPACITY: usize = 64;
+/// The `AudioEvent::tag` of the event reporting that a `Control::fade_to` is done.
+pub const FADE_DONE_TAG: u64 = u64::MAX;
+/// How many commands from a device's `ControlHandle`s can wait for the next callback.
+pub const HANDLE_CAPACITY: usize = 256;
+pub const MIN_RATE: f32 = 0.25;
+pub const MAX_RATE: f32 = 4.0;
 
-pub struct Sound {
-    buffer: SoundData16,
-    buf_size: usize,
-    volume: u16,
-    mute: bool,
-    current: usize,
-    called: usize,
-    remain: usize,
+// samples `set_data_from_iter` copies per lock
+const FILL_CHUNK: usize = 256;
+
+// frames at the end of a fast-forward segment that blend into the data after the skip
+const FAST_FORWARD_FADE: usize = 32;
+
+// internal Q16 gain, so every coarse level and every linear step is exact
+const GAIN_ONE: u32 = 1 << 16;
+
+/// A sample type the playback callback can be opened with.
+///
+/// Every format converts through the signed 16-bit domain of `to_i32`/`from_i32`, or the
+/// -1.0..=1.0 one of `to_f32`/`from_f32`, so code writing silence or a level never
+/// needs to know a format's bias. Silence is `AudioFormatNum::SILENCE`: 0x80 for `u8`,
+/// `SETUP_U16` for `u16`, 0 for `i16` and `f32`.
+pub trait Sample: AudioFormatNum + Copy + Send + Sync + 'static {
+    /// The zero level signed samples are offset around; equal to `SILENCE`.
+    const MID: Self;
+    /// Applies a Q16 gain (`1 << 16` is unity) to a single sample.
+    fn scale(self, gain: u32) -> Self;
+    /// Converts a signed 16-bit sample into this sample type.
+    fn from_i16(sample: i16) -> Self;
+    /// Squashes peaks above `LIMITER_THRESHOLD` smoothly into the valid range.
+    fn soft_limit(self) -> Self;
+    /// The sample as signed 16-bit, widened so effects can overshoot.
+    fn to_i32(self) -> i32;
+    /// The inverse of `to_i32`, clamping to the signed 16-bit range.
+    fn from_i32(singed_sample: i32) -> Self;
+    /// The sample's bit pattern, so it can travel through an atomic.
+    fn to_raw_bits(self) -> u32;
+    fn from_raw_bits(bits: u32) -> Self;
+    /// Linear interpolation towards `next`, `frac` being in 0.0..1.0.
+    fn lerp(self, next: Self, frac: f32) -> Self;
+    /// The sample normalized so full scale is -1.0..1.0.
+    fn to_f32(self) -> f32 {
+        self.to_i32() as f32 / SETUP_U16 as f32
+    }
+    /// The inverse of `to_f32`, clamping to the format's range.
+    fn from_f32(sample: f32) -> Self {
+        Self::from_i32((sample.clamp(-1.0, 1.0) * SETUP_U16 as f32).round() as i32)
+    }
+    /// `scale` from `src` into `dst`, up to the end of the shorter slice.
+    fn scale_block(dst: &mut [Self], src: &[Self], gain: u32) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d = s.scale(gain);
+        }
+    }
+    /// `from_i16` from `src` into `dst`, up to the end of the shorter slice.
+    fn from_i16_block(dst: &mut [Self], src: &[i16]) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d = Self::from_i16(*s);
+        }
+    }
 }
 
-pub type SoundDevice = AudioDevice<Sound>;
+/// Fraction of full scale below which `LimiterMode::Soft` leaves samples untouched.
+pub const LIMITER_THRESHOLD: f32 = 0.75;
 
-pub trait Control {
-    fn set_mute(&mut self, specifier: bool);
-    fn set_volume(&mut self, volume: u16);
-    fn set_data(&mut self, offset: usize, sound: &[u16]);
-    fn push_data(&mut self, sound: &[u16]);
-    fn set_silent_data(&mut self);
-    fn buf_size(&mut self) -> usize;
-    fn mute(&mut self) -> bool;
-    fn volume(&mut self) -> u16;
-    fn current(&mut self) -> usize;
-    fn called(&mut self) -> usize;
-    fn remain(&mut self) -> usize;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LimiterMode {
+    /// Only the hard clamp to the sample range, which is always applied.
+    #[default]
+    Off,
+    /// A tanh knee above `LIMITER_THRESHOLD` that approaches full scale without reaching it.
+    Soft,
+}
+
+// Rounds toward negative infinity like the old shift ladder did, then clamps, so a
+// gain above unity saturates instead of wrapping.
+fn apply_gain(singed_sample: i32, gain: u32) -> i32 {
+    clamp_i16((singed_sample as i64 * gain as i64) >> 16)
 }
 
-impl Control for SoundDevice {
-    fn set_mute(&mut self, specifier: bool) {
-        let mut locked = self.lock();
-        locked.mute = specifier;
+fn clamp_i16(singed_sample: i64) -> i32 {
+    singed_sample.clamp(i16::MIN as i64, i16::MAX as i64) as i32
+}
+
+/// Soft knee on a sample normalized to -1.0..=1.0 (any input is accepted).
+fn soft_knee(x: f32) -> f32 {
+    let magnitude = x.abs();
+    if magnitude <= LIMITER_THRESHOLD {
+        return x;
     }
+    let headroom = 1.0 - LIMITER_THRESHOLD;
+    let limited = LIMITER_THRESHOLD + headroom * ((magnitude - LIMITER_THRESHOLD) / headroom).tanh();
+    limited.copysign(x)
+}
 
-    fn set_volume(&mut self, volume: u16) {
-        let mut locked = self.lock();
-        locked.volume = volume;
+/// `soft_knee` in the signed 16-bit domain; exact below the threshold.
+fn soft_limit_i32(singed_sample: i32) -> i32 {
+    let full_scale = SETUP_U16 as f32;
+    if (singed_sample.unsigned_abs() as f32) <= LIMITER_THRESHOLD * full_scale {
+        return singed_sample;
     }
+    clamp_i16((soft_knee(singed_sample as f32 / full_scale) * full_scale).round() as i64)
+}
 
-    fn set_data(&mut self, offset: usize, sound: &[u16]) {
-        let mut locked = self.lock();
-        let mut pos = offset;
-        let len = locked.buf_size;
-        for a in sound {
-            locked.buffer[pos % len] = *a;
-            pos += 1;
-        }
-        locked.remain += sound.len();
+/// Q16 gain of a coarse 0..=7 volume level: each step is a factor of two, 7 is unity.
+fn level_gain(volume: u16) -> u32 {
+    match volume {
+        0 => 0,
+        v => GAIN_ONE >> (MAX_VOLUME - v.min(MAX_VOLUME)),
     }
+}
 
-    fn push_data(&mut self, sound: &[u16]) {
-        let mut locked = self.lock();
-        let mut pos = locked.current + locked.remain;
-        let len = locked.buf_size;
-        for a in sound {
-            locked.buffer[pos % len] = *a;
-            pos += 1;
-        }
-        locked.remain += sound.len();
+fn db_to_factor(db: f32) -> f32 {
+    10f32.powf(db.min(0.0) / 20.0)
+}
+
+impl Sample for u8 {
+    const MID: Self = 0x80;
+
+    fn scale(self, gain: u32) -> Self {
+        Self::from_i32(apply_gain(self.to_i32(), gain))
     }
 
-    fn set_silent_data(&mut self) {
-        let mut locked = self.lock();
-        for d in locked.buffer.iter_mut() {
-            *d = SETUP_U16 as u16;
-        }
-        locked.current = 0;
-        locked.remain = locked.buf_size;
+    fn from_i16(sample: i16) -> Self {
+        Self::from_i32(sample as i32)
     }
 
-    fn buf_size(&mut self) -> usize {
-        let locked = self.lock();
-        locked.buf_size
+    fn soft_limit(self) -> Self {
+        Self::from_i32(soft_limit_i32(self.to_i32()))
     }
 
-    fn mute(&mut self) -> bool {
-        let locked = self.lock();
-        locked.mute
+    fn to_i32(self) -> i32 {
+        (self as i32 - Self::MID as i32) << 8
     }
 
-    fn volume(&mut self) -> u16 {
-        let locked = self.lock();
-        locked.volume
+    /// Drops the low byte, like the 8-bit PCM encoders.
+    fn from_i32(singed_sample: i32) -> Self {
+        ((clamp_i16(singed_sample as i64) + SETUP_U16) >> 8) as u8
     }
 
-    fn current(&mut self) -> usize {
-        let locked = self.lock();
-        locked.current
+    fn to_raw_bits(self) -> u32 {
+        self as u32
     }
 
-    fn called(&mut self) -> usize {
-        let locked = self.lock();
-        locked.called
+    fn from_raw_bits(bits: u32) -> Self {
+        bits as u8
     }
 
-    fn remain(&mut self) -> usize {
-        let locked = self.lock();
-        locked.remain
+    fn lerp(self, next: Self, frac: f32) -> Self {
+        (self as f32 + (next as f32 - self as f32) * frac).round() as u8
     }
 }
 
-impl AudioCallback for Sound {
-    type Channel = u16;
+impl Sample for u16 {
+    const MID: Self = SETUP_U16 as u16;
 
-    fn callback(&mut self, out: &mut [u16]) {
-        for dst in out.iter_mut() {
-            if self.remain == 0 {
-                *dst = SETUP_U16 as u16;
-            } else {
-                let output = if self.mute || self.volume == 0 {
-                    0
-                } else {
-                    let pos = self.current % self.buf_size;
-                    let raw_sample = *self.buffer.get(pos).unwrap_or(&(SETUP_U16 as u16));
-                    let singed_sample = raw_sample as i32 - SETUP_U16;
-                    let scaled_singed_sample = match self.volume {
-                        0 => 0,
-                        1 => singed_sample >> 6,
-                        2 => singed_sample >> 5,
-                        3 => singed_sample >> 4,
-                        4 => singed_sample >> 3,
-                        5 => singed_sample >> 2,
-                        6 => singed_sample >> 1,
-                        _ => singed_sample ,
-                    };
-                    scaled_singed_sample
-                };
-                *dst = (output + SETUP_U16) as u16;
-                self.current += 1;
-                self.remain -= 1;
-            }
+    fn scale(self, gain: u32) -> Self {
+        let singed_sample = self as i32 - SETUP_U16;
+        (apply_gain(singed_sample, gain) + SETUP_U16) as u16
+    }
+
+    fn from_i16(sample: i16) -> Self {
+        (sample as i32 + SETUP_U16) as u16
+    }
+
+    fn soft_limit(self) -> Self {
+        (soft_limit_i32(self as i32 - SETUP_U16) + SETUP_U16) as u16
+    }
+
+    fn to_i32(self) -> i32 {
+        self as i32 - SETUP_U16
+    }
+
+    fn from_i32(singed_sample: i32) -> Self {
+        (clamp_i16(singed_sample as i64) + SETUP_U16) as u16
+    }
+
+    fn to_raw_bits(self) -> u32 {
+        self as u32
+    }
+
+    fn from_raw_bits(bits: u32) -> Self {
+        bits as u16
+    }
+
+    fn lerp(self, next: Self, frac: f32) -> Self {
+        (self as f32 + (next as f32 - self as f32) * frac).round() as u16
+    }
+
+    fn scale_block(dst: &mut [Self], src: &[Self], gain: u32) {
+        dsp::scale_u16(dst, src, gain);
+    }
+
+    fn from_i16_block(dst: &mut [Self], src: &[i16]) {
+        dsp::i16_to_u16(dst, src);
+    }
+}
+
+impl Sample for i16 {
+    const MID: Self = 0;
+
+    fn scale(self, gain: u32) -> Self {
+        apply_gain(self as i32, gain) as i16
+    }
+
+    fn from_i16(sample: i16) -> Self {
+        sample
+    }
+
+    fn soft_limit(self) -> Self {
+        soft_limit_i32(self as i32) as i16
+    }
+
+    fn to_i32(self) -> i32 {
+        self as i32
+    }
+
+    fn from_i32(singed_sample: i32) -> Self {
+        clamp_i16(singed_sample as i64) as i16
+    }
+
+    fn to_raw_bits(self) -> u32 {
+        self as u16 as u32
+    }
+
+    fn from_raw_bits(bits: u32) -> Self {
+        bits as u16 as i16
+    }
+
+    fn lerp(self, next: Self, frac: f32) -> Self {
+        (self as f32 + (next as f32 - self as f32) * frac).round() as i16
+    }
+
+    fn scale_block(dst: &mut [Self], src: &[Self], gain: u32) {
+        dsp::scale_i16(dst, src, gain);
+    }
+
+    fn from_i16_block(dst: &mut [Self], src: &[i16]) {
+        let n = dst.len().min(src.len());
+        dst[..n].copy_from_slice(&src[..n]);
+    }
+}
+
+impl Sample for f32 {
+    const MID: Self = 0.0;
+
+    fn scale(self, gain: u32) -> Self {
+        (self * (gain as f32 / GAIN_ONE as f32)).clamp(-1.0, 1.0)
+    }
+
+    fn from_i16(sample: i16) -> Self {
+        sample as f32 / SETUP_U16 as f32
+    }
+
+    fn soft_limit(self) -> Self {
+        soft_knee(self)
+    }
+
+    fn to_i32(self) -> i32 {
+        (self * SETUP_U16 as f32).round() as i32
+    }
+
+    fn from_i32(singed_sample: i32) -> Self {
+        clamp_i16(singed_sample as i64) as f32 / SETUP_U16 as f32
+    }
+
+    fn to_raw_bits(self) -> u32 {
+        self.to_bits()
+    }
+
+    fn from_raw_bits(bits: u32) -> Self {
+        f32::from_bits(bits)
+    }
+
+    fn lerp(self, next: Self, frac: f32) -> Self {
+        self + (next - self) * frac
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        sample.clamp(-1.0, 1.0)
+    }
+}
+
+/// The spec SDL actually opened the device with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSpecInfo {
+    pub freq: i32,
+    pub channels: u8,
+    /// Callback buffer size in frames.
+    pub samples: u16,
+}
+
+impl From<&AudioSpec> for AudioSpecInfo {
+    fn from(spec: &AudioSpec) -> Self {
+        Self {
+            freq: spec.freq,
+            channels: spec.channels,
+            samples: spec.samples,
         }
-        self.called += 1;
     }
 }
 
-pub struct AudioContext {
-    sdl_context: sdl2::Sdl,
-    audio_subsystem: sdl2::AudioSubsystem,
-    desired_spec: AudioSpecDesired,
+/// Copies `data` into the ring `buffer` starting at `offset`, in at most two slices.
+///
+/// When `data` is longer than the buffer only its last `buffer.len()` samples survive, at the
+/// same positions a one-by-one wrapping write would have left them.
+fn copy_wrapping<T: Copy>(buffer: &mut [T], offset: usize, data: &[T]) {
+    let len = buffer.len();
+    if len == 0 {
+        return;
+    }
+    let skipped = data.len().saturating_sub(len);
+    let data = &data[skipped..];
+    let start = (offset % len + skipped % len) % len;
+    let (tail, head) = data.split_at(data.len().min(len - start));
+    buffer[start..start + tail.len()].copy_from_slice(tail);
+    buffer[..head.len()].copy_from_slice(head);
 }
 
-impl Default for AudioContext {
+/// Pads `buffer` with silence to a whole number of frames, at least one.
+fn pad_to_frames<T: Sample>(buffer: &mut Vec<T>, frame_len: usize) {
+    let len = buffer.len().div_ceil(frame_len).max(1) * frame_len;
+    buffer.resize(len, T::SILENCE);
+}
+
+/// Samples in `secs` at the obtained spec, rounded up to whole frames and never less
+/// than one callback period.
+fn duration_len(spec: &AudioSpecInfo, secs: f64) -> usize {
+    let frames = (secs * spec.freq.max(1) as f64).ceil() as usize;
+    frames.max(spec.samples as usize).max(1) * (spec.channels as usize).max(1)
+}
+
+/// Sets the `SDL_AUDIODRIVER` hint at `priority`, or unsets its value for `None`, which
+/// `sdl2::hint` can't; false when SDL refused it.
+fn set_driver_hint(value: Option<&str>, priority: SDL_HintPriority) -> bool {
+    let value = value.map(|value| CString::new(value).expect("SDL driver names have no NUL"));
+    let value = value.as_ref().map_or(std::ptr::null(), |value| value.as_ptr());
+    // SAFETY: both strings outlive the call, and SDL copies what it keeps.
+    unsafe { SDL_SetHintWithPriority(c"SDL_AUDIODRIVER".as_ptr(), value, priority) == SDL_bool::SDL_TRUE }
+}
+
+fn check_len(len: usize) -> Result<(), AudioError> {
+    if len == 0 {
+        return Err(AudioError::invalid_param("len", "the buffer needs at least one sample"));
+    }
+    Ok(())
+}
+
+/// Fills `out` from the ring `buffer` starting at `offset`, wrapping as often as needed.
+fn read_wrapping<T: Copy>(buffer: &[T], offset: usize, out: &mut [T]) {
+    let len = buffer.len();
+    if len == 0 {
+        return;
+    }
+    let mut start = offset % len;
+    let mut rest = out;
+    while !rest.is_empty() {
+        let n = rest.len().min(len - start);
+        let (chunk, tail) = rest.split_at_mut(n);
+        chunk.copy_from_slice(&buffer[start..start + n]);
+        rest = tail;
+        start = 0;
+    }
+}
+
+/// What `Control::commit` does with the read position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommitPosition {
+    /// Carry on at the same index in the new buffer, or at 0 when it is too short.
+    #[default]
+    Keep,
+    /// Start the new buffer from the beginning, as after `restart`.
+    Restart,
+}
+
+// Filled by `stage_data` without taking the device lock; the callback never touches it.
+struct BackBuffer<T> {
+    data: Vec<T>,
+    staged: bool,
+}
+
+// Bumped at the end of every callback for `wait_for_callback` and `on_callback`; the
+// lock is only held for the update, so the callback never waits on a sleeping caller.
+struct CallbackSignal {
+    last: Mutex<Tick>,
+    ready: Condvar,
+}
+
+// `info.called` follows the device's count, resets included; `ticks` only counts
+// callbacks, so an observer never mistakes a reset for one
+struct Tick {
+    ticks: u64,
+    info: CallbackInfo,
+}
+
+impl Default for CallbackSignal {
     fn default() -> Self {
-        Self::new()
+        let info = CallbackInfo { called: 0, consumed: 0, at: Instant::now() };
+        Self { last: Mutex::new(Tick { ticks: 0, info }), ready: Condvar::new() }
     }
 }
 
-impl AudioContext {
-    pub fn new() -> Self {
-        let sdl_context = sdl2::init().unwrap();
-        let audio_subsystem = sdl_context.audio().unwrap();
-        Self::with_subsystem(audio_subsystem)
+impl CallbackSignal {
+    fn notify(&self, called: u64, consumed: u64) {
+        let mut last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        last.ticks += 1;
+        last.info = CallbackInfo { called, consumed, at: Instant::now() };
+        drop(last);
+        self.ready.notify_all();
     }
 
-    pub fn with_subsystem(audio_subsystem: sdl2::AudioSubsystem) -> Self {
-        let sdl_context = audio_subsystem.sdl();
-        let desired_spec = AudioSpecDesired {
-            freq: None,     // default
-            channels: None, // default
-            samples: None,  // default
+    fn reset(&self) {
+        self.last.lock().unwrap_or_else(PoisonError::into_inner).info.called = 0;
+        self.ready.notify_all();
+    }
+
+    fn ticks(&self) -> u64 {
+        self.last.lock().unwrap_or_else(PoisonError::into_inner).ticks
+    }
+
+    /// Waits until the count moves on from `after`; a reset to 0 counts as moving on.
+    fn wait(&self, after: u64, timeout: Duration) -> Result<u64, Timeout> {
+        let last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        let (last, result) = self.ready
+            .wait_timeout_while(last, timeout, |last| last.info.called == after)
+            .unwrap_or_else(PoisonError::into_inner);
+        if result.timed_out() {
+            Err(Timeout)
+        } else {
+            Ok(last.info.called)
+        }
+    }
+
+    /// Waits for a callback after the `after`th, returning the new tick count and the
+    /// latest callback's info; `None` once `stop` is set.
+    fn wait_tick(&self, after: u64, stop: &AtomicBool) -> Option<(u64, CallbackInfo)> {
+        let last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        let last = self.ready
+            .wait_while(last, |last| last.ticks == after && !stop.load(Ordering::Acquire))
+            .unwrap_or_else(PoisonError::into_inner);
+        (!stop.load(Ordering::Acquire)).then_some((last.ticks, last.info))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayMode {
+    /// Plays only what has been written (`remain`), wrapping around the buffer. Silence otherwise.
+    #[default]
+    Stream,
+    /// Repeats the whole buffer (or the loop region) regardless of `remain`, as many
+    /// times as `LoopCount` allows.
+    Loop,
+    /// Plays from `current` up to the end of the buffer once, then outputs silence.
+    OneShot,
+}
+
+/// What happens to the read position while a sound is muted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MutePolicy {
+    /// Playback carries on silently, like background music that moves on.
+    #[default]
+    Advance,
+    /// `current` stays put once the mute ramp has faded out, and playback resumes from
+    /// there on unmute. A held one-shot is never finished while muted.
+    Hold,
+}
+
+/// How a stereo device routes its two channels to the speakers, see
+/// `Control::set_channel_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    #[default]
+    Stereo,
+    /// Left plays on the right speaker and right on the left, for reversed wiring.
+    SwapLeftRight,
+    /// The average of both channels on both speakers.
+    MonoSum,
+    /// The left channel on both speakers; the right one is dropped.
+    LeftOnly,
+    /// The right channel on both speakers; the left one is dropped.
+    RightOnly,
+}
+
+/// How `Control::set_volume_percent` turns a 0.0..=100.0 slider position into a gain.
+/// Whatever the curve, a slider at 0 is silence.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum VolumeCurve {
+    /// The nearest coarse `set_volume` level, 6 dB apart.
+    #[default]
+    Shift,
+    /// A gain proportional to the position.
+    Linear,
+    /// Even steps in decibels, from `floor_db` at 0 up to unity at 100. As with
+    /// `set_volume_db`, anything at or below the device's `db_floor` is silence too.
+    Logarithmic { floor_db: f32 },
+    /// Maps 0.0..=1.0 to a linear factor, clamped to 0.0..=1.0.
+    Custom(fn(f32) -> f32),
+}
+
+impl VolumeCurve {
+    /// The linear factor, 0.0..=1.0, the curve gives `percent`; out-of-range and NaN
+    /// positions count as the nearest end.
+    pub fn factor(&self, percent: f32) -> f32 {
+        let position = if percent.is_nan() { 0.0 } else { percent.clamp(0.0, 100.0) / 100.0 };
+        let factor = match *self {
+            VolumeCurve::Shift => {
+                let level = (position * MAX_VOLUME as f32).round() as u16;
+                level_gain(level) as f32 / GAIN_ONE as f32
+            }
+            VolumeCurve::Linear => position,
+            VolumeCurve::Logarithmic { floor_db } => db_to_factor(floor_db * (1.0 - position)),
+            VolumeCurve::Custom(curve) => curve(position),
         };
+        if factor.is_nan() { 0.0 } else { factor.clamp(0.0, 1.0) }
+    }
+
+    /// The Q16 gain for `percent` on a device whose `db_floor` is `db_floor`.
+    fn gain(&self, percent: f32, db_floor: f32) -> u32 {
+        let factor = self.factor(percent);
+        if percent.is_nan() || percent <= 0.0 {
+            return 0;
+        }
+        if matches!(self, VolumeCurve::Logarithmic { .. }) && 20.0 * factor.log10() <= db_floor {
+            return 0;
+        }
+        (factor * GAIN_ONE as f32).round() as u32
+    }
+}
+
+/// How `Control::queue_next` moves on to the queued buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transition {
+    /// The first sample of the new buffer directly follows the last of the old one.
+    #[default]
+    Gapless,
+    /// The last `samples` samples of the old pass are mixed linearly into the first
+    /// `samples` of the new buffer, which then carries on after them.
+    Crossfade { samples: usize },
+}
+
+/// How many passes `PlayMode::Loop` plays, see `Control::set_loop_count`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoopCount {
+    #[default]
+    Infinite,
+    /// Counts down at every wrap; at 0 the sound is finished and plays silence.
+    Times(u32),
+}
+
+/// Playback passed a `Control::notify_at` marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioEvent {
+    pub tag: u64,
+    /// `current` on the frame that passed the marker; later than requested for a marker
+    /// that was already in the past when it was set.
+    pub position: u64,
+    /// `called` at the start of the callback that passed it.
+    pub callback: u64,
+}
+
+/// How long the callback takes to run, see `Control::callback_stats`. All times are in
+/// microseconds; `period_us - max_us` is the worst headroom seen.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CallbackStats {
+    /// Callbacks timed since timing was enabled or last reset.
+    pub measured: u64,
+    pub last_us: u64,
+    pub min_us: u64,
+    pub max_us: u64,
+    /// Exponentially weighted, each callback counting 1/16.
+    pub avg_us: f64,
+    /// Time one callback block lasts at the obtained rate, i.e. the deadline.
+    pub period_us: u64,
+}
+
+impl CallbackStats {
+    fn record(&mut self, elapsed: Duration) {
+        let us = elapsed.as_micros() as u64;
+        if self.measured == 0 {
+            self.min_us = us;
+            self.avg_us = us as f64;
+        }
+        self.measured += 1;
+        self.last_us = us;
+        self.min_us = self.min_us.min(us);
+        self.max_us = self.max_us.max(us);
+        self.avg_us += (us as f64 - self.avg_us) / 16.0;
+    }
+}
+
+/// Playback counters and settings captured under one lock, see `Control::playback_status`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackStatus {
+    pub current: u64,
+    pub called: u64,
+    pub remain: usize,
+    pub underruns: u64,
+    pub volume: u16,
+    pub gain: u16,
+    pub mute: bool,
+    /// The buffer's length in samples, not frames, as `Control::buf_samples`.
+    pub buf_size: usize,
+    pub mode: PlayMode,
+    pub finished: bool,
+    /// See `Control::generation`.
+    pub generation: u64,
+}
+
+/// A stretch of the buffer that is safe to overwrite, from `Control::write_window`.
+///
+/// `len` samples starting at index `offset`, wrapping at `buf_size`; `parts` splits
+/// it where it wraps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteWindow {
+    pub offset: usize,
+    pub len: usize,
+    /// The buffer length in samples the window was taken at; after a resize it is stale.
+    pub buf_size: usize,
+}
+
+impl WriteWindow {
+    /// The window as buffer index ranges: up to the end of the buffer, then from its
+    /// start. The second is empty when the window doesn't wrap.
+    pub fn parts(&self) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let first = self.len.min(self.buf_size - self.offset);
+        (self.offset..self.offset + first, 0..self.len - first)
+    }
+}
+
+pub struct Sound<T: Sample = u16> {
+    buffer: SampleBuffer<T>,
+    buf_size: usize,
+    channels: usize,
+    spec: AudioSpecInfo,
+    gain: u32,
+    // 0..=MAX_VOLUME, applied on top of `gain`
+    master_volume: u16,
+    mute: bool,
+    mute_policy: MutePolicy,
+    volume_curve: VolumeCurve,
+    // both keep counting across wraps; the buffer index is `pos()`
+    current: u64,
+    called: u64,
+    remain: usize,
+    mode: PlayMode,
+    finished: bool,
+    db_floor: f32,
+    ramp_samples: usize,
+    ramp_target: u32,
+    ramp_step: u32,
+    applied_gain: u32,
+    // `fade_to`'s, moving `gain` frame by frame while it runs
+    fade: Option<Fade>,
+    pan: Option<f32>,
+    pan_gains: [u32; 2],
+    // applied to whole blocks once they are rendered, stereo only
+    channel_mode: ChannelMode,
+    underruns: u64,
+    played_frames: u64,
+    // samples taken from the buffer, and how many of them take_consumed has reported
+    consumed: u64,
+    consumed_reported: u64,
+    // playback speed in buffer frames per output frame, and the read position's
+    // fraction of a frame past `current`
+    rate: f32,
+    frac: f64,
+    // `set_input_rate`'s rate, None for the device's own, and how many buffer frames
+    // that makes one output frame; exactly 1.0 when the two rates match
+    input_rate: Option<u32>,
+    input_step: f64,
+    reversed: bool,
+    // `set_fast_forward`'s factor, and the output frames played of the current segment
+    fast_forward: u8,
+    ff_pos: usize,
+    // `Loop` mode plays start..end over and over once it gets there
+    loop_region: Option<(usize, usize)>,
+    loop_count: LoopCount,
+    loops_left: LoopCount,
+    // `queue_next`'s buffer, and the one it replaced, kept so the callback never frees it
+    next: Option<(Vec<T>, Transition)>,
+    retired: Option<SampleBuffer<T>>,
+    // pending writes sorted by position, and applied ones waiting to be freed outside
+    // the callback; together they never exceed SCHEDULE_CAPACITY
+    scheduled: Vec<(u64, Vec<T>)>,
+    spent: Vec<Vec<T>>,
+    // markers sorted by position, and events the callback has produced but nobody polled
+    markers: Vec<(u64, u64)>,
+    events: Vec<AudioEvent>,
+    dropped_events: u64,
+    // `set_drift_tracking`'s estimate of the true sample rate
+    drift: Option<DriftEstimator>,
+    // bumped by every change to the buffer's contents, see `Control::generation`
+    generation: u64,
+    // `Control::set_label`'s, and what `poll_device_events` hands out
+    label: Option<String>,
+    // what the device was opened by, so `AudioContext::reopen` opens the same one
+    device_name: Option<String>,
+    lifecycle: Lifecycle,
+    meter: LevelMeter,
+    // None while timing is off, so the callback doesn't even read the clock
+    timing: Option<CallbackStats>,
+    // the message of a panic caught in the callback, which stays silent from then on
+    poisoned: Option<String>,
+    limiter: LimiterMode,
+    // set by `Control::stop` until the next resume or restart
+    stopped: bool,
+    // `resume_when_filled`'s threshold while it waits for data
+    preroll: Option<usize>,
+    // where a draining stream ends, counted like `current`
+    drain_at: Option<u64>,
+    envelope: Option<EnvelopeState>,
+    // `set_lfo`'s oscillators; None when off
+    tremolo: Option<Lfo>,
+    vibrato: Option<Lfo>,
+    effects: EffectChain,
+    // one callback block, allocated when effects are set so the callback never does
+    effect_buf: Vec<i32>,
+    commands: Option<CommandReceiver>,
+    // the queues shared by `control_handle`'s handles, and one to clone for the next caller
+    handle: Option<(CommandReceiver, ControlHandle<T>)>,
+    // every finished block is copied here while `start_recording` is on
+    recording: Option<RecordingTap>,
+    back: Arc<Mutex<BackBuffer<T>>>,
+    signal: Arc<CallbackSignal>,
+    // `on_callback`'s thread, stopped when replaced or dropped with the sound
+    observer: Option<Observer>,
+}
+
+// how many leading samples `Sound`'s `Debug` shows
+const DEBUG_HEAD: usize = 4;
+
+/// A summary instead of every field: a buffer can be megabytes.
+impl<T: Sample + fmt::Debug> fmt::Debug for Sound<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Head<'a, T>(&'a [T], usize);
+        impl<T: fmt::Debug> fmt::Debug for Head<'_, T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut list = f.debug_list();
+                list.entries(self.0);
+                if self.1 > self.0.len() {
+                    list.entry(&format_args!("..{} more", self.1 - self.0.len()));
+                }
+                list.finish()
+            }
+        }
+        f.debug_struct("Sound")
+            .field("freq", &self.spec.freq)
+            .field("channels", &self.channels)
+            .field("buf_size", &self.buf_size)
+            .field("head", &Head(&self.buffer[..self.buf_size.min(DEBUG_HEAD)], self.buf_size))
+            .field("mode", &self.mode)
+            .field("volume", &self.volume())
+            .field("mute", &self.mute)
+            .field("current", &self.current)
+            .field("remain", &self.remain)
+            .field("called", &self.called)
+            .field("finished", &self.finished)
+            .field("poisoned", &self.poisoned.is_some())
+            .finish()
+    }
+}
+
+impl<T: Sample> Sound<T> {
+    fn new(len: usize, spec: AudioSpecInfo) -> Self {
+        let channels = (spec.channels as usize).max(1);
+        let len = len.div_ceil(channels) * channels;
         Self {
-            sdl_context,
-            audio_subsystem,
-            desired_spec,
+            buffer: vec![T::SILENCE; len].into(),
+            buf_size: len,
+            channels,
+            spec,
+            gain: 0,
+            master_volume: MAX_VOLUME,
+            current: 0,
+            mute: false,
+            mute_policy: MutePolicy::Advance,
+            volume_curve: VolumeCurve::Shift,
+            called: 0,
+            remain: 0,
+            mode: PlayMode::Stream,
+            finished: false,
+            db_floor: DEFAULT_DB_FLOOR,
+            ramp_samples: DEFAULT_RAMP_SAMPLES,
+            ramp_target: 0,
+            ramp_step: 0,
+            applied_gain: 0,
+            fade: None,
+            pan: None,
+            pan_gains: [GAIN_ONE; 2],
+            channel_mode: ChannelMode::Stereo,
+            underruns: 0,
+            played_frames: 0,
+            consumed: 0,
+            consumed_reported: 0,
+            rate: 1.0,
+            frac: 0.0,
+            input_rate: None,
+            input_step: 1.0,
+            reversed: false,
+            fast_forward: 1,
+            ff_pos: 0,
+            loop_region: None,
+            loop_count: LoopCount::Infinite,
+            loops_left: LoopCount::Infinite,
+            next: None,
+            retired: None,
+            scheduled: Vec::with_capacity(SCHEDULE_CAPACITY),
+            spent: Vec::with_capacity(SCHEDULE_CAPACITY),
+            markers: Vec::with_capacity(EVENT_CAPACITY),
+            events: Vec::with_capacity(EVENT_CAPACITY),
+            dropped_events: 0,
+            drift: None,
+            generation: 0,
+            label: None,
+            device_name: None,
+            lifecycle: Lifecycle::default(),
+            meter: LevelMeter::new(1),
+            timing: None,
+            poisoned: None,
+            limiter: LimiterMode::Off,
+            stopped: false,
+            preroll: None,
+            drain_at: None,
+            envelope: None,
+            tremolo: None,
+            vibrato: None,
+            effects: Vec::new(),
+            effect_buf: Vec::new(),
+            commands: None,
+            handle: None,
+            recording: None,
+            back: Arc::new(Mutex::new(BackBuffer { data: Vec::new(), staged: false })),
+            signal: Arc::default(),
+            observer: None,
         }
     }
 
-    pub fn sdl(&self) -> sdl2::Sdl {
-        self.sdl_context.clone()
+    /// Number of buffer samples making up one output frame.
+    fn frame_len(&self) -> usize {
+        if self.pan.is_some() {
+            1
+        } else {
+            self.channels
+        }
     }
 
-    pub fn freq(&self) -> Option<i32> {
-        self.desired_spec.freq
+    fn set_pan(&mut self, pan: f32) {
+        if self.channels != 2 || pan.is_nan() {
+            return;
+        }
+        let pan = pan.clamp(-1.0, 1.0);
+        // constant power: both channels at -3 dB in the center
+        let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+        let to_gain = |factor: f32| (factor * GAIN_ONE as f32).round() as u32;
+        self.pan = Some(pan);
+        self.pan_gains = [to_gain(angle.cos()), to_gain(angle.sin())];
     }
 
-    pub fn set_freq(&mut self, freq: Option<i32>) {
-        self.desired_spec.freq = freq;
+    fn set_channel_mode(&mut self, mode: ChannelMode) {
+        if self.channels == 2 {
+            self.channel_mode = mode;
+        }
     }
 
-    pub fn channels(&self) -> Option<u8> {
-        self.desired_spec.channels
+    /// Routes a rendered block's frames according to `channel_mode`.
+    fn apply_channel_mode(&self, out: &mut [T]) {
+        let route: fn(T, T) -> (T, T) = match self.channel_mode {
+            ChannelMode::Stereo => return,
+            ChannelMode::SwapLeftRight => |left, right| (right, left),
+            // in the signed domain, so the bias cancels out and nothing wraps
+            ChannelMode::MonoSum => |left, right| {
+                let mono = T::from_i32((left.to_i32() + right.to_i32()) / 2);
+                (mono, mono)
+            },
+            ChannelMode::LeftOnly => |left, _| (left, left),
+            ChannelMode::RightOnly => |_, right| (right, right),
+        };
+        for frame in out.chunks_exact_mut(2) {
+            (frame[0], frame[1]) = route(frame[0], frame[1]);
+        }
     }
 
-    pub fn set_channels(&mut self, channels: Option<u8>) {
-        self.desired_spec.channels = channels;
+    fn set_volume(&mut self, volume: u16) {
+        self.fade = None;
+        self.gain = level_gain(volume);
     }
 
-    pub fn samples(&self) -> Option<u16> {
-        self.desired_spec.samples
+    fn set_gain(&mut self, gain: u16) {
+        self.fade = None;
+        self.gain = gain.min(UNITY_GAIN) as u32 * (GAIN_ONE / UNITY_GAIN as u32);
     }
 
-    pub fn set_samples(&mut self, samples: Option<u16>) {
-        self.desired_spec.samples = samples;
+    fn set_volume_db(&mut self, db: f32) {
+        self.fade = None;
+        self.gain = if db.is_nan() || db <= self.db_floor {
+            0
+        } else {
+            (db_to_factor(db) * GAIN_ONE as f32).round() as u32
+        };
     }
 
-    pub fn open_device(&self, len: usize) -> Result<SoundDevice, String> {
-        self.audio_subsystem.open_playback(None, &self.desired_spec, |_spec| {
-            Sound {
-                buffer: vec![SETUP_U16 as u16; len],
-                buf_size: len,
-                volume: 0,
-                current: 0,
-                mute: false,
-                called: 0,
-                remain: 0,
+    fn volume_db(&self) -> f32 {
+        match self.gain {
+            0 => f32::NEG_INFINITY,
+            gain => 20.0 * (gain as f32 / GAIN_ONE as f32).log10(),
+        }
+    }
+
+    fn volume(&self) -> u16 {
+        // the highest coarse level not louder than the current gain
+        (0..=MAX_VOLUME).rev().find(|v| level_gain(*v) <= self.gain).unwrap_or(0)
+    }
+
+    /// The one-line summary `Control::describe` returns.
+    fn describe(&self) -> String {
+        format!(
+            "{}{}Hz {}ch buf={} pos={} vol={} muted={} called={}",
+            self.label.as_ref().map_or(String::new(), |label| format!("{}: ", label)),
+            self.spec.freq, self.channels, self.buf_size, self.current, self.volume(), self.mute, self.called,
+        )
+    }
+
+    /// `gain` scaled by the master volume; exactly `gain` at `MAX_VOLUME`.
+    fn target_gain(&self) -> u32 {
+        ((self.gain as u64 * level_gain(self.master_volume) as u64) >> 16) as u32
+    }
+
+    /// Starts a fade of the volume to `volume` over `frames` frames, from wherever the
+    /// gain is now, a fade or volume ramp in progress included.
+    fn fade_to(&mut self, volume: u16, frames: u64) {
+        // `step_gain` follows the fade exactly, so it has to start from what is being
+        // played, taken back out of the master volume; muted, nothing is heard anyway
+        let master = level_gain(self.master_volume) as u64;
+        let from = if self.mute || self.ramp_samples == 0 || master == 0 {
+            self.gain
+        } else {
+            ((self.applied_gain as u64) << 16).div_ceil(master).min(GAIN_ONE as u64) as u32
+        };
+        self.fade = Some(Fade::new(from, level_gain(volume), frames));
+    }
+
+    /// Moves a running fade on by a frame, reporting it once it is done.
+    fn step_fade(&mut self) {
+        let Some(fade) = self.fade.as_mut() else {
+            return;
+        };
+        self.gain = fade.next();
+        if fade.is_done() {
+            self.fade = None;
+            if self.events.len() < self.events.capacity() {
+                self.events.push(AudioEvent { tag: FADE_DONE_TAG, position: self.current, callback: self.called });
+            } else {
+                self.dropped_events += 1;
             }
-        })
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    /// Moves the applied gain one frame closer to the volume/mute target.
+    fn step_gain(&mut self) -> u32 {
+        let target = if self.mute { 0 } else { self.target_gain() };
+        // a fade is its own ramp, so the gain follows it exactly
+        if self.ramp_samples == 0 || (self.fade.is_some() && !self.mute) {
+            self.ramp_target = target;
+            self.applied_gain = target;
+            return target;
+        }
+        if target != self.ramp_target {
+            // retarget from wherever the previous ramp got to
+            self.ramp_target = target;
+            self.ramp_step = (target.abs_diff(self.applied_gain) / self.ramp_samples as u32).max(1);
+        }
+        if self.applied_gain < target {
+            self.applied_gain = (self.applied_gain + self.ramp_step).min(target);
+        } else if self.applied_gain > target {
+            self.applied_gain = self.applied_gain.saturating_sub(self.ramp_step).max(target);
+        }
+        self.applied_gain
+    }
+
+    /// Swaps in `buffer`, padded with silence to a whole number of frames (at least one),
+    /// and returns the previous one. `current` keeps its position in the buffer when it
+    /// still fits and restarts from 0 otherwise; `remain` is capped to the new size.
+    fn replace_buffer(&mut self, buffer: Vec<T>) -> Vec<T> {
+        self.install_padded(buffer).into_vec()
+    }
+
+    /// `replace_buffer`, returning the previous buffer as it was, shared or not, so
+    /// nothing is copied.
+    fn install_padded(&mut self, mut buffer: Vec<T>) -> SampleBuffer<T> {
+        pad_to_frames(&mut buffer, self.frame_len());
+        self.install(buffer.into())
+    }
+
+    /// `replace_buffer` without a copy: the sound reads straight from `data` and copies
+    /// it only on the first write. Data that isn't a whole number of frames is copied
+    /// and padded up front instead.
+    fn share_buffer(&mut self, data: Arc<[T]>) -> SampleBuffer<T> {
+        let frame_len = self.frame_len();
+        if data.is_empty() || !data.len().is_multiple_of(frame_len) {
+            return self.install_padded(data.to_vec());
+        }
+        self.install(SampleBuffer::Shared(data))
+    }
+
+    /// Swaps in a buffer that is already a whole number of frames long.
+    fn install(&mut self, buffer: SampleBuffer<T>) -> SampleBuffer<T> {
+        let len = buffer.len();
+        let pos = self.pos();
+        self.current = if pos < len { pos as u64 } else { 0 };
+        self.remain = self.remain.min(len);
+        self.buf_size = len;
+        if self.loop_region.is_some_and(|(_, end)| end > len) {
+            self.loop_region = None;
+        }
+        self.generation += 1;
+        std::mem::replace(&mut self.buffer, buffer)
+    }
+
+    fn write_available(&self) -> usize {
+        self.buf_size.saturating_sub(self.remain)
+    }
+
+    fn write(&mut self, samples: &[T]) -> usize {
+        let accepted = samples.len().min(self.write_available());
+        let start = self.pos() + self.remain;
+        copy_wrapping(&mut self.buffer, start, &samples[..accepted]);
+        self.remain += accepted;
+        self.generation += 1;
+        accepted
+    }
+
+    /// Everything but the next callback's block and `margin` more samples past the read
+    /// position, in whole frames.
+    fn write_window(&self, margin: usize) -> WriteWindow {
+        let frame_len = self.frame_len();
+        // buffer frames the next callback reads at the current speed
+        let speed = self.rate as f64 * self.input_step * self.fast_forward as f64;
+        let block = (self.spec.samples as f64 * speed).ceil() as usize * frame_len;
+        let reserved = (block + margin).div_ceil(frame_len) * frame_len;
+        let len = self.buf_size.saturating_sub(reserved);
+        let len = len - len % frame_len;
+        WriteWindow { offset: (self.pos() + reserved) % self.buf_size, len, buf_size: self.buf_size }
+    }
+
+    /// Ends a pending preroll once enough data is waiting; true when the device should
+    /// start now.
+    fn preroll_filled(&mut self) -> bool {
+        match self.preroll {
+            Some(threshold) if self.remain >= threshold => {
+                self.preroll = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn set_data_wrapping(&mut self, offset: usize, sound: &[T]) {
+        let offset = offset - offset % self.frame_len();
+        copy_wrapping(&mut self.buffer, offset, sound);
+        self.remain += sound.len();
+        self.generation += 1;
+    }
+
+    fn crossfade_data(&mut self, offset: usize, sound: &[T], fade_samples: usize, blend_tail: bool) {
+        let frame_len = self.frame_len();
+        let offset = offset - offset % frame_len;
+        let frames = sound.len().div_ceil(frame_len);
+        let fade = (fade_samples / frame_len).min(if blend_tail { frames / 2 } else { frames });
+        let len = self.buffer.len();
+        for (i, sample) in sound.iter().enumerate() {
+            let frame = i / frame_len;
+            // weight of the new data, in steps of 1 / (fade + 1) so neither end is skipped
+            let step = if frame < fade {
+                frame + 1
+            } else if blend_tail && frame >= frames - fade {
+                frames - frame
+            } else {
+                fade + 1
+            };
+            let dst = &mut self.buffer[(offset + i) % len];
+            *dst = if step > fade {
+                *sample
+            } else {
+                let old = dst.to_i32() as i64;
+                let new = sample.to_i32() as i64;
+                T::from_i32((old + (new - old) * step as i64 / (fade + 1) as i64) as i32)
+            };
+        }
+        self.remain += sound.len();
+        self.generation += 1;
+    }
+
+    fn schedule_data(&mut self, at: u64, sound: Vec<T>) -> Result<(), ScheduleError> {
+        if at < self.current {
+            return Err(ScheduleError::InPast { at, current: self.current });
+        }
+        // the caller frees what the callback has applied
+        self.spent.clear();
+        if self.scheduled.len() >= SCHEDULE_CAPACITY {
+            return Err(ScheduleError::QueueFull { capacity: SCHEDULE_CAPACITY });
+        }
+        let index = self.scheduled.partition_point(|(pending, _)| *pending <= at);
+        self.scheduled.insert(index, (at, sound));
+        Ok(())
+    }
+
+    /// Applies every scheduled write playback has reached.
+    fn apply_scheduled(&mut self) {
+        while self.scheduled.first().is_some_and(|(at, _)| *at <= self.current) {
+            let (at, sound) = self.scheduled.remove(0);
+            self.set_data_wrapping((at % self.buf_size as u64) as usize, &sound);
+            self.spent.push(sound);
+        }
+    }
+
+    fn notify_at(&mut self, position: u64, tag: u64) -> Result<(), ScheduleError> {
+        if self.markers.len() >= EVENT_CAPACITY {
+            return Err(ScheduleError::QueueFull { capacity: EVENT_CAPACITY });
+        }
+        let index = self.markers.partition_point(|(pending, _)| *pending <= position);
+        self.markers.insert(index, (position, tag));
+        Ok(())
+    }
+
+    /// Turns every marker playback has reached into an event, never growing `events`.
+    fn fire_markers(&mut self) {
+        while self.markers.first().is_some_and(|(at, _)| *at <= self.current) {
+            let (_, tag) = self.markers.remove(0);
+            if self.events.len() < self.events.capacity() {
+                self.events.push(AudioEvent { tag, position: self.current, callback: self.called });
+            } else {
+                self.dropped_events += 1;
+            }
+        }
+    }
+
+    fn try_set_data(&mut self, offset: usize, sound: &[T]) -> Result<(), WriteError> {
+        if offset >= self.buf_size {
+            return Err(WriteError::OffsetOutOfRange { offset, buf_size: self.buf_size });
+        }
+        if sound.len() > self.buf_size {
+            return Err(WriteError::TooLong { len: sound.len(), fits: self.buf_size });
+        }
+        self.set_data_wrapping(offset, sound);
+        Ok(())
+    }
+
+    fn position_ms(&self) -> u64 {
+        match self.spec.freq {
+            freq if freq > 0 => self.played_frames * 1000 / freq as u64,
+            _ => 0,
+        }
+    }
+
+    fn position_secs(&self) -> f64 {
+        match self.spec.freq {
+            freq if freq > 0 => self.played_frames as f64 / freq as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// How long `unplayed` buffer samples take to play at the current rate and fast
+    /// forward, plus one SDL block in flight.
+    fn latency_of(&self, unplayed: u64) -> Duration {
+        let speed = self.rate as f64 * self.input_step * self.fast_forward as f64;
+        let frames = (unplayed / self.frame_len() as u64) as f64 / speed + self.spec.samples as f64;
+        Duration::from_secs_f64(frames / self.spec.freq.max(1) as f64)
+    }
+
+    fn set_effects(&mut self, effects: EffectChain) {
+        let block = (self.spec.samples as usize).max(1) * self.channels;
+        self.effect_buf.resize(if effects.is_empty() { 0 } else { block }, 0);
+        self.effects = effects;
+    }
+
+    fn run_effects(&mut self, out: &mut [T]) {
+        for chunk in out.chunks_mut(self.effect_buf.len()) {
+            let buf = &mut self.effect_buf[..chunk.len()];
+            for (dst, sample) in buf.iter_mut().zip(chunk.iter()) {
+                *dst = sample.to_i32();
+            }
+            for effect in self.effects.iter_mut() {
+                effect.process(buf);
+            }
+            for (dst, singed_sample) in chunk.iter_mut().zip(buf.iter()) {
+                *dst = T::from_i32(*singed_sample);
+            }
+        }
+    }
+
+    /// Takes over a spec obtained for a replacement device. The buffer is kept and
+    /// re-rounded to whole frames if the channel count changed.
+    fn adopt_spec(&mut self, spec: AudioSpecInfo) {
+        let channels = (spec.channels as usize).max(1);
+        if channels != self.channels {
+            self.channels = channels;
+            if channels != 2 {
+                self.pan = None;
+                self.channel_mode = ChannelMode::Stereo;
+            }
+            if let Some((next, _)) = self.next.as_mut() {
+                pad_to_frames(next, channels);
+            }
+            let buffer = std::mem::take(&mut self.buffer);
+            self.replace_buffer(buffer.into_vec());
+        }
+        self.spec = spec;
+        self.update_input_step();
+        for lfo in [&mut self.tremolo, &mut self.vibrato].into_iter().flatten() {
+            lfo.set_sample_rate(spec.freq);
+        }
+        if !self.effects.is_empty() {
+            let effects = std::mem::take(&mut self.effects);
+            self.set_effects(effects);
+        }
+    }
+
+    fn limit(&self, sample: T) -> T {
+        match self.limiter {
+            LimiterMode::Off => sample,
+            LimiterMode::Soft => sample.soft_limit(),
+        }
+    }
+
+    fn status(&self) -> PlaybackStatus {
+        PlaybackStatus {
+            current: self.current,
+            called: self.called,
+            remain: self.remain,
+            underruns: self.underruns,
+            volume: self.volume(),
+            gain: self.gain(),
+            mute: self.mute,
+            buf_size: self.buf_size,
+            mode: self.mode,
+            finished: self.finished,
+            generation: self.generation,
+        }
+    }
+
+    fn gain(&self) -> u16 {
+        (self.gain / (GAIN_ONE / UNITY_GAIN as u32)) as u16
+    }
+
+    fn commit(&mut self, position: CommitPosition) -> bool {
+        let back = self.back.clone();
+        let mut back = back.lock().unwrap_or_else(PoisonError::into_inner);
+        if !back.staged {
+            return false;
+        }
+        let staged = std::mem::take(&mut back.data);
+        back.data = self.replace_buffer(staged);
+        back.staged = false;
+        if position == CommitPosition::Restart {
+            self.current = 0;
+            self.finished = false;
+        }
+        true
+    }
+
+    /// Index of the read position in the buffer.
+    fn pos(&self) -> usize {
+        (self.current % self.buf_size as u64) as usize
+    }
+
+    fn set_current(&mut self, pos: usize) {
+        let pos = pos % self.buf_size;
+        self.current = (pos - pos % self.frame_len()) as u64;
+        self.frac = 0.0;
+        self.finished = false;
+        self.drain_at = None;
+    }
+
+    fn reset_counters(&mut self) {
+        self.current = 0;
+        self.frac = 0.0;
+        self.drain_at = None;
+        self.called = 0;
+        self.signal.reset();
+    }
+
+    fn set_rate(&mut self, rate: f32) {
+        if rate.is_nan() {
+            return;
+        }
+        self.rate = rate.clamp(MIN_RATE, MAX_RATE);
+        if self.rate == 1.0 && self.input_step == 1.0 {
+            // back on whole frames, so normal speed plays the buffer untouched
+            self.frac = 0.0;
+        }
+    }
+
+    fn set_input_rate(&mut self, hz: u32) {
+        self.input_rate = (hz > 0).then_some(hz);
+        self.update_input_step();
+    }
+
+    fn update_input_step(&mut self) {
+        let device = self.spec.freq.max(1) as u32;
+        self.input_step = match self.input_rate {
+            Some(hz) if hz != device => hz as f64 / device as f64,
+            _ => 1.0,
+        };
+        if self.rate == 1.0 && self.input_step == 1.0 && self.vibrato.is_none() {
+            self.frac = 0.0;
+        }
+    }
+
+    fn set_lfo(&mut self, target: LfoTarget, freq_hz: f32, depth: f32) {
+        let lfo = Lfo::new(freq_hz, depth, self.spec.freq);
+        match target {
+            LfoTarget::Volume => self.tremolo = lfo,
+            LfoTarget::Rate => {
+                self.vibrato = lfo;
+                if lfo.is_none() && self.rate == 1.0 && self.input_step == 1.0 {
+                    self.frac = 0.0;
+                }
+            }
+        }
+    }
+
+    fn set_fast_forward(&mut self, factor: u8) {
+        self.fast_forward = factor.max(1);
+        self.ff_pos = 0;
+    }
+
+    /// Output frames in one fast-forward segment: a callback block, long enough to fade.
+    fn ff_segment(&self) -> usize {
+        (self.spec.samples as usize).max(2 * FAST_FORWARD_FADE)
+    }
+
+    /// Frames skipped after each segment, so `fast_forward` times as many are consumed.
+    fn ff_skip(&self) -> usize {
+        (self.fast_forward as usize - 1) * self.ff_segment()
+    }
+
+    /// `fetch`, crossfaded over the last frames of a fast-forward segment into the data
+    /// the skip lands on, so the seam doesn't click.
+    fn fetch_skipping(&self, offset: usize) -> T {
+        let sample = self.fetch(offset);
+        if self.fast_forward == 1 {
+            return sample;
+        }
+        let Some(into) = (self.ff_pos + FAST_FORWARD_FADE).checked_sub(self.ff_segment()) else {
+            return sample;
+        };
+        let skip = self.ff_skip() * self.frame_len();
+        let pos = self.pos();
+        let ahead = match self.mode {
+            // the data before the read position may not be what was played
+            _ if self.plays_backwards() => return sample,
+            PlayMode::Stream if skip + self.frame_len() > self.remain => return sample,
+            PlayMode::Stream => self.buffer[(pos + skip + offset) % self.buf_size],
+            PlayMode::Loop => self.buffer[(self.loop_forward(pos, skip) + offset) % self.buf_size],
+            PlayMode::OneShot => *self.buffer.get(pos + skip + offset).unwrap_or(&T::SILENCE),
+        };
+        sample.lerp(ahead, (into + 1) as f32 / (FAST_FORWARD_FADE + 1) as f32)
+    }
+
+    /// Sample `offset` of the frame at the read position, mixed with the queued buffer
+    /// during a crossfade.
+    fn fetch(&self, offset: usize) -> T {
+        let sample = self.interpolated(offset);
+        let Some((next, Transition::Crossfade { samples })) = &self.next else {
+            return sample;
+        };
+        if !self.switches_at_pass_end() {
+            return sample;
+        }
+        let pos = self.pos();
+        let end = self.pass_end(pos);
+        let fade = self.fade_len(*samples, next.len());
+        let distance = end - pos;
+        if distance > fade {
+            return sample;
+        }
+        // how far into the new buffer the fade has got
+        let into = fade - distance;
+        let incoming = *next.get(into + offset).unwrap_or(&T::SILENCE);
+        sample.lerp(incoming, into as f32 / fade as f32)
+    }
+
+    /// Sample `offset` of the frame at the read position, interpolated towards the next
+    /// frame when the position is between two frames.
+    fn interpolated(&self, offset: usize) -> T {
+        let pos = (self.pos() + offset) % self.buf_size;
+        let sample = *self.buffer.get(pos).unwrap_or(&T::SILENCE);
+        if self.frac == 0.0 {
+            return sample;
+        }
+        let frame_len = self.frame_len();
+        let frame = self.pos();
+        let (next, has_next) = match self.mode {
+            // looped playback interpolates across the loop point
+            PlayMode::Loop if self.reversed => (self.loop_backward(frame, frame_len), true),
+            PlayMode::Loop => (self.loop_forward(frame, frame_len), true),
+            PlayMode::OneShot if self.reversed => (frame.wrapping_sub(frame_len), frame >= frame_len),
+            PlayMode::OneShot => (frame + frame_len, frame + frame_len < self.buf_size),
+            // the next frame may not be written yet
+            PlayMode::Stream => (frame + frame_len, self.remain >= 2 * frame_len),
+        };
+        match self.buffer.get((next + offset) % self.buf_size) {
+            Some(next) if has_next => sample.lerp(*next, self.frac as f32),
+            _ => sample,
+        }
+    }
+
+    /// A stream is always played forwards, since that is the direction it is written in.
+    fn plays_backwards(&self) -> bool {
+        self.reversed && self.mode != PlayMode::Stream
+    }
+
+    /// Moves the read position `taken` samples towards the start of the buffer. Looped
+    /// playback wraps to the last frame, a one-shot finishes after playing frame 0.
+    fn step_back(&mut self, taken: usize) {
+        let pos = self.pos();
+        if self.mode == PlayMode::Loop {
+            let next = self.loop_backward(pos, taken);
+            if taken > pos || next != pos - taken {
+                self.count_loop();
+            }
+            self.current = next as u64;
+        } else if taken > pos {
+            self.current = 0;
+            self.finished = true;
+        } else {
+            self.current = (pos - taken) as u64;
+        }
+    }
+
+    /// Where a looped read position at buffer index `pos` ends up `taken` samples later.
+    /// Reaching the end of the loop region jumps back to its start; outside the region,
+    /// e.g. in an intro, playback runs on and wraps at the end of the buffer.
+    fn loop_forward(&self, pos: usize, taken: usize) -> usize {
+        match self.loop_region {
+            Some((start, end)) if pos < end && pos + taken >= end => start + (pos + taken - end) % (end - start),
+            _ => (pos + taken) % self.buf_size,
+        }
+    }
+
+    fn step_loop(&mut self, taken: usize) {
+        let pos = self.pos();
+        let end = self.pass_end(pos);
+        if pos + taken >= end && self.switches_at_pass_end() {
+            self.switch_to_next(pos + taken - end);
+            return;
+        }
+        let next = self.loop_forward(pos, taken);
+        if next != pos + taken {
+            self.count_loop();
+        }
+        if self.loop_region.is_some() {
+            self.current = next as u64;
+        } else {
+            self.current += taken as u64;
+        }
+    }
+
+    /// Where the current pass ends: the end of the loop region once playback is in or
+    /// before it, the end of the buffer otherwise.
+    fn pass_end(&self, pos: usize) -> usize {
+        match (self.mode, self.loop_region) {
+            (PlayMode::Loop, Some((_, end))) if pos < end => end,
+            _ => self.buf_size,
+        }
+    }
+
+    /// Whether the queued buffer takes over at the end of this pass: always for a
+    /// one-shot, on the last counted pass of a loop. Reversed playback never switches.
+    fn switches_at_pass_end(&self) -> bool {
+        if self.next.is_none() || self.reversed {
+            return false;
+        }
+        match self.mode {
+            PlayMode::Stream => false,
+            PlayMode::Loop => matches!(self.loops_left, LoopCount::Infinite | LoopCount::Times(1)),
+            PlayMode::OneShot => true,
+        }
+    }
+
+    /// A crossfade of `samples`, in whole frames and no longer than either buffer.
+    fn fade_len(&self, samples: usize, next_len: usize) -> usize {
+        let frame_len = self.frame_len();
+        let fade = samples.min(next_len).min(self.buf_size);
+        fade - fade % frame_len
+    }
+
+    /// Swaps in the queued buffer `overshoot` samples past the end of the old pass.
+    fn switch_to_next(&mut self, overshoot: usize) {
+        let Some((next, transition)) = self.next.take() else {
+            return;
+        };
+        let fade = match transition {
+            Transition::Gapless => 0,
+            Transition::Crossfade { samples } => self.fade_len(samples, next.len()),
+        };
+        self.loop_region = None;
+        // `queue_next` padded it, so this never allocates on the audio thread
+        self.retired = Some(self.install(next.into()));
+        self.current = ((fade + overshoot) % self.buf_size) as u64;
+        self.loops_left = self.loop_count;
+        self.finished = false;
+    }
+
+    /// Called at every wrap of a looped sound.
+    fn count_loop(&mut self) {
+        if let LoopCount::Times(left) = &mut self.loops_left {
+            *left = left.saturating_sub(1);
+            if *left == 0 {
+                self.finished = true;
+            }
+        }
+    }
+
+    fn set_loop_count(&mut self, count: LoopCount) {
+        self.loop_count = count;
+        self.loops_left = count;
+        match count {
+            LoopCount::Times(0) => self.finished = true,
+            // a loop that ran out plays on from where it stopped
+            _ if self.mode == PlayMode::Loop => self.finished = false,
+            _ => (),
+        }
+    }
+
+    /// Lets what is pending play out and finishes after it; true when nothing was.
+    fn start_drain(&mut self) -> bool {
+        match self.mode {
+            PlayMode::Stream => {
+                // a partial frame never plays, so the mark is on a frame boundary
+                let at = self.current + (self.remain - self.remain % self.frame_len()) as u64;
+                self.drain_at = Some(at);
+                self.finish_drain();
+            }
+            // the pass in progress becomes the last; the loop count itself is kept
+            PlayMode::Loop if self.loops_left != LoopCount::Times(0) => self.loops_left = LoopCount::Times(1),
+            PlayMode::Loop | PlayMode::OneShot => (),
+        }
+        self.finished
+    }
+
+    /// What a stream may still play: everything written, or up to the mark while draining.
+    fn stream_left(&self) -> usize {
+        match self.drain_at {
+            Some(at) => self.remain.min(at.saturating_sub(self.current) as usize),
+            None => self.remain,
+        }
+    }
+
+    fn finish_drain(&mut self) {
+        if self.drain_at.is_some_and(|at| self.current >= at) {
+            self.finished = true;
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current = 0;
+        self.frac = 0.0;
+        self.drain_at = None;
+        self.loops_left = self.loop_count;
+        self.finished = self.loop_count == LoopCount::Times(0);
+    }
+
+    /// `loop_forward` for reversed playback: passing the region's start jumps to its end.
+    fn loop_backward(&self, pos: usize, taken: usize) -> usize {
+        match self.loop_region {
+            Some((start, end)) if (start..end).contains(&pos) && taken > pos - start => {
+                let under = taken - (pos - start);
+                end - ((under - 1) % (end - start) + 1)
+            }
+            _ => (pos + self.buf_size - taken % self.buf_size) % self.buf_size,
+        }
+    }
+
+    fn set_loop_region(&mut self, start: usize, end: usize) -> Result<(), AudioError> {
+        let frame_len = self.frame_len();
+        let (start, end) = (start - start % frame_len, end - end % frame_len);
+        if end > self.buf_size {
+            return Err(AudioError::invalid_param("loop_region", "end is past the end of the buffer"));
+        }
+        if start >= end {
+            return Err(AudioError::invalid_param("loop_region", "start must be at least one frame before end"));
+        }
+        self.loop_region = Some((start, end));
+        Ok(())
+    }
+
+    /// Moves the read position on by `rate` and returns how many whole frames it passed,
+    /// adding the fast-forward skip at the end of each segment.
+    fn advance(&mut self) -> usize {
+        let frames = self.advance_rate();
+        if self.fast_forward == 1 {
+            return frames;
+        }
+        self.ff_pos += 1;
+        if self.ff_pos < self.ff_segment() {
+            return frames;
+        }
+        self.ff_pos = 0;
+        frames + self.ff_skip()
+    }
+
+    fn advance_rate(&mut self) -> usize {
+        let rate = match self.vibrato.as_mut() {
+            Some(lfo) => lfo.next_rate(self.rate).clamp(MIN_RATE as f64, MAX_RATE as f64),
+            None if self.rate == 1.0 && self.input_step == 1.0 => return 1,
+            None => self.rate as f64,
+        };
+        self.frac += rate * self.input_step;
+        let whole = self.frac.floor();
+        self.frac -= whole;
+        whole as usize
+    }
+}
+
+pub type SoundDevice<T = u16> = AudioDevice<Sound<T>>;
+pub type SoundDeviceU8 = SoundDevice<u8>;
+pub type SoundDeviceI16 = SoundDevice<i16>;
+pub type SoundDeviceF32 = SoundDevice<f32>;
+
+/// Controls a device's sound: a `SoundDevice`, or a `MockDevice` in tests.
+///
+/// Every method takes the device lock, which the callback holds while it renders, so a
+/// call waits for a running callback and a long one, like a big `set_data`, delays the
+/// next. Keep calls short on small callback sizes; a thread that must never hold up the
+/// callback uses a `commander` or `control_handle` instead, which queue volume, mute,
+/// rate, seeks and writes for the callback to apply without either side waiting.
+pub trait Control<T: Sample = u16> {
+    fn set_mute(&mut self, specifier: bool);
+    /// Whether muted playback keeps advancing; `MutePolicy::Advance` by default.
+    fn set_mute_policy(&mut self, policy: MutePolicy);
+    fn mute_policy(&mut self) -> MutePolicy;
+    /// Sets the coarse 0..=7 volume level (6 dB per step, 7 is unity). Larger values clamp to 7.
+    fn set_volume(&mut self, volume: u16);
+    /// Sets a linear gain where `UNITY_GAIN` (256) is unity. Larger values clamp to 256.
+    fn set_gain(&mut self, gain: u16);
+    /// Same as `set_data_wrapping`.
+    fn set_data(&mut self, offset: usize, sound: &[T]);
+    /// Writes interleaved samples starting at `offset`, wrapping at the end of the buffer.
+    /// Offsets past the end wrap too, and a slice longer than the buffer overwrites its own start.
+    ///
+    /// On a multi-channel device `offset` is rounded down to the start of its frame.
+    fn set_data_wrapping(&mut self, offset: usize, sound: &[T]);
+    /// `set_data_wrapping` for lazily generated samples, without collecting them first.
+    /// Takes at most `max` samples, capped to `buf_samples`, and returns how many the
+    /// iterator yielded.
+    ///
+    /// The iterator runs outside the lock, between copies of a few hundred samples, so
+    /// a slow iterator never stalls the callback. The flip side is that the callback may
+    /// play the region while it is only partly written; write ahead of the read position.
+    fn set_data_from_iter(&mut self, offset: usize, iter: impl Iterator<Item = T>, max: usize) -> usize;
+    /// Like `set_data_wrapping`, but rejects an `offset` outside the buffer and a slice
+    /// that would overwrite itself. Writes that merely cross the end still wrap.
+    fn try_set_data(&mut self, offset: usize, sound: &[T]) -> Result<(), WriteError>;
+    /// `set_data_wrapping` that blends the first `fade_samples` of `sound` linearly from
+    /// the buffer's current contents into the new data, so overwriting what is playing
+    /// doesn't click at the write boundary. With `blend_tail` the last `fade_samples`
+    /// ramp back into the old contents as well. Frames share one weight.
+    fn crossfade_data(&mut self, offset: usize, sound: &[T], fade_samples: usize, blend_tail: bool);
+    /// Writes `sound` at `at % buf_samples`, like `set_data`, on the exact frame where
+    /// `current` reaches `at`, so it is heard from that frame on. Positions playback
+    /// has already passed are rejected rather than applied late; up to
+    /// `SCHEDULE_CAPACITY` writes can wait at once.
+    ///
+    /// `at` counts like `current`, which keeps growing across wraps in `Stream` and
+    /// whole-buffer `Loop` playback.
+    fn schedule_data(&mut self, at: u64, sound: Vec<T>) -> Result<(), ScheduleError>;
+    /// Drops every scheduled write that hasn't been applied yet.
+    fn clear_scheduled(&mut self);
+    /// Reports an `AudioEvent` tagged `tag` from the frame where `current` reaches
+    /// `position`, counted like `schedule_data`'s; a marker already passed fires on the
+    /// next frame played. Up to `EVENT_CAPACITY` markers can wait at once.
+    fn notify_at(&mut self, position: u64, tag: u64) -> Result<(), ScheduleError>;
+    /// Takes the events fired since the last poll, oldest first, without waiting.
+    /// At most `EVENT_CAPACITY` are held between polls; see `dropped_events`.
+    fn poll_events(&mut self) -> Vec<AudioEvent>;
+    /// Events lost because `poll_events` wasn't called often enough.
+    fn dropped_events(&mut self) -> u64;
+    /// Largest output magnitude over the metering window, 0.0..=1.0. Measured on what
+    /// was actually played, after volume, mute, pan and effects.
+    fn peak_level(&mut self) -> f32;
+    /// RMS of the output over the metering window, 0.0..=1.0; about 0.707 for a
+    /// full-scale sine.
+    fn rms_level(&mut self) -> f32;
+    /// Number of recent callback blocks the levels cover, 1 by default. Changing it
+    /// starts the measurement over.
+    fn set_meter_window(&mut self, blocks: usize);
+    fn meter_window(&mut self) -> usize;
+    /// Turns callback timing on or off; off by default, and then it costs nothing.
+    fn set_timing_enabled(&mut self, enabled: bool);
+    /// How long recent callbacks took against the time they had. All zero, apart from
+    /// `period_us`, while timing is off.
+    fn callback_stats(&mut self) -> CallbackStats;
+    /// Starts the timing statistics over.
+    fn reset_stats(&mut self);
+    /// Turns on measuring the rate the device really plays at, off by default. The
+    /// callback notes when each block starts; turning it on again starts over, and so
+    /// does the first callback after a pause.
+    fn set_drift_tracking(&mut self, enabled: bool);
+    /// Output frames per second actually played, fitted over the last 25 seconds or so so
+    /// scheduling jitter averages out. `None` while tracking is off and for the first
+    /// couple of seconds after turning it on or resuming.
+    fn measured_rate(&mut self) -> Option<f64>;
+    /// How far the device's clock, frames played at the obtained rate, has got ahead of
+    /// wall time since tracking last started over, in seconds; negative when the card runs slow.
+    /// `None` whenever `measured_rate` is.
+    fn clock_drift(&mut self) -> Option<f64>;
+    /// True once a panic in the callback, e.g. in an effect, has been caught. The device
+    /// then plays silence for good instead of running the broken code again.
+    fn is_poisoned(&mut self) -> bool;
+    /// The message of the caught panic, when it had one.
+    fn panic_message(&mut self) -> Option<String>;
+    /// Copies everything the callback outputs from now on, after volume and effects,
+    /// to `sink`. A background thread does the writing: when it falls more than about
+    /// half a second behind, whole blocks are left out and counted instead of making the
+    /// callback wait. A recording already running is stopped and finished like
+    /// `stop_recording` would, and its stats are returned. The error of finishing it
+    /// is returned too, though by then the new recording is running; an error starting
+    /// the new one leaves the old one running.
+    fn start_recording(&mut self, sink: RecordingSink) -> Result<Option<RecordingStats>, WavError>;
+    /// Waits for the writer to save what was recorded, finishing the WAV file. Returns
+    /// zeroed stats when nothing was being recorded, and the writer's error if any.
+    fn stop_recording(&mut self) -> Result<RecordingStats, WavError>;
+    /// A one-line summary for logs, e.g.
+    /// `48000Hz 2ch buf=96000 pos=12345 vol=7 muted=false called=88`. `pos` is `current`.
+    /// A labelled device's summary starts with the label, e.g. `music: 48000Hz ...`.
+    fn describe(&mut self) -> String;
+    /// Names the device in `describe` and its `DeviceEvent`s, e.g. "music" or "sfx".
+    fn set_label(&mut self, label: &str);
+    fn label(&mut self) -> Option<String>;
+    /// The name the device was opened by, as listed by `playback_devices`; `None` for
+    /// the default device. `AudioContext::reopen` opens the same one again.
+    fn device_name(&mut self) -> Option<String>;
+    /// Takes the lifecycle events since the last poll, oldest first: opening, underruns,
+    /// a lost device, a caught panic, recordings starting and stopping. The callback only
+    /// queues them, so log or trace them from here. Up to `EVENT_CAPACITY` are held
+    /// between polls; later ones are dropped.
+    ///
+    /// With the `tracing` feature each event is also emitted, with its label, on the
+    /// polling thread under the `audio_lib3` target.
+    fn poll_device_events(&mut self) -> Vec<DeviceEvent>;
+    fn set_data_i16(&mut self, offset: usize, sound: &[i16]);
+    fn push_data(&mut self, sound: &[T]);
+    /// How many samples `write` can take right now without touching unplayed data.
+    fn write_available(&mut self) -> usize;
+    /// Appends at the write cursor (`current + remain`) and returns how many samples were
+    /// accepted; the rest did not fit in front of the read cursor.
+    ///
+    /// In `PlayMode::Stream` the callback plays silence once it catches up with the write
+    /// cursor and counts the callback in `underruns`.
+    fn write(&mut self, samples: &[T]) -> usize;
+    /// Number of callbacks that had to play silence because the stream ran dry
+    /// (`PlayMode::Stream` only). Counted without allocating, once per starved callback.
+    fn underruns(&mut self) -> u64;
+    fn reset_underruns(&mut self);
+    /// Reads buffer contents starting at `offset` with the same wrapping as `set_data`.
+    ///
+    /// The copy is made under a single lock, so it never mixes samples from before and
+    /// after a callback.
+    fn get_data(&mut self, offset: usize, out: &mut [T]);
+    /// A copy of the whole buffer, taken under a single lock.
+    fn snapshot(&mut self) -> Vec<T>;
+    /// Goes up with every change to the buffer's contents: writes, fills, replacing or
+    /// resizing the buffer, and the callback's own changes (scheduled writes, a queued
+    /// buffer taking over, `Commander` writes). A reader copying a region in several
+    /// calls compares it before and after, and copies again when it moved.
+    fn generation(&mut self) -> u64;
+    /// `get_data` that also returns the `generation` the copy belongs to. Both come from
+    /// the same lock, so the copy is never torn and never needs a retry.
+    fn read_consistent(&mut self, offset: usize, out: &mut [T]) -> u64;
+    /// Where `set_data` can write right now without racing the callback: the whole
+    /// buffer except the block the next callback plays from `current`, at the current
+    /// speed, and `margin_samples` more after it. Taken under one lock.
+    ///
+    /// The window is advisory and goes stale as playback moves on. Its start is heard
+    /// once the next callback and `margin_samples` more have played, so fill it from the
+    /// front, and finish before then; pick a margin covering how late the write can be,
+    /// e.g. a block or two for a writer that runs once per callback. The rest of the
+    /// window stays safe for longer, up to a buffer's length of playback for its end.
+    ///
+    /// Assumes forward playback in `Loop` or `OneShot` mode; a `Stream` producer uses
+    /// `write`, which never overwrites unplayed data.
+    fn write_window(&mut self, margin_samples: usize) -> WriteWindow;
+    /// Copies the front of `data` into `window`, as much as fits, and returns how many
+    /// samples were written; 0 when the buffer was resized since the window was taken.
+    fn write_into_window(&mut self, window: WriteWindow, data: &[T]) -> usize;
+    /// Fills the buffer with silence. `current`, `called` and `remain` are left alone,
+    /// so pacing keeps working; call `rewind` as well to start over.
+    fn clear(&mut self);
+    fn fill(&mut self, value: T);
+    /// Swaps in a new buffer under the lock and hands back the old one for reuse.
+    ///
+    /// The buffer is padded with silence to a whole number of frames. The read position
+    /// is kept if it is still inside the new buffer, otherwise it goes back to 0.
+    fn replace_buffer(&mut self, buffer: Vec<T>) -> Vec<T>;
+    /// Grows or shrinks the buffer keeping the samples at the same indices. Growing pads
+    /// with silence, shrinking drops the tail. Returns the old allocation.
+    fn resize_buffer(&mut self, len: usize) -> Vec<T>;
+    fn set_silent_data(&mut self);
+    /// The buffer's length in interleaved samples: `buf_frames` times `channels`.
+    fn buf_samples(&mut self) -> usize;
+    #[deprecated(note = "counts samples, not frames; use `buf_samples` or `buf_frames`")]
+    fn buf_size(&mut self) -> usize {
+        self.buf_samples()
+    }
+    /// The buffer's length in frames of one sample per channel.
+    fn buf_frames(&mut self) -> usize;
+    fn channels(&mut self) -> usize;
+    fn spec(&mut self) -> AudioSpecInfo;
+    /// Plays the buffer as mono data panned between -1.0 (left) and 1.0 (right).
+    ///
+    /// Only stereo devices can pan; on any other device this does nothing.
+    fn set_pan(&mut self, pan: f32);
+    /// Goes back to playing the buffer as interleaved frames.
+    fn clear_pan(&mut self);
+    fn pan(&mut self) -> Option<f32>;
+    /// Routes the two channels of a stereo device to its speakers: swapped, summed to
+    /// mono, or one of them on both. Applied to the callback's output after volume, pan
+    /// and effects, so it covers everything the device plays.
+    ///
+    /// Only stereo devices have channels to route; on any other device this does
+    /// nothing and the mode stays `Stereo`.
+    fn set_channel_mode(&mut self, mode: ChannelMode);
+    fn channel_mode(&mut self) -> ChannelMode;
+    fn mute(&mut self) -> bool;
+    /// The coarse level, rounded down when the gain was set with `set_gain`.
+    fn volume(&mut self) -> u16;
+    fn gain(&mut self) -> u16;
+    /// An application-wide level on top of the volume or gain, e.g. for a settings menu,
+    /// with the same 0..=7 range and curve as `set_volume`. `MAX_VOLUME` (the default)
+    /// leaves the gain untouched; mute still silences everything.
+    fn set_master_volume(&mut self, volume: u16);
+    fn master_volume(&mut self) -> u16;
+    /// Sets the gain in decibels, 0.0 being unity. Positive values clamp to 0.0,
+    /// anything at or below the floor (`set_db_floor`) is silence.
+    fn set_volume_db(&mut self, db: f32);
+    /// The current gain in decibels, `f32::NEG_INFINITY` when silent.
+    fn volume_db(&mut self) -> f32;
+    fn set_db_floor(&mut self, floor: f32);
+    fn db_floor(&mut self) -> f32;
+    /// Sets the gain from a 0.0..=100.0 slider position through the `volume_curve`.
+    /// The curve is evaluated here, on the calling thread, so the callback only ever
+    /// sees the resulting gain.
+    fn set_volume_percent(&mut self, percent: f32);
+    /// `VolumeCurve::Shift` by default. Changing it leaves the current gain alone.
+    fn set_volume_curve(&mut self, curve: VolumeCurve);
+    fn volume_curve(&mut self) -> VolumeCurve;
+    /// Moves the volume to the coarse level `volume` over `duration` at the obtained
+    /// rate, in even decibel steps, starting from the current gain, a fade in progress
+    /// included, so it never jumps. When it is done the volume is `volume` and
+    /// `poll_events` reports an event tagged `FADE_DONE_TAG`. Setting the volume or
+    /// gain any other way cancels the fade without an event; mute still applies on top.
+    fn fade_to(&mut self, volume: u16, duration: Duration);
+    /// `fade_to` `MAX_VOLUME`.
+    fn fade_in(&mut self, duration: Duration);
+    /// `fade_to` silence.
+    fn fade_out(&mut self, duration: Duration);
+    fn is_fading(&mut self) -> bool;
+    /// Number of frames a volume or mute change is spread over. 0 applies changes instantly.
+    fn set_ramp_samples(&mut self, samples: usize);
+    fn ramp_samples(&mut self) -> usize;
+    /// Output is always hard clamped to the sample range; `LimiterMode::Soft` adds a knee.
+    fn set_limiter(&mut self, mode: LimiterMode);
+    fn limiter(&mut self) -> LimiterMode;
+    /// Replaces the effect chain, run in order on every block; an empty chain turns
+    /// effects off. See `effects::Effect`.
+    fn set_effects(&mut self, effects: EffectChain);
+    fn current(&mut self) -> u64;
+    fn called(&mut self) -> u64;
+    fn remain(&mut self) -> usize;
+    /// All counters from a single lock, so they are consistent with each other.
+    /// Prefer this over calling the individual getters one after another.
+    ///
+    /// Not named `status` because `AudioDevice::status` already reports the SDL device state.
+    fn playback_status(&mut self) -> PlaybackStatus;
+    /// Runs `f` under a single lock, so the callback sees all of its changes or none of
+    /// them, e.g. a new voice's data, volume and position together. Keep `f` short: the
+    /// callback waits for it.
+    fn with_locked<R>(&mut self, f: impl FnOnce(&mut SoundState<'_, T>) -> R) -> R;
+    /// Frames taken from the buffer since the device was opened. Unlike `current` this
+    /// is not affected by seeking and does not count silence played after an underrun
+    /// or the end of a one-shot.
+    fn position_frames(&mut self) -> u64;
+    /// `position_frames` at the obtained sample rate, in whole milliseconds.
+    fn position_ms(&mut self) -> u64;
+    fn position_secs(&mut self) -> f64;
+    /// Stops the callback; `current`, `called` and `remain` stay frozen until `resume`.
+    /// A no-op unless `Playing`, see `PlaybackState`.
+    fn pause(&mut self);
+    /// Runs a `Paused` or `Stopped` device again.
+    fn resume(&mut self);
+    /// Pauses the device until `threshold` samples (at most `buf_samples`) are waiting in
+    /// front of the read cursor, then resumes it, so playback starts on real data
+    /// instead of a burst of silence. Only `write` and `push_data` fill it up: the one
+    /// crossing the threshold resumes the device before returning. A `commander` or
+    /// `control_handle` can't, since the callback applies their writes.
+    ///
+    /// On a newly opened device `current` is still 0 when it starts. `pause`, `resume`,
+    /// `restart` or `stop` in the meantime cancel the wait.
+    fn resume_when_filled(&mut self, threshold: usize);
+    fn is_paused(&mut self) -> bool;
+    /// Pauses and rewinds, like `pause` followed by `restart`; the next `resume` or
+    /// `restart` plays from the start.
+    fn stop(&mut self);
+    /// Lets what has been written so far play out, then finishes: a stream stops at
+    /// today's write cursor (later writes wait, unplayed, until `restart` or a seek), a
+    /// loop ends with the pass in progress as if its `LoopCount` ran out, and a one-shot
+    /// plays to its end as usual. The state then becomes `Finished` and the callback plays
+    /// silence; with nothing left to play, the device is also paused right away.
+    fn drain(&mut self);
+    /// `drain`, then waits for it to finish and pauses the device, or gives up after
+    /// `timeout`. A paused device always times out.
+    fn drain_blocking(&mut self, timeout: Duration) -> Result<(), Timeout>;
+    fn state(&mut self) -> PlaybackState;
+    /// False once SDL has stopped the device, e.g. because it was unplugged. A lost
+    /// device never comes back; hand it to `AudioContext::reopen`.
+    fn is_alive(&mut self) -> bool;
+    fn set_mode(&mut self, mode: PlayMode);
+    fn mode(&mut self) -> PlayMode;
+    /// True once a `OneShot` playback has reached the end of the buffer, or a `Loop` has
+    /// used up its `LoopCount`.
+    fn finished(&mut self) -> bool;
+    /// Moves `current` back to 0, clears `finished` and starts the loop count over. A
+    /// `Finished` or `Stopped` device starts playing again; a paused one stays paused.
+    fn restart(&mut self);
+    /// Moves the read position to `pos % buf_samples`, rounded down to a frame boundary.
+    ///
+    /// The callback never runs while the lock is held, so the seek takes effect
+    /// from the first sample of the next callback. Works the same while muted.
+    fn set_current(&mut self, pos: usize);
+    /// `set_data` at a frame instead of an interleaved sample offset.
+    fn set_data_at(&mut self, at: Frames, sound: &[T]);
+    /// The frame `current` is in.
+    fn position(&mut self) -> Frames;
+    /// `set_current` to the start of frame `at`, wrapping at the end of the buffer.
+    fn set_position(&mut self, at: Frames);
+    fn rewind(&mut self);
+    /// Zeroes `current` and `called`, e.g. when starting a new track. The read position
+    /// moves to the start of the buffer; pending `schedule_data` writes and `notify_at`
+    /// markers keep their positions. Wakes any `wait_for_callback`.
+    fn reset_counters(&mut self);
+    /// Plays the buffer `rate` times as fast, interpolating between neighbouring frames.
+    /// Clamped to `MIN_RATE..=MAX_RATE`; 1.0 plays the data exactly as written.
+    /// `current` reports the whole frame the read position is in.
+    fn set_rate(&mut self, rate: f32);
+    fn rate(&mut self) -> f32;
+    /// Declares the buffer's data as sampled at `hz`, so it plays at the right pitch and
+    /// duration whatever rate the device obtained. The callback does the conversion,
+    /// stepping through the buffer `hz / freq` frames per output frame with the same
+    /// linear interpolation as `set_rate`, which it multiplies. That works for streams
+    /// too, and the data is stored as written.
+    ///
+    /// 0, or the device's own rate, turns the conversion off and the buffer plays
+    /// untouched. A reopened device at another rate keeps converting from `hz`.
+    fn set_input_rate(&mut self, hz: u32);
+    /// The rate the data is taken to be at; the device's unless `set_input_rate` said
+    /// otherwise.
+    fn input_rate(&mut self) -> u32;
+    /// Modulates the volume (tremolo) or the rate (vibrato) with a sine wave of
+    /// `freq_hz`, timed by the device's obtained sample rate. `depth` is 0.0..=1.0, see
+    /// `LfoTarget`; a depth or frequency of 0 turns that target's LFO off, leaving the
+    /// output exactly as without one. Setting it again starts the wave over.
+    fn set_lfo(&mut self, target: LfoTarget, freq_hz: f32, depth: f32);
+    /// Consumes the buffer `factor` times as fast without changing the pitch: after
+    /// every segment of output (a callback block, at least 64 frames) the read position
+    /// skips the next `factor - 1` segments, crossfading into the data it lands on.
+    /// `take_consumed` and `current` count the skipped frames too, so pacing a writer
+    /// by them keeps up. Composes with `set_rate`; a factor of 1 (or 0) plays normally.
+    fn set_fast_forward(&mut self, factor: u8);
+    fn fast_forward(&mut self) -> u8;
+    /// Plays `Loop` and `OneShot` buffers backwards from `current`, wrapping to the last
+    /// frame in a loop; a reversed one-shot finishes after frame 0, so start it with
+    /// `set_current(buf_samples - 1)`. Composes with `set_rate`. Streams always play forwards.
+    fn set_reversed(&mut self, reversed: bool);
+    fn reversed(&mut self) -> bool;
+    /// In `PlayMode::Loop`, jumps back to `start` whenever playback reaches `end`
+    /// (exclusive) instead of wrapping at the end of the buffer, so an intro before
+    /// `start` plays once. The jump happens at the exact frame, even mid-callback.
+    ///
+    /// Both ends are rounded down to frame boundaries; an empty or inverted region, or
+    /// one reaching past the buffer, is rejected. Replacing the buffer with one too
+    /// short for the region clears it.
+    fn set_loop_region(&mut self, start: usize, end: usize) -> Result<(), AudioError>;
+    /// Goes back to wrapping at the end of the buffer.
+    fn clear_loop_region(&mut self);
+    fn loop_region(&mut self) -> Option<(usize, usize)>;
+    /// Limits `PlayMode::Loop` to a number of passes, counting the one in progress:
+    /// every wrap (or jump back to the loop region's start) uses one up, and after the
+    /// last the sound is finished. `Times(0)` finishes it right away, and any other
+    /// count on a loop that already ran out makes it play on. `restart` starts the count
+    /// over.
+    fn set_loop_count(&mut self, count: LoopCount);
+    /// Passes left including the current one.
+    fn remaining_loops(&mut self) -> LoopCount;
+    /// Switches to `data` when this pass completes: at the end of a one-shot, or at the
+    /// wrap ending a loop's last counted pass (the next wrap for an infinite loop, a
+    /// loop region's end included). The new buffer starts from its beginning with the
+    /// loop region cleared and the loop count started over. Streams and reversed
+    /// playback don't switch.
+    ///
+    /// Queuing again before the switch replaces the queued buffer and returns it.
+    fn queue_next(&mut self, data: Vec<T>, transition: Transition) -> Option<Vec<T>>;
+    /// Takes back the queued buffer so no switch happens.
+    fn cancel_next(&mut self) -> Option<Vec<T>>;
+    /// A handle that controls the device without ever blocking the audio thread: up to
+    /// `capacity` commands and `ring_len` samples wait in pre-allocated queues until the
+    /// next callback applies them. Replaces the queues of an earlier `commander`.
+    fn commander(&mut self, capacity: usize, ring_len: usize) -> Commander<T>;
+    /// A cloneable, `Send` handle for controlling the device from other threads through
+    /// its own queues, so it works alongside a `commander`. Up to `HANDLE_CAPACITY`
+    /// commands and a buffer's worth of samples wait for the next callback. Every call
+    /// returns a handle to the same queues.
+    ///
+    /// Takes `&mut self` only to reach the sound through the device lock once.
+    fn control_handle(&mut self) -> ControlHandle<T>;
+    /// Copies `data` into a back buffer for the next `commit`. The device lock is only
+    /// held to find the back buffer, not for the copy, so a full-buffer update never
+    /// delays the callback. Staging again before committing replaces the staged data.
+    fn stage_data(&mut self, data: &[T]);
+    /// Swaps the staged back buffer in under one short lock, so the callback moves to
+    /// it at its next block and never plays a mix of old and new data. The buffer is
+    /// padded like `replace_buffer`. Returns false when nothing was staged.
+    fn commit(&mut self, position: CommitPosition) -> bool;
+    /// Blocks until the callback has run at least once more and returns the new `called`
+    /// count, or gives up after `timeout`. A paused device always times out.
+    fn wait_for_callback(&mut self, timeout: Duration) -> Result<u64, Timeout>;
+    /// Runs `f` after every callback, on a thread of its own rather than the audio
+    /// thread, woken by the same signal as `wait_for_callback`. Calls come in order, one
+    /// per callback while `f` keeps up; when it falls behind, the callbacks it missed
+    /// are folded into the next call, with `called` jumping ahead and `consumed` a
+    /// running total. A new observer replaces the previous one.
+    ///
+    /// Dropping the device or calling `clear_on_callback` never waits for the thread: a
+    /// call already running finishes, and no more follow.
+    fn on_callback(&mut self, f: impl FnMut(CallbackInfo) + Send + 'static);
+    fn clear_on_callback(&mut self);
+    /// Samples the device takes per callback: the obtained `samples` times channels.
+    fn samples_per_callback(&mut self) -> usize;
+    /// Output latency: how long until a sample written now at the write cursor is heard.
+    ///
+    /// That is the unplayed data in the buffer (`remain` in `PlayMode::Stream`; other
+    /// modes have no write cursor, so use `latency_to`) played at the current rate, plus
+    /// the obtained `samples` frames SDL hands to the driver each callback, all over the
+    /// obtained rate. SDL's own double-buffering and the driver's buffers come on top and
+    /// can't be queried, so treat the result as accurate to about one callback period.
+    fn latency(&mut self) -> Duration;
+    /// `latency` with the caller's own high-water mark instead of the write cursor:
+    /// `written` is the position, counted like `current`, up to which data is in place.
+    /// A mark at or behind `current` gives just the SDL block.
+    fn latency_to(&mut self, written: u64) -> Duration;
+    /// Samples the callback has taken from the buffer since the previous call (since
+    /// opening, the first time). Silence played after an underrun or the end of a
+    /// one-shot is not counted, so writing exactly this many keeps the fill level steady.
+    fn take_consumed(&mut self) -> usize;
+}
+
+// One implementation for real and mock devices, so both run exactly the same code.
+// `$device` must provide `lock`, `pause`, `resume` and `status` like `AudioDevice`.
+macro_rules! impl_control {
+    ($device:ident) => {
+        impl<T: Sample> Control<T> for $device<T> {
+            fn set_mute(&mut self, specifier: bool) {
+                let mut locked = self.lock();
+                locked.mute = specifier;
+            }
+
+            fn set_mute_policy(&mut self, policy: MutePolicy) {
+                let mut locked = self.lock();
+                locked.mute_policy = policy;
+            }
+
+            fn mute_policy(&mut self) -> MutePolicy {
+                let locked = self.lock();
+                locked.mute_policy
+            }
+
+            fn set_volume(&mut self, volume: u16) {
+                let mut locked = self.lock();
+                locked.set_volume(volume);
+            }
+
+            fn set_gain(&mut self, gain: u16) {
+                let mut locked = self.lock();
+                locked.set_gain(gain);
+            }
+
+            fn set_data(&mut self, offset: usize, sound: &[T]) {
+                self.set_data_wrapping(offset, sound);
+            }
+
+            fn set_data_wrapping(&mut self, offset: usize, sound: &[T]) {
+                let mut locked = self.lock();
+                locked.set_data_wrapping(offset, sound);
+            }
+
+            fn set_data_from_iter(&mut self, offset: usize, iter: impl Iterator<Item = T>, max: usize) -> usize {
+                let (start, max) = {
+                    let locked = self.lock();
+                    (offset - offset % locked.frame_len(), max.min(locked.buf_size))
+                };
+                let mut iter = iter.take(max);
+                let mut chunk = [T::SILENCE; FILL_CHUNK];
+                let mut written = 0;
+                loop {
+                    // zip asks the chunk first, so no sample is pulled and then dropped
+                    let n = chunk.iter_mut().zip(&mut iter).map(|(dst, sample)| *dst = sample).count();
+                    if n == 0 {
+                        return written;
+                    }
+                    let mut locked = self.lock();
+                    copy_wrapping(&mut locked.buffer, start + written, &chunk[..n]);
+                    locked.remain += n;
+                    locked.generation += 1;
+                    written += n;
+                }
+            }
+
+            fn try_set_data(&mut self, offset: usize, sound: &[T]) -> Result<(), WriteError> {
+                let mut locked = self.lock();
+                locked.try_set_data(offset, sound)
+            }
+
+            fn crossfade_data(&mut self, offset: usize, sound: &[T], fade_samples: usize, blend_tail: bool) {
+                let mut locked = self.lock();
+                locked.crossfade_data(offset, sound, fade_samples, blend_tail);
+            }
+
+            fn schedule_data(&mut self, at: u64, sound: Vec<T>) -> Result<(), ScheduleError> {
+                let mut locked = self.lock();
+                locked.schedule_data(at, sound)
+            }
+
+            fn clear_scheduled(&mut self) {
+                let mut locked = self.lock();
+                locked.scheduled.clear();
+                locked.spent.clear();
+            }
+
+            fn notify_at(&mut self, position: u64, tag: u64) -> Result<(), ScheduleError> {
+                let mut locked = self.lock();
+                locked.notify_at(position, tag)
+            }
+
+            fn poll_events(&mut self) -> Vec<AudioEvent> {
+                // allocated before locking so the lock is held only for the swap
+                let fresh = Vec::with_capacity(EVENT_CAPACITY);
+                let mut locked = self.lock();
+                std::mem::replace(&mut locked.events, fresh)
+            }
+
+            fn dropped_events(&mut self) -> u64 {
+                let locked = self.lock();
+                locked.dropped_events
+            }
+
+            fn peak_level(&mut self) -> f32 {
+                let locked = self.lock();
+                locked.meter.peak()
+            }
+
+            fn rms_level(&mut self) -> f32 {
+                let locked = self.lock();
+                locked.meter.rms()
+            }
+
+            fn set_meter_window(&mut self, blocks: usize) {
+                let meter = LevelMeter::new(blocks);
+                let mut locked = self.lock();
+                locked.meter = meter;
+            }
+
+            fn meter_window(&mut self) -> usize {
+                let locked = self.lock();
+                locked.meter.window()
+            }
+
+            fn set_timing_enabled(&mut self, enabled: bool) {
+                let mut locked = self.lock();
+                if enabled != locked.timing.is_some() {
+                    locked.timing = enabled.then(CallbackStats::default);
+                }
+            }
+
+            fn callback_stats(&mut self) -> CallbackStats {
+                let locked = self.lock();
+                let period_us = match locked.spec.freq {
+                    freq if freq > 0 => locked.spec.samples as u64 * 1_000_000 / freq as u64,
+                    _ => 0,
+                };
+                CallbackStats { period_us, ..locked.timing.unwrap_or_default() }
+            }
+
+            fn reset_stats(&mut self) {
+                let mut locked = self.lock();
+                if let Some(stats) = locked.timing.as_mut() {
+                    *stats = CallbackStats::default();
+                }
+            }
+
+            fn set_drift_tracking(&mut self, enabled: bool) {
+                let mut locked = self.lock();
+                locked.drift = enabled.then(|| DriftEstimator::new(locked.spec.freq));
+            }
+
+            fn measured_rate(&mut self) -> Option<f64> {
+                let locked = self.lock();
+                locked.drift.as_ref()?.measured_rate()
+            }
+
+            fn clock_drift(&mut self) -> Option<f64> {
+                let locked = self.lock();
+                locked.drift.as_ref()?.clock_drift()
+            }
+
+            fn is_poisoned(&mut self) -> bool {
+                let locked = self.lock();
+                locked.poisoned.is_some()
+            }
+
+            fn panic_message(&mut self) -> Option<String> {
+                let locked = self.lock();
+                locked.poisoned.clone()
+            }
+
+            fn describe(&mut self) -> String {
+                let locked = self.lock();
+                locked.describe()
+            }
+
+            fn start_recording(&mut self, sink: RecordingSink) -> Result<Option<RecordingStats>, WavError> {
+                let spec = self.lock().spec;
+                let tap = RecordingTap::start::<T>(sink, spec)?;
+                let previous = {
+                    let mut locked = self.lock();
+                    locked.lifecycle.note(DeviceEventKind::RecordingStarted);
+                    locked.recording.replace(tap)
+                };
+                previous.map(RecordingTap::finish).transpose()
+            }
+
+            fn stop_recording(&mut self) -> Result<RecordingStats, WavError> {
+                // joined outside the lock, so the callback keeps running meanwhile
+                let recording = {
+                    let mut locked = self.lock();
+                    let recording = locked.recording.take();
+                    if recording.is_some() {
+                        locked.lifecycle.note(DeviceEventKind::RecordingStopped);
+                    }
+                    recording
+                };
+                recording.map_or(Ok(RecordingStats::default()), RecordingTap::finish)
+            }
+
+            fn set_label(&mut self, label: &str) {
+                let mut locked = self.lock();
+                locked.label = Some(label.to_string());
+            }
+
+            fn label(&mut self) -> Option<String> {
+                let locked = self.lock();
+                locked.label.clone()
+            }
+
+            fn device_name(&mut self) -> Option<String> {
+                let locked = self.lock();
+                locked.device_name.clone()
+            }
+
+            fn poll_device_events(&mut self) -> Vec<DeviceEvent> {
+                let alive = self.status() != AudioStatus::Stopped;
+                let events = {
+                    let mut locked = self.lock();
+                    locked.lifecycle.check_alive(alive);
+                    let Sound { label, lifecycle, .. } = &mut *locked;
+                    lifecycle.take(label.as_deref())
+                };
+                #[cfg(feature = "tracing")]
+                events.iter().for_each(lifecycle::trace);
+                events
+            }
+
+            fn set_data_i16(&mut self, offset: usize, sound: &[i16]) {
+                let mut locked = self.lock();
+                let len = locked.buf_size;
+                let offset = offset - offset % locked.frame_len();
+                // in runs up to the end of the buffer, wrapping as often as it takes
+                let (mut pos, mut rest) = (offset % len, sound);
+                while !rest.is_empty() {
+                    let n = rest.len().min(len - pos);
+                    T::from_i16_block(&mut locked.buffer[pos..pos + n], &rest[..n]);
+                    (pos, rest) = (0, &rest[n..]);
+                }
+                locked.remain += sound.len();
+                locked.generation += 1;
+            }
+
+            fn push_data(&mut self, sound: &[T]) {
+                let filled = {
+                    let mut locked = self.lock();
+                    let start = locked.pos() + locked.remain;
+                    copy_wrapping(&mut locked.buffer, start, sound);
+                    locked.remain += sound.len();
+                    locked.generation += 1;
+                    locked.preroll_filled()
+                };
+                if filled {
+                    Control::resume(self);
+                }
+            }
+
+            fn write_available(&mut self) -> usize {
+                let locked = self.lock();
+                locked.write_available()
+            }
+
+            fn write(&mut self, samples: &[T]) -> usize {
+                let (accepted, filled) = {
+                    let mut locked = self.lock();
+                    let accepted = locked.write(samples);
+                    (accepted, locked.preroll_filled())
+                };
+                if filled {
+                    Control::resume(self);
+                }
+                accepted
+            }
+
+            fn underruns(&mut self) -> u64 {
+                let locked = self.lock();
+                locked.underruns
+            }
+
+            fn reset_underruns(&mut self) {
+                let mut locked = self.lock();
+                locked.underruns = 0;
+            }
+
+            fn get_data(&mut self, offset: usize, out: &mut [T]) {
+                let locked = self.lock();
+                read_wrapping(&locked.buffer, offset, out);
+            }
+
+            fn snapshot(&mut self) -> Vec<T> {
+                let locked = self.lock();
+                locked.buffer.to_vec()
+            }
+
+            fn generation(&mut self) -> u64 {
+                let locked = self.lock();
+                locked.generation
+            }
+
+            fn read_consistent(&mut self, offset: usize, out: &mut [T]) -> u64 {
+                let locked = self.lock();
+                read_wrapping(&locked.buffer, offset, out);
+                locked.generation
+            }
+
+            fn write_window(&mut self, margin_samples: usize) -> WriteWindow {
+                let locked = self.lock();
+                locked.write_window(margin_samples)
+            }
+
+            fn write_into_window(&mut self, window: WriteWindow, data: &[T]) -> usize {
+                let mut locked = self.lock();
+                if window.buf_size != locked.buf_size {
+                    return 0;
+                }
+                let written = data.len().min(window.len);
+                copy_wrapping(&mut locked.buffer, window.offset, &data[..written]);
+                locked.generation += 1;
+                written
+            }
+
+            fn clear(&mut self) {
+                self.fill(T::SILENCE);
+            }
+
+            fn fill(&mut self, value: T) {
+                let mut locked = self.lock();
+                locked.buffer.fill(value);
+                locked.generation += 1;
+            }
+
+            fn replace_buffer(&mut self, buffer: Vec<T>) -> Vec<T> {
+                let mut locked = self.lock();
+                locked.replace_buffer(buffer)
+            }
+
+            fn resize_buffer(&mut self, len: usize) -> Vec<T> {
+                // allocated before taking the lock, but copied and swapped under a single
+                // one, so no write or callback falls in between
+                let mut buffer = Vec::with_capacity(len);
+                let mut locked = self.lock();
+                let kept = len.min(locked.buf_size);
+                buffer.extend_from_slice(&locked.buffer[..kept]);
+                buffer.resize(len, T::SILENCE);
+                locked.replace_buffer(buffer)
+            }
+
+            fn set_silent_data(&mut self) {
+                let mut locked = self.lock();
+                for d in locked.buffer.iter_mut() {
+                    *d = T::SILENCE;
+                }
+                locked.current = 0;
+                locked.remain = locked.buf_size;
+                locked.generation += 1;
+            }
+
+            fn buf_samples(&mut self) -> usize {
+                let locked = self.lock();
+                locked.buf_size
+            }
+
+            fn buf_frames(&mut self) -> usize {
+                let locked = self.lock();
+                locked.buf_size / locked.frame_len()
+            }
+
+            fn channels(&mut self) -> usize {
+                let locked = self.lock();
+                locked.channels
+            }
+
+            fn spec(&mut self) -> AudioSpecInfo {
+                let locked = self.lock();
+                locked.spec
+            }
+
+            fn set_pan(&mut self, pan: f32) {
+                let mut locked = self.lock();
+                locked.set_pan(pan);
+            }
+
+            fn set_channel_mode(&mut self, mode: ChannelMode) {
+                let mut locked = self.lock();
+                locked.set_channel_mode(mode);
+            }
+
+            fn channel_mode(&mut self) -> ChannelMode {
+                let locked = self.lock();
+                locked.channel_mode
+            }
+
+            fn clear_pan(&mut self) {
+                let mut locked = self.lock();
+                locked.pan = None;
+            }
+
+            fn pan(&mut self) -> Option<f32> {
+                let locked = self.lock();
+                locked.pan
+            }
+
+            fn mute(&mut self) -> bool {
+                let locked = self.lock();
+                locked.mute
+            }
+
+            fn volume(&mut self) -> u16 {
+                let locked = self.lock();
+                locked.volume()
+            }
+
+            fn gain(&mut self) -> u16 {
+                let locked = self.lock();
+                locked.gain()
+            }
+
+            fn set_master_volume(&mut self, volume: u16) {
+                let mut locked = self.lock();
+                locked.master_volume = volume.min(MAX_VOLUME);
+            }
+
+            fn master_volume(&mut self) -> u16 {
+                let locked = self.lock();
+                locked.master_volume
+            }
+
+            fn set_volume_db(&mut self, db: f32) {
+                let mut locked = self.lock();
+                locked.set_volume_db(db);
+            }
+
+            fn volume_db(&mut self) -> f32 {
+                let locked = self.lock();
+                locked.volume_db()
+            }
+
+            fn set_db_floor(&mut self, floor: f32) {
+                let mut locked = self.lock();
+                locked.db_floor = floor;
+            }
+
+            fn db_floor(&mut self) -> f32 {
+                let locked = self.lock();
+                locked.db_floor
+            }
+
+            fn set_volume_percent(&mut self, percent: f32) {
+                // a custom curve runs outside the lock, so it can't delay the callback
+                let (curve, db_floor) = {
+                    let locked = self.lock();
+                    (locked.volume_curve, locked.db_floor)
+                };
+                let gain = curve.gain(percent, db_floor);
+                let mut locked = self.lock();
+                locked.fade = None;
+                locked.gain = gain;
+            }
+
+            fn set_volume_curve(&mut self, curve: VolumeCurve) {
+                let mut locked = self.lock();
+                locked.volume_curve = curve;
+            }
+
+            fn volume_curve(&mut self) -> VolumeCurve {
+                let locked = self.lock();
+                locked.volume_curve
+            }
+
+            fn fade_to(&mut self, volume: u16, duration: Duration) {
+                let mut locked = self.lock();
+                let frames = (duration.as_secs_f64() * locked.spec.freq.max(0) as f64).round() as u64;
+                locked.fade_to(volume, frames);
+            }
+
+            fn fade_in(&mut self, duration: Duration) {
+                self.fade_to(MAX_VOLUME, duration);
+            }
+
+            fn fade_out(&mut self, duration: Duration) {
+                self.fade_to(0, duration);
+            }
+
+            fn is_fading(&mut self) -> bool {
+                let locked = self.lock();
+                locked.fade.is_some()
+            }
+
+            fn set_ramp_samples(&mut self, samples: usize) {
+                let mut locked = self.lock();
+                locked.ramp_samples = samples;
+            }
+
+            fn ramp_samples(&mut self) -> usize {
+                let locked = self.lock();
+                locked.ramp_samples
+            }
+
+            fn set_limiter(&mut self, mode: LimiterMode) {
+                let mut locked = self.lock();
+                locked.limiter = mode;
+            }
+
+            fn limiter(&mut self) -> LimiterMode {
+                let locked = self.lock();
+                locked.limiter
+            }
+
+            fn set_effects(&mut self, effects: EffectChain) {
+                let mut locked = self.lock();
+                locked.set_effects(effects);
+            }
+
+            fn current(&mut self) -> u64 {
+                let locked = self.lock();
+                locked.current
+            }
+
+            fn called(&mut self) -> u64 {
+                let locked = self.lock();
+                locked.called
+            }
+
+            fn remain(&mut self) -> usize {
+                let locked = self.lock();
+                locked.remain
+            }
+
+            fn playback_status(&mut self) -> PlaybackStatus {
+                let locked = self.lock();
+                locked.status()
+            }
+
+            fn with_locked<R>(&mut self, f: impl FnOnce(&mut SoundState<'_, T>) -> R) -> R {
+                let mut locked = self.lock();
+                f(&mut SoundState::new(&mut locked))
+            }
+
+            fn position_frames(&mut self) -> u64 {
+                let locked = self.lock();
+                locked.played_frames
+            }
+
+            fn position_ms(&mut self) -> u64 {
+                let locked = self.lock();
+                locked.position_ms()
+            }
+
+            fn position_secs(&mut self) -> f64 {
+                let locked = self.lock();
+                locked.position_secs()
+            }
+
+            fn pause(&mut self) {
+                Transitions::request(self, Request::Pause);
+            }
+
+            fn resume(&mut self) {
+                Transitions::request(self, Request::Resume);
+            }
+
+            fn resume_when_filled(&mut self, threshold: usize) {
+                Control::pause(self);
+                let filled = {
+                    let mut locked = self.lock();
+                    locked.preroll = Some(threshold.min(locked.buf_size));
+                    locked.preroll_filled()
+                };
+                if filled {
+                    Control::resume(self);
+                }
+            }
+
+            fn stop(&mut self) {
+                Transitions::request(self, Request::Stop);
+            }
+
+            fn drain(&mut self) {
+                let drained = {
+                    let mut locked = self.lock();
+                    locked.start_drain()
+                };
+                if drained {
+                    Self::pause(self);
+                }
+            }
+
+            fn drain_blocking(&mut self, timeout: Duration) -> Result<(), Timeout> {
+                self.drain();
+                let deadline = Instant::now() + timeout;
+                while !self.lock().finished {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(Timeout);
+                    }
+                    self.wait_for_callback(left)?;
+                }
+                Self::pause(self);
+                Ok(())
+            }
+
+            fn state(&mut self) -> PlaybackState {
+                let (alive, paused) = (self.status() != AudioStatus::Stopped, self.status() == AudioStatus::Paused);
+                let locked = self.lock();
+                PlaybackState::derive(alive, paused, &locked)
+            }
+
+            fn is_paused(&mut self) -> bool {
+                self.status() != AudioStatus::Playing
+            }
+
+            fn is_alive(&mut self) -> bool {
+                self.status() != AudioStatus::Stopped
+            }
+
+            fn set_mode(&mut self, mode: PlayMode) {
+                let mut locked = self.lock();
+                locked.mode = mode;
+            }
+
+            fn mode(&mut self) -> PlayMode {
+                let locked = self.lock();
+                locked.mode
+            }
+
+            fn finished(&mut self) -> bool {
+                let locked = self.lock();
+                locked.finished
+            }
+
+            fn restart(&mut self) {
+                Transitions::request(self, Request::Restart);
+            }
+
+            fn set_data_at(&mut self, at: Frames, sound: &[T]) {
+                let mut locked = self.lock();
+                let frames = (locked.buf_size / locked.frame_len()) as u64;
+                let offset = (at.0 % frames) as usize * locked.frame_len();
+                locked.set_data_wrapping(offset, sound);
+            }
+
+            fn position(&mut self) -> Frames {
+                let locked = self.lock();
+                Frames(locked.current / locked.frame_len() as u64)
+            }
+
+            fn set_position(&mut self, at: Frames) {
+                let mut locked = self.lock();
+                let frames = (locked.buf_size / locked.frame_len()) as u64;
+                let pos = (at.0 % frames) as usize * locked.frame_len();
+                locked.set_current(pos);
+            }
+
+            fn set_current(&mut self, pos: usize) {
+                let mut locked = self.lock();
+                locked.set_current(pos);
+            }
+
+            fn rewind(&mut self) {
+                self.set_current(0);
+            }
+
+            fn reset_counters(&mut self) {
+                let mut locked = self.lock();
+                locked.reset_counters();
+            }
+
+            fn set_rate(&mut self, rate: f32) {
+                let mut locked = self.lock();
+                locked.set_rate(rate);
+            }
+
+            fn rate(&mut self) -> f32 {
+                let locked = self.lock();
+                locked.rate
+            }
+
+            fn set_input_rate(&mut self, hz: u32) {
+                let mut locked = self.lock();
+                locked.set_input_rate(hz);
+            }
+
+            fn input_rate(&mut self) -> u32 {
+                let locked = self.lock();
+                locked.input_rate.unwrap_or(locked.spec.freq.max(0) as u32)
+            }
+
+            fn set_lfo(&mut self, target: LfoTarget, freq_hz: f32, depth: f32) {
+                let mut locked = self.lock();
+                locked.set_lfo(target, freq_hz, depth);
+            }
+
+            fn set_fast_forward(&mut self, factor: u8) {
+                let mut locked = self.lock();
+                locked.set_fast_forward(factor);
+            }
+
+            fn fast_forward(&mut self) -> u8 {
+                let locked = self.lock();
+                locked.fast_forward
+            }
+
+            fn set_reversed(&mut self, reversed: bool) {
+                let mut locked = self.lock();
+                locked.reversed = reversed;
+            }
+
+            fn reversed(&mut self) -> bool {
+                let locked = self.lock();
+                locked.reversed
+            }
+
+            fn set_loop_region(&mut self, start: usize, end: usize) -> Result<(), AudioError> {
+                let mut locked = self.lock();
+                locked.set_loop_region(start, end)
+            }
+
+            fn clear_loop_region(&mut self) {
+                let mut locked = self.lock();
+                locked.loop_region = None;
+            }
+
+            fn loop_region(&mut self) -> Option<(usize, usize)> {
+                let locked = self.lock();
+                locked.loop_region
+            }
+
+            fn set_loop_count(&mut self, count: LoopCount) {
+                let mut locked = self.lock();
+                locked.set_loop_count(count);
+            }
+
+            fn remaining_loops(&mut self) -> LoopCount {
+                let locked = self.lock();
+                locked.loops_left
+            }
+
+            fn queue_next(&mut self, mut data: Vec<T>, transition: Transition) -> Option<Vec<T>> {
+                // padded out here, so the callback can install it without allocating; to
+                // whole device frames, which stay whole if `set_pan` changes the frame
+                let channels = self.lock().channels;
+                pad_to_frames(&mut data, channels);
+                let (queued, retired) = {
+                    let mut locked = self.lock();
+                    let queued = locked.next.replace((data, transition)).map(|(data, _)| data);
+                    (queued, locked.retired.take())
+                };
+                // freed here rather than in the callback
+                drop(retired);
+                queued
+            }
+
+            fn cancel_next(&mut self) -> Option<Vec<T>> {
+                let mut locked = self.lock();
+                locked.next.take().map(|(data, _)| data)
+            }
+
+            fn commander(&mut self, capacity: usize, ring_len: usize) -> Commander<T> {
+                let mut locked = self.lock();
+                let (commander, receiver) = Commander::new(&locked, capacity, ring_len);
+                locked.commands = Some(receiver);
+                commander
+            }
+
+            fn control_handle(&mut self) -> ControlHandle<T> {
+                let mut locked = self.lock();
+                if let Some((_, handle)) = locked.handle.as_ref() {
+                    return handle.clone();
+                }
+                let ring_len = locked.buf_size;
+                let (commander, receiver) = Commander::new(&locked, HANDLE_CAPACITY, ring_len);
+                let handle = ControlHandle::new(commander);
+                locked.handle = Some((receiver, handle.clone()));
+                handle
+            }
+
+            fn stage_data(&mut self, data: &[T]) {
+                let back = {
+                    let locked = self.lock();
+                    locked.back.clone()
+                };
+                // a panic mid-copy leaves at worst a half-staged buffer, never a broken one
+                let mut back = back.lock().unwrap_or_else(PoisonError::into_inner);
+                back.data.clear();
+                back.data.extend_from_slice(data);
+                back.staged = true;
+            }
+
+            fn commit(&mut self, position: CommitPosition) -> bool {
+                let mut locked = self.lock();
+                locked.commit(position)
+            }
+
+            fn wait_for_callback(&mut self, timeout: Duration) -> Result<u64, Timeout> {
+                // the count is read under the device lock, so no callback slips in between
+                let (signal, called) = {
+                    let locked = self.lock();
+                    (locked.signal.clone(), locked.called)
+                };
+                signal.wait(called, timeout)
+            }
+
+            fn on_callback(&mut self, f: impl FnMut(CallbackInfo) + Send + 'static) {
+                // the tick is read under the device lock, so the first call is for the
+                // first callback after this one returns
+                let (signal, ticks) = {
+                    let locked = self.lock();
+                    (locked.signal.clone(), locked.signal.ticks())
+                };
+                let observer = Observer::start(signal, ticks, f);
+                // the old one is stopped once the lock is released, not holding up the callback
+                let old = self.lock().observer.replace(observer);
+                drop(old);
+            }
+
+            fn clear_on_callback(&mut self) {
+                let old = self.lock().observer.take();
+                drop(old);
+            }
+
+            fn samples_per_callback(&mut self) -> usize {
+                let locked = self.lock();
+                locked.spec.samples as usize * locked.channels
+            }
+
+            fn latency(&mut self) -> Duration {
+                let locked = self.lock();
+                let unplayed = if locked.mode == PlayMode::Stream { locked.remain as u64 } else { 0 };
+                locked.latency_of(unplayed)
+            }
+
+            fn latency_to(&mut self, written: u64) -> Duration {
+                let locked = self.lock();
+                locked.latency_of(written.saturating_sub(locked.current))
+            }
+
+            fn take_consumed(&mut self) -> usize {
+                let mut locked = self.lock();
+                let consumed = locked.consumed - locked.consumed_reported;
+                locked.consumed_reported = locked.consumed;
+                consumed as usize
+            }
+        }
+    };
+}
+
+impl_control!(SoundDevice);
+impl_control!(MockDevice);
+
+// Applies `PlaybackState::step`, implemented by `impl_control`'s devices so the table is
+// the only place pause, resume, restart and stop are decided.
+trait Transitions {
+    fn request(&mut self, request: Request);
+}
+
+macro_rules! impl_transitions {
+    ($device:ident) => {
+        impl<T: Sample> Transitions for $device<T> {
+            fn request(&mut self, request: Request) {
+                let Some(step) = Control::state(self).step(request) else {
+                    return;
+                };
+                if step.run == Some(false) {
+                    Self::pause(self);
+                }
+                {
+                    let mut locked = self.lock();
+                    if step.rewind {
+                        locked.restart();
+                    }
+                    locked.stopped = step.stopped;
+                    locked.preroll = None;
+                }
+                if step.run == Some(true) {
+                    Self::resume(self);
+                }
+            }
+        }
+    };
+}
+
+impl_transitions!(SoundDevice);
+impl_transitions!(MockDevice);
+
+impl<T: Sample> Sound<T> {
+    /// One block of playback; `callback` runs it behind `catch_unwind`.
+    fn render(&mut self, out: &mut [T]) {
+        let started = self.timing.is_some().then(Instant::now);
+        if let Some(drift) = self.drift.as_mut() {
+            drift.observe_block(started.unwrap_or_else(Instant::now), out.len() / self.channels);
+        }
+        if let Some(commands) = self.commands.take() {
+            commands.drain(self);
+            self.commands = Some(commands);
+        }
+        if let Some((commands, handle)) = self.handle.take() {
+            commands.drain(self);
+            self.handle = Some((commands, handle));
+        }
+        let starved = if self.is_simple_block(out.len()) {
+            self.render_contiguous(out)
+        } else {
+            self.render_frames(out)
+        };
+        if !self.effects.is_empty() {
+            self.run_effects(out);
+        }
+        self.apply_channel_mode(out);
+        self.meter.measure(out);
+        if starved {
+            self.underruns += 1;
+        }
+        self.lifecycle.underrun(starved, self.current);
+        self.called += 1;
+        if let Some(commands) = self.commands.as_ref() {
+            commands.publish(self);
+        }
+        if let Some((commands, _)) = self.handle.as_ref() {
+            commands.publish(self);
+        }
+        if let (Some(stats), Some(started)) = (self.timing.as_mut(), started) {
+            stats.record(started.elapsed());
+        }
+        self.signal.notify(self.called, self.consumed);
+    }
+
+    /// Plays a block frame by frame, handling everything `render_contiguous` can't.
+    /// Returns whether a stream ran dry.
+    fn render_frames(&mut self, out: &mut [T]) -> bool {
+        let frame_len = self.frame_len();
+        let mut starved = false;
+        for frame in out.chunks_mut(self.channels) {
+            if !self.scheduled.is_empty() {
+                self.apply_scheduled();
+            }
+            if !self.markers.is_empty() {
+                self.fire_markers();
+            }
+            self.step_fade();
+            let mut gain = self.step_gain();
+            if gain == 0 && self.mute && self.mute_policy == MutePolicy::Hold {
+                // before the availability check, so nothing runs out or underruns
+                frame.fill(T::SILENCE);
+                continue;
+            }
+            let available = match self.mode {
+                // never split a frame, so a partial write can't shift channels
+                PlayMode::Stream => self.stream_left() >= frame_len,
+                PlayMode::Loop => !self.finished,
+                PlayMode::OneShot => !self.finished && self.pos() + frame_len <= self.buf_size,
+            };
+            if !available {
+                match self.mode {
+                    PlayMode::Stream if self.drain_at.is_some() => self.finished = true,
+                    PlayMode::Stream => starved = true,
+                    PlayMode::Loop => (),
+                    PlayMode::OneShot => self.finished = true,
+                }
+                frame.fill(T::SILENCE);
+                continue;
+            }
+            if let Some(envelope) = self.envelope.as_mut() {
+                match envelope.next() {
+                    Some(level) => gain = ((gain as u64 * level as u64) >> 16) as u32,
+                    None => {
+                        // a finished release frees the voice whatever it was playing
+                        self.envelope = None;
+                        self.mode = PlayMode::OneShot;
+                        self.finished = true;
+                        frame.fill(T::SILENCE);
+                        continue;
+                    }
+                }
+            }
+            if let Some(lfo) = self.tremolo.as_mut() {
+                gain = ((gain as u64 * lfo.next_gain() as u64) >> 16) as u32;
+            }
+            if self.pan.is_some() {
+                let raw_sample = self.fetch_skipping(0);
+                for (dst, pan_gain) in frame.iter_mut().zip(self.pan_gains) {
+                    // both factors are at most unity, so the product stays within Q16
+                    let gain = ((gain as u64 * pan_gain as u64) >> 16) as u32;
+                    *dst = if gain == 0 { T::SILENCE } else { self.limit(raw_sample.scale(gain)) };
+                }
+            } else {
+                for (offset, dst) in frame.iter_mut().enumerate() {
+                    *dst = if gain == 0 { T::SILENCE } else { self.limit(self.fetch_skipping(offset).scale(gain)) };
+                }
+            }
+            let mut frames = self.advance();
+            if self.mode == PlayMode::Stream {
+                // a fast rate can't skip past the write cursor
+                frames = frames.min(self.stream_left() / frame_len);
+            }
+            let taken = frames * frame_len;
+            self.played_frames += frames as u64;
+            self.consumed += taken as u64;
+            if self.plays_backwards() {
+                self.step_back(taken);
+                continue;
+            }
+            match self.mode {
+                PlayMode::Stream => {
+                    self.current += taken as u64;
+                    self.remain -= taken;
+                    self.finish_drain();
+                }
+                PlayMode::Loop => self.step_loop(taken),
+                // a one-shot's read position never passes the end, so it is its own index
+                PlayMode::OneShot if self.pos() + taken >= self.buf_size && self.switches_at_pass_end() => {
+                    self.switch_to_next(self.pos() + taken - self.buf_size);
+                }
+                PlayMode::OneShot => {
+                    self.current += taken as u64;
+                    self.finished = self.current >= self.buf_size as u64;
+                }
+            }
+        }
+        starved
+    }
+
+    /// Whether the whole block can go through `render_contiguous`: whole frames at unity
+    /// rate going forwards, a settled gain, and nothing that acts on single frames.
+    fn is_simple_block(&self, len: usize) -> bool {
+        let target = if self.mute { 0 } else { self.target_gain() };
+        len.is_multiple_of(self.channels)
+            && self.rate == 1.0
+            && self.input_step == 1.0
+            && self.frac == 0.0
+            && !self.plays_backwards()
+            && self.envelope.is_none()
+            && self.fade.is_none()
+            && self.tremolo.is_none()
+            && self.vibrato.is_none()
+            && self.fast_forward == 1
+            && self.pan.is_none()
+            && self.scheduled.is_empty()
+            && self.markers.is_empty()
+            && self.loop_region.is_none()
+            && self.next.is_none()
+            && (self.ramp_samples == 0 || (self.applied_gain == target && self.ramp_target == target))
+            && !(self.mute && self.mute_policy == MutePolicy::Hold)
+    }
+
+    /// `render_frames` for a simple block: copies whole runs of the buffer up to the
+    /// next wrap point with the gain and limiter decided once. Produces exactly the same
+    /// output and state.
+    fn render_contiguous(&mut self, out: &mut [T]) -> bool {
+        // what `step_gain` would have done on every frame
+        let gain = if self.mute { 0 } else { self.target_gain() };
+        self.ramp_target = gain;
+        self.applied_gain = gain;
+        let frame_len = self.frame_len();
+        let mut done = 0;
+        while done < out.len() {
+            let pos = self.pos();
+            let playable = match self.mode {
+                PlayMode::Stream => self.stream_left() - self.stream_left() % frame_len,
+                PlayMode::Loop | PlayMode::OneShot if self.finished => 0,
+                PlayMode::Loop | PlayMode::OneShot => self.buf_size - pos,
+            };
+            let n = playable.min(self.buf_size - pos).min(out.len() - done);
+            if n == 0 {
+                out[done..].fill(T::SILENCE);
+                match self.mode {
+                    PlayMode::Stream if self.drain_at.is_some() => self.finished = true,
+                    PlayMode::Stream => return true,
+                    PlayMode::Loop => (),
+                    PlayMode::OneShot => self.finished = true,
+                }
+                return false;
+            }
+            let src = &self.buffer[pos..pos + n];
+            let dst = &mut out[done..done + n];
+            match (gain, self.limiter) {
+                (0, _) => dst.fill(T::SILENCE),
+                (_, LimiterMode::Off) => T::scale_block(dst, src, gain),
+                (_, LimiterMode::Soft) => {
+                    T::scale_block(dst, src, gain);
+                    dst.iter_mut().for_each(|d| *d = d.soft_limit());
+                }
+            }
+            self.played_frames += (n / frame_len) as u64;
+            self.consumed += n as u64;
+            self.current += n as u64;
+            match self.mode {
+                PlayMode::Stream => {
+                    self.remain -= n;
+                    self.finish_drain();
+                }
+                PlayMode::Loop if pos + n == self.buf_size => self.count_loop(),
+                PlayMode::Loop => (),
+                PlayMode::OneShot => self.finished = self.current >= self.buf_size as u64,
+            }
+            done += n;
+        }
+        false
+    }
+}
+
+impl<T: Sample> AudioCallback for Sound<T> {
+    type Channel = T;
+
+    fn callback(&mut self, out: &mut [T]) {
+        if self.poisoned.is_none() {
+            // an unwind out of here would abort the process from SDL's thread
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.render(out)));
+            if let Err(payload) = result {
+                self.poisoned = Some(describe_panic(payload.as_ref()));
+                self.lifecycle.note(DeviceEventKind::Poisoned);
+            }
+        }
+        if self.poisoned.is_some() {
+            // whatever panicked would most likely panic again, so it never runs again
+            out.fill(T::SILENCE);
+            self.called += 1;
+            self.signal.notify(self.called, self.consumed);
+        }
+        if let Some(recording) = &self.recording {
+            recording.push(out);
+        }
+    }
+}
+
+fn describe_panic(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "the audio callback panicked".to_string()
+    }
+}
+
+pub struct AudioContext {
+    sdl_context: sdl2::Sdl,
+    audio_subsystem: sdl2::AudioSubsystem,
+    desired_spec: AudioSpecDesired,
+    auto_resume: bool,
+}
+
+impl fmt::Debug for AudioContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AudioContext")
+            .field("driver", &self.current_driver())
+            .field("freq", &self.desired_spec.freq)
+            .field("channels", &self.desired_spec.channels)
+            .field("samples", &self.desired_spec.samples)
+            .field("auto_resume", &self.auto_resume)
+            .finish()
+    }
+}
+
+impl Default for AudioContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioContext {
+    /// # Panics
+    ///
+    /// Panics when SDL or its audio subsystem can't be initialized; see `try_new`.
+    pub fn new() -> Self {
+        Self::try_new().unwrap()
+    }
+
+    /// The preferred way to set up a context; `try_new` uses SDL's defaults for everything.
+    pub fn builder() -> AudioContextBuilder {
+        AudioContextBuilder::new()
+    }
+
+    /// Starts configuring a playback device on this context.
+    pub fn device(&self) -> DeviceBuilder<'_> {
+        DeviceBuilder::new(self)
+    }
+
+    pub fn try_new() -> Result<Self, AudioError> {
+        let sdl_context = sdl2::init().map_err(AudioError::SdlInit)?;
+        let audio_subsystem = sdl_context.audio().map_err(AudioError::NoAudioSubsystem)?;
+        Ok(Self::with_subsystem(audio_subsystem))
+    }
+
+    /// Names of the audio drivers compiled into SDL, in the order SDL tries them.
+    /// Not all of them necessarily work on this machine.
+    pub fn available_drivers() -> Vec<String> {
+        sdl2::audio::drivers().map(str::to_string).collect()
+    }
+
+    /// `try_new` with a specific audio driver, e.g. `"dummy"` for tests and headless
+    /// servers or `"pulseaudio"` over `"pipewire"`. On error nothing is left changed, so
+    /// the caller can fall back to `try_new`.
+    ///
+    /// SDL picks the driver when its audio subsystem first starts, so this fails with
+    /// `AudioError::DriverInUse` while another context in the process still holds it on
+    /// a different driver. The driver is asked for through SDL's `SDL_AUDIODRIVER` hint,
+    /// at the priority the hint already had and put back as it was afterwards; a hint
+    /// the application pinned with `Hint::Override` is left alone and wins. SDL prefers
+    /// the environment variable over the hint, so while it is set, and on SDL before
+    /// 2.0.22, which doesn't read the hint, the variable is swapped for the call instead.
+    pub fn with_driver(name: &str) -> Result<Self, AudioError> {
+        if !sdl2::audio::drivers().any(|driver| driver == name) {
+            return Err(AudioError::UnknownDriver(name.to_string()));
+        }
+        let sdl_context = sdl2::init().map_err(AudioError::SdlInit)?;
+        let version = sdl2::version::version();
+        let reads_hint = (version.major, version.minor, version.patch) >= (2, 0, 22);
+        let audio_subsystem = if reads_hint && std::env::var_os("SDL_AUDIODRIVER").is_none() {
+            let previous = sdl2::hint::get("SDL_AUDIODRIVER");
+            // SDL only takes a hint at or above the priority it has, so the lowest one
+            // that works is the one to put the previous value back at
+            let priority = [SDL_HintPriority::SDL_HINT_DEFAULT, SDL_HintPriority::SDL_HINT_NORMAL]
+                .into_iter()
+                .find(|priority| set_driver_hint(Some(name), *priority));
+            let audio_subsystem = sdl_context.audio();
+            if let Some(priority) = priority {
+                set_driver_hint(previous.as_deref(), priority);
+            }
+            audio_subsystem
+        } else {
+            let previous = std::env::var_os("SDL_AUDIODRIVER");
+            std::env::set_var("SDL_AUDIODRIVER", name);
+            let audio_subsystem = sdl_context.audio();
+            match previous {
+                Some(previous) => std::env::set_var("SDL_AUDIODRIVER", previous),
+                None => std::env::remove_var("SDL_AUDIODRIVER"),
+            }
+            audio_subsystem
+        };
+        let context = Self::with_subsystem(audio_subsystem.map_err(AudioError::NoAudioSubsystem)?);
+        let current = context.current_driver();
+        if current != name {
+            return Err(AudioError::DriverInUse { requested: name.to_string(), current: current.to_string() });
+        }
+        Ok(context)
+    }
+
+    pub fn with_subsystem(audio_subsystem: sdl2::AudioSubsystem) -> Self {
+        let sdl_context = audio_subsystem.sdl();
+        let desired_spec = AudioSpecDesired {
+            freq: None,     // default
+            channels: None, // default
+            samples: None,  // default
+        };
+        Self {
+            sdl_context,
+            audio_subsystem,
+            desired_spec,
+            auto_resume: false,
+        }
+    }
+
+    pub fn sdl(&self) -> sdl2::Sdl {
+        self.sdl_context.clone()
+    }
+
+    pub fn freq(&self) -> Option<i32> {
+        self.desired_spec.freq
+    }
+
+    pub fn set_freq(&mut self, freq: Option<i32>) {
+        self.desired_spec.freq = freq;
+    }
+
+    pub fn channels(&self) -> Option<u8> {
+        self.desired_spec.channels
+    }
+
+    pub fn set_channels(&mut self, channels: Option<u8>) {
+        self.desired_spec.channels = channels;
+    }
+
+    pub fn samples(&self) -> Option<u16> {
+        self.desired_spec.samples
+    }
+
+    pub fn set_samples(&mut self, samples: Option<u16>) {
+        self.desired_spec.samples = samples;
+    }
+
+    pub fn auto_resume(&self) -> bool {
+        self.auto_resume
+    }
+
+    /// When set, devices are resumed right after opening instead of starting paused.
+    pub fn set_auto_resume(&mut self, auto_resume: bool) {
+        self.auto_resume = auto_resume;
+    }
+
+    /// Converts decibels to the linear `Control::set_gain` scale (`UNITY_GAIN` at 0 dB).
+    pub fn db_to_gain(db: f32) -> u16 {
+        if db.is_nan() {
+            return 0;
+        }
+        (db_to_factor(db) * UNITY_GAIN as f32).round() as u16
+    }
+
+    pub fn open_device(&self, len: usize) -> Result<SoundDevice, AudioError> {
+        self.open_device_as::<u16>(len)
+    }
+
+    /// `open_device` that starts playing by itself once `threshold` samples have been
+    /// written, see `Control::resume_when_filled`.
+    pub fn open_device_prerolled(&self, len: usize, threshold: usize) -> Result<SoundDevice, AudioError> {
+        check_len(len)?;
+        let mut device = self.open_playback(None, |_| len, Vec::new(), false)?;
+        device.resume_when_filled(threshold);
+        Ok(device)
+    }
+
+    pub fn open_device_u8(&self, len: usize) -> Result<SoundDeviceU8, AudioError> {
+        self.open_device_as::<u8>(len)
+    }
+
+    pub fn open_device_i16(&self, len: usize) -> Result<SoundDeviceI16, AudioError> {
+        self.open_device_as::<i16>(len)
+    }
+
+    pub fn open_device_f32(&self, len: usize) -> Result<SoundDeviceF32, AudioError> {
+        self.open_device_as::<f32>(len)
+    }
+
+    pub fn open_device_as<T: Sample>(&self, len: usize) -> Result<SoundDevice<T>, AudioError> {
+        check_len(len)?;
+        self.open_playback(None, |_| len, Vec::new(), self.auto_resume)
+    }
+
+    /// Opens the default device with a buffer holding `duration` of audio at the obtained
+    /// rate and channel count; `Control::buf_frames` reports the resulting frame count.
+    pub fn open_device_with_duration(&self, duration: Duration) -> Result<SoundDevice, AudioError> {
+        let secs = duration.as_secs_f64();
+        self.open_playback(None, |spec| duration_len(spec, secs), Vec::new(), self.auto_resume)
+    }
+
+    /// `open_device` with an effect chain in place before the first callback.
+    pub fn open_device_with_effects(&self, len: usize, effects: EffectChain) -> Result<SoundDevice, AudioError> {
+        check_len(len)?;
+        self.open_playback(None, |_| len, effects, self.auto_resume)
+    }
+
+    /// Opens the playback device called `name`, as listed by `playback_devices`.
+    ///
+    /// Like `open_device` this can be called again while earlier devices are open, with
+    /// the same or another name; each device asks for the context's spec. Past SDL's limit
+    /// on open devices it fails with `AudioError::TooManyDevices`, and on drivers with a
+    /// single device with `AudioError::SingleDeviceDriver`. See `DeviceGroup`.
+    pub fn open_device_named(&self, name: &str, len: usize) -> Result<SoundDevice, AudioError> {
+        check_len(len)?;
+        self.open_playback(Some(name), |_| len, Vec::new(), self.auto_resume)
+    }
+
+    /// Opens the default recording device with a ring of `len` samples, negotiating
+    /// the spec the same way as `open_device`.
+    pub fn open_capture_device(&self, len: usize) -> Result<CaptureDevice, AudioError> {
+        self.open_capture_device_as::<u16>(len)
+    }
+
+    pub fn open_capture_device_as<T: Sample>(&self, len: usize) -> Result<CaptureDevice<T>, AudioError> {
+        check_len(len)?;
+        let device = self.audio_subsystem.open_capture(None, &self.desired_spec, |spec| {
+            Recorder::new(len, AudioSpecInfo::from(&spec))
+        }).map_err(AudioError::from_open)?;
+        if self.auto_resume {
+            device.resume();
+        }
+        Ok(device)
+    }
+
+    pub fn playback_devices(&self) -> Result<Vec<String>, AudioError> {
+        let count = self.audio_subsystem.num_audio_playback_devices()
+            .ok_or_else(|| AudioError::DeviceEnumeration(sdl2::get_error()))?;
+        (0..count)
+            .map(|index| self.audio_subsystem.audio_playback_device_name(index))
+            .collect::<Result<_, _>>()
+            .map_err(AudioError::DeviceEnumeration)
+    }
+
+    pub fn current_driver(&self) -> &'static str {
+        self.audio_subsystem.current_audio_driver()
+    }
+
+    /// Opens a device that plays a `chip::Chip` with `pulses` pulse channels, silent
+    /// until its registers are written through `chip::ChipControl`.
+    pub fn open_chip_device(&self, pulses: usize) -> Result<chip::ChipDevice, AudioError> {
+        let device = self.audio_subsystem.open_playback(None, &self.desired_spec, |spec| {
+            chip::ChipPlayer::new(chip::Chip::new(pulses), AudioSpecInfo::from(&spec))
+        }).map_err(AudioError::from_open)?;
+        if self.auto_resume {
+            device.resume();
+        }
+        Ok(device)
+    }
+
+    /// Opens the default device for pushing samples with `QueuePlayer::queue` instead of
+    /// writing a buffer the callback plays; see `QueueDevice` for the latency tradeoff.
+    pub fn open_queue(&self) -> Result<QueueDevice, AudioError> {
+        let queue = self.audio_subsystem.open_queue::<u16, _>(None, &self.desired_spec).map_err(AudioError::from_open)?;
+        let mut device = QueueDevice::new(queue);
+        if self.auto_resume {
+            device.resume();
+        }
+        Ok(device)
+    }
+
+    /// Opens a device that mixes `voices` independent sounds of `len` samples each.
+    pub fn open_mixer_device(&self, voices: usize, len: usize) -> Result<MixerDevice, AudioError> {
+        self.open_mixer_device_with_policy(voices, len, StealPolicy::default())
+    }
+
+    pub fn open_mixer_device_with_policy(
+        &self,
+        voices: usize,
+        len: usize,
+        policy: StealPolicy,
+    ) -> Result<MixerDevice, AudioError> {
+        check_len(len)?;
+        let device = self.audio_subsystem.open_playback(None, &self.desired_spec, |spec| {
+            Mixer::new(voices, len, AudioSpecInfo::from(&spec), policy)
+        }).map_err(AudioError::from_open)?;
+        if self.auto_resume {
+            device.resume();
+        }
+        Ok(device)
+    }
+
+    /// Opens a new device for the sound of `old`, typically after `is_alive` turned
+    /// false: the one of the same name if `old` was opened by name, the default one
+    /// otherwise. The buffer, position, volume, mute, mode, pan and effects carry
+    /// over, and the new device asks for the old device's spec. It is resumed if `old`
+    /// was playing.
+    ///
+    /// `old` is closed first; if the new device can't be opened its sound is lost.
+    pub fn reopen<T: Sample>(&self, old: SoundDevice<T>) -> Result<SoundDevice<T>, AudioError> {
+        let resume = old.status() == AudioStatus::Playing;
+        let mut sound = old.close_and_get_callback();
+        let name = sound.device_name.clone();
+        let desired = AudioSpecDesired {
+            freq: Some(sound.spec.freq),
+            channels: Some(sound.spec.channels),
+            samples: Some(sound.spec.samples),
+        };
+        let device = self.audio_subsystem.open_playback(name.as_deref(), &desired, |spec| {
+            sound.adopt_spec(AudioSpecInfo::from(&spec));
+            sound.lifecycle.opened(sound.spec);
+            sound
+        }).map_err(AudioError::from_open)?;
+        if resume {
+            device.resume();
+        }
+        Ok(device)
+    }
+
+    /// `len` picks the buffer length once the obtained spec is known.
+    pub(crate) fn open_playback<T: Sample>(
+        &self,
+        device: Option<&str>,
+        len: impl FnOnce(&AudioSpecInfo) -> usize,
+        effects: EffectChain,
+        resume: bool,
+    ) -> Result<SoundDevice<T>, AudioError> {
+        let device = self.audio_subsystem.open_playback(device, &self.desired_spec, |spec| {
+            let spec = AudioSpecInfo::from(&spec);
+            let mut sound = Sound::new(len(&spec), spec);
+            sound.device_name = device.map(str::to_string);
+            sound.set_effects(effects);
+            sound.lifecycle.opened(spec);
+            sound
+        }).map_err(AudioError::from_open)?;
+        if resume {
+            device.resume();
+        }
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(channels: u8) -> AudioSpecInfo {
+        AudioSpecInfo {
+            freq: 48000,
+            channels,
+            samples: 512,
+        }
+    }
+
+    fn instant<T: Sample>(len: usize, channels: u8) -> Sound<T> {
+        let mut sound = Sound::new(len, spec(channels));
+        sound.ramp_samples = 0;
+        sound
+    }
+
+    #[test]
+    fn it_works() {
+        assert_eq!(2 + 2, 4);
+    }
+
+    #[test]
+    fn f32_callback_scales_by_volume() {
+        let mut sound = instant::<f32>(4, 1);
+        sound.buffer.copy_from_slice(&[1.0, -1.0, 0.5, -0.5]);
+        sound.remain = 4;
+        sound.set_volume(6);
+        let mut out = [1.0f32; 6];
+        sound.callback(&mut out);
+        assert_eq!(out, [0.5, -0.5, 0.25, -0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn f32_callback_mute_outputs_zero() {
+        let mut sound = instant::<f32>(2, 1);
+        sound.buffer.copy_from_slice(&[1.0, -1.0]);
+        sound.remain = 2;
+        sound.set_volume(7);
+        sound.mute = true;
+        let mut out = [1.0f32; 2];
+        sound.callback(&mut out);
+        assert_eq!(out, [0.0, 0.0]);
+        assert_eq!(sound.current, 2);
+    }
+
+    #[test]
+    fn i16_to_u16_covers_full_range() {
+        assert_eq!(u16::from_i16(i16::MIN), 0);
+        assert_eq!(u16::from_i16(0), SETUP_U16 as u16);
+        assert_eq!(u16::from_i16(i16::MAX), u16::MAX);
+        assert_eq!(f32::from_i16(i16::MIN), -1.0);
+    }
+
+    #[test]
+    fn i16_callback_matches_u16_callback() {
+        let data: [i16; 4] = [i16::MIN, -1234, 0, i16::MAX];
+        let mut signed = instant::<i16>(4, 1);
+        let mut biased = instant::<u16>(4, 1);
+        for (i, a) in data.iter().enumerate() {
+            signed.buffer[i] = i16::from_i16(*a);
+            biased.buffer[i] = u16::from_i16(*a);
+        }
+        for volume in 0..=7 {
+            signed.set_volume(volume);
+            biased.set_volume(volume);
+            signed.remain = 4;
+            biased.remain = 4;
+            let mut signed_out = [0i16; 4];
+            let mut biased_out = [0u16; 4];
+            signed.callback(&mut signed_out);
+            biased.callback(&mut biased_out);
+            for (s, b) in signed_out.iter().zip(biased_out.iter()) {
+                assert_eq!(u16::from_i16(*s), *b);
+            }
+        }
+    }
+
+    #[test]
+    fn stereo_buffer_is_whole_frames() {
+        let sound = instant::<u16>(5, 2);
+        assert_eq!(sound.buf_size, 6);
+        assert_eq!(sound.buffer.len(), 6);
+    }
+
+    #[test]
+    fn stereo_wrap_keeps_channels_aligned() {
+        const L: u16 = 0x9000;
+        const R: u16 = 0x7000;
+        let mut sound = instant::<u16>(4, 2);
+        sound.set_volume(7);
+        sound.current = 6;
+        // odd-length write crossing the end of the buffer
+        for (pos, a) in (6..).zip([L, R, L]) {
+            sound.buffer[pos % sound.buf_size] = a;
+        }
+        sound.remain = 3;
+        let mut out = [0u16; 4];
+        sound.callback(&mut out);
+        assert_eq!(out, [L, R, SETUP_U16 as u16, SETUP_U16 as u16]);
+        assert_eq!(sound.buffer[0], L);
+        // completing the frame plays it with the channels in place
+        sound.buffer[1] = R;
+        sound.remain += 1;
+        sound.callback(&mut out);
+        assert_eq!(out, [L, R, SETUP_U16 as u16, SETUP_U16 as u16]);
+        assert_eq!(sound.pos(), 2);
+    }
+
+    #[test]
+    fn loop_mode_ignores_remain() {
+        let mut sound = instant::<u16>(2, 1);
+        sound.buffer.copy_from_slice(&[1, 2]);
+        sound.set_volume(7);
+        sound.mode = PlayMode::Loop;
+        let mut out = [0u16; 5];
+        sound.callback(&mut out);
+        assert_eq!(out, [1, 2, 1, 2, 1]);
+        assert_eq!(sound.remain, 0);
+    }
+
+    #[test]
+    fn one_shot_pads_with_silence_and_finishes() {
+        let mut sound = instant::<u16>(3, 1);
+        sound.buffer.copy_from_slice(&[1, 2, 3]);
+        sound.set_volume(7);
+        sound.mode = PlayMode::OneShot;
+        sound.current = 1;
+        let mut out = [0u16; 2];
+        sound.callback(&mut out);
+        assert_eq!(out, [2, 3]);
+        assert!(sound.finished);
+        let mut out = [0u16; 4];
+        sound.current = 0;
+        sound.finished = false;
+        sound.callback(&mut out);
+        assert_eq!(out, [1, 2, 3, SETUP_U16 as u16]);
+        assert!(sound.finished);
+        sound.callback(&mut out);
+        assert_eq!(out, [SETUP_U16 as u16; 4]);
+        assert_eq!(sound.current, 3);
+    }
+
+    #[test]
+    fn seek_wraps_and_applies_on_next_callback() {
+        let mut sound = instant::<u16>(4, 1);
+        sound.buffer.copy_from_slice(&[1, 2, 3, 4]);
+        sound.set_volume(7);
+        sound.mode = PlayMode::Loop;
+        sound.set_current(6);
+        assert_eq!(sound.current, 2);
+        let mut out = [0u16; 3];
+        sound.callback(&mut out);
+        assert_eq!(out, [3, 4, 1]);
+    }
+
+    #[test]
+    fn seek_while_muted() {
+        let mut sound = instant::<u16>(4, 1);
+        sound.buffer.copy_from_slice(&[1, 2, 3, 4]);
+        sound.set_volume(7);
+        sound.mode = PlayMode::Loop;
+        sound.mute = true;
+        sound.set_current(1);
+        let mut out = [0u16; 2];
+        sound.callback(&mut out);
+        assert_eq!(out, [SETUP_U16 as u16; 2]);
+        sound.mute = false;
+        sound.set_current(1);
+        sound.callback(&mut out);
+        assert_eq!(out, [2, 3]);
+    }
+
+    #[test]
+    fn minus_six_db_is_about_half() {
+        let mut sound = instant::<u16>(1, 1);
+        sound.buffer[0] = 0;
+        sound.mode = PlayMode::Loop;
+        sound.set_volume_db(-6.0);
+        let mut out = [0u16; 1];
+        sound.callback(&mut out);
+        let ratio = (out[0] as i32 - SETUP_U16) as f32 / -SETUP_U16 as f32;
+        assert!((ratio - 0.5).abs() < 0.01, "ratio {}", ratio);
+        assert!((sound.volume_db() + 6.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn db_floor_and_unity() {
+        let mut sound = instant::<u16>(1, 1);
+        sound.set_volume_db(0.0);
+        assert_eq!(sound.gain, GAIN_ONE);
+        sound.set_volume_db(12.0);
+        assert_eq!(sound.gain, GAIN_ONE);
+        sound.set_volume_db(DEFAULT_DB_FLOOR);
+        assert_eq!(sound.gain, 0);
+        assert_eq!(sound.volume_db(), f32::NEG_INFINITY);
+        sound.db_floor = -90.0;
+        sound.set_volume_db(-80.0);
+        assert!(sound.gain > 0);
+        assert_eq!(AudioContext::db_to_gain(0.0), UNITY_GAIN);
+        assert_eq!(AudioContext::db_to_gain(-6.0206), 128);
+    }
+
+    #[test]
+    fn volume_curves_map_the_slider_onto_gains() {
+        let log = VolumeCurve::Logarithmic { floor_db: -40.0 };
+        assert!((log.factor(0.0) - 0.01).abs() < 1e-6);
+        assert_eq!(log.factor(100.0), 1.0);
+        assert_eq!(log.gain(100.0, DEFAULT_DB_FLOOR), GAIN_ONE);
+        assert_eq!(log.gain(0.0, DEFAULT_DB_FLOOR), 0);
+        assert_eq!(log.gain(1.0, -39.0), 0);
+        assert!(log.gain(1.0, DEFAULT_DB_FLOOR) > 0);
+        for curve in [log, VolumeCurve::Shift, VolumeCurve::Linear, VolumeCurve::Custom(|x| x * x)] {
+            let factors: Vec<f32> = (0..=1000).map(|i| curve.factor(i as f32 / 10.0)).collect();
+            assert!(factors.windows(2).all(|w| w[0] <= w[1]), "{:?}", curve);
+            assert_eq!((curve.factor(-5.0), curve.factor(f32::NAN)), (curve.factor(0.0), curve.factor(0.0)));
+            assert_eq!(curve.factor(250.0), 1.0);
+        }
+        // the shift curve lands on the coarse levels, the linear one in between
+        for volume in 0..=MAX_VOLUME {
+            let percent = volume as f32 * 100.0 / MAX_VOLUME as f32;
+            assert_eq!(VolumeCurve::Shift.gain(percent, DEFAULT_DB_FLOOR), level_gain(volume));
+        }
+        assert_eq!(VolumeCurve::Linear.gain(50.0, DEFAULT_DB_FLOOR), GAIN_ONE / 2);
+        assert_eq!(VolumeCurve::Custom(|_| 7.0).factor(10.0), 1.0);
+    }
+
+    #[test]
+    fn mute_ramps_down_monotonically() {
+        let mut sound = Sound::<u16>::new(1, spec(1));
+        sound.buffer[0] = 0xc000;
+        sound.mode = PlayMode::Loop;
+        sound.set_volume(7);
+        let mut out = [0u16; DEFAULT_RAMP_SAMPLES];
+        sound.callback(&mut out);
+        assert_eq!(*out.last().unwrap(), 0xc000);
+        sound.mute = true;
+        sound.callback(&mut out);
+        assert!(out.windows(2).all(|w| w[1] <= w[0]));
+        assert!(out[0] < 0xc000);
+        assert_eq!(*out.last().unwrap(), SETUP_U16 as u16);
+    }
+
+    #[test]
+    fn ramp_retargets_mid_ramp() {
+        let mut sound = Sound::<u16>::new(1, spec(1));
+        sound.buffer[0] = 0xc000;
+        sound.mode = PlayMode::Loop;
+        sound.set_volume(7);
+        let mut out = [0u16; DEFAULT_RAMP_SAMPLES / 2];
+        sound.callback(&mut out);
+        let halfway = *out.last().unwrap();
+        assert!(halfway > SETUP_U16 as u16 && halfway < 0xc000);
+        sound.set_volume(0);
+        let mut out = [0u16; 1];
+        sound.callback(&mut out);
+        // turns around from where it was instead of snapping
+        assert!(out[0] < halfway && out[0] > SETUP_U16 as u16);
+    }
+
+    #[test]
+    fn zero_ramp_is_instant() {
+        let mut sound = instant::<u16>(1, 1);
+        sound.buffer[0] = 0xc000;
+        sound.mode = PlayMode::Loop;
+        sound.set_volume(7);
+        let mut out = [0u16; 2];
+        sound.callback(&mut out);
+        assert_eq!(out, [0xc000; 2]);
+        sound.mute = true;
+        sound.callback(&mut out);
+        assert_eq!(out, [SETUP_U16 as u16; 2]);
+    }
+
+    #[test]
+    fn center_pan_is_identical_on_both_channels() {
+        let mut sound = instant::<u16>(3, 2);
+        sound.set_pan(0.0);
+        sound.buffer[..3].copy_from_slice(&[0, 0x1234, 0xffff]);
+        sound.remain = 3;
+        sound.set_volume(7);
+        let mut out = [0u16; 8];
+        sound.callback(&mut out);
+        for frame in out[..6].chunks(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+        assert_eq!(&out[6..], &[SETUP_U16 as u16; 2]);
+        assert_eq!(sound.current, 3);
+        // -3 dB each side
+        let level = (out[0] as i32 - SETUP_U16) as f32 / -SETUP_U16 as f32;
+        assert!((level - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.001);
+    }
+
+    #[test]
+    fn hard_pan_stays_in_range() {
+        let mut sound = instant::<u16>(2, 2);
+        sound.set_pan(-1.0);
+        sound.buffer[..2].copy_from_slice(&[0, 0xffff]);
+        sound.mode = PlayMode::Loop;
+        sound.set_volume(7);
+        let mut out = [0u16; 4];
+        sound.callback(&mut out);
+        assert_eq!(out, [0, SETUP_U16 as u16, 0xffff, SETUP_U16 as u16]);
+        sound.set_pan(1.0);
+        sound.callback(&mut out);
+        assert_eq!(out, [SETUP_U16 as u16, 0, SETUP_U16 as u16, 0xffff]);
+    }
+
+    #[test]
+    fn pan_is_ignored_on_mono() {
+        let mut sound = instant::<u16>(2, 1);
+        sound.set_pan(1.0);
+        assert_eq!(sound.pan, None);
+        assert_eq!(sound.frame_len(), 1);
+    }
+
+    #[test]
+    fn channel_modes_route_each_frame() {
+        let mut sound = instant::<u16>(6, 2);
+        sound.buffer.copy_from_slice(&[0x1000, 0xf000, 0xffff, 0xffff, 0, 0]);
+        sound.mode = PlayMode::Loop;
+        sound.set_volume(7);
+        let mut out = [0u16; 6];
+        for (mode, expected) in [
+            (ChannelMode::Stereo, [0x1000, 0xf000, 0xffff, 0xffff, 0, 0]),
+            (ChannelMode::SwapLeftRight, [0xf000, 0x1000, 0xffff, 0xffff, 0, 0]),
+            // full scale on both sides averages to full scale, not past it
+            (ChannelMode::MonoSum, [0x8000, 0x8000, 0xffff, 0xffff, 0, 0]),
+            (ChannelMode::LeftOnly, [0x1000, 0x1000, 0xffff, 0xffff, 0, 0]),
+            (ChannelMode::RightOnly, [0xf000, 0xf000, 0xffff, 0xffff, 0, 0]),
+        ] {
+            sound.set_channel_mode(mode);
+            sound.callback(&mut out);
+            assert_eq!(out, expected, "{:?}", mode);
+        }
+
+        let mut mono = instant::<u16>(2, 1);
+        mono.set_channel_mode(ChannelMode::SwapLeftRight);
+        assert_eq!(mono.channel_mode, ChannelMode::Stereo);
+        // and a stereo device reopened as mono drops its mode
+        sound.adopt_spec(spec(1));
+        assert_eq!(sound.channel_mode, ChannelMode::Stereo);
+    }
+
+    #[test]
+    fn spec_info_round_trips() {
+        let obtained = AudioSpec {
+            freq: 44100,
+            format: sdl2::audio::AudioFormat::u16_sys(),
+            channels: 2,
+            silence: 0,
+            samples: 1024,
+            size: 4096,
+        };
+        let info = AudioSpecInfo::from(&obtained);
+        assert_eq!(info, AudioSpecInfo { freq: 44100, channels: 2, samples: 1024 });
+        let sound = Sound::<u16>::new(8, info);
+        assert_eq!(sound.spec, info);
+        assert_eq!(sound.channels, 2);
+    }
+
+    #[test]
+    fn try_set_data_rejects_out_of_range() {
+        let mut sound = instant::<u16>(4, 1);
+        assert_eq!(
+            sound.try_set_data(4, &[1]),
+            Err(WriteError::OffsetOutOfRange { offset: 4, buf_size: 4 })
+        );
+        let err = sound.try_set_data(0, &[1; 5]).unwrap_err();
+        assert_eq!(err, WriteError::TooLong { len: 5, fits: 4 });
+        assert_eq!(err.fits(), 4);
+        assert_eq!(sound.remain, 0);
+        assert_eq!(sound.try_set_data(3, &[1, 2]), Ok(()));
+        assert_eq!(sound.buffer, [2, SETUP_U16 as u16, SETUP_U16 as u16, 1]);
+        assert_eq!(sound.remain, 2);
+    }
+
+    #[test]
+    fn set_data_wrapping_overwrites_itself() {
+        let mut sound = instant::<u16>(3, 1);
+        sound.set_data_wrapping(4, &[1, 2, 3, 4]);
+        assert_eq!(sound.buffer, [3, 4, 2]);
+    }
+
+    #[test]
+    fn copy_wrapping_matches_per_sample_loop() {
+        for len in 1..=5 {
+            for offset in 0..12 {
+                for data_len in 0..12 {
+                    let data: Vec<u16> = (1..=data_len as u16).collect();
+                    let mut expected = vec![0u16; len];
+                    for (pos, a) in (offset..).zip(&data) {
+                        expected[pos % len] = *a;
+                    }
+                    let mut buffer = vec![0u16; len];
+                    copy_wrapping(&mut buffer, offset, &data);
+                    assert_eq!(buffer, expected, "len {} offset {} data {}", len, offset, data_len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn read_wrapping_mirrors_copy_wrapping() {
+        let buffer = [1u16, 2, 3, 4];
+        let mut out = [0u16; 6];
+        read_wrapping(&buffer, 6, &mut out);
+        assert_eq!(out, [3, 4, 1, 2, 3, 4]);
+        let mut written = [0u16; 4];
+        copy_wrapping(&mut written, 3, &[9, 8]);
+        let mut back = [0u16; 2];
+        read_wrapping(&written, 3, &mut back);
+        assert_eq!(back, [9, 8]);
+    }
+
+    #[test]
+    fn replace_buffer_keeps_position_when_it_fits() {
+        let mut sound = instant::<u16>(4, 1);
+        sound.current = 6;
+        sound.remain = 4;
+        let old = sound.replace_buffer(vec![7; 8]);
+        assert_eq!(old.len(), 4);
+        assert_eq!((sound.buf_size, sound.current, sound.remain), (8, 2, 4));
+        sound.current = 5;
+        sound.replace_buffer(vec![7; 3]);
+        assert_eq!((sound.buf_size, sound.current, sound.remain), (3, 0, 3));
+    }
+
+    #[test]
+    fn replace_buffer_pads_to_whole_frames() {
+        let mut sound = instant::<u16>(4, 2);
+        sound.replace_buffer(vec![1, 2, 3]);
+        assert_eq!(sound.buffer, [1, 2, 3, SETUP_U16 as u16]);
+        sound.replace_buffer(Vec::new());
+        assert_eq!(sound.buf_size, 2);
+    }
+
+    #[test]
+    fn write_never_overtakes_the_read_cursor() {
+        let mut sound = instant::<u16>(4, 1);
+        sound.set_volume(7);
+        assert_eq!(sound.write(&[1, 2, 3]), 3);
+        assert_eq!(sound.write_available(), 1);
+        assert_eq!(sound.write(&[4, 5, 6]), 1);
+        let mut out = [0u16; 2];
+        sound.callback(&mut out);
+        assert_eq!(out, [1, 2]);
+        assert_eq!(sound.write(&[5, 6, 7]), 2);
+        let mut out = [0u16; 5];
+        sound.callback(&mut out);
+        assert_eq!(out, [3, 4, 5, 6, SETUP_U16 as u16]);
+        assert_eq!(sound.underruns, 1);
+    }
+
+    #[test]
+    fn underruns_count_starved_callbacks() {
+        let mut sound = instant::<u16>(8, 1);
+        let mut out = [0u16; 4];
+        sound.write(&[1; 4]);
+        sound.callback(&mut out);
+        assert_eq!(sound.underruns, 0);
+        for _ in 0..3 {
+            sound.callback(&mut out);
+        }
+        assert_eq!(sound.underruns, 3);
+        // a partly fed callback counts as starved too
+        sound.write(&[1; 2]);
+        sound.callback(&mut out);
+        assert_eq!(sound.underruns, 4);
+        sound.write(&[1; 4]);
+        sound.callback(&mut out);
+        assert_eq!(sound.underruns, 4);
+        // looping never starves
+        sound.mode = PlayMode::Loop;
+        sound.callback(&mut out);
+        assert_eq!(sound.underruns, 4);
+    }
+
+    #[test]
+    fn status_is_coherent() {
+        let mut sound = instant::<u16>(8, 1);
+        sound.set_volume(5);
+        sound.write(&[1; 6]);
+        let mut out = [0u16; 4];
+        sound.callback(&mut out);
+        sound.callback(&mut out);
+        let status = sound.status();
+        assert_eq!(status.called, 2);
+        assert_eq!(status.current, 6);
+        assert_eq!(status.remain, 0);
+        assert_eq!(status.underruns, 1);
+        assert_eq!(status.current, status.remain as u64 + 6);
+        assert_eq!((status.volume, status.gain), (5, 64));
+        assert_eq!(status.buf_size, 8);
+        assert_eq!(status.mode, PlayMode::Stream);
+    }
+
+    #[test]
+    fn position_counts_frames_across_wraps() {
+        let mut sound = instant::<u16>(96, 2);
+        sound.mode = PlayMode::Loop;
+        let mut out = [0u16; 960];
+        for _ in 0..100 {
+            sound.callback(&mut out);
+        }
+        assert_eq!(sound.played_frames, 48000);
+        assert_eq!(sound.position_ms(), 1000);
+        assert_eq!(sound.position_secs(), 1.0);
+    }
+
+    #[test]
+    fn position_ms_does_not_drift() {
+        let mut sound = instant::<u16>(4, 1);
+        sound.spec.freq = 44100;
+        // ten minutes and one frame
+        sound.played_frames = 44100 * 600 + 1;
+        assert_eq!(sound.position_ms(), 600_000);
+        sound.played_frames = 44100 * 600 - 1;
+        assert_eq!(sound.position_ms(), 599_999);
+    }
+
+    #[test]
+    fn gain_above_unity_saturates() {
+        assert_eq!(apply_gain(i16::MIN as i32, 4 * GAIN_ONE), i16::MIN as i32);
+        assert_eq!(apply_gain(i16::MAX as i32, u32::MAX), i16::MAX as i32);
+        assert_eq!(0xffffu16.scale(3 * GAIN_ONE), 0xffff);
+        assert_eq!(0.9f32.scale(2 * GAIN_ONE), 1.0);
+    }
+
+    #[test]
+    fn soft_limiter_stays_in_range() {
+        assert_eq!(soft_limit_i32(1000), 1000);
+        assert_eq!(soft_limit_i32(-24576), -24576);
+        let mut last = 0;
+        for x in (0..=200_000).step_by(1000) {
+            let y = soft_limit_i32(x);
+            assert!(y >= last && y <= i16::MAX as i32, "{} -> {}", x, y);
+            assert!(soft_limit_i32(-x) >= i16::MIN as i32);
+            last = y;
+        }
+        let mut sound = instant::<u16>(2, 1);
+        sound.limiter = LimiterMode::Soft;
+        sound.mode = PlayMode::Loop;
+        sound.set_volume(7);
+        sound.buffer.copy_from_slice(&[0, 0xffff]);
+        let mut out = [0u16; 2];
+        sound.callback(&mut out);
+        assert!(out[0] > 0 && out[1] < 0xffff);
+        assert!(1.0f32.soft_limit() < 1.0 && 1.0f32.soft_limit() > LIMITER_THRESHOLD);
+    }
+
+    #[test]
+    fn coarse_levels_match_shift_ladder() {
+        for raw in [0u16, 1, 0x7fff, 0x8000, 0x8001, 0xabcd, 0xffff] {
+            let singed_sample = raw as i32 - SETUP_U16;
+            for volume in 0..=MAX_VOLUME {
+                let expected = match volume {
+                    0 => 0,
+                    v => singed_sample >> (MAX_VOLUME - v),
+                };
+                assert_eq!(raw.scale(level_gain(volume)), (expected + SETUP_U16) as u16);
+            }
+        }
+    }
+
+    /// What every format has to agree on: silence is the midpoint, and the midpoint,
+    /// full scale and clamping survive both conversions.
+    fn check_format<T: Sample + PartialEq + fmt::Debug>(min: T, max: T, max_i32: i32) {
+        assert_eq!(T::MID, T::SILENCE);
+        assert_eq!((T::MID.to_i32(), T::MID.to_f32()), (0, 0.0));
+        assert_eq!((T::from_i32(0), T::from_f32(0.0), T::from_i16(0)), (T::MID, T::MID, T::MID));
+        assert_eq!((min.to_i32(), min.to_f32()), (-32768, -1.0));
+        assert_eq!(max.to_i32(), max_i32);
+        assert!(max.to_f32() > 0.99 && max.to_f32() <= 1.0);
+        assert_eq!((T::from_i32(i32::MIN), T::from_i32(i32::MAX)), (min, max));
+        assert_eq!((T::from_f32(-2.0), T::from_f32(2.0)), (min, T::from_f32(1.0)));
+        assert_eq!((T::from_i32(min.to_i32()), T::from_i32(max.to_i32())), (min, max));
+        assert_eq!((max.scale(GAIN_ONE), max.scale(0), min.scale(0)), (max, T::MID, T::MID));
+        assert!(min.lerp(max, 0.5).to_i32().abs() <= 256);
+        assert_eq!(T::from_raw_bits(max.to_raw_bits()), max);
+    }
+
+    #[test]
+    fn every_format_agrees_on_silence_and_full_scale() {
+        check_format::<u8>(0, 0xff, 0x7f00);
+        check_format::<u16>(0, 0xffff, 0x7fff);
+        check_format::<i16>(i16::MIN, i16::MAX, 0x7fff);
+        check_format::<f32>(-1.0, 32767.0 / 32768.0, 0x7fff);
+        // u8 keeps the high byte of the signed 16-bit value
+        assert_eq!((u8::from_i32(0x4000), u8::from_i32(0x40ff), u8::from_i32(-1)), (0xc0, 0xc0, 0x7f));
+        assert_eq!((u8::from_i16(-0x4000), 0xc0u8.scale(GAIN_ONE / 2)), (0x40, 0xa0));
+        assert_eq!((0.5f32.to_f32(), 0xc000u16.to_f32(), 0x40i16.to_f32()), (0.5, 0.5, 0x40 as f32 / 32768.0));
+    }
+
+    #[test]
+    fn u8_devices_play_and_clear_around_their_own_midpoint() {
+        let mut device = MockDevice::<u8>::new(4, spec(1));
+        device.set_ramp_samples(0);
+        device.resume();
+        device.set_mode(PlayMode::Loop);
+        device.set_volume(MAX_VOLUME);
+        assert_eq!(device.drive_callback(2), [0x80; 2]);
+        device.set_data(0, &[0xc0, 0x40, 0xff, 0]);
+        device.rewind();
+        assert_eq!(device.drive_callback(4), [0xc0, 0x40, 0xff, 0]);
+        device.set_volume(MAX_VOLUME - 1);
+        assert_eq!(device.drive_callback(4), [0xa0, 0x60, 0xbf, 0x40]);
+        device.clear();
+        assert_eq!(device.drive_callback(2), [0x80; 2]);
+    }
+
+    #[test]
+    fn linear_gain_half_and_zero() {
+        let mut sound = instant::<u16>(3, 1);
+        sound.buffer.copy_from_slice(&[0, 0xc000, 0xffff]);
+        sound.mode = PlayMode::Loop;
+        sound.set_gain(128);
+        let mut out = [0u16; 3];
+        sound.callback(&mut out);
+        assert_eq!(out, [0x4000, 0xa000, 0xbfff]);
+        sound.set_gain(0);
+        sound.callback(&mut out);
+        assert_eq!(out, [SETUP_U16 as u16; 3]);
+    }
+
+    #[test]
+    fn gain_and_volume_clamp() {
+        let mut sound = instant::<u16>(1, 1);
+        sound.set_gain(1000);
+        assert_eq!(sound.gain, GAIN_ONE);
+        assert_eq!(0u16.scale(sound.gain), 0);
+        assert_eq!(0xffffu16.scale(sound.gain), 0xffff);
+        sound.set_volume(65535);
+        assert_eq!(sound.gain, GAIN_ONE);
+        assert_eq!(sound.volume(), MAX_VOLUME);
+        sound.set_gain(100);
+        assert_eq!(sound.volume(), 5);
+    }
+
+    #[test]
+    fn debug_summarizes_instead_of_dumping_the_buffer() {
+        let mut sound = instant::<u16>(96000, 2);
+        sound.set_volume(6);
+        sound.set_data_wrapping(0, &[1, 2, 3, 4, 5]);
+        sound.callback(&mut [0u16; 4]);
+        assert_eq!(
+            format!("{:?}", sound),
+            "Sound { freq: 48000, channels: 2, buf_size: 96000, head: [1, 2, 3, 4, ..95996 more], mode: Stream, \
+             volume: 6, mute: false, current: 4, remain: 1, called: 1, finished: false, poisoned: false }",
+        );
+        assert_eq!(sound.describe(), "48000Hz 2ch buf=96000 pos=4 vol=6 muted=false called=1");
+        let short = instant::<f32>(2, 1);
+        assert!(format!("{:?}", short).contains("head: [0.0, 0.0], mode"));
+    }
+
+    #[test]
+    fn contiguous_blocks_match_frame_by_frame_playback() {
+        let modes = [PlayMode::Stream, PlayMode::Loop, PlayMode::OneShot];
+        for seed in 0..300 {
+            let params = generators::white_noise(9, 0x7fff, seed + 1000);
+            let pick = |i: usize, n: usize| params[i] as usize % n;
+            let channels = 1 + pick(0, 2) as u8;
+            let len = 1 + pick(1, 40);
+            let data = generators::white_noise(len, 0x7fff, seed);
+            let make = || {
+                let mut sound = instant::<u16>(len, channels);
+                sound.mode = modes[pick(2, 3)];
+                sound.set_volume(pick(3, 8) as u16);
+                sound.limiter = [LimiterMode::Off, LimiterMode::Soft][pick(4, 2)];
+                sound.mute = pick(5, 4) == 0;
+                sound.set_data_wrapping(0, &data);
+                sound.remain = pick(6, sound.buf_size + 1);
+                sound.set_current(pick(7, len));
+                sound.set_loop_count([LoopCount::Infinite, LoopCount::Times(2)][pick(8, 2)]);
+                sound
+            };
+            let (mut frames, mut contiguous) = (make(), make());
+            for block in [7, 16, 1, 40] {
+                let mut expected = vec![0u16; block * channels as usize];
+                let mut out = expected.clone();
+                assert!(contiguous.is_simple_block(out.len()));
+                let starved = frames.render_frames(&mut expected);
+                assert_eq!(contiguous.render_contiguous(&mut out), starved, "seed {}", seed);
+                assert_eq!(out, expected, "seed {}", seed);
+                let state = |s: &Sound<u16>| (s.current, s.remain, s.finished, s.loops_left, s.consumed, s.played_frames);
+                assert_eq!(state(&contiguous), state(&frames), "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn master_volume_multiplies_the_gain() {
+        let mut sound = instant::<i16>(4, 1);
+        sound.buffer.copy_from_slice(&[1000, -1001, i16::MAX, i16::MIN]);
+        sound.mode = PlayMode::Loop;
+        sound.set_volume(MAX_VOLUME);
+        let mut out = [0i16; 4];
+        sound.callback(&mut out);
+        assert_eq!(out, [1000, -1001, i16::MAX, i16::MIN]);
+        sound.master_volume = 6;
+        sound.callback(&mut out);
+        assert_eq!(out, [500, -501, 16383, -16384]);
+        sound.set_gain(UNITY_GAIN / 2);
+        sound.callback(&mut out);
+        assert_eq!(out, [250, -251, 8191, -8192]);
+        sound.mute = true;
+        sound.callback(&mut out);
+        assert_eq!(out, [0; 4]);
+    }
+
+    #[test]
+    fn effects_run_on_signed_blocks_and_clamp() {
+        let mut sound = instant::<i16>(4, 1);
+        sound.buffer.copy_from_slice(&[1000, -1000, 20000, -20000]);
+        sound.mode = PlayMode::Loop;
+        sound.set_volume(MAX_VOLUME);
+        sound.set_effects(vec![Box::new(effects::Gain::new(2.0)), Box::new(effects::Gain::new(0.75))]);
+        // longer than one 512-frame block, so chunking is exercised
+        let mut out = vec![0i16; 1028];
+        sound.callback(&mut out);
+        // no clamping between stages
+        assert_eq!(out[1024..], [1500, -1500, 30000, -30000]);
+        sound.set_effects(vec![Box::new(effects::Gain::new(2.0))]);
+        sound.callback(&mut out[..4]);
+        assert_eq!(out[..4], [2000, -2000, i16::MAX, i16::MIN]);
+        sound.set_effects(Vec::new());
+        assert!(sound.effect_buf.is_empty());
+        sound.callback(&mut out[..4]);
+        assert_eq!(out[..4], [1000, -1000, 20000, -20000]);
+    }
+
+    #[test]
+    fn duration_len_rounds_up_to_frames_and_one_period() {
+        let spec = spec(2);
+        assert_eq!(duration_len(&spec, 0.1), 9600);
+        assert_eq!(duration_len(&spec, 0.10001), 9602);
+        assert_eq!(duration_len(&spec, 0.0), 1024);
+        let odd = AudioSpecInfo { freq: 44100, channels: 1, samples: 0 };
+        assert_eq!(duration_len(&odd, 0.0), 1);
+    }
+
+    #[test]
+    fn adopt_spec_keeps_the_sound() {
+        let mut sound = instant::<u16>(6, 2);
+        sound.buffer.copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        sound.set_pan(0.5);
+        sound.set_gain(100);
+        sound.current = 4;
+        sound.adopt_spec(AudioSpecInfo { freq: 44100, ..spec(2) });
+        assert_eq!((sound.spec.freq, sound.current, sound.gain()), (44100, 4, 100));
+        assert!(sound.pan.is_some());
+        sound.set_effects(vec![Box::new(effects::Gain::new(1.0))]);
+        sound.adopt_spec(AudioSpecInfo { samples: 64, ..spec(4) });
+        assert_eq!((sound.channels, sound.buf_size, sound.current), (4, 8, 4));
+        assert_eq!(sound.buffer, [1, 2, 3, 4, 5, 6, 0x8000, 0x8000]);
+        assert!(sound.pan.is_none());
+        assert_eq!(sound.effect_buf.len(), 256);
+    }
+
+    #[test]
+    fn wait_for_callback_wakes_on_the_next_block() {
+        let mut sound = instant::<u16>(8, 1);
+        let signal = sound.signal.clone();
+        assert_eq!(signal.wait(0, Duration::from_millis(1)), Err(Timeout));
+        let player = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            sound.callback(&mut [0u16; 4]);
+        });
+        assert_eq!(signal.wait(0, Duration::from_secs(5)), Ok(1));
+        player.join().unwrap();
+        assert_eq!(signal.wait(0, Duration::ZERO), Ok(1));
+    }
+
+    #[test]
+    fn counters_past_the_32_bit_range_keep_indexing_the_buffer() {
+        let mut sound = instant::<u16>(3, 1);
+        sound.set_volume(MAX_VOLUME);
+        sound.mode = PlayMode::Loop;
+        sound.buffer.copy_from_slice(&[1, 2, 3]);
+        // u32::MAX is a multiple of 3, so this is index 2 of the buffer
+        sound.current = u32::MAX as u64 - 1;
+        sound.called = u32::MAX as u64;
+        let mut out = [0u16; 4];
+        sound.callback(&mut out);
+        assert_eq!(out, [3, 1, 2, 3]);
+        assert_eq!(sound.current, u32::MAX as u64 + 3);
+        assert_eq!(sound.called, u32::MAX as u64 + 1);
+        sound.callback(&mut out);
+        assert_eq!(out, [1, 2, 3, 1]);
+
+        let signal = sound.signal.clone();
+        sound.reset_counters();
+        assert_eq!((sound.current, sound.called), (0, 0));
+        assert_eq!(signal.wait(u32::MAX as u64 + 2, Duration::ZERO), Ok(0));
+        sound.callback(&mut out);
+        assert_eq!(out, [1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn half_rate_interpolates_and_wraps_in_loop_mode() {
+        let mut sound = instant::<u16>(4, 1);
+        sound.set_volume(MAX_VOLUME);
+        sound.buffer.copy_from_slice(&[100, 200, 300, 500]);
+        sound.mode = PlayMode::Loop;
+        sound.set_rate(0.5);
+        let mut out = [0u16; 10];
+        sound.callback(&mut out);
+        assert_eq!(out, [100, 150, 200, 250, 300, 400, 500, 300, 100, 150]);
+        assert_eq!(sound.current, 5);
+        sound.set_rate(1.0);
+        assert_eq!(sound.frac, 0.0);
+    }
+
+    #[test]
+    fn double_rate_stops_at_the_write_cursor() {
+        let mut sound = instant::<u16>(8, 2);
+        sound.set_volume(MAX_VOLUME);
+        sound.write(&[1, 2, 3, 4, 5, 6]);
+        sound.set_rate(2.0);
+        let mut out = [0u16; 6];
+        sound.callback(&mut out);
+        assert_eq!(out, [1, 2, 5, 6, SETUP_U16 as u16, SETUP_U16 as u16]);
+        assert_eq!((sound.current, sound.remain, sound.underruns), (6, 0, 1));
+        sound.set_rate(100.0);
+        assert_eq!(sound.rate, MAX_RATE);
+    }
+
+    #[test]
+    fn reversed_one_shot_plays_a_descending_ramp_then_silence() {
+        let mut sound = instant::<u16>(8, 1);
+        sound.set_volume(MAX_VOLUME);
+        sound.buffer.copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        sound.mode = PlayMode::OneShot;
+        sound.reversed = true;
+        sound.set_current(7);
+        let mut out = [0u16; 10];
+        sound.callback(&mut out);
+        let silence = SETUP_U16 as u16;
+        assert_eq!(out, [7, 6, 5, 4, 3, 2, 1, 0, silence, silence]);
+        assert!(sound.finished);
+        sound.set_current(7);
+        sound.set_rate(2.0);
+        sound.callback(&mut out[..5]);
+        assert_eq!(out[..5], [7, 5, 3, 1, silence]);
+    }
+
+    #[test]
+    fn reversed_loop_wraps_to_the_last_frame() {
+        let mut sound = instant::<u16>(6, 2);
+        sound.set_volume(MAX_VOLUME);
+        sound.buffer.copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        sound.mode = PlayMode::Loop;
+        sound.reversed = true;
+        sound.set_current(2);
+        let mut out = [0u16; 8];
+        sound.callback(&mut out);
+        assert_eq!(out, [3, 4, 1, 2, 5, 6, 3, 4]);
+        assert_eq!(sound.current, 0);
+    }
+
+    #[test]
+    fn loop_region_plays_the_intro_once_and_jumps_mid_block() {
+        let mut sound = instant::<u16>(8, 1);
+        sound.set_volume(MAX_VOLUME);
+        sound.buffer.copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        sound.mode = PlayMode::Loop;
+        assert!(sound.set_loop_region(3, 3).is_err());
+        assert!(sound.set_loop_region(5, 2).is_err());
+        assert!(sound.set_loop_region(2, 9).is_err());
+        sound.set_loop_region(2, 5).unwrap();
+        let mut out = [0u16; 10];
+        sound.callback(&mut out);
+        assert_eq!(out, [0, 1, 2, 3, 4, 2, 3, 4, 2, 3]);
+        sound.reversed = true;
+        sound.callback(&mut out[..4]);
+        assert_eq!(out[..4], [4, 3, 2, 4]);
+        sound.loop_region = None;
+        sound.reversed = false;
+        sound.callback(&mut out[..6]);
+        assert_eq!(out[..6], [3, 4, 5, 6, 7, 0]);
+    }
+
+    #[test]
+    fn loop_count_plays_the_buffer_n_times() {
+        let mut sound = instant::<u16>(3, 1);
+        sound.set_volume(MAX_VOLUME);
+        sound.buffer.copy_from_slice(&[1, 2, 3]);
+        sound.mode = PlayMode::Loop;
+        sound.set_loop_count(LoopCount::Times(2));
+        let mut out = [0u16; 8];
+        sound.callback(&mut out);
+        let silence = SETUP_U16 as u16;
+        assert_eq!(out, [1, 2, 3, 1, 2, 3, silence, silence]);
+        assert!(sound.finished);
+        assert_eq!(sound.loops_left, LoopCount::Times(0));
+        sound.restart();
+        sound.callback(&mut out[..2]);
+        // mid-pass changes count from the next wrap on
+        sound.set_loop_count(LoopCount::Times(1));
+        sound.callback(&mut out[2..]);
+        assert_eq!(out, [1, 2, 3, silence, silence, silence, silence, silence]);
+        sound.set_loop_count(LoopCount::Times(0));
+        assert!(sound.finished);
+        // a new count brings a finished loop back
+        sound.set_loop_count(LoopCount::Times(1));
+        assert!(!sound.finished);
+        sound.callback(&mut out[..5]);
+        assert_eq!(out[..5], [1, 2, 3, silence, silence]);
+        assert!(sound.finished);
+    }
+
+    #[test]
+    fn queued_buffer_follows_without_a_gap() {
+        let mut sound = instant::<u16>(3, 1);
+        sound.set_volume(MAX_VOLUME);
+        sound.buffer.copy_from_slice(&[1, 2, 3]);
+        sound.mode = PlayMode::OneShot;
+        sound.next = Some((vec![7, 8], Transition::Gapless));
+        let mut out = [0u16; 6];
+        sound.callback(&mut out);
+        let silence = SETUP_U16 as u16;
+        assert_eq!(out, [1, 2, 3, 7, 8, silence]);
+        assert_eq!(sound.retired.as_deref(), Some(&[1, 2, 3][..]));
+        // a loop switches only after its last counted pass
+        sound.buffer = vec![1, 2].into();
+        sound.buf_size = 2;
+        sound.mode = PlayMode::Loop;
+        sound.set_loop_count(LoopCount::Times(2));
+        sound.restart();
+        sound.next = Some((vec![5, 6, 7], Transition::Gapless));
+        sound.callback(&mut out);
+        assert_eq!(out, [1, 2, 1, 2, 5, 6]);
+    }
+
+    #[test]
+    fn crossfade_mixes_the_end_into_the_start() {
+        let mut sound = instant::<u16>(6, 1);
+        sound.set_volume(MAX_VOLUME);
+        sound.buffer.copy_from_slice(&[1000; 6]);
+        sound.mode = PlayMode::OneShot;
+        sound.next = Some((vec![2000, 2000, 2000, 2000, 3000, 3000], Transition::Crossfade { samples: 4 }));
+        let mut out = [0u16; 8];
+        sound.callback(&mut out);
+        assert_eq!(out, [1000, 1000, 1000, 1250, 1500, 1750, 3000, 3000]);
+        assert_eq!(sound.current, 6);
+    }
+
+    #[test]
+    fn crossfade_data_ramps_into_and_out_of_the_new_data() {
+        let mut sound = instant::<i16>(10, 1);
+        sound.buffer.fill(300);
+        sound.crossfade_data(1, &[0; 8], 2, true);
+        assert_eq!(sound.buffer, [300, 200, 100, 0, 0, 0, 0, 100, 200, 300]);
+        assert_eq!(sound.remain, 8);
+        let mut stereo = instant::<i16>(8, 2);
+        stereo.buffer.fill(-900);
+        stereo.crossfade_data(6, &[0, 90, 0, 90, 0, 90], 4, false);
+        assert_eq!(stereo.buffer, [-300, -240, 0, 90, -900, -900, -600, -570]);
     }
 }
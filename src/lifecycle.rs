@@ -0,0 +1,110 @@
+use std::time::Instant;
+
+use crate::{AudioSpecInfo, EVENT_CAPACITY};
+
+/// Something that happened to a device, from `Control::poll_device_events`, for telling
+/// devices apart in logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceEvent {
+    /// The device's `label` at the time of the poll.
+    pub label: Option<String>,
+    /// When it happened; the callback takes the time itself.
+    pub at: Instant,
+    pub kind: DeviceEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEventKind {
+    /// SDL opened the device with this spec.
+    Opened(AudioSpecInfo),
+    /// A stream ran dry at `current` = `position`. Reported once until it is fed again;
+    /// `Control::underruns` counts every starved callback.
+    Underrun { position: u64 },
+    /// SDL stopped the device, noticed at the first poll after it happened.
+    Lost,
+    /// A panic was caught in the callback; the message is in `Control::panic_message`.
+    Poisoned,
+    RecordingStarted,
+    RecordingStopped,
+}
+
+/// Events waiting for `poll_device_events`. The callback appends to it without
+/// allocating or formatting anything, and the label is only attached on the control
+/// side; past `EVENT_CAPACITY` waiting events new ones are dropped.
+#[derive(Debug)]
+pub(crate) struct Lifecycle {
+    events: Vec<(Instant, DeviceEventKind)>,
+    // whether the last callback starved, so a run of them is one underrun
+    starving: bool,
+    lost: bool,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self { events: Vec::with_capacity(EVENT_CAPACITY), starving: false, lost: false }
+    }
+}
+
+impl Lifecycle {
+    pub(crate) fn note(&mut self, kind: DeviceEventKind) {
+        if self.events.len() < self.events.capacity() {
+            self.events.push((Instant::now(), kind));
+        }
+    }
+
+    /// Starts over on a newly opened (or reopened) device.
+    pub(crate) fn opened(&mut self, spec: AudioSpecInfo) {
+        self.lost = false;
+        self.starving = false;
+        self.note(DeviceEventKind::Opened(spec));
+    }
+
+    /// Called after every callback block with whether it starved.
+    pub(crate) fn underrun(&mut self, starved: bool, position: u64) {
+        if starved && !self.starving {
+            self.note(DeviceEventKind::Underrun { position });
+        }
+        self.starving = starved;
+    }
+
+    /// Notes once that the device is gone, when `alive` says so.
+    pub(crate) fn check_alive(&mut self, alive: bool) {
+        if !alive && !self.lost {
+            self.lost = true;
+            self.note(DeviceEventKind::Lost);
+        }
+    }
+
+    /// Everything waiting, oldest first, labelled; the capacity stays for the callback.
+    pub(crate) fn take(&mut self, label: Option<&str>) -> Vec<DeviceEvent> {
+        self.events
+            .drain(..)
+            .map(|(at, kind)| DeviceEvent { label: label.map(str::to_string), at, kind })
+            .collect()
+    }
+}
+
+/// Emits `event` to the current tracing subscriber, on the polling thread: the label,
+/// how long ago it happened in microseconds and the details, problems at `warn` and
+/// `error`, the rest at `info`.
+#[cfg(feature = "tracing")]
+pub(crate) fn trace(event: &DeviceEvent) {
+    let label = event.label.as_deref().unwrap_or("");
+    let age_us = event.at.elapsed().as_micros() as u64;
+    match event.kind {
+        DeviceEventKind::Opened(spec) => tracing::info!(
+            target: "audio_lib3",
+            label,
+            age_us,
+            freq = spec.freq,
+            channels = spec.channels,
+            samples = spec.samples,
+            "device opened"
+        ),
+        DeviceEventKind::Underrun { position } => tracing::warn!(target: "audio_lib3", label, age_us, position, "underrun"),
+        DeviceEventKind::Lost => tracing::warn!(target: "audio_lib3", label, age_us, "device lost"),
+        DeviceEventKind::Poisoned => tracing::error!(target: "audio_lib3", label, age_us, "callback poisoned"),
+        DeviceEventKind::RecordingStarted => tracing::info!(target: "audio_lib3", label, age_us, "recording started"),
+        DeviceEventKind::RecordingStopped => tracing::info!(target: "audio_lib3", label, age_us, "recording stopped"),
+    }
+}
@@ -0,0 +1,302 @@
+//! A whole `SoundBank` as one binary asset, so a build pipeline can cache processed
+//! sounds instead of decoding WAV files at startup. All numbers are little endian:
+//!
+//! - the magic `AL3B` and a `u16` format version
+//! - a `u32` entry count, then per entry: name length (`u32`) and UTF-8 name, sample
+//!   rate (`u32`), channels (`u16`), a loop flag (`u8`) with loop start and end
+//!   (`u64` each, 0 without loop points), and offset and length into the blob (`u64`
+//!   samples each)
+//! - the blob: a `u64` sample count, then the samples of every entry as `u16`s
+//!
+//! With the `serde` feature, `StoredSoundBank` holds the same manifest and blob for any
+//! serde format instead, the blob as bytes (a hex string in text formats like JSON)
+//! rather than a sequence of numbers.
+
+use std::io::{Read, Write};
+
+use crate::{BankError, SoundBank, SoundData16, SoundInfo};
+
+const MAGIC: &[u8; 4] = b"AL3B";
+const VERSION: u16 = 1;
+
+/// The entries of a stored bank, in the order their samples follow in the blob.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SoundBankManifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestEntry {
+    pub name: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub loop_region: Option<(usize, usize)>,
+    /// Where the entry's samples start in the blob, in samples.
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl ManifestEntry {
+    fn info(&self) -> SoundInfo {
+        SoundInfo { sample_rate: self.sample_rate, channels: self.channels, loop_region: self.loop_region }
+    }
+}
+
+/// Offset-binary samples stored as little-endian bytes; with the `serde` feature they
+/// serialize as one byte string, or as hex in human-readable formats.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SampleBlob(pub SoundData16);
+
+/// A whole bank as one value: what `to_manifest` writes, for a serde format instead.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StoredSoundBank {
+    pub manifest: SoundBankManifest,
+    pub samples: SampleBlob,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SampleBlob {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = self.0.iter().flat_map(|sample| sample.to_le_bytes());
+        if serializer.is_human_readable() {
+            let hex: String = bytes.flat_map(|byte| [HEX[byte as usize >> 4], HEX[byte as usize & 15]]).collect();
+            serializer.serialize_str(&hex)
+        } else {
+            serializer.serialize_bytes(&bytes.collect::<Vec<u8>>())
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SampleBlob {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let bytes = if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            let digit = |c: u8| (c as char).to_digit(16).ok_or_else(|| D::Error::custom("sample data is not hex"));
+            if !hex.len().is_multiple_of(2) {
+                return Err(D::Error::custom("sample data ends in half a byte"));
+            }
+            hex.as_bytes().chunks_exact(2).map(|pair| Ok((digit(pair[0])? << 4 | digit(pair[1])?) as u8)).collect::<Result<Vec<u8>, _>>()?
+        } else {
+            serde_bytes::ByteBuf::deserialize(deserializer)?.into_vec()
+        };
+        if !bytes.len().is_multiple_of(2) {
+            return Err(D::Error::custom("sample data ends in half a sample"));
+        }
+        Ok(SampleBlob(bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect()))
+    }
+}
+
+#[cfg(feature = "serde")]
+const HEX: [char; 16] = ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f'];
+
+impl SoundBank<String> {
+    /// The entries `to_manifest` writes, sorted by name.
+    pub fn manifest(&self) -> SoundBankManifest {
+        let mut names: Vec<&String> = self.keys().collect();
+        names.sort();
+        let mut offset = 0;
+        let entries = names.into_iter().map(|name| {
+            let (len, info) = (self.get(name).map_or(0, |data| data.len()), self.info(name).copied().unwrap_or_default());
+            let entry = ManifestEntry {
+                name: name.clone(),
+                sample_rate: info.sample_rate,
+                channels: info.channels,
+                loop_region: info.loop_region,
+                offset,
+                len,
+            };
+            offset += len;
+            entry
+        }).collect();
+        SoundBankManifest { entries }
+    }
+
+    /// The manifest and every entry's samples, e.g. to serialize with serde.
+    pub fn to_stored(&self) -> StoredSoundBank {
+        let manifest = self.manifest();
+        let mut samples = Vec::with_capacity(manifest.entries.iter().map(|entry| entry.len).sum());
+        for entry in &manifest.entries {
+            samples.extend(self.get(&entry.name).into_iter().flat_map(|data| data.iter()));
+        }
+        StoredSoundBank { manifest, samples: SampleBlob(samples) }
+    }
+
+    /// The inverse of `to_stored`.
+    pub fn from_stored(stored: &StoredSoundBank) -> Result<Self, BankError> {
+        let blob = &stored.samples.0;
+        let mut bank = SoundBank::new();
+        for entry in &stored.manifest.entries {
+            let data = entry.offset.checked_add(entry.len).and_then(|end| blob.get(entry.offset..end))
+                .ok_or(BankError::Malformed("an entry reaches past the sample data"))?;
+            bank.insert_with_info(entry.name.clone(), data, entry.info());
+        }
+        Ok(bank)
+    }
+
+    /// Writes the manifest and the blob of samples in the format described in the module
+    /// docs.
+    pub fn to_manifest(&self, mut writer: impl Write) -> Result<(), BankError> {
+        let manifest = self.manifest();
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(manifest.entries.len() as u32).to_le_bytes());
+        let mut samples = 0;
+        for entry in &manifest.entries {
+            out.extend_from_slice(&(entry.name.len() as u32).to_le_bytes());
+            out.extend_from_slice(entry.name.as_bytes());
+            out.extend_from_slice(&entry.sample_rate.to_le_bytes());
+            out.extend_from_slice(&entry.channels.to_le_bytes());
+            let (start, end) = entry.loop_region.unwrap_or((0, 0));
+            out.push(entry.loop_region.is_some() as u8);
+            for value in [start, end, entry.offset, entry.len] {
+                out.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+            samples += entry.len;
+        }
+        out.extend_from_slice(&(samples as u64).to_le_bytes());
+        out.reserve(samples * 2);
+        for entry in &manifest.entries {
+            for sample in self.get(&entry.name).into_iter().flat_map(|data| data.iter()) {
+                out.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        writer.write_all(&out)?;
+        Ok(())
+    }
+
+    /// Reads a bank written by `to_manifest`.
+    pub fn from_manifest(mut reader: impl Read) -> Result<Self, BankError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(truncated)?;
+        if &magic != MAGIC {
+            return Err(BankError::Malformed("not a sound bank"));
+        }
+        let version = u16::from_le_bytes(read_array(&mut reader)?);
+        if version != VERSION {
+            return Err(BankError::UnsupportedVersion(version));
+        }
+        let count = u32::from_le_bytes(read_array(&mut reader)?);
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let name_len = u32::from_le_bytes(read_array(&mut reader)?) as u64;
+            let mut name = Vec::new();
+            reader.by_ref().take(name_len).read_to_end(&mut name)?;
+            if name.len() as u64 != name_len {
+                return Err(BankError::Malformed("the file ends inside the manifest"));
+            }
+            let name = String::from_utf8(name).map_err(|_| BankError::Malformed("an entry name is not UTF-8"))?;
+            let sample_rate = u32::from_le_bytes(read_array(&mut reader)?);
+            let channels = u16::from_le_bytes(read_array(&mut reader)?);
+            let [looped] = read_array(&mut reader)?;
+            let mut values = [0; 4];
+            for value in values.iter_mut() {
+                *value = u64::from_le_bytes(read_array(&mut reader)?) as usize;
+            }
+            let [start, end, offset, len] = values;
+            let loop_region = (looped != 0).then_some((start, end));
+            entries.push(ManifestEntry { name, sample_rate, channels, loop_region, offset, len });
+        }
+        let samples = u64::from_le_bytes(read_array(&mut reader)?);
+        let mut blob = Vec::new();
+        reader.take(samples.saturating_mul(2)).read_to_end(&mut blob)?;
+        if blob.len() as u64 != samples.saturating_mul(2) {
+            return Err(BankError::Malformed("the file ends inside the sample data"));
+        }
+        let blob = blob.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        SoundBank::from_stored(&StoredSoundBank { manifest: SoundBankManifest { entries }, samples: SampleBlob(blob) })
+    }
+}
+
+fn truncated(err: std::io::Error) -> BankError {
+    if err.kind() == std::io::ErrorKind::UnexpectedEof {
+        BankError::Malformed("the file ends inside the manifest")
+    } else {
+        BankError::Io(err)
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], BankError> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes).map_err(truncated)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banks_round_trip_through_the_binary_format() {
+        let mut bank = SoundBank::new();
+        bank.insert_with_info("music".to_string(), vec![0x1234; 1000], SoundInfo {
+            sample_rate: 44100,
+            channels: 2,
+            loop_region: Some((200, 1000)),
+        });
+        bank.insert("blip".to_string(), vec![0x8000, 0xffff, 0]);
+        let mut bytes = Vec::new();
+        bank.to_manifest(&mut bytes).unwrap();
+        // the samples are stored as plain 16-bit words
+        assert!(bytes.len() < 1003 * 2 + 128);
+        let manifest = bank.manifest();
+        assert_eq!(manifest.entries[0].name, "blip");
+        assert_eq!((manifest.entries[1].offset, manifest.entries[1].len), (3, 1000));
+
+        let loaded = SoundBank::from_manifest(&bytes[..]).unwrap();
+        assert_eq!(loaded.manifest(), manifest);
+        assert_eq!(loaded.get("blip").map(|data| data.to_vec()), Some(vec![0x8000, 0xffff, 0]));
+        assert_eq!(loaded.info("music"), bank.info("music"));
+        assert!(loaded.get("music").unwrap().iter().all(|s| *s == 0x1234));
+    }
+
+    #[test]
+    fn damaged_banks_are_rejected() {
+        let mut bank = SoundBank::new();
+        bank.insert("blip".to_string(), vec![1, 2, 3]);
+        let mut bytes = Vec::new();
+        bank.to_manifest(&mut bytes).unwrap();
+        let err = SoundBank::from_manifest(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert!(matches!(err, BankError::Malformed(_)), "{}", err);
+        let err = SoundBank::from_manifest(&b"RIFF...."[..]).unwrap_err();
+        assert!(matches!(err, BankError::Malformed("not a sound bank")));
+        bytes[4] = 9;
+        assert!(matches!(SoundBank::from_manifest(&bytes[..]), Err(BankError::UnsupportedVersion(9))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn stored_banks_round_trip_through_serde() {
+        let mut bank = SoundBank::new();
+        bank.insert_with_info("music".to_string(), vec![0x1234; 1000], SoundInfo {
+            sample_rate: 44100,
+            channels: 2,
+            loop_region: Some((200, 1000)),
+        });
+        bank.insert("blip".to_string(), vec![0x8000, 0xffff, 0]);
+        let stored = bank.to_stored();
+
+        let bytes = bincode::serialize(&stored).unwrap();
+        // two bytes a sample, not a sequence of numbers
+        assert!(bytes.len() < 1003 * 2 + 128, "{}", bytes.len());
+        let decoded: StoredSoundBank = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, stored);
+
+        let json = serde_json::to_string(&stored).unwrap();
+        assert!(json.contains("\"samples\":\"0080ffff00003412"), "{}", &json[..200]);
+        let decoded: StoredSoundBank = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, stored);
+
+        let loaded = SoundBank::from_stored(&decoded).unwrap();
+        assert_eq!(loaded.manifest(), bank.manifest());
+        assert_eq!(loaded.get("blip").map(|data| data.to_vec()), Some(vec![0x8000, 0xffff, 0]));
+        assert_eq!(loaded.info("music"), bank.info("music"));
+        assert!(serde_json::from_str::<SampleBlob>("\"0080f\"").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{Sample, SETUP_U16};
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BlockLevel {
+    peak: u32,
+    sum_squares: u64,
+    samples: usize,
+}
+
+/// Peak and RMS of the last few callback blocks, measured on the final output.
+pub(crate) struct LevelMeter {
+    blocks: Vec<BlockLevel>,
+    next: usize,
+}
+
+impl LevelMeter {
+    pub(crate) fn new(window: usize) -> Self {
+        Self {
+            blocks: vec![BlockLevel::default(); window.max(1)],
+            next: 0,
+        }
+    }
+
+    pub(crate) fn window(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub(crate) fn measure<T: Sample>(&mut self, out: &[T]) {
+        let mut level = BlockLevel { samples: out.len(), ..Default::default() };
+        for sample in out {
+            let singed_sample = sample.to_i32();
+            level.peak = level.peak.max(singed_sample.unsigned_abs());
+            level.sum_squares += (singed_sample as i64 * singed_sample as i64) as u64;
+        }
+        self.blocks[self.next] = level;
+        self.next = (self.next + 1) % self.blocks.len();
+    }
+
+    /// The largest magnitude in the window, 1.0 being full scale.
+    pub(crate) fn peak(&self) -> f32 {
+        let peak = self.blocks.iter().map(|block| block.peak).max().unwrap_or(0);
+        (peak as f32 / SETUP_U16 as f32).min(1.0)
+    }
+
+    /// Root mean square over every sample in the window; a full-scale sine gives 0.707.
+    pub(crate) fn rms(&self) -> f32 {
+        let samples: usize = self.blocks.iter().map(|block| block.samples).sum();
+        if samples == 0 {
+            return 0.0;
+        }
+        let sum_squares: u64 = self.blocks.iter().map(|block| block.sum_squares).sum();
+        ((sum_squares as f64 / samples as f64).sqrt() / SETUP_U16 as f64).min(1.0) as f32
+    }
+}
@@ -0,0 +1,528 @@
+use std::sync::Arc;
+
+use sdl2::audio::{AudioCallback, AudioDevice};
+
+use crate::{soft_limit_i32, AudioSpecInfo, Envelope, EnvelopeState, LimiterMode, PlayMode, Sample, Sound, GAIN_ONE, MAX_VOLUME};
+
+/// What `MixerControl::play` does when every voice is busy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StealPolicy {
+    /// Don't play the new sound.
+    Reject,
+    /// Replace the one-shot voice that was started first.
+    #[default]
+    StealOldest,
+    /// Replace the one-shot voice with the lowest gain, the oldest one on a tie.
+    StealQuietest,
+}
+
+/// How `MixerControl::play_with` starts a sound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayOptions {
+    /// Coarse volume, clamped to `MAX_VOLUME`.
+    pub volume: u16,
+    /// Stereo pan from -1.0 (left) to 1.0 (right); `None` plays centered.
+    pub pan: Option<f32>,
+    /// Loops until stopped instead of playing once. A looping voice is never stolen.
+    pub looped: bool,
+}
+
+impl Default for PlayOptions {
+    fn default() -> Self {
+        Self { volume: MAX_VOLUME, pan: None, looped: false }
+    }
+}
+
+/// Refers to a sound started by `MixerControl::play`.
+///
+/// Once its voice is reused for another sound the handle goes stale: it reports
+/// finished and all calls through it are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoiceHandle {
+    voice: usize,
+    generation: u64,
+}
+
+impl VoiceHandle {
+    pub fn voice(&self) -> usize {
+        self.voice
+    }
+}
+
+/// Plays several `Sound` voices on one device, summing them with saturation.
+///
+/// Every voice behaves exactly like a single-sound device: it has its own buffer,
+/// position, volume, mute and play mode.
+pub struct Mixer {
+    voices: Vec<Sound<u16>>,
+    spec: AudioSpecInfo,
+    scratch: Vec<u16>,
+    mix: Vec<i32>,
+    called: u64,
+    policy: StealPolicy,
+    limiter: LimiterMode,
+    // bumped on every `play`; a voice's value is both its handle generation and its age
+    generations: Vec<u64>,
+    next_generation: u64,
+    // restarted on the voice whenever it (re)starts playing
+    envelopes: Vec<Option<Envelope>>,
+}
+
+pub type MixerDevice = AudioDevice<Mixer>;
+
+impl Mixer {
+    pub(crate) fn new(voices: usize, len: usize, spec: AudioSpecInfo, policy: StealPolicy) -> Self {
+        // one SDL callback block, so mixing never allocates
+        let block = (spec.samples as usize).max(1) * (spec.channels as usize).max(1);
+        Self {
+            voices: (0..voices).map(|_| Sound::new(len, spec)).collect(),
+            spec,
+            scratch: vec![u16::MID; block],
+            mix: vec![0; block],
+            called: 0,
+            policy,
+            limiter: LimiterMode::Off,
+            generations: vec![0; voices],
+            next_generation: 1,
+            envelopes: vec![None; voices],
+        }
+    }
+
+    fn is_busy(voice: &Sound<u16>) -> bool {
+        match voice.mode {
+            PlayMode::Stream => voice.remain > 0,
+            PlayMode::Loop | PlayMode::OneShot => !voice.finished,
+        }
+    }
+
+    fn pick_voice(&self) -> Option<usize> {
+        if let Some(free) = self.voices.iter().position(|v| !Self::is_busy(v)) {
+            return Some(free);
+        }
+        let one_shots = self.voices.iter().enumerate()
+            .filter(|(_, v)| v.mode == PlayMode::OneShot)
+            .map(|(i, v)| (i, v.gain, self.generations[i]));
+        match self.policy {
+            StealPolicy::Reject => None,
+            StealPolicy::StealOldest => one_shots.min_by_key(|(_, _, age)| *age).map(|(i, _, _)| i),
+            StealPolicy::StealQuietest => one_shots.min_by_key(|(_, gain, age)| (*gain, *age)).map(|(i, _, _)| i),
+        }
+    }
+
+    fn play(&mut self, data: &[u16]) -> Option<VoiceHandle> {
+        self.start(|sound| {
+            sound.replace_buffer(data.to_vec());
+        })
+    }
+
+    fn play_shared(&mut self, data: Arc<[u16]>) -> Option<VoiceHandle> {
+        self.start(|sound| {
+            sound.share_buffer(data);
+        })
+    }
+
+    fn play_with(&mut self, data: Arc<[u16]>, options: PlayOptions) -> Option<VoiceHandle> {
+        let handle = self.play_shared(data)?;
+        let sound = &mut self.voices[handle.voice];
+        sound.set_volume(options.volume);
+        sound.applied_gain = sound.target_gain();
+        sound.pan = None;
+        if let Some(pan) = options.pan {
+            sound.set_pan(pan);
+        }
+        if options.looped {
+            sound.mode = PlayMode::Loop;
+        }
+        Some(handle)
+    }
+
+    /// Starts a one-shot on a voice picked for it, after `load` has put its data in place.
+    fn start(&mut self, load: impl FnOnce(&mut Sound<u16>)) -> Option<VoiceHandle> {
+        let voice = self.pick_voice()?;
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        self.generations[voice] = generation;
+        let sound = &mut self.voices[voice];
+        load(sound);
+        sound.mode = PlayMode::OneShot;
+        sound.mute = false;
+        sound.gain = GAIN_ONE;
+        sound.applied_gain = sound.target_gain();
+        sound.set_current(0);
+        sound.envelope = self.envelopes[voice].map(EnvelopeState::new);
+        Some(VoiceHandle { voice, generation })
+    }
+
+    fn restart(&mut self, voice: usize) {
+        self.voices[voice].set_current(0);
+        self.voices[voice].envelope = self.envelopes[voice].map(EnvelopeState::new);
+    }
+
+    fn voice_of(&mut self, handle: VoiceHandle) -> Option<&mut Sound<u16>> {
+        if self.generations.get(handle.voice) == Some(&handle.generation) {
+            self.voices.get_mut(handle.voice)
+        } else {
+            None
+        }
+    }
+}
+
+pub trait MixerControl {
+    fn voices(&mut self) -> usize;
+    fn spec(&mut self) -> AudioSpecInfo;
+    fn set_voice_data(&mut self, voice: usize, offset: usize, sound: &[u16]);
+    fn set_voice_volume(&mut self, voice: usize, volume: u16);
+    fn set_voice_gain(&mut self, voice: usize, gain: u16);
+    fn set_voice_mute(&mut self, voice: usize, specifier: bool);
+    fn set_voice_mode(&mut self, voice: usize, mode: PlayMode);
+    fn set_voice_pan(&mut self, voice: usize, pan: f32);
+    fn restart_voice(&mut self, voice: usize);
+    fn voice_current(&mut self, voice: usize) -> u64;
+    fn voice_finished(&mut self, voice: usize) -> bool;
+    /// Shapes the voice with `envelope` from now on, restarting it with every `play` or
+    /// `restart_voice`. The voice holds at the sustain level until `release_voice`.
+    fn set_voice_envelope(&mut self, voice: usize, envelope: Option<Envelope>);
+    /// Starts the release of the voice's envelope; once it has run out the voice is
+    /// finished and free for `play`. Without an envelope this does nothing.
+    fn release_voice(&mut self, voice: usize);
+    fn called(&mut self) -> u64;
+    /// `Control::set_master_volume` for every voice at once; `play` keeps it.
+    fn set_master_volume(&mut self, volume: u16);
+    fn master_volume(&mut self) -> u16;
+    /// The sum of all voices is always hard clamped; `LimiterMode::Soft` adds a knee.
+    fn set_limiter(&mut self, mode: LimiterMode);
+    /// Starts `data` as a one-shot on a free voice at unity gain, stealing a busy
+    /// one-shot voice according to the device's `StealPolicy` when none is free.
+    /// Returns `None` when nothing could be freed up.
+    ///
+    /// The voice buffer is replaced by a copy of `data`.
+    fn play(&mut self, data: &[u16]) -> Option<VoiceHandle>;
+    /// `play` without copying: the voice reads straight from `data`, so any number of
+    /// voices can play one sample for the memory of a single copy. Writing to the voice,
+    /// e.g. with `set_voice_data`, first gives it a private copy (copy-on-write); `data`
+    /// itself is never changed. Data that isn't a whole number of frames is copied.
+    fn play_shared(&mut self, data: Arc<[u16]>) -> Option<VoiceHandle>;
+    /// `play_shared` with the volume, pan and looping of `options` applied before the
+    /// sound is heard.
+    fn play_with(&mut self, data: Arc<[u16]>, options: PlayOptions) -> Option<VoiceHandle>;
+    fn stop(&mut self, handle: VoiceHandle);
+    fn set_handle_volume(&mut self, handle: VoiceHandle, volume: u16);
+    /// `release_voice` for the voice still playing `handle`.
+    fn release(&mut self, handle: VoiceHandle);
+    /// True when the sound has played out, was stopped, or its voice was stolen.
+    fn handle_finished(&mut self, handle: VoiceHandle) -> bool;
+}
+
+impl MixerControl for MixerDevice {
+    fn voices(&mut self) -> usize {
+        let locked = self.lock();
+        locked.voices.len()
+    }
+
+    fn spec(&mut self) -> AudioSpecInfo {
+        let locked = self.lock();
+        locked.spec
+    }
+
+    fn set_voice_data(&mut self, voice: usize, offset: usize, sound: &[u16]) {
+        let mut locked = self.lock();
+        locked.voices[voice].set_data_wrapping(offset, sound);
+    }
+
+    fn set_voice_volume(&mut self, voice: usize, volume: u16) {
+        let mut locked = self.lock();
+        locked.voices[voice].set_volume(volume);
+    }
+
+    fn set_voice_gain(&mut self, voice: usize, gain: u16) {
+        let mut locked = self.lock();
+        locked.voices[voice].set_gain(gain);
+    }
+
+    fn set_voice_mute(&mut self, voice: usize, specifier: bool) {
+        let mut locked = self.lock();
+        locked.voices[voice].mute = specifier;
+    }
+
+    fn set_voice_mode(&mut self, voice: usize, mode: PlayMode) {
+        let mut locked = self.lock();
+        locked.voices[voice].mode = mode;
+    }
+
+    fn set_voice_pan(&mut self, voice: usize, pan: f32) {
+        let mut locked = self.lock();
+        locked.voices[voice].set_pan(pan);
+    }
+
+    fn restart_voice(&mut self, voice: usize) {
+        let mut locked = self.lock();
+        locked.restart(voice);
+    }
+
+    fn voice_current(&mut self, voice: usize) -> u64 {
+        let locked = self.lock();
+        locked.voices[voice].current
+    }
+
+    fn voice_finished(&mut self, voice: usize) -> bool {
+        let locked = self.lock();
+        locked.voices[voice].finished
+    }
+
+    fn set_voice_envelope(&mut self, voice: usize, envelope: Option<Envelope>) {
+        let mut locked = self.lock();
+        locked.envelopes[voice] = envelope;
+        locked.voices[voice].envelope = envelope.map(EnvelopeState::new);
+    }
+
+    fn release_voice(&mut self, voice: usize) {
+        let mut locked = self.lock();
+        if let Some(envelope) = locked.voices[voice].envelope.as_mut() {
+            envelope.release();
+        }
+    }
+
+    fn called(&mut self) -> u64 {
+        let locked = self.lock();
+        locked.called
+    }
+
+    fn set_master_volume(&mut self, volume: u16) {
+        let mut locked = self.lock();
+        for voice in locked.voices.iter_mut() {
+            voice.master_volume = volume.min(MAX_VOLUME);
+        }
+    }
+
+    fn master_volume(&mut self) -> u16 {
+        let locked = self.lock();
+        locked.voices.first().map_or(MAX_VOLUME, |voice| voice.master_volume)
+    }
+
+    fn set_limiter(&mut self, mode: LimiterMode) {
+        let mut locked = self.lock();
+        locked.limiter = mode;
+    }
+
+    fn play(&mut self, data: &[u16]) -> Option<VoiceHandle> {
+        let mut locked = self.lock();
+        locked.play(data)
+    }
+
+    fn play_shared(&mut self, data: Arc<[u16]>) -> Option<VoiceHandle> {
+        let mut locked = self.lock();
+        locked.play_shared(data)
+    }
+
+    fn play_with(&mut self, data: Arc<[u16]>, options: PlayOptions) -> Option<VoiceHandle> {
+        let mut locked = self.lock();
+        locked.play_with(data, options)
+    }
+
+    fn stop(&mut self, handle: VoiceHandle) {
+        let mut locked = self.lock();
+        if let Some(voice) = locked.voice_of(handle) {
+            voice.finished = true;
+        }
+    }
+
+    fn set_handle_volume(&mut self, handle: VoiceHandle, volume: u16) {
+        let mut locked = self.lock();
+        if let Some(voice) = locked.voice_of(handle) {
+            voice.set_volume(volume);
+        }
+    }
+
+    fn release(&mut self, handle: VoiceHandle) {
+        let mut locked = self.lock();
+        if let Some(envelope) = locked.voice_of(handle).and_then(|voice| voice.envelope.as_mut()) {
+            envelope.release();
+        }
+    }
+
+    fn handle_finished(&mut self, handle: VoiceHandle) -> bool {
+        let mut locked = self.lock();
+        locked.voice_of(handle).is_none_or(|voice| voice.finished)
+    }
+}
+
+impl AudioCallback for Mixer {
+    type Channel = u16;
+
+    fn callback(&mut self, out: &mut [u16]) {
+        let block = self.scratch.len();
+        for chunk in out.chunks_mut(block) {
+            let n = chunk.len();
+            let mix = &mut self.mix[..n];
+            mix.fill(0);
+            for voice in self.voices.iter_mut() {
+                voice.callback(&mut self.scratch[..n]);
+                for (acc, sample) in mix.iter_mut().zip(&self.scratch[..n]) {
+                    *acc += sample.to_i32();
+                }
+            }
+            for (dst, acc) in chunk.iter_mut().zip(mix.iter()) {
+                *dst = u16::from_i32(match self.limiter {
+                    LimiterMode::Off => *acc,
+                    LimiterMode::Soft => soft_limit_i32(*acc),
+                });
+            }
+        }
+        self.called += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SETUP_U16;
+
+    fn mixer(voices: usize, len: usize) -> Mixer {
+        let spec = AudioSpecInfo { freq: 48000, channels: 1, samples: 4 };
+        let mut mixer = Mixer::new(voices, len, spec, StealPolicy::StealOldest);
+        for voice in mixer.voices.iter_mut() {
+            voice.ramp_samples = 0;
+            voice.set_volume(7);
+        }
+        mixer
+    }
+
+    #[test]
+    fn loop_and_one_shots_play_together() {
+        let mut mixer = mixer(4, 4);
+        let offset = |v: i32| (v + SETUP_U16) as u16;
+        mixer.voices[0].mode = PlayMode::Loop;
+        mixer.voices[0].set_data_wrapping(0, &[offset(100), offset(-100), offset(100), offset(-100)]);
+        for voice in 1..4 {
+            mixer.voices[voice].mode = PlayMode::OneShot;
+            mixer.voices[voice].set_data_wrapping(0, &[offset(10); 2]);
+        }
+        let mut out = [0u16; 6];
+        mixer.callback(&mut out);
+        let signed: Vec<i32> = out.iter().map(|s| *s as i32 - SETUP_U16).collect();
+        assert_eq!(signed, [130, -70, 100, -100, 100, -100]);
+        assert!(mixer.voices[1..].iter().all(|v| v.finished));
+        assert!(!mixer.voices[0].finished);
+    }
+
+    #[test]
+    fn envelope_release_frees_a_held_voice() {
+        let mut mixer = mixer(1, 4);
+        let offset = |v: i32| (v + SETUP_U16) as u16;
+        mixer.envelopes[0] = Some(Envelope::new(0, 0, 1.0, 2));
+        let handle = mixer.play(&[offset(1000); 4]).unwrap();
+        // held note
+        mixer.voices[0].mode = PlayMode::Loop;
+        let mut out = [0u16; 6];
+        mixer.callback(&mut out);
+        assert!(out.iter().all(|s| *s == offset(1000)));
+        mixer.voice_of(handle).unwrap().envelope.as_mut().unwrap().release();
+        let mut out = [0u16; 4];
+        mixer.callback(&mut out);
+        assert_eq!(out, [offset(500), offset(0), offset(0), offset(0)]);
+        assert!(mixer.voices[0].finished);
+        assert!(mixer.play(&[offset(1); 4]).is_some());
+    }
+
+    #[test]
+    fn play_uses_free_voices_first() {
+        let mut mixer = mixer(2, 4);
+        let a = mixer.play(&[1; 4]).unwrap();
+        let b = mixer.play(&[2; 4]).unwrap();
+        assert_ne!(a.voice(), b.voice());
+        let mut out = [0u16; 4];
+        mixer.callback(&mut out);
+        // both played out, so the first slot is free again
+        let c = mixer.play(&[3; 4]).unwrap();
+        assert_eq!(c.voice(), 0);
+        assert!(mixer.voice_of(a).is_none());
+    }
+
+    #[test]
+    fn shared_voices_play_one_copy_of_the_data() {
+        let mut mixer = mixer(100, 4);
+        let offset = |v: i32| (v + SETUP_U16) as u16;
+        let data: Arc<[u16]> = vec![offset(1); 512 * 1024].into();
+        for _ in 0..100 {
+            mixer.play_shared(data.clone()).unwrap();
+        }
+        // each voice holds a reference, none a copy
+        assert_eq!(Arc::strong_count(&data), 101);
+        let mut out = [0u16; 4];
+        mixer.callback(&mut out);
+        assert!(out.iter().all(|s| *s == offset(100)));
+        // writing to a voice copies the data for it alone
+        mixer.voices[0].set_data_wrapping(0, &[offset(2); 4]);
+        assert_eq!(Arc::strong_count(&data), 100);
+        assert!(data.iter().all(|s| *s == offset(1)));
+    }
+
+    #[test]
+    fn play_with_applies_the_options() {
+        let mut mixer = mixer(2, 4);
+        let offset = |v: i32| (v + SETUP_U16) as u16;
+        let data: Arc<[u16]> = vec![offset(1000); 4].into();
+        let options = PlayOptions { volume: 6, looped: true, ..PlayOptions::default() };
+        let handle = mixer.play_with(data, options).unwrap();
+        let mut out = [0u16; 6];
+        mixer.callback(&mut out);
+        // half volume, and still going after the end of the data
+        assert!(out.iter().all(|s| *s == offset(500)));
+        assert_eq!(mixer.voice_of(handle).unwrap().mode, PlayMode::Loop);
+    }
+
+    #[test]
+    fn steal_oldest_makes_old_handles_inert() {
+        let mut mixer = mixer(2, 4);
+        let a = mixer.play(&[1; 4]).unwrap();
+        let b = mixer.play(&[2; 4]).unwrap();
+        let c = mixer.play(&[3; 4]).unwrap();
+        assert_eq!(c.voice(), a.voice());
+        assert!(mixer.voice_of(a).is_none());
+        assert!(mixer.voice_of(b).is_some());
+        assert!(mixer.voice_of(c).is_some());
+    }
+
+    #[test]
+    fn steal_quietest_and_reject() {
+        let mut mixer = mixer(2, 4);
+        mixer.policy = StealPolicy::StealQuietest;
+        let a = mixer.play(&[1; 4]).unwrap();
+        let b = mixer.play(&[2; 4]).unwrap();
+        mixer.voice_of(b).unwrap().set_volume(3);
+        let c = mixer.play(&[3; 4]).unwrap();
+        assert_eq!(c.voice(), b.voice());
+        assert!(mixer.voice_of(a).is_some());
+        mixer.policy = StealPolicy::Reject;
+        assert_eq!(mixer.play(&[4; 4]), None);
+    }
+
+    #[test]
+    fn looping_voices_are_never_stolen() {
+        let mut mixer = mixer(1, 4);
+        mixer.voices[0].mode = PlayMode::Loop;
+        assert_eq!(mixer.play(&[1; 4]), None);
+    }
+
+    #[test]
+    fn sum_saturates() {
+        let mut mixer = mixer(3, 2);
+        for voice in mixer.voices.iter_mut() {
+            voice.mode = PlayMode::Loop;
+            voice.set_data_wrapping(0, &[0xffff, 0]);
+        }
+        let mut out = [0u16; 2];
+        mixer.callback(&mut out);
+        assert_eq!(out, [0xffff, 0]);
+        mixer.limiter = LimiterMode::Soft;
+        mixer.callback(&mut out);
+        assert_eq!(out, [0xffff, 0]);
+        // a sum just over the knee is squashed below the plain sum
+        for voice in mixer.voices.iter_mut() {
+            voice.set_data_wrapping(0, &[(SETUP_U16 + 10000) as u16; 2]);
+        }
+        mixer.callback(&mut out);
+        let singed_sample = out[0] as i32 - SETUP_U16;
+        assert!(singed_sample > 24576 && singed_sample < 30000, "{}", singed_sample);
+    }
+}
@@ -0,0 +1,880 @@
+use std::ops::{Deref, DerefMut};
+
+use sdl2::audio::{AudioCallback, AudioStatus};
+
+use crate::{AudioSpecInfo, Sample, Sound};
+
+/// A `Control` implementation without SDL: the same `Sound` and callback as a real
+/// device, driven by hand with `drive_callback`.
+///
+/// Like a freshly opened device it starts paused; while paused or unplugged the
+/// callback isn't run and `drive_callback` returns silence.
+pub struct MockDevice<T: Sample = u16> {
+    sound: Sound<T>,
+    paused: bool,
+    alive: bool,
+}
+
+/// What `MockDevice::lock` hands out, standing in for `AudioDeviceLockGuard`.
+pub struct MockLock<'a, T: Sample>(&'a mut Sound<T>);
+
+impl<T: Sample> Deref for MockLock<'_, T> {
+    type Target = Sound<T>;
+
+    fn deref(&self) -> &Sound<T> {
+        self.0
+    }
+}
+
+impl<T: Sample> DerefMut for MockLock<'_, T> {
+    fn deref_mut(&mut self) -> &mut Sound<T> {
+        self.0
+    }
+}
+
+impl<T: Sample> MockDevice<T> {
+    pub fn new(len: usize, spec: AudioSpecInfo) -> Self {
+        Self {
+            sound: Sound::new(len, spec),
+            paused: true,
+            alive: true,
+        }
+    }
+
+    pub fn lock(&mut self) -> MockLock<'_, T> {
+        MockLock(&mut self.sound)
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn status(&self) -> AudioStatus {
+        match (self.alive, self.paused) {
+            (false, _) => AudioStatus::Stopped,
+            (true, true) => AudioStatus::Paused,
+            (true, false) => AudioStatus::Playing,
+        }
+    }
+
+    /// Simulates the device going away, as when it is unplugged.
+    pub fn unplug(&mut self) {
+        self.alive = false;
+    }
+
+    /// Runs one callback for `frames` frames and returns what it produced.
+    pub fn drive_callback(&mut self, frames: usize) -> Vec<T> {
+        let mut out = vec![T::SILENCE; frames * self.sound.channels];
+        self.drive_callback_into(&mut out);
+        out
+    }
+
+    /// `drive_callback` into a caller-provided buffer of interleaved samples.
+    pub fn drive_callback_into(&mut self, out: &mut [T]) {
+        if self.status() == AudioStatus::Playing {
+            self.sound.callback(out);
+        } else {
+            out.fill(T::SILENCE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::effects::Effect;
+    use crate::{AudioEvent, CommitPosition, Control, MutePolicy, PlayMode, ScheduleError, EVENT_CAPACITY, SCHEDULE_CAPACITY, SETUP_U16};
+    use crate::{generators, Frames, LfoTarget, LoopCount, PlaybackState, RecordingSink, RecordingStats, Transition, WriteWindow, MAX_VOLUME};
+
+    fn device(len: usize, channels: u8) -> MockDevice {
+        let mut device = MockDevice::new(len, AudioSpecInfo { freq: 48000, channels, samples: 4 });
+        device.set_ramp_samples(0);
+        device.resume();
+        device
+    }
+
+    // an effect that poisons whatever device runs it
+    struct Panics;
+
+    impl Effect for Panics {
+        fn process(&mut self, _: &mut [i32]) {
+            panic!("broken effect");
+        }
+    }
+
+    #[test]
+    fn plays_what_was_written_at_the_set_volume() {
+        let mut device = device(8, 1);
+        device.set_volume(6);
+        device.write(&[0xc000, 0x4000, 0xc000]);
+        assert_eq!(device.drive_callback(4), [0xa000, 0x6000, 0xa000, SETUP_U16 as u16]);
+        assert_eq!((device.current(), device.remain(), device.underruns()), (3, 0, 1));
+        device.set_mute(true);
+        device.set_mode(PlayMode::Loop);
+        assert_eq!(device.drive_callback(2), [SETUP_U16 as u16; 2]);
+        assert_eq!(device.called(), 2);
+        assert_eq!(device.samples_per_callback(), 4);
+    }
+
+    #[test]
+    fn wraps_around_the_buffer() {
+        let mut device = device(4, 2);
+        device.set_volume(7);
+        device.set_data(2, &[1, 2, 3, 4]);
+        device.set_current(2);
+        assert_eq!(device.drive_callback(2), [1, 2, 3, 4]);
+        assert_eq!(device.current(), 6);
+        assert_eq!(device.snapshot(), [3, 4, 1, 2]);
+        assert_eq!(device.take_consumed(), 4);
+        device.write(&[5, 6]);
+        device.drive_callback(3);
+        assert_eq!((device.take_consumed(), device.take_consumed()), (2, 0));
+    }
+
+    #[test]
+    fn paused_and_unplugged_devices_stay_silent() {
+        let mut device = device(4, 1);
+        device.set_volume(7);
+        device.set_mode(PlayMode::Loop);
+        device.fill(0xffff);
+        device.pause();
+        assert!(device.is_paused());
+        assert_eq!(device.drive_callback(2), [SETUP_U16 as u16; 2]);
+        assert_eq!(device.called(), 0);
+        device.resume();
+        assert_eq!(device.drive_callback(2), [0xffff; 2]);
+        device.unplug();
+        assert!(!device.is_alive());
+        assert_eq!(device.drive_callback(2), [SETUP_U16 as u16; 2]);
+    }
+
+    #[test]
+    fn staged_data_plays_only_after_commit() {
+        let mut device = device(4, 1);
+        device.set_volume(7);
+        device.set_mode(PlayMode::Loop);
+        device.fill(1);
+        assert!(!device.commit(CommitPosition::Keep));
+        device.stage_data(&[2, 3, 4, 5]);
+        assert_eq!(device.drive_callback(2), [1, 1]);
+        assert!(device.commit(CommitPosition::Keep));
+        assert_eq!(device.drive_callback(2), [4, 5]);
+        device.stage_data(&[6, 7, 8, 9]);
+        assert!(device.commit(CommitPosition::Restart));
+        assert_eq!(device.drive_callback(2), [6, 7]);
+        assert!(!device.commit(CommitPosition::Restart));
+    }
+
+    #[test]
+    fn scheduled_writes_land_on_the_exact_sample() {
+        let mut device = device(4, 1);
+        device.set_volume(7);
+        device.set_mode(PlayMode::Loop);
+        device.fill(1);
+        device.schedule_data(6, vec![2, 3]).unwrap();
+        device.schedule_data(5, vec![9]).unwrap();
+        assert_eq!(device.drive_callback(3), [1, 1, 1]);
+        assert_eq!(device.drive_callback(6), [1, 1, 9, 2, 3, 1]);
+        let err = device.schedule_data(8, vec![4]);
+        assert_eq!(err, Err(ScheduleError::InPast { at: 8, current: 9 }));
+        for at in 0..SCHEDULE_CAPACITY {
+            device.schedule_data(100 + at as u64, vec![5]).unwrap();
+        }
+        let err = device.schedule_data(200, vec![5]);
+        assert_eq!(err, Err(ScheduleError::QueueFull { capacity: SCHEDULE_CAPACITY }));
+    }
+
+    #[test]
+    fn markers_fire_on_the_frame_that_reaches_them() {
+        let mut device = device(8, 2);
+        device.set_mode(PlayMode::Loop);
+        device.notify_at(4, 1).unwrap();
+        device.notify_at(3, 2).unwrap();
+        device.drive_callback(2);
+        assert!(device.poll_events().is_empty());
+        device.drive_callback(2);
+        let events = device.poll_events();
+        assert_eq!(events, [
+            AudioEvent { tag: 2, position: 4, callback: 1 },
+            AudioEvent { tag: 1, position: 4, callback: 1 },
+        ]);
+        // already passed, so it fires straight away
+        device.notify_at(0, 3).unwrap();
+        device.drive_callback(1);
+        assert_eq!(device.poll_events(), [AudioEvent { tag: 3, position: 8, callback: 2 }]);
+        for tag in 0..EVENT_CAPACITY as u64 + 2 {
+            if device.notify_at(0, tag).is_err() {
+                device.drive_callback(1);
+                device.notify_at(0, tag).unwrap();
+            }
+        }
+        device.drive_callback(1);
+        assert_eq!(device.poll_events().len(), EVENT_CAPACITY);
+        assert_eq!(device.dropped_events(), 2);
+    }
+
+    #[test]
+    fn meters_a_full_scale_sine_after_volume() {
+        let mut device = device(480, 1);
+        device.set_volume(7);
+        device.set_mode(PlayMode::Loop);
+        device.set_data(0, &crate::generators::sine(1000.0, 48000, 480, u16::MAX));
+        device.drive_callback(480);
+        assert!((device.peak_level() - 1.0).abs() < 0.001);
+        assert!((device.rms_level() - 0.707).abs() < 0.001);
+        device.set_volume(6);
+        device.set_meter_window(2);
+        device.drive_callback(480);
+        assert!((device.peak_level() - 0.5).abs() < 0.001);
+        assert!((device.rms_level() - 0.354).abs() < 0.001);
+        device.set_mute(true);
+        device.drive_callback(480);
+        assert!((device.rms_level() - 0.25).abs() < 0.001);
+        device.drive_callback(480);
+        assert_eq!((device.peak_level(), device.rms_level()), (0.0, 0.0));
+    }
+
+    #[test]
+    fn timing_stats_only_count_while_enabled() {
+        let mut device = device(8, 1);
+        device.drive_callback(4);
+        let stats = device.callback_stats();
+        assert_eq!((stats.measured, stats.period_us), (0, 83));
+        device.set_timing_enabled(true);
+        device.drive_callback(4);
+        device.drive_callback(4);
+        let stats = device.callback_stats();
+        assert_eq!(stats.measured, 2);
+        assert!(stats.min_us <= stats.max_us && stats.avg_us <= stats.max_us as f64);
+        device.reset_stats();
+        assert_eq!(device.callback_stats().measured, 0);
+        device.set_timing_enabled(false);
+        device.drive_callback(4);
+        assert_eq!(device.callback_stats().measured, 0);
+    }
+
+    #[test]
+    fn muted_playback_advances_by_default() {
+        let mut device = device(4, 1);
+        device.set_volume(7);
+        device.set_mode(PlayMode::OneShot);
+        device.set_data(0, &[1, 2, 3, 4]);
+        assert_eq!(device.drive_callback(1), [1]);
+        device.set_mute(true);
+        assert_eq!(device.drive_callback(2), [SETUP_U16 as u16; 2]);
+        assert_eq!(device.current(), 3);
+        device.set_mute(false);
+        assert_eq!(device.drive_callback(2), [4, SETUP_U16 as u16]);
+        assert!(device.finished());
+    }
+
+    #[test]
+    fn held_playback_resumes_where_it_was_muted() {
+        let mut device = device(4, 1);
+        device.set_volume(7);
+        device.set_mode(PlayMode::OneShot);
+        device.set_mute_policy(MutePolicy::Hold);
+        device.set_data(0, &[1, 2, 3, 4]);
+        assert_eq!(device.drive_callback(1), [1]);
+        device.set_mute(true);
+        // far longer than what is left of the one-shot
+        assert_eq!(device.drive_callback(8), [SETUP_U16 as u16; 8]);
+        assert_eq!((device.current(), device.called()), (1, 2));
+        assert!(!device.finished());
+        device.set_mute(false);
+        assert_eq!(device.drive_callback(4), [2, 3, 4, SETUP_U16 as u16]);
+        assert!(device.finished());
+    }
+
+    #[test]
+    fn a_panicking_effect_poisons_the_device_into_silence() {
+        struct Broken(Arc<AtomicUsize>);
+        impl Effect for Broken {
+            fn process(&mut self, _: &mut [i32]) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                panic!("broken effect");
+            }
+        }
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut device = device(4, 1);
+        device.set_volume(7);
+        device.set_mode(PlayMode::Loop);
+        device.fill(0xc000);
+        assert_eq!(device.drive_callback(2), [0xc000; 2]);
+        assert!(!device.is_poisoned());
+        device.set_effects(vec![Box::new(Broken(runs.clone()))]);
+        assert_eq!(device.drive_callback(2), [SETUP_U16 as u16; 2]);
+        assert!(device.is_poisoned());
+        assert_eq!(device.panic_message().as_deref(), Some("broken effect"));
+        assert_eq!(device.drive_callback(2), [SETUP_U16 as u16; 2]);
+        assert_eq!((runs.load(Ordering::Relaxed), device.called()), (1, 3));
+    }
+
+    #[test]
+    fn with_locked_applies_a_batch_under_one_lock() {
+        let mut device = device(4, 1);
+        device.set_mode(PlayMode::Loop);
+        let remain = device.with_locked(|state| {
+            state.set_data(0, &[1, 2, 3, 4]);
+            state.set_volume(7);
+            state.set_current(2);
+            state.remain()
+        });
+        assert_eq!(remain, 4);
+        assert_eq!(device.drive_callback(3), [3, 4, 1]);
+        assert_eq!(device.with_locked(|state| (state.current(), state.buf_samples())), (5, 4));
+    }
+
+    #[test]
+    fn set_data_from_iter_wraps_like_set_data() {
+        let mut device = device(600, 2);
+        let written = device.set_data_from_iter(501, (0..).map(|i| i as u16), 1000);
+        assert_eq!(written, 600);
+        let mut expected = vec![0u16; 600];
+        for (pos, sample) in (500..).zip(0..600u16) {
+            expected[pos % 600] = sample;
+        }
+        assert_eq!(device.snapshot(), expected);
+        assert_eq!(device.remain(), 600);
+        // a short iterator ends the write early
+        assert_eq!(device.set_data_from_iter(0, [7, 8, 9].into_iter(), 10), 3);
+        assert_eq!(device.snapshot()[..4], [7, 8, 9, expected[3]]);
+    }
+
+    #[test]
+    fn describe_reports_the_device_in_one_line() {
+        let mut device = device(96000, 2);
+        assert_eq!(device.describe(), "48000Hz 2ch buf=96000 pos=0 vol=0 muted=false called=0");
+        device.set_volume(7);
+        device.set_mute(true);
+        device.write(&[0x9000; 12345 * 2]);
+        device.drive_callback(12345 * 2);
+        assert_eq!(device.describe(), "48000Hz 2ch buf=96000 pos=24690 vol=7 muted=true called=1");
+    }
+
+    #[test]
+    fn typed_positions_count_stereo_frames() {
+        let mut device = device(8, 2);
+        device.set_volume(MAX_VOLUME);
+        device.set_mode(PlayMode::Loop);
+        // frame 5 wraps to frame 1, samples 2 and 3
+        device.set_data_at(Frames(5), &[0x9000, 0xa000]);
+        assert_eq!(device.snapshot()[2..4], [0x9000, 0xa000]);
+        device.drive_callback(3);
+        assert_eq!((device.current(), device.position()), (6, Frames(3)));
+        device.set_position(Frames(1));
+        assert_eq!(device.current(), 2);
+        assert_eq!(device.drive_callback(1), [0x9000, 0xa000]);
+    }
+
+    #[test]
+    fn recording_captures_exactly_what_was_played() {
+        let mut device = device(64, 2);
+        device.set_volume(6);
+        device.set_mode(PlayMode::Loop);
+        device.set_data(0, &generators::white_noise(64, 0x7fff, 7));
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        assert_eq!(device.start_recording(RecordingSink::Memory(recorded.clone())).unwrap(), None);
+        let mut played = Vec::new();
+        for frames in [4, 30, 1, 17] {
+            played.extend(device.drive_callback(frames));
+        }
+        let stats = device.stop_recording().unwrap();
+        assert_eq!(stats, RecordingStats { samples_written: 104, dropped_blocks: 0 });
+        assert_eq!(*recorded.lock().unwrap(), played);
+        // a WAV file holds the same samples
+        let path = std::env::temp_dir().join(format!("audio-lib3-recording-{}.wav", std::process::id()));
+        // starting over hands back the stats of the recording it replaces
+        device.start_recording(RecordingSink::Memory(Arc::default())).unwrap();
+        device.drive_callback(3);
+        let replaced = device.start_recording(RecordingSink::Wav(path.clone())).unwrap();
+        assert_eq!(replaced, Some(RecordingStats { samples_written: 6, dropped_blocks: 0 }));
+        let played = device.drive_callback(20);
+        device.stop_recording().unwrap();
+        let loaded = crate::wav::load_wav(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!((loaded.sample_rate, loaded.channels), (48000, 2));
+        assert_eq!(loaded.data, played);
+        assert_eq!(device.stop_recording().unwrap(), RecordingStats::default());
+    }
+
+    #[test]
+    fn recording_clamps_full_scale_float_samples() {
+        let mut device = MockDevice::<f32>::new(4, AudioSpecInfo { freq: 48000, channels: 1, samples: 4 });
+        device.set_ramp_samples(0);
+        device.set_volume(MAX_VOLUME);
+        device.set_mode(PlayMode::Loop);
+        device.set_data(0, &[1.0, -1.0, 0.5, 0.0]);
+        device.resume();
+        let path = std::env::temp_dir().join(format!("audio-lib3-recording-f32-{}.wav", std::process::id()));
+        device.start_recording(RecordingSink::Wav(path.clone())).unwrap();
+        device.drive_callback(4);
+        device.stop_recording().unwrap();
+        let bytes = std::fs::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        // +1.0 is one past i16::MAX, and must not wrap around to -32768
+        let samples: Vec<i16> = bytes.unwrap()[44..].chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+        assert_eq!(samples, [32767, -32768, 16384, 0]);
+    }
+
+    #[test]
+    fn recording_drops_blocks_the_writer_has_no_room_for() {
+        let mut device = device(8, 1);
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        device.start_recording(RecordingSink::Memory(recorded.clone())).unwrap();
+        // more than the half second the recording can hold at once
+        device.drive_callback(30000);
+        device.drive_callback(100);
+        let stats = device.stop_recording().unwrap();
+        assert_eq!(stats, RecordingStats { samples_written: 100, dropped_blocks: 1 });
+        assert_eq!(recorded.lock().unwrap().len(), 100);
+    }
+
+    #[test]
+    fn tremolo_follows_a_sinusoid() {
+        let mut device = device(16, 1);
+        device.set_mode(PlayMode::Loop);
+        device.fill(0xc000);
+        device.set_volume(MAX_VOLUME);
+        let plain = device.drive_callback(480);
+        device.set_lfo(LfoTarget::Volume, 0.0, 0.5);
+        assert_eq!(device.drive_callback(480), plain);
+        device.set_lfo(LfoTarget::Volume, 150.0, 0.5);
+        // 0.5 depth dips to 50% and back, 320 frames per cycle at 48 kHz
+        for (i, sample) in device.drive_callback(960).iter().enumerate() {
+            let phase = std::f64::consts::TAU * 150.0 * i as f64 / 48000.0;
+            let expected = 16384.0 * (1.0 - 0.25 * (1.0 - phase.sin()));
+            assert!((sample.to_i32() as f64 - expected).abs() <= 1.0, "frame {}: {} vs {}", i, sample.to_i32(), expected);
+        }
+        device.set_lfo(LfoTarget::Volume, 150.0, 0.0);
+        assert_eq!(device.drive_callback(480), plain);
+    }
+
+    #[test]
+    fn vibrato_swings_the_read_position() {
+        let mut device = device(48000, 1);
+        device.set_mode(PlayMode::Loop);
+        device.set_lfo(LfoTarget::Rate, 100.0, 0.5);
+        // a quarter cycle ahead by depth * period / 2pi, then level again after a whole one
+        device.drive_callback(120);
+        assert!((157..=159).contains(&device.current()), "{}", device.current());
+        device.drive_callback(360);
+        assert!((479..=481).contains(&device.current()), "{}", device.current());
+        device.set_lfo(LfoTarget::Rate, 100.0, 0.0);
+        let at = device.current();
+        device.drive_callback(100);
+        assert_eq!(device.current(), at + 100);
+    }
+
+    #[test]
+    fn fast_forward_skips_segments_and_fades_across_the_seam() {
+        let mut device = device(4096, 1);
+        device.set_mode(PlayMode::Loop);
+        device.set_volume(MAX_VOLUME);
+        let ramp: Vec<u16> = (0..4096).map(|i| i * 8).collect();
+        device.set_data(0, &ramp);
+        device.take_consumed();
+        // segments of 64 frames, each followed by a skip of 192
+        device.set_fast_forward(4);
+        let out = device.drive_callback(128);
+        assert_eq!(out[..32], ramp[..32]);
+        for (i, sample) in out[32..64].iter().enumerate() {
+            assert!((ramp[32 + i]..=ramp[32 + i + 192]).contains(sample));
+        }
+        assert_eq!(out[64..96], ramp[256..288]);
+        assert_eq!((device.current(), device.take_consumed()), (512, 512));
+        device.set_fast_forward(1);
+        assert_eq!(device.drive_callback(100), ramp[512..612]);
+        assert_eq!(device.take_consumed(), 100);
+    }
+
+    #[test]
+    fn latency_counts_unplayed_data_and_one_block() {
+        // 4-frame blocks at 48 kHz; 480 stereo frames written are 10 ms
+        let mut device = device(4800, 2);
+        assert_eq!(device.samples_per_callback(), 8);
+        let block = Duration::from_secs_f64(4.0 / 48000.0);
+        assert_eq!(device.latency(), block);
+        device.write(&[0x8000; 960]);
+        assert_eq!(device.latency(), Duration::from_secs_f64(484.0 / 48000.0));
+        device.drive_callback(80);
+        assert_eq!(device.latency(), Duration::from_secs_f64(404.0 / 48000.0));
+        device.set_rate(2.0);
+        assert_eq!(device.latency(), Duration::from_secs_f64(204.0 / 48000.0));
+        device.set_mode(PlayMode::Loop);
+        assert_eq!(device.latency(), block);
+        let at = device.current();
+        assert_eq!(device.latency_to(at + 200), Duration::from_secs_f64(54.0 / 48000.0));
+        assert_eq!(device.latency_to(0), block);
+    }
+
+    #[test]
+    fn every_transition_follows_the_table() {
+        use PlaybackState::*;
+        let in_state = |state| {
+            let mut device = device(4, 1);
+            device.set_volume(MAX_VOLUME);
+            device.fill(0xc000);
+            device.set_mode(PlayMode::Loop);
+            device.set_current(2);
+            match state {
+                Playing => (),
+                Paused => Control::pause(&mut device),
+                Stopped => Control::stop(&mut device),
+                Finished => {
+                    device.set_mode(PlayMode::OneShot);
+                    device.drive_callback(5);
+                }
+                DeviceLost => device.unplug(),
+                Poisoned => {
+                    device.set_effects(vec![Box::new(Panics)]);
+                    device.drive_callback(1);
+                }
+            }
+            assert_eq!(device.state(), state);
+            device
+        };
+        type Request = fn(&mut MockDevice);
+        let requests: [(&str, Request); 4] = [
+            ("pause", |d| Control::pause(d)),
+            ("resume", |d| Control::resume(d)),
+            ("restart", |d| Control::restart(d)),
+            ("stop", |d| Control::stop(d)),
+        ];
+        for from in [Playing, Paused, Finished, Stopped, DeviceLost, Poisoned] {
+            for (name, request) in requests {
+                let expected = match (from, name) {
+                    (Playing, "pause") => Paused,
+                    (Paused | Stopped, "resume") => Playing,
+                    (Finished | Stopped, "restart") => Playing,
+                    (Playing | Paused | Finished, "stop") => Stopped,
+                    (from, _) => from,
+                };
+                let mut device = in_state(from);
+                request(&mut device);
+                assert_eq!(device.state(), expected, "{:?} then {}", from, name);
+                let rewound = from == Stopped || name == "restart" || name == "stop";
+                let before = device.current();
+                let played = device.drive_callback(1);
+                match expected {
+                    Playing => {
+                        assert_eq!(played, [0xc000], "{:?} then {}", from, name);
+                        assert_eq!(before, if rewound { 0 } else { 2 }, "{:?} then {}", from, name);
+                    }
+                    _ => assert_eq!(played, [SETUP_U16 as u16], "{:?} then {}", from, name),
+                }
+                if matches!(expected, Paused | Stopped | DeviceLost) {
+                    assert_eq!(device.current(), before, "{:?} then {}", from, name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_prerolled_device_starts_once_enough_is_written() {
+        let mut device = device(8, 1);
+        device.set_volume(MAX_VOLUME);
+        device.resume_when_filled(4);
+        assert_eq!(device.state(), PlaybackState::Paused);
+        assert_eq!(device.write(&[0xc000, 0xc001]), 2);
+        assert!(device.is_paused());
+        assert_eq!(device.drive_callback(2), [SETUP_U16 as u16; 2]);
+        assert_eq!(device.current(), 0);
+        device.push_data(&[0xc002, 0xc003]);
+        assert_eq!(device.state(), PlaybackState::Playing);
+        assert_eq!(device.drive_callback(4), [0xc000, 0xc001, 0xc002, 0xc003]);
+
+        // an explicit resume cancels the wait
+        device.resume_when_filled(100);
+        Control::resume(&mut device);
+        Control::pause(&mut device);
+        device.write(&[0xc000; 8]);
+        assert!(device.is_paused());
+    }
+
+    #[test]
+    fn drain_plays_out_what_was_written_then_finishes() {
+        let mut stream = device(16, 1);
+        stream.set_volume(MAX_VOLUME);
+        stream.write(&[0xc000; 6]);
+        stream.drain();
+        // written after the drain, so never played
+        stream.write(&[0x9000; 2]);
+        assert_eq!(stream.state(), PlaybackState::Playing);
+        assert_eq!(stream.drive_callback(4), [0xc000; 4]);
+        assert_eq!(stream.drive_callback(4), [0xc000, 0xc000, SETUP_U16 as u16, SETUP_U16 as u16]);
+        assert_eq!(stream.state(), PlaybackState::Finished);
+        assert_eq!(stream.underruns(), 0);
+        assert_eq!((stream.current(), stream.remain()), (6, 2));
+
+        // a loop ends with the pass in progress and keeps its loop count
+        let mut looped = device(4, 1);
+        looped.set_volume(MAX_VOLUME);
+        looped.set_data(0, &[0xc000, 0xc001, 0xc002, 0xc003]);
+        looped.set_mode(PlayMode::Loop);
+        looped.drive_callback(6);
+        looped.drain();
+        assert_eq!(looped.drive_callback(4), [0xc002, 0xc003, SETUP_U16 as u16, SETUP_U16 as u16]);
+        assert_eq!(looped.state(), PlaybackState::Finished);
+        assert_eq!(looped.remaining_loops(), LoopCount::Times(0));
+        Control::restart(&mut looped);
+        assert_eq!(looped.remaining_loops(), LoopCount::Infinite);
+        assert_eq!(looped.drive_callback(6), [0xc000, 0xc001, 0xc002, 0xc003, 0xc000, 0xc001]);
+
+        // nothing pending: finished and paused at once
+        let mut idle = device(8, 1);
+        idle.drain();
+        assert!(idle.is_paused());
+        assert_eq!(idle.state(), PlaybackState::Finished);
+        assert_eq!(idle.drain_blocking(Duration::from_millis(1)), Ok(()));
+    }
+
+    #[test]
+    fn set_volume_percent_goes_through_the_active_curve() {
+        use crate::VolumeCurve;
+        let mut device = device(4, 1);
+        device.set_volume_percent(100.0);
+        assert_eq!(device.volume(), MAX_VOLUME);
+        device.set_volume_curve(VolumeCurve::Logarithmic { floor_db: -48.0 });
+        device.set_volume_percent(50.0);
+        // -24 dB is a little under a 16th of full scale
+        device.write(&[0xc000]);
+        let played = device.drive_callback(1)[0];
+        let expected = 0x8000 as f32 + 0x4000 as f32 * 10f32.powf(-1.2);
+        assert!((played as f32 - expected).abs() <= 1.0, "{:#x}", played);
+        device.set_volume_percent(0.0);
+        assert_eq!(device.volume_db(), f32::NEG_INFINITY);
+        assert!(matches!(device.volume_curve(), VolumeCurve::Logarithmic { .. }));
+    }
+
+    #[test]
+    fn lifecycle_events_are_queued_by_the_callback_and_labelled_when_polled() {
+        use crate::DeviceEventKind;
+        let mut device = device(8, 1);
+        device.set_label("sfx");
+        assert_eq!(device.label().as_deref(), Some("sfx"));
+        assert!(device.describe().starts_with("sfx: 48000Hz 1ch"));
+        device.write(&[0xc000; 2]);
+        // three starved callbacks in a row are one underrun, and a fed one ends it
+        for _ in 0..3 {
+            device.drive_callback(4);
+        }
+        device.write(&[0xc000; 4]);
+        device.drive_callback(4);
+        device.drive_callback(4);
+        device.start_recording(RecordingSink::Memory(Arc::default())).unwrap();
+        device.stop_recording().unwrap();
+        device.set_effects(vec![Box::new(Panics)]);
+        device.drive_callback(1);
+        device.unplug();
+        let events = device.poll_device_events();
+        assert!(events.iter().all(|event| event.label.as_deref() == Some("sfx")));
+        assert!(events.windows(2).all(|w| w[0].at <= w[1].at));
+        let kinds: Vec<_> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, [
+            DeviceEventKind::Underrun { position: 2 },
+            DeviceEventKind::Underrun { position: 6 },
+            DeviceEventKind::RecordingStarted,
+            DeviceEventKind::RecordingStopped,
+            DeviceEventKind::Poisoned,
+            DeviceEventKind::Lost,
+        ]);
+        assert!(device.poll_device_events().is_empty());
+    }
+
+    #[test]
+    fn write_windows_stay_clear_of_the_next_callback() {
+        let mut device = device(16, 2);
+        device.set_mode(PlayMode::Loop);
+        device.set_volume(MAX_VOLUME);
+        device.fill(0x9000);
+        device.set_current(2);
+        // the next callback reads samples 2..10, the margin rounds up to a frame
+        let window = device.write_window(1);
+        assert_eq!(window, WriteWindow { offset: 12, len: 6, buf_size: 16 });
+        assert_eq!(window.parts(), (12..16, 0..2));
+        assert_eq!(device.write_into_window(window, &[0xc000; 10]), 6);
+        let mut expected = [0xc000u16; 16];
+        expected[2..12].fill(0x9000);
+        assert_eq!(device.snapshot(), expected);
+        assert!(device.drive_callback(5).iter().all(|s| *s == 0x9000));
+        assert!(device.drive_callback(3).iter().all(|s| *s == 0xc000));
+
+        // at double speed a callback reads the whole buffer
+        device.set_rate(2.0);
+        assert_eq!(device.write_window(0).len, 0);
+        device.set_rate(1.0);
+        let stale = device.write_window(0);
+        device.replace_buffer(vec![0x9000; 32]);
+        assert_eq!(device.write_into_window(stale, &[0xc000; 4]), 0);
+    }
+
+    #[test]
+    fn odd_length_queued_data_is_padded_before_the_callback_sees_it() {
+        let mut device = device(4, 2);
+        device.set_mode(PlayMode::OneShot);
+        device.set_volume(MAX_VOLUME);
+        device.set_data(0, &[0x9000, 0xa000, 0xb000, 0xc000]);
+        assert_eq!(device.queue_next(vec![0xd000, 0xe000, 0xf000], Transition::Gapless), None);
+        let silence = SETUP_U16 as u16;
+        // half a frame would shift the channels, so it is filled out with silence
+        assert_eq!(device.lock().next.as_ref().map(|(next, _)| next.len()), Some(4));
+        assert_eq!(device.drive_callback(4), [0x9000, 0xa000, 0xb000, 0xc000, 0xd000, 0xe000, 0xf000, silence]);
+        assert_eq!(device.snapshot(), [0xd000, 0xe000, 0xf000, silence]);
+        // nothing at all still makes a frame
+        device.restart();
+        device.queue_next(Vec::new(), Transition::Gapless);
+        assert_eq!(device.lock().next.as_ref().map(|(next, _)| next.len()), Some(2));
+    }
+
+    #[test]
+    fn cleared_buffer_plays_silence() {
+        let mut device = device(4, 1);
+        device.set_volume(MAX_VOLUME);
+        device.write(&[1, 2, 3, 4]);
+        assert_eq!(device.drive_callback(2), [1, 2]);
+        device.clear();
+        // what was still pending plays on, as silence
+        assert_eq!(device.drive_callback(2), [SETUP_U16 as u16; 2]);
+        assert_eq!((device.called(), device.remain(), device.underruns()), (2, 0, 0));
+    }
+
+    #[test]
+    fn resize_buffer_keeps_the_front_and_pads_the_rest() {
+        let mut device = device(4, 1);
+        device.set_data(0, &[1, 2, 3, 4]);
+        assert_eq!(device.resize_buffer(6), [1, 2, 3, 4]);
+        assert_eq!(device.snapshot(), [1, 2, 3, 4, SETUP_U16 as u16, SETUP_U16 as u16]);
+        device.resize_buffer(2);
+        assert_eq!((device.snapshot(), device.buf_samples()), (vec![1, 2], 2));
+    }
+
+    #[test]
+    fn every_buffer_change_bumps_the_generation() {
+        let mut device = device(8, 1);
+        let mut last = device.generation();
+        let mut bumped = |device: &mut MockDevice, what: &str| {
+            let now = device.generation();
+            assert!(now > last, "{} left the generation at {}", what, now);
+            last = now;
+        };
+        device.set_data(0, &[0xc000; 2]);
+        bumped(&mut device, "set_data");
+        device.write(&[0xc000]);
+        bumped(&mut device, "write");
+        device.fill(0x9000);
+        bumped(&mut device, "fill");
+        device.clear();
+        bumped(&mut device, "clear");
+        device.replace_buffer(vec![0x9000; 8]);
+        bumped(&mut device, "replace_buffer");
+        device.with_locked(|state| state.fill(0xa000));
+        bumped(&mut device, "with_locked");
+        // the callback applies this one, and publishes it to handles
+        let handle = device.control_handle();
+        device.schedule_data(1, vec![0xb000]).unwrap();
+        let before = device.generation();
+        device.drive_callback(2);
+        bumped(&mut device, "a scheduled write");
+        assert_eq!(handle.generation(), device.generation());
+        assert_eq!(device.playback_status().generation, device.generation());
+
+        let mut out = [0u16; 2];
+        let generation = device.read_consistent(0, &mut out);
+        assert_eq!((generation, out), (device.generation(), [0xa000, 0xb000]));
+        assert!(generation > before);
+        // reading and playing leave it alone
+        device.snapshot();
+        device.drive_callback(2);
+        assert_eq!(device.generation(), generation);
+    }
+
+    #[test]
+    fn input_rate_converts_to_the_device_rate() {
+        let mut device = MockDevice::new(22050, AudioSpecInfo { freq: 44100, channels: 1, samples: 512 });
+        device.set_ramp_samples(0);
+        device.resume();
+        device.set_volume(MAX_VOLUME);
+        device.set_mode(PlayMode::Loop);
+        device.set_data(0, &generators::sine(1000.0, 22050, 22050, 0x4000));
+        device.set_input_rate(22050);
+        assert_eq!(device.input_rate(), 22050);
+        // one second at the device rate plays the whole second of data once
+        let out = device.drive_callback(44100);
+        let rising = out.windows(2).filter(|w| w[0] < 0x8000 && w[1] >= 0x8000).count();
+        assert!((999..=1001).contains(&rising), "{} cycles", rising);
+        assert_eq!(device.current(), 22050);
+
+        // the device's own rate bypasses the conversion entirely
+        device.set_input_rate(44100);
+        device.rewind();
+        let direct = device.drive_callback(8);
+        assert_eq!(direct, device.snapshot()[..8]);
+        device.set_input_rate(0);
+        assert_eq!(device.input_rate(), 44100);
+    }
+
+    #[test]
+    fn fades_last_their_duration_and_report_when_done() {
+        use crate::FADE_DONE_TAG;
+        let mut device = device(4, 1);
+        device.set_volume(MAX_VOLUME);
+        device.set_mode(PlayMode::Loop);
+        device.fill(0xc000);
+        // 10 ms at 48 kHz is 480 frames, played in blocks of 4
+        device.fade_out(Duration::from_millis(10));
+        let mut played = Vec::new();
+        while device.is_fading() {
+            played.extend(device.drive_callback(4));
+        }
+        assert!(played.len().abs_diff(480) <= 4, "{} frames", played.len());
+        assert!(played.windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(*played.last().unwrap(), SETUP_U16 as u16);
+        assert_eq!(device.volume(), 0);
+        let events = device.poll_events();
+        assert_eq!(events.len(), 1);
+        // from the fade's last frame, the first at the target volume
+        assert_eq!((events[0].tag, events[0].position), (FADE_DONE_TAG, 479));
+
+        // even steps in decibels: halfway through a fade from -60 dB is at -30 dB
+        device.fade_in(Duration::from_millis(10));
+        let up: Vec<u16> = (0..60).flat_map(|_| device.drive_callback(4)).collect();
+        let level = (up[239] - 0x8000) as f32 / 0x4000 as f32;
+        assert!((20.0 * level.log10() + 30.0).abs() < 0.5, "{}", level);
+
+        // a new fade carries on from the current gain instead of jumping
+        device.fade_out(Duration::from_millis(10));
+        let next = device.drive_callback(1)[0];
+        assert!(next.abs_diff(up[239]) <= up[239].abs_diff(up[238]) + 1, "{:#x} after {:#x}", next, up[239]);
+        device.set_volume(3);
+        assert!(!device.is_fading());
+        assert!(device.poll_events().is_empty());
+    }
+
+    #[test]
+    fn a_fade_started_mid_ramp_carries_on_from_the_ramp() {
+        let mut device = device(4, 1);
+        device.set_ramp_samples(128);
+        device.set_master_volume(6);
+        device.set_mode(PlayMode::Loop);
+        device.fill(0xc000);
+        device.set_volume(MAX_VOLUME);
+        // halfway up the volume ramp
+        let before = device.drive_callback(64)[63];
+        assert!(before > 0x8800 && before < 0x9800, "{:#x}", before);
+        device.fade_out(Duration::from_millis(10));
+        let after = device.drive_callback(4);
+        let step = |a: u16, b: u16| a.abs_diff(b);
+        assert!(step(before, after[0]) < 0x80, "{:#x} after {:#x}", after[0], before);
+        assert!(after.windows(2).all(|w| w[0] >= w[1]));
+    }
+}
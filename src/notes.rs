@@ -0,0 +1,240 @@
+//! Note names and MIDI numbers as frequencies, and `Jingle` for rendering short tunes
+//! offline with the `generators` waveforms.
+//!
+//! Pitches use twelve-tone equal temperament with A4 (MIDI note 69) at 440 Hz. Octaves
+//! follow scientific pitch notation: C4 is middle C, MIDI note 60.
+
+use std::time::Duration;
+
+use crate::{generators, NoteError, Sample, SoundData16};
+
+/// How long each note of a `Jingle` fades in and out by default.
+pub const DEFAULT_FADE: Duration = Duration::from_millis(5);
+
+pub fn midi_to_freq(note: u8) -> f32 {
+    440.0 * 2f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+/// Parses names like `C4`, `c#4`, `Eb3` or `A-1`: a letter, any number of `#` or `b`,
+/// and an octave from -1 to 9, as long as the note stays within MIDI's 0..=127.
+pub fn name_to_midi(name: &str) -> Result<u8, NoteError> {
+    let mut chars = name.chars();
+    let letter = chars.next().ok_or(NoteError::Empty)?;
+    let semitone: i32 = match letter.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return Err(NoteError::UnknownLetter(letter)),
+    };
+    let rest = chars.as_str();
+    let octave_at = rest.find(|c| c != '#' && c != 'b').unwrap_or(rest.len());
+    let (accidentals, octave) = rest.split_at(octave_at);
+    let shift: i32 = accidentals.chars().map(|c| if c == '#' { 1 } else { -1 }).sum();
+    if octave.is_empty() {
+        return Err(NoteError::MissingOctave(name.to_string()));
+    }
+    let octave: i32 = octave.parse().ok().filter(|o| (-1..=9).contains(o))
+        .ok_or_else(|| NoteError::InvalidOctave(name.to_string()))?;
+    let midi = (octave + 1) * 12 + semitone + shift;
+    u8::try_from(midi).ok().filter(|m| *m <= 127).ok_or_else(|| NoteError::OutOfRange(name.to_string()))
+}
+
+pub fn name_to_freq(name: &str) -> Result<f32, NoteError> {
+    name_to_midi(name).map(midi_to_freq)
+}
+
+/// The generator a `Jingle` note is played with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Sine,
+    /// The fraction of each period spent high, as in `generators::square`.
+    Square(f32),
+    Triangle,
+    Sawtooth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Segment {
+    // None for a rest
+    tone: Option<(f32, Waveform)>,
+    duration: Duration,
+}
+
+/// A sequence of notes and rests rendered back to back into one mono buffer:
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use audio_lib3::notes::{Jingle, Waveform};
+///
+/// let ms = Duration::from_millis;
+/// let data = Jingle::new()
+///     .named("C5", ms(200), Waveform::Square(0.5))?
+///     .named("E5", ms(200), Waveform::Square(0.5))?
+///     .named("G5", ms(400), Waveform::Square(0.5))?
+///     .render(48000);
+/// # Ok::<(), audio_lib3::NoteError>(())
+/// ```
+///
+/// Every note fades in and out over `fade` (`DEFAULT_FADE`, at most half the note), so
+/// the tune doesn't click where notes meet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jingle {
+    segments: Vec<Segment>,
+    amplitude: u16,
+    fade: Duration,
+}
+
+impl Default for Jingle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jingle {
+    pub fn new() -> Self {
+        Self { segments: Vec::new(), amplitude: i16::MAX as u16 / 2, fade: DEFAULT_FADE }
+    }
+
+    /// Peak deviation from the midpoint for every note; half of full scale by default.
+    pub fn amplitude(mut self, amplitude: u16) -> Self {
+        self.amplitude = amplitude;
+        self
+    }
+
+    pub fn fade(mut self, fade: Duration) -> Self {
+        self.fade = fade;
+        self
+    }
+
+    pub fn note(mut self, freq_hz: f32, duration: Duration, waveform: Waveform) -> Self {
+        self.segments.push(Segment { tone: Some((freq_hz, waveform)), duration });
+        self
+    }
+
+    /// `note` by name, see `name_to_midi`.
+    pub fn named(self, name: &str, duration: Duration, waveform: Waveform) -> Result<Self, NoteError> {
+        Ok(self.note(name_to_freq(name)?, duration, waveform))
+    }
+
+    pub fn rest(mut self, duration: Duration) -> Self {
+        self.segments.push(Segment { tone: None, duration });
+        self
+    }
+
+    /// How long the rendered jingle plays.
+    pub fn duration(&self) -> Duration {
+        self.segments.iter().map(|segment| segment.duration).sum()
+    }
+
+    /// Renders the whole sequence as mono offset-binary samples. Note boundaries are
+    /// rounded from the start of the jingle, so long tunes don't drift off the beat.
+    pub fn render(&self, sample_rate: u32) -> SoundData16 {
+        let at = |time: Duration| (time.as_secs_f64() * sample_rate as f64).round() as usize;
+        let fade = at(self.fade);
+        let mut data = Vec::with_capacity(at(self.duration()));
+        let mut elapsed = Duration::ZERO;
+        for segment in &self.segments {
+            let start = at(elapsed);
+            elapsed += segment.duration;
+            let len = at(elapsed) - start;
+            let Some((freq, waveform)) = segment.tone else {
+                data.resize(data.len() + len, u16::MID);
+                continue;
+            };
+            let amplitude = self.amplitude;
+            let mut tone = match waveform {
+                Waveform::Sine => generators::sine(freq, sample_rate, len, amplitude),
+                Waveform::Square(duty) => generators::square(freq, sample_rate, len, amplitude, duty),
+                Waveform::Triangle => generators::triangle(freq, sample_rate, len, amplitude),
+                Waveform::Sawtooth => generators::sawtooth(freq, sample_rate, len, amplitude),
+            };
+            apply_fade(&mut tone, fade.min(len / 2));
+            data.extend(tone);
+        }
+        data
+    }
+}
+
+/// Linear ramps over the first and last `fade` samples, from and to silence.
+fn apply_fade(tone: &mut [u16], fade: usize) {
+    let len = tone.len();
+    for i in 0..fade {
+        let factor = i as f64 / fade as f64;
+        for at in [i, len - 1 - i] {
+            tone[at] = u16::from_i32((tone[at].to_i32() as f64 * factor).round() as i32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SETUP_U16;
+
+    fn crossings(data: &[u16]) -> usize {
+        let signed: Vec<i32> = data.iter().map(|s| *s as i32 - SETUP_U16).collect();
+        signed.windows(2).filter(|pair| pair[0] < 0 && pair[1] >= 0).count()
+    }
+
+    #[test]
+    fn names_and_midi_numbers_map_to_frequencies() {
+        assert_eq!(midi_to_freq(69), 440.0);
+        assert!((midi_to_freq(60) - 261.6256).abs() < 1e-3);
+        assert_eq!(name_to_midi("C4"), Ok(60));
+        assert_eq!(name_to_midi("c#4"), Ok(61));
+        assert_eq!(name_to_midi("Db4"), Ok(61));
+        assert_eq!(name_to_midi("B#3"), Ok(60));
+        assert_eq!(name_to_midi("C-1"), Ok(0));
+        assert_eq!(name_to_midi("G9"), Ok(127));
+        assert_eq!(name_to_freq("A5"), Ok(880.0));
+    }
+
+    #[test]
+    fn invalid_names_say_what_is_wrong() {
+        assert_eq!(name_to_midi(""), Err(NoteError::Empty));
+        assert_eq!(name_to_midi("H4"), Err(NoteError::UnknownLetter('H')));
+        assert_eq!(name_to_midi("C#"), Err(NoteError::MissingOctave("C#".to_string())));
+        assert_eq!(name_to_midi("C4x"), Err(NoteError::InvalidOctave("C4x".to_string())));
+        assert_eq!(name_to_midi("C10"), Err(NoteError::InvalidOctave("C10".to_string())));
+        assert_eq!(name_to_midi("Cb-1"), Err(NoteError::OutOfRange("Cb-1".to_string())));
+        assert_eq!(name_to_midi("G#9"), Err(NoteError::OutOfRange("G#9".to_string())));
+        assert_eq!(NoteError::UnknownLetter('H').to_string(), "unknown note letter 'H'");
+    }
+
+    #[test]
+    fn jingle_segments_play_their_notes_without_clicks() {
+        let ms = Duration::from_millis;
+        let jingle = Jingle::new()
+            .named("C5", ms(200), Waveform::Square(0.5)).unwrap()
+            .named("E5", ms(200), Waveform::Sine).unwrap()
+            .rest(ms(50))
+            .named("G5", ms(250), Waveform::Triangle).unwrap();
+        let data = jingle.render(48000);
+        assert_eq!(data.len(), 48000 * 700 / 1000);
+        let bounds = [0, 9600, 19200, 21600, 33600];
+        for (segment, expected) in [(0, 523.25), (1, 659.26), (3, 783.99)] {
+            let part = &data[bounds[segment]..bounds[segment + 1]];
+            let seconds = part.len() as f32 / 48000.0;
+            let measured = crossings(part) as f32 / seconds;
+            assert!((measured - expected).abs() < 10.0, "segment {}: {} Hz", segment, measured);
+        }
+        assert!(data[19200..21600].iter().all(|s| *s == SETUP_U16 as u16));
+        // where one note ends and the next begins, the level is close to the midpoint
+        for at in &bounds[1..4] {
+            let jump = (data[*at] as i32 - data[*at - 1] as i32).abs();
+            assert!(jump < 1000, "jump of {} at {}", jump, at);
+        }
+    }
+
+    #[test]
+    fn fades_fit_short_notes() {
+        let data = Jingle::new().fade(Duration::from_secs(1)).note(1000.0, Duration::from_millis(1), Waveform::Square(0.5)).render(8000);
+        assert_eq!(data.len(), 8);
+        assert_eq!(data[0], SETUP_U16 as u16);
+        assert!(data.iter().any(|s| *s != SETUP_U16 as u16));
+    }
+}
@@ -0,0 +1,55 @@
+//! `Control::on_callback`: a thread of its own sleeps on the signal every callback
+//! already sends for `wait_for_callback`, and runs the observer when it fires. The
+//! callback only timestamps and notifies; it never waits on the observer or runs it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::time::Instant;
+
+use crate::CallbackSignal;
+
+/// What `Control::on_callback`'s observer is told about a callback that just ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallbackInfo {
+    /// The `called` count including this callback.
+    pub called: u64,
+    /// Buffer samples the callback has taken in total, across all blocks so far.
+    pub consumed: u64,
+    /// When the callback finished, taken on the audio thread.
+    pub at: Instant,
+}
+
+/// The device's end of an observer thread; dropping it lets the thread finish.
+pub(crate) struct Observer {
+    signal: Arc<CallbackSignal>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Observer {
+    /// Starts calling `f` for every callback after the one numbered `ticks`.
+    pub(crate) fn start(signal: Arc<CallbackSignal>, ticks: u64, mut f: impl FnMut(CallbackInfo) + Send + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let (signal, stop) = (signal.clone(), stop.clone());
+            std::thread::spawn(move || {
+                let mut seen = ticks;
+                while let Some((ticks, info)) = signal.wait_tick(seen, &stop) {
+                    seen = ticks;
+                    f(info);
+                }
+            });
+        }
+        Self { signal, stop }
+    }
+}
+
+/// Never joins: the device may be dropped from inside code the observer waits on, so
+/// the thread finishes on its own once its current call returns.
+impl Drop for Observer {
+    fn drop(&mut self) {
+        // under the signal's lock, so the thread can't miss it between check and sleep
+        let _last = self.signal.last.lock().unwrap_or_else(PoisonError::into_inner);
+        self.stop.store(true, Ordering::Release);
+        self.signal.ready.notify_all();
+    }
+}
@@ -0,0 +1,203 @@
+//! Headerless PCM in any of the common sample encodings, to and from offset-binary `u16`.
+
+use crate::generators::Rng;
+use crate::{PcmError, Sample, SoundData16};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmSample {
+    U8,
+    I8,
+    I16,
+    /// Offset binary, the crate's own representation.
+    U16,
+    /// -1.0..=1.0; anything outside is clamped on load.
+    F32,
+}
+
+impl PcmSample {
+    pub fn bytes(self) -> usize {
+        match self {
+            PcmSample::U8 | PcmSample::I8 => 1,
+            PcmSample::I16 | PcmSample::U16 => 2,
+            PcmSample::F32 => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample: PcmSample,
+    pub endian: Endian,
+    pub channels: u8,
+}
+
+impl PcmFormat {
+    pub fn new(sample: PcmSample, endian: Endian, channels: u8) -> Self {
+        Self { sample, endian, channels }
+    }
+
+    pub fn frame_bytes(&self) -> usize {
+        self.sample.bytes() * self.channels as usize
+    }
+}
+
+fn decode(bytes: &[u8], format: &PcmFormat) -> u16 {
+    let word = |b: [u8; 2]| match format.endian {
+        Endian::Little => u16::from_le_bytes(b),
+        Endian::Big => u16::from_be_bytes(b),
+    };
+    match format.sample {
+        PcmSample::U8 => (bytes[0] as u16) << 8,
+        PcmSample::I8 => u16::from_i16((bytes[0] as i8 as i16) << 8),
+        PcmSample::I16 => u16::from_i16(word([bytes[0], bytes[1]]) as i16),
+        PcmSample::U16 => word([bytes[0], bytes[1]]),
+        PcmSample::F32 => {
+            let b = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            let sample = match format.endian {
+                Endian::Little => f32::from_le_bytes(b),
+                Endian::Big => f32::from_be_bytes(b),
+            };
+            u16::from_i32((sample.clamp(-1.0, 1.0) * 32768.0).round() as i32)
+        }
+    }
+}
+
+fn encode(sample: u16, format: &PcmFormat, out: &mut Vec<u8>) {
+    let word = |w: u16| match format.endian {
+        Endian::Little => w.to_le_bytes(),
+        Endian::Big => w.to_be_bytes(),
+    };
+    match format.sample {
+        PcmSample::U8 => out.push((sample >> 8) as u8),
+        PcmSample::I8 => out.push((sample.to_i32() >> 8) as i8 as u8),
+        PcmSample::I16 => out.extend_from_slice(&word(sample.to_i32() as u16)),
+        PcmSample::U16 => out.extend_from_slice(&word(sample)),
+        PcmSample::F32 => {
+            let sample = sample.to_i32() as f32 / 32768.0;
+            out.extend_from_slice(&match format.endian {
+                Endian::Little => sample.to_le_bytes(),
+                Endian::Big => sample.to_be_bytes(),
+            });
+        }
+    }
+}
+
+/// Converts interleaved raw PCM to offset-binary samples.
+pub fn load_raw(bytes: &[u8], format: PcmFormat) -> Result<SoundData16, PcmError> {
+    if format.channels == 0 {
+        return Err(PcmError::ZeroChannels);
+    }
+    if !bytes.len().is_multiple_of(format.frame_bytes()) {
+        return Err(PcmError::TrailingBytes { len: bytes.len(), frame_bytes: format.frame_bytes() });
+    }
+    Ok(bytes.chunks_exact(format.sample.bytes()).map(|b| decode(b, &format)).collect())
+}
+
+/// The inverse of `load_raw`. 8-bit encodings drop the low byte.
+pub fn to_raw(data: &[u16], format: PcmFormat) -> Result<Vec<u8>, PcmError> {
+    if format.channels == 0 {
+        return Err(PcmError::ZeroChannels);
+    }
+    if !data.len().is_multiple_of(format.channels as usize) {
+        return Err(PcmError::PartialFrame { len: data.len(), channels: format.channels });
+    }
+    let mut bytes = Vec::with_capacity(data.len() * format.sample.bytes());
+    for sample in data {
+        encode(*sample, &format, &mut bytes);
+    }
+    Ok(bytes)
+}
+
+/// Rounds `value`, in 16-bit steps, to an offset-binary sample after adding TPDF dither:
+/// the sum of two uniform values within half a step, so it spans ±1 LSB and averages to
+/// zero. Values past full scale clamp rather than wrap.
+pub(crate) fn dither_to_u16(value: f64, rng: &mut Rng) -> u16 {
+    let noise = (rng.next_signed() + rng.next_signed()) * 0.5;
+    u16::from_i32((value + noise).round().clamp(i16::MIN as f64, i16::MAX as f64) as i32)
+}
+
+/// -1.0..=1.0 samples to offset binary with TPDF dither, so quiet fades turn into a little
+/// noise instead of a staircase. The same `seed` always gives the same output.
+pub fn from_f32_dithered(data: &[f32], seed: u64) -> SoundData16 {
+    let mut rng = Rng(seed);
+    data.iter().map(|sample| dither_to_u16(sample.clamp(-1.0, 1.0) as f64 * 32768.0, &mut rng)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [PcmSample; 5] = [PcmSample::U8, PcmSample::I8, PcmSample::I16, PcmSample::U16, PcmSample::F32];
+
+    #[test]
+    fn loads_each_encoding() {
+        let mono = |sample, endian| PcmFormat::new(sample, endian, 1);
+        assert_eq!(load_raw(&[0, 0x80, 0xff], mono(PcmSample::U8, Endian::Little)).unwrap(), [0, 0x8000, 0xff00]);
+        assert_eq!(load_raw(&[0x80, 0, 0x7f], mono(PcmSample::I8, Endian::Little)).unwrap(), [0, 0x8000, 0xff00]);
+        assert_eq!(load_raw(&[0x00, 0x80, 0xff, 0x7f], mono(PcmSample::I16, Endian::Little)).unwrap(), [0, 0xffff]);
+        assert_eq!(load_raw(&[0x80, 0x00, 0x7f, 0xff], mono(PcmSample::I16, Endian::Big)).unwrap(), [0, 0xffff]);
+        assert_eq!(load_raw(&[0x12, 0x34], mono(PcmSample::U16, Endian::Big)).unwrap(), [0x1234]);
+        let floats: Vec<u8> = [-2.0f32, 0.5, 3.0].iter().flat_map(|f| f.to_be_bytes()).collect();
+        assert_eq!(load_raw(&floats, mono(PcmSample::F32, Endian::Big)).unwrap(), [0, 0xc000, 0xffff]);
+    }
+
+    #[test]
+    fn rejects_partial_frames() {
+        let stereo = PcmFormat::new(PcmSample::I16, Endian::Little, 2);
+        assert_eq!(load_raw(&[0; 6], stereo), Err(PcmError::TrailingBytes { len: 6, frame_bytes: 4 }));
+        assert_eq!(to_raw(&[0; 3], stereo), Err(PcmError::PartialFrame { len: 3, channels: 2 }));
+        let none = PcmFormat::new(PcmSample::U8, Endian::Little, 0);
+        assert_eq!(load_raw(&[0], none), Err(PcmError::ZeroChannels));
+    }
+
+    #[test]
+    fn to_raw_round_trips() {
+        let data: Vec<u16> = (0..32).map(|i| (i * 2053) as u16 & 0xff00).collect();
+        for sample in ALL {
+            for endian in [Endian::Little, Endian::Big] {
+                let format = PcmFormat::new(sample, endian, 2);
+                let raw = to_raw(&data, format).unwrap();
+                assert_eq!(raw.len(), data.len() * sample.bytes());
+                assert_eq!(load_raw(&raw, format).unwrap(), data, "{:?} {:?}", sample, endian);
+            }
+        }
+    }
+
+    #[test]
+    fn dithering_is_reproducible_and_clamps() {
+        let data: Vec<f32> = (0..256).map(|i| (i as f32 / 128.0 - 1.0) * 0.01).collect();
+        assert_eq!(from_f32_dithered(&data, 7), from_f32_dithered(&data, 7));
+        assert_ne!(from_f32_dithered(&data, 7), from_f32_dithered(&data, 8));
+        let extremes = from_f32_dithered(&[1.0, 2.0, f32::MAX, -1.0, -2.0, f32::MIN].repeat(100), 3);
+        assert!(extremes.chunks(6).all(|c| c[..3].iter().all(|s| *s == 0xffff) && c[3..].iter().all(|s| *s <= 1)));
+    }
+
+    #[test]
+    fn dither_turns_a_quiet_ramp_into_noise() {
+        // a ramp spanning three steps: plain rounding gives long runs of the same value
+        let len = 30000;
+        let ramp: Vec<f32> = (0..len).map(|i| 3.0 * i as f32 / len as f32 / 32768.0).collect();
+        let errors = |out: &[u16]| -> Vec<f64> {
+            out.iter().zip(&ramp).map(|(o, r)| o.to_i32() as f64 - *r as f64 * 32768.0).collect()
+        };
+        let lag_one = |e: &[f64]| {
+            let mean = e.iter().sum::<f64>() / e.len() as f64;
+            let var: f64 = e.iter().map(|x| (x - mean).powi(2)).sum();
+            e.windows(2).map(|w| (w[0] - mean) * (w[1] - mean)).sum::<f64>() / var
+        };
+        let plain = errors(&load_raw(&ramp.iter().flat_map(|f| f.to_le_bytes()).collect::<Vec<_>>(), PcmFormat::new(PcmSample::F32, Endian::Little, 1)).unwrap());
+        let dithered = errors(&from_f32_dithered(&ramp, 1));
+        assert!(lag_one(&plain) > 0.99, "{}", lag_one(&plain));
+        assert!(lag_one(&dithered).abs() < 0.05, "{}", lag_one(&dithered));
+        // centred: no DC offset, and never more than the dither's reach plus rounding
+        assert!((dithered.iter().sum::<f64>() / len as f64).abs() < 0.02);
+        assert!(dithered.iter().all(|e| e.abs() <= 1.5));
+    }
+}
@@ -0,0 +1,98 @@
+//! Clean-up for loaded sample data, before it goes into a buffer. Every function
+//! works on offset-binary samples centered on `u16::MID`, the crate's native format.
+
+use std::ops::Range;
+
+use crate::Sample;
+
+fn signed(sample: u16) -> i32 {
+    sample.to_i32()
+}
+
+fn offset(singed_sample: i32) -> u16 {
+    u16::from_i32(singed_sample)
+}
+
+/// Moves the data so its mean sits on `u16::MID`. Samples pushed past either end
+/// clamp. Interleaved channels share one mean, so deinterleave first for per-channel
+/// offsets.
+pub fn remove_dc(data: &mut [u16]) {
+    if data.is_empty() {
+        return;
+    }
+    let sum: i64 = data.iter().map(|s| signed(*s) as i64).sum();
+    let mean = (sum as f64 / data.len() as f64).round() as i32;
+    for sample in data.iter_mut() {
+        *sample = offset(signed(*sample) - mean);
+    }
+}
+
+/// Scales the data so its peak deviation from the midpoint is `target_peak` (clamped
+/// to 0.0..=1.0) of full scale, where 1.0 is `i16::MAX`. Silent data is left alone.
+pub fn normalize(data: &mut [u16], target_peak: f32) {
+    let peak = data.iter().map(|s| signed(*s).unsigned_abs()).max().unwrap_or(0);
+    if peak == 0 {
+        return;
+    }
+    let target = target_peak.clamp(0.0, 1.0) as f64 * i16::MAX as f64;
+    let factor = target / peak as f64;
+    for sample in data.iter_mut() {
+        *sample = offset((signed(*sample) as f64 * factor).round() as i32);
+    }
+}
+
+/// The range of `data` between the first and the last sample further than
+/// `threshold` from the midpoint; empty when every sample is within it.
+pub fn trim_silence(data: &[u16], threshold: u16) -> Range<usize> {
+    let loud = |s: &u16| signed(*s).unsigned_abs() > threshold as u32;
+    match data.iter().position(loud) {
+        Some(start) => start..data.iter().rposition(loud).map_or(start, |end| end + 1),
+        None => 0..0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MID: u16 = u16::MID;
+
+    #[test]
+    fn remove_dc_recenters_and_clamps() {
+        let mut data = [MID + 1000, MID + 3000, MID + 2000, MID + 2000];
+        remove_dc(&mut data);
+        assert_eq!(data, [MID - 1000, MID + 1000, MID, MID]);
+        // a mean far below the midpoint pushes the top sample past the end
+        let mut data = [0, 0, 0, u16::MAX];
+        remove_dc(&mut data);
+        assert_eq!(data, [16384, 16384, 16384, u16::MAX]);
+        remove_dc(&mut []);
+    }
+
+    #[test]
+    fn normalize_hits_the_target_peak() {
+        let mut data = [MID + 100, MID - 200, MID, MID + 50];
+        normalize(&mut data, 0.5);
+        let peak = (i16::MAX as f64 * 0.5 / 200.0) * 200.0;
+        assert_eq!(data[1], MID - peak.round() as u16);
+        assert_eq!(data[0], MID + (peak / 2.0).round() as u16);
+        assert_eq!(data[2], MID);
+        // full scale both ways, even from the asymmetric bottom
+        let mut data = [0, MID + 1];
+        normalize(&mut data, 1.0);
+        assert_eq!(data, [1, MID + 1]);
+        let mut silent = [MID; 4];
+        normalize(&mut silent, 1.0);
+        assert_eq!(silent, [MID; 4]);
+    }
+
+    #[test]
+    fn trim_silence_finds_the_loud_part() {
+        let data = [MID, MID + 3, MID - 10, MID, MID + 20, MID - 2, MID];
+        assert_eq!(trim_silence(&data, 5), 2..5);
+        assert_eq!(trim_silence(&data, 0), 1..6);
+        assert_eq!(trim_silence(&data, 20), 0..0);
+        assert_eq!(trim_silence(&[], 0), 0..0);
+        assert_eq!(trim_silence(&[0], 100), 0..1);
+    }
+}
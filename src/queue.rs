@@ -0,0 +1,578 @@
+//! Non-blocking control transport: commands travel through a bounded channel and
+//! samples through a pre-allocated atomic ring, both drained at the top of the callback.
+//! Getters read counters the callback publishes after every block.
+//!
+//! `Control` itself still goes through the device lock; this is the path for callers
+//! that can't risk holding up the callback.
+
+#[cfg(target_has_atomic = "64")]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::{PlayMode, Sample, Sound, MAX_VOLUME};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    SetVolume(u16),
+    SetGain(u16),
+    SetMute(bool),
+    SetMasterVolume(u16),
+    SetMode(PlayMode),
+    SetRate(f32),
+    SetCurrent(usize),
+    /// The next `n` samples in the ring go to the write cursor.
+    Write(usize),
+    /// The next `n` samples in the ring go to the buffer at `offset`, like `set_data`.
+    SetData { offset: usize, n: usize },
+}
+
+/// Single-producer single-consumer ring of sample bits.
+pub(crate) struct SampleRing {
+    slots: Box<[AtomicU32]>,
+    // total samples ever pushed and popped; the difference is the fill level
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl SampleRing {
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            slots: (0..len.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn free(&self) -> usize {
+        self.slots.len() - (self.head.load(Ordering::Relaxed) - self.tail.load(Ordering::Acquire))
+    }
+
+    pub(crate) fn push<T: Sample>(&self, samples: &[T]) {
+        let head = self.head.load(Ordering::Relaxed);
+        for (i, sample) in samples.iter().enumerate() {
+            self.slots[(head + i) % self.slots.len()].store(sample.to_raw_bits(), Ordering::Relaxed);
+        }
+        self.head.store(head + samples.len(), Ordering::Release);
+    }
+
+    pub(crate) fn pop<T: Sample>(&self, n: usize, mut sink: impl FnMut(T)) {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let available = self.head.load(Ordering::Acquire) - tail;
+        let n = n.min(available);
+        for i in 0..n {
+            sink(T::from_raw_bits(self.slots[(tail + i) % self.slots.len()].load(Ordering::Relaxed)));
+        }
+        self.tail.store(tail + n, Ordering::Release);
+    }
+}
+
+/// A `u64` that one thread stores and any thread loads without locking. Where the
+/// target has no 64-bit atomics, the 32-bit ones the counters were widened for, it is a
+/// `SeqCounter` instead.
+#[cfg(target_has_atomic = "64")]
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicU64);
+
+#[cfg(target_has_atomic = "64")]
+impl Counter {
+    pub(crate) fn load(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn store(&self, value: u64) {
+        self.0.store(value, Ordering::Release);
+    }
+}
+
+#[cfg(not(target_has_atomic = "64"))]
+pub(crate) type Counter = SeqCounter;
+
+/// A seqlock over the two halves of a `u64`: `seq` is odd while `store` is writing
+/// them, and `load` tries again until it reads both halves between two stores. There
+/// must be a single writer; it never waits, and readers only spin while it is storing.
+#[cfg(any(test, not(target_has_atomic = "64")))]
+#[derive(Debug, Default)]
+pub(crate) struct SeqCounter {
+    seq: AtomicUsize,
+    low: AtomicU32,
+    high: AtomicU32,
+}
+
+#[cfg(any(test, not(target_has_atomic = "64")))]
+impl SeqCounter {
+    pub(crate) fn load(&self) -> u64 {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq.is_multiple_of(2) {
+                let low = self.low.load(Ordering::Relaxed);
+                let high = self.high.load(Ordering::Relaxed);
+                std::sync::atomic::fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    return (high as u64) << 32 | low as u64;
+                }
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    pub(crate) fn store(&self, value: u64) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        std::sync::atomic::fence(Ordering::Release);
+        self.low.store(value as u32, Ordering::Relaxed);
+        self.high.store((value >> 32) as u32, Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+}
+
+/// Counters copied out of the callback after every block.
+#[derive(Default)]
+struct Published {
+    current: Counter,
+    remain: AtomicUsize,
+    called: Counter,
+    underruns: Counter,
+    buf_size: AtomicUsize,
+    finished: AtomicBool,
+    generation: Counter,
+    // f32 bits
+    peak: AtomicU32,
+    rms: AtomicU32,
+}
+
+/// The callback's end of a `Commander`.
+pub(crate) struct CommandReceiver {
+    commands: Receiver<Command>,
+    ring: Arc<SampleRing>,
+    published: Arc<Published>,
+}
+
+impl CommandReceiver {
+    /// Applies every queued command; never blocks.
+    pub(crate) fn drain<T: Sample>(&self, sound: &mut Sound<T>) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::SetVolume(volume) => sound.set_volume(volume),
+                Command::SetGain(gain) => sound.set_gain(gain),
+                Command::SetMute(mute) => sound.mute = mute,
+                Command::SetMasterVolume(volume) => sound.master_volume = volume.min(MAX_VOLUME),
+                Command::SetMode(mode) => sound.mode = mode,
+                Command::SetRate(rate) => sound.set_rate(rate),
+                Command::SetCurrent(pos) => sound.set_current(pos),
+                Command::Write(n) => {
+                    // whatever no longer fits in front of the read cursor is dropped
+                    let fits = sound.write_available();
+                    let start = sound.pos() + sound.remain;
+                    let len = sound.buffer.len();
+                    let mut written = 0;
+                    self.ring.pop::<T>(n, |sample| {
+                        if written < fits {
+                            sound.buffer[(start + written) % len] = sample;
+                            written += 1;
+                        }
+                    });
+                    sound.remain += written;
+                    sound.generation += 1;
+                }
+                Command::SetData { offset, n } => {
+                    let offset = offset - offset % sound.frame_len();
+                    let len = sound.buffer.len();
+                    let mut written = 0;
+                    self.ring.pop::<T>(n, |sample| {
+                        sound.buffer[(offset + written) % len] = sample;
+                        written += 1;
+                    });
+                    sound.remain += written;
+                    sound.generation += 1;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn publish<T: Sample>(&self, sound: &Sound<T>) {
+        let published = &self.published;
+        published.current.store(sound.current);
+        published.remain.store(sound.remain, Ordering::Relaxed);
+        published.called.store(sound.called);
+        published.underruns.store(sound.underruns);
+        published.buf_size.store(sound.buf_size, Ordering::Relaxed);
+        published.generation.store(sound.generation);
+        published.peak.store(sound.meter.peak().to_bits(), Ordering::Relaxed);
+        published.rms.store(sound.meter.rms().to_bits(), Ordering::Relaxed);
+        published.finished.store(sound.finished, Ordering::Release);
+    }
+}
+
+/// Sends control operations to a device without taking its lock, so the audio thread
+/// never waits on the caller. Create one with `Control::commander`.
+///
+/// Setters return false when the command queue is full. Getters report the state at
+/// the end of the last callback, so they lag behind commands still in the queue.
+pub struct Commander<T: Sample = u16> {
+    commands: SyncSender<Command>,
+    ring: Arc<SampleRing>,
+    published: Arc<Published>,
+    _sample: std::marker::PhantomData<fn(T)>,
+}
+
+impl<T: Sample> Commander<T> {
+    /// `capacity` commands and `ring_len` samples can be in flight at once.
+    pub(crate) fn new(sound: &Sound<T>, capacity: usize, ring_len: usize) -> (Self, CommandReceiver) {
+        let (commands, receiver) = sync_channel(capacity.max(1));
+        let ring = Arc::new(SampleRing::new(ring_len));
+        let published = Arc::new(Published::default());
+        let receiver = CommandReceiver { commands: receiver, ring: ring.clone(), published: published.clone() };
+        receiver.publish(sound);
+        let commander = Self { commands, ring, published, _sample: std::marker::PhantomData };
+        (commander, receiver)
+    }
+
+    fn send(&self, command: Command) -> bool {
+        match self.commands.try_send(command) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    pub fn set_volume(&self, volume: u16) -> bool {
+        self.send(Command::SetVolume(volume))
+    }
+
+    pub fn set_gain(&self, gain: u16) -> bool {
+        self.send(Command::SetGain(gain))
+    }
+
+    pub fn set_mute(&self, mute: bool) -> bool {
+        self.send(Command::SetMute(mute))
+    }
+
+    pub fn set_master_volume(&self, volume: u16) -> bool {
+        self.send(Command::SetMasterVolume(volume))
+    }
+
+    pub fn set_mode(&self, mode: PlayMode) -> bool {
+        self.send(Command::SetMode(mode))
+    }
+
+    pub fn set_rate(&self, rate: f32) -> bool {
+        self.send(Command::SetRate(rate))
+    }
+
+    pub fn set_current(&self, pos: usize) -> bool {
+        self.send(Command::SetCurrent(pos))
+    }
+
+    /// Queues samples for the write cursor, like `Control::write`, and returns how many
+    /// were taken: no more than the ring has room for and the device had free at the
+    /// last callback.
+    ///
+    /// Takes `&mut self` because the sample ring has a single producer.
+    pub fn write(&mut self, samples: &[T]) -> usize {
+        let n = samples.len().min(self.ring.free()).min(self.write_available());
+        if n == 0 {
+            return 0;
+        }
+        self.ring.push(&samples[..n]);
+        if self.send(Command::Write(n)) {
+            n
+        } else {
+            // nobody will claim these samples, so take them back out of the ring
+            self.ring.head.fetch_sub(n, Ordering::Release);
+            0
+        }
+    }
+
+    /// Queues samples for the buffer at `offset`, like `Control::set_data`, and returns
+    /// how many were taken: the front of `samples`, as many as the ring has room for.
+    pub fn set_data(&mut self, offset: usize, samples: &[T]) -> usize {
+        let n = samples.len().min(self.ring.free());
+        if n == 0 {
+            return 0;
+        }
+        self.ring.push(&samples[..n]);
+        if self.send(Command::SetData { offset, n }) {
+            n
+        } else {
+            self.ring.head.fetch_sub(n, Ordering::Release);
+            0
+        }
+    }
+
+    /// `Control::write_available` as of the last callback, less samples still queued.
+    pub fn write_available(&self) -> usize {
+        let queued = self.ring.slots.len() - self.ring.free();
+        let published = &self.published;
+        published.buf_size.load(Ordering::Relaxed)
+            .saturating_sub(published.remain.load(Ordering::Relaxed))
+            .saturating_sub(queued)
+    }
+
+    pub fn current(&self) -> u64 {
+        self.published.current.load()
+    }
+
+    pub fn remain(&self) -> usize {
+        self.published.remain.load(Ordering::Relaxed)
+    }
+
+    pub fn called(&self) -> u64 {
+        self.published.called.load()
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.published.underruns.load()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.published.finished.load(Ordering::Acquire)
+    }
+
+    /// `Control::generation` as of the last callback.
+    pub fn generation(&self) -> u64 {
+        self.published.generation.load()
+    }
+
+    /// `Control::peak_level` without taking the device lock.
+    pub fn peak_level(&self) -> f32 {
+        f32::from_bits(self.published.peak.load(Ordering::Relaxed))
+    }
+
+    pub fn rms_level(&self) -> f32 {
+        f32::from_bits(self.published.rms.load(Ordering::Relaxed))
+    }
+}
+
+/// A `Commander` that can be cloned and sent to any thread, from
+/// `Control::control_handle`, while the device stays with the thread that opened it for
+/// `pause`, `resume` and dropping. Every handle of a device shares one set of queues;
+/// handles only ever wait for each other, never for the callback.
+///
+/// The methods work like `Commander`'s: setters return false when the queue is full, and
+/// getters report the end of the last callback. Once the device is dropped, setters
+/// fail and getters keep their last values.
+pub struct ControlHandle<T: Sample = u16> {
+    commander: Arc<Mutex<Commander<T>>>,
+}
+
+impl<T: Sample> Clone for ControlHandle<T> {
+    fn clone(&self) -> Self {
+        Self { commander: self.commander.clone() }
+    }
+}
+
+impl<T: Sample> ControlHandle<T> {
+    pub(crate) fn new(commander: Commander<T>) -> Self {
+        Self { commander: Arc::new(Mutex::new(commander)) }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Commander<T>) -> R) -> R {
+        f(&mut self.commander.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub fn set_volume(&self, volume: u16) -> bool {
+        self.with(|commander| commander.set_volume(volume))
+    }
+
+    pub fn set_gain(&self, gain: u16) -> bool {
+        self.with(|commander| commander.set_gain(gain))
+    }
+
+    pub fn set_mute(&self, mute: bool) -> bool {
+        self.with(|commander| commander.set_mute(mute))
+    }
+
+    pub fn set_master_volume(&self, volume: u16) -> bool {
+        self.with(|commander| commander.set_master_volume(volume))
+    }
+
+    pub fn set_mode(&self, mode: PlayMode) -> bool {
+        self.with(|commander| commander.set_mode(mode))
+    }
+
+    pub fn set_rate(&self, rate: f32) -> bool {
+        self.with(|commander| commander.set_rate(rate))
+    }
+
+    pub fn set_current(&self, pos: usize) -> bool {
+        self.with(|commander| commander.set_current(pos))
+    }
+
+    pub fn rewind(&self) -> bool {
+        self.set_current(0)
+    }
+
+    /// `Commander::write`; at most a buffer's worth of samples can be in flight.
+    pub fn write(&self, samples: &[T]) -> usize {
+        self.with(|commander| commander.write(samples))
+    }
+
+    /// `Commander::set_data`.
+    pub fn set_data(&self, offset: usize, samples: &[T]) -> usize {
+        self.with(|commander| commander.set_data(offset, samples))
+    }
+
+    pub fn write_available(&self) -> usize {
+        self.with(|commander| commander.write_available())
+    }
+
+    pub fn current(&self) -> u64 {
+        self.with(|commander| commander.current())
+    }
+
+    pub fn remain(&self) -> usize {
+        self.with(|commander| commander.remain())
+    }
+
+    pub fn called(&self) -> u64 {
+        self.with(|commander| commander.called())
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.with(|commander| commander.underruns())
+    }
+
+    pub fn finished(&self) -> bool {
+        self.with(|commander| commander.finished())
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.with(|commander| commander.generation())
+    }
+
+    pub fn peak_level(&self) -> f32 {
+        self.with(|commander| commander.peak_level())
+    }
+
+    pub fn rms_level(&self) -> f32 {
+        self.with(|commander| commander.rms_level())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::audio::AudioCallback;
+
+    use super::*;
+    use crate::{AudioSpecInfo, SETUP_U16};
+
+    fn sound(len: usize) -> Sound<u16> {
+        let mut sound = Sound::new(len, AudioSpecInfo { freq: 48000, channels: 1, samples: 64 });
+        sound.ramp_samples = 0;
+        sound
+    }
+
+    #[test]
+    fn commands_apply_at_the_next_callback() {
+        let mut sound = sound(8);
+        let (mut commander, receiver) = Commander::new(&sound, 8, 8);
+        sound.commands = Some(receiver);
+        assert!(commander.set_volume(7));
+        assert_eq!(commander.write(&[0xc000, 0x4000]), 2);
+        assert_eq!(sound.remain, 0);
+        let mut out = [0u16; 3];
+        sound.callback(&mut out);
+        assert_eq!(out, [0xc000, 0x4000, SETUP_U16 as u16]);
+        assert_eq!((commander.current(), commander.remain(), commander.called()), (2, 0, 1));
+        assert!(commander.set_mute(true));
+        assert!(commander.set_current(0));
+        sound.callback(&mut out);
+        assert!(sound.mute);
+        assert_eq!(commander.current(), 0);
+    }
+
+    #[test]
+    fn full_queues_reject_instead_of_blocking() {
+        let sound = sound(4);
+        let (mut commander, _receiver) = Commander::new(&sound, 1, 4);
+        assert!(commander.set_gain(10));
+        assert!(!commander.set_gain(20));
+        // the queued write would have no command to announce it
+        assert_eq!(commander.write(&[1, 2]), 0);
+        assert_eq!(commander.write_available(), 4);
+    }
+
+    #[test]
+    fn stress_writes_and_volume_changes_lose_nothing() {
+        const TOTAL: usize = 64 * 500;
+        let mut sound = sound(4096);
+        sound.set_volume(MAX_VOLUME);
+        let (mut commander, receiver) = Commander::new(&sound, 256, 4096);
+        sound.commands = Some(receiver);
+        // never the silence the callback pads a starved block with
+        let value = |i: usize| 0x9000 + (i % 0x1000) as u16;
+        let producer = std::thread::spawn(move || {
+            let mut sent = 0;
+            while sent < TOTAL {
+                // unity either way, so the data comes out unchanged
+                if sent % 128 == 0 {
+                    commander.set_volume(MAX_VOLUME);
+                } else {
+                    commander.set_gain(crate::UNITY_GAIN);
+                }
+                let chunk: Vec<u16> = (sent..(sent + 64).min(TOTAL)).map(value).collect();
+                sent += commander.write(&chunk);
+                std::thread::yield_now();
+            }
+            commander
+        });
+        // 64-frame blocks as fast as this thread can go, faster than any real device
+        let mut played: Vec<u16> = Vec::with_capacity(TOTAL);
+        let mut starved_blocks = 0;
+        let mut out = [0u16; 64];
+        while played.len() < TOTAL {
+            sound.callback(&mut out);
+            let silent = out.iter().filter(|s| **s == SETUP_U16 as u16).count();
+            starved_blocks += (silent > 0) as u64;
+            played.extend(out.iter().filter(|s| **s != SETUP_U16 as u16).copied());
+            assert!(sound.called < 100_000_000, "the producer stalled");
+        }
+        let commander = producer.join().unwrap();
+        // every sample once, in order, and a starved callback only when the ring was
+        // empty, never because a command or write was stuck behind a lock
+        assert!(played.iter().copied().eq((0..TOTAL).map(value)));
+        assert_eq!(sound.underruns, starved_blocks);
+        assert_eq!(sound.volume(), MAX_VOLUME);
+        sound.callback(&mut out);
+        assert_eq!((commander.called(), commander.underruns()), (sound.called, sound.underruns));
+    }
+
+    #[test]
+    fn seq_counters_never_tear() {
+        // both halves always match, so a torn read would show as a mismatch
+        let counter = Arc::new(SeqCounter::default());
+        let writer = {
+            let counter = counter.clone();
+            std::thread::spawn(move || {
+                for i in 0..200_000u64 {
+                    counter.store(i << 32 | i);
+                }
+            })
+        };
+        let mut last = 0;
+        while !writer.is_finished() {
+            let value = counter.load();
+            assert_eq!(value >> 32, value & 0xffff_ffff);
+            assert!(value >= last);
+            last = value;
+        }
+        writer.join().unwrap();
+        assert_eq!(counter.load(), 199_999 << 32 | 199_999);
+    }
+
+    #[test]
+    fn set_data_lands_at_its_offset() {
+        let mut sound = sound(8);
+        sound.set_volume(MAX_VOLUME);
+        sound.mode = PlayMode::Loop;
+        let (mut commander, receiver) = Commander::new(&sound, 8, 8);
+        sound.commands = Some(receiver);
+        assert_eq!(commander.set_data(6, &[0xa000, 0xb000, 0xc000]), 3);
+        let mut out = [0u16; 8];
+        sound.callback(&mut out);
+        let silence = SETUP_U16 as u16;
+        assert_eq!(out, [0xc000, silence, silence, silence, silence, silence, 0xa000, 0xb000]);
+        assert_eq!(commander.generation(), sound.generation);
+    }
+}
@@ -0,0 +1,136 @@
+use sdl2::audio::{AudioQueue, AudioStatus};
+
+use crate::{level_gain, AudioError, AudioSpecInfo, Sample, GAIN_ONE, MAX_VOLUME, UNITY_GAIN};
+
+/// Push-model playback on SDL's audio queue: no callback and no ring buffer, samples
+/// are handed to SDL with `QueuePlayer::queue` and played in order.
+///
+/// Everything queued is latency. A callback device plays from a fixed buffer, so a seek
+/// or a volume change is heard within a block; here anything already queued plays out
+/// as it was queued first, volume and mute included, since they are applied before
+/// queueing. Keep `queued_bytes` down to a few blocks' worth when changes must be heard
+/// quickly; queue more when the producer is bursty and latency doesn't matter.
+///
+/// When the queue runs dry SDL plays its own silence, which for `u16` is 0x8080 rather
+/// than `SETUP_U16`, a small step away from the midpoint. Keep the queue fed (queueing
+/// while muted does that) or pause the device to avoid it.
+pub struct QueueDevice {
+    queue: AudioQueue<u16>,
+    spec: AudioSpecInfo,
+    gain: u32,
+    // 0..=MAX_VOLUME, applied on top of `gain`
+    master_volume: u16,
+    mute: bool,
+    // the scaled copy of the samples being queued, kept to avoid allocating each time
+    scaled: Vec<u16>,
+}
+
+impl QueueDevice {
+    pub(crate) fn new(queue: AudioQueue<u16>) -> Self {
+        let spec = AudioSpecInfo::from(queue.spec());
+        Self { queue, spec, gain: 0, master_volume: MAX_VOLUME, mute: false, scaled: Vec::new() }
+    }
+
+    fn target_gain(&self) -> u32 {
+        ((self.gain as u64 * level_gain(self.master_volume) as u64) >> 16) as u32
+    }
+}
+
+/// What a `QueueDevice` can do. Volume, gain and mute work like `Control`'s, starting
+/// at volume 0 the same way, but apply to samples as they are queued, not to what is
+/// already waiting.
+pub trait QueuePlayer {
+    /// Appends interleaved samples, scaled by the current volume (silence while muted,
+    /// so the timeline keeps going). Must be whole frames.
+    fn queue(&mut self, samples: &[u16]) -> Result<(), AudioError>;
+    /// Bytes waiting to be played, two per sample.
+    fn queued_bytes(&mut self) -> u32;
+    /// Drops everything not yet played.
+    fn clear(&mut self);
+    fn pause(&mut self);
+    fn resume(&mut self);
+    fn is_paused(&mut self) -> bool;
+    fn set_volume(&mut self, volume: u16);
+    fn volume(&mut self) -> u16;
+    fn set_gain(&mut self, gain: u16);
+    fn gain(&mut self) -> u16;
+    fn set_master_volume(&mut self, volume: u16);
+    fn master_volume(&mut self) -> u16;
+    fn set_mute(&mut self, specifier: bool);
+    fn mute(&mut self) -> bool;
+    fn spec(&mut self) -> AudioSpecInfo;
+}
+
+impl QueuePlayer for QueueDevice {
+    fn queue(&mut self, samples: &[u16]) -> Result<(), AudioError> {
+        let channels = (self.spec.channels as usize).max(1);
+        if !samples.len().is_multiple_of(channels) {
+            return Err(AudioError::invalid_param("samples", "not a whole number of frames"));
+        }
+        let gain = if self.mute { 0 } else { self.target_gain() };
+        let data = if gain == GAIN_ONE {
+            samples
+        } else {
+            self.scaled.resize(samples.len(), u16::MID);
+            u16::scale_block(&mut self.scaled, samples, gain);
+            &self.scaled
+        };
+        self.queue.queue_audio(data).map_err(AudioError::Queue)
+    }
+
+    fn queued_bytes(&mut self) -> u32 {
+        self.queue.size()
+    }
+
+    fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    fn pause(&mut self) {
+        self.queue.pause();
+    }
+
+    fn resume(&mut self) {
+        self.queue.resume();
+    }
+
+    fn is_paused(&mut self) -> bool {
+        self.queue.status() == AudioStatus::Paused
+    }
+
+    fn set_volume(&mut self, volume: u16) {
+        self.gain = level_gain(volume);
+    }
+
+    fn volume(&mut self) -> u16 {
+        (0..=MAX_VOLUME).rev().find(|v| level_gain(*v) <= self.gain).unwrap_or(0)
+    }
+
+    fn set_gain(&mut self, gain: u16) {
+        self.gain = gain.min(UNITY_GAIN) as u32 * (GAIN_ONE / UNITY_GAIN as u32);
+    }
+
+    fn gain(&mut self) -> u16 {
+        (self.gain / (GAIN_ONE / UNITY_GAIN as u32)) as u16
+    }
+
+    fn set_master_volume(&mut self, volume: u16) {
+        self.master_volume = volume.min(MAX_VOLUME);
+    }
+
+    fn master_volume(&mut self) -> u16 {
+        self.master_volume
+    }
+
+    fn set_mute(&mut self, specifier: bool) {
+        self.mute = specifier;
+    }
+
+    fn mute(&mut self) -> bool {
+        self.mute
+    }
+
+    fn spec(&mut self) -> AudioSpecInfo {
+        self.spec
+    }
+}
@@ -0,0 +1,537 @@
+//! Dynamic rate control: stretches or squeezes produced audio by a fraction of a percent
+//! so the device buffer neither drains nor overfills when the producer's clock and the
+//! sound card's clock disagree.
+//!
+//! Each tick, feed the fill level (`PlaybackStatus::remain`) to `RateController::update`
+//! and pass the ratio it returns to `StreamResampler::process` before writing.
+//!
+//! `AvSync` does the same against an external clock instead of the fill level, keeping
+//! audio locked to video.
+//!
+//! `Control::measured_rate` estimates how fast the sound card really runs, for
+//! trimming such a ratio by the card's own drift.
+
+use std::time::{Duration, Instant};
+
+use crate::{Frames, Sample, SampleRate};
+
+const DEFAULT_MAX_DEVIATION: f64 = 0.005;
+const DEFAULT_SMOOTHING: f64 = 0.05;
+// at 60 ticks a second and the default deviation, a steady drift is worked off over
+// two or three minutes, overshooting by a couple of percent at most
+const DEFAULT_INTEGRAL_GAIN: f64 = 0.0005;
+
+/// Largest change of `AvSync`'s ratio away from 1.0 (±0.2%), reached at `AV_FULL_SCALE_MS`.
+pub const AV_MAX_DEVIATION: f64 = 0.002;
+const AV_FULL_SCALE_MS: f64 = 5.0;
+// correction starts once the error exceeds the first and stops once it is back within
+// the second, so an error hovering around one value doesn't keep moving the ratio
+const AV_START_MS: f64 = 2.0;
+const AV_STOP_MS: f64 = 0.5;
+
+// the drift estimator keeps one observation per interval, so its window spans about
+// DRIFT_OBSERVATIONS intervals (25 s), and needs a few before it says anything
+const DRIFT_OBSERVATIONS: usize = 256;
+const DRIFT_INTERVAL: f64 = 0.1;
+const DRIFT_MIN_OBSERVATIONS: usize = 16;
+// a callback later than this many blocks (and at least one interval) after the last one
+// means the device was paused or stalled, and the estimator starts over
+const DRIFT_GAP_BLOCKS: f64 = 4.0;
+
+/// Turns the buffer fill level into a resample ratio, output samples per input sample.
+///
+/// Below the target the ratio goes above 1.0 so more samples get written, above it
+/// below 1.0. The fill level is smoothed before use, so a single late or early tick
+/// barely moves the ratio and the pitch change stays inaudible. An integral term picks
+/// up a steady clock drift, so the fill settles on the target rather than beside it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateController {
+    target_fill: usize,
+    max_deviation: f64,
+    smoothing: f64,
+    integral_gain: f64,
+    smoothed_fill: Option<f64>,
+    // accumulated error, in the same units as the proportional term
+    integral: f64,
+    ratio: f64,
+}
+
+impl RateController {
+    pub fn new(target_fill: usize) -> Self {
+        Self {
+            target_fill,
+            max_deviation: DEFAULT_MAX_DEVIATION,
+            smoothing: DEFAULT_SMOOTHING,
+            integral_gain: DEFAULT_INTEGRAL_GAIN,
+            smoothed_fill: None,
+            integral: 0.0,
+            ratio: 1.0,
+        }
+    }
+
+    pub fn target_fill(&self) -> usize {
+        self.target_fill
+    }
+
+    pub fn set_target_fill(&mut self, target_fill: usize) {
+        self.target_fill = target_fill;
+    }
+
+    /// Largest change of the ratio away from 1.0; 0.005 (±0.5%) by default.
+    pub fn set_max_deviation(&mut self, max_deviation: f64) {
+        self.max_deviation = max_deviation.clamp(0.0, 0.5);
+    }
+
+    /// Weight of each new fill reading, 0.0..=1.0; smaller is smoother but slower.
+    pub fn set_smoothing(&mut self, smoothing: f64) {
+        self.smoothing = smoothing.clamp(0.0, 1.0);
+    }
+
+    /// Share of each tick's error added to the integral term; 0.0 turns it off.
+    pub fn set_integral_gain(&mut self, integral_gain: f64) {
+        self.integral_gain = integral_gain.clamp(0.0, 1.0);
+    }
+
+    /// The ratio returned by the last `update`.
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Takes the current fill level in samples and returns the ratio for the next block.
+    pub fn update(&mut self, fill: usize) -> f64 {
+        let fill = fill as f64;
+        let smoothed = match self.smoothed_fill {
+            Some(smoothed) => smoothed + (fill - smoothed) * self.smoothing,
+            None => fill,
+        };
+        self.smoothed_fill = Some(smoothed);
+        let target = (self.target_fill as f64).max(1.0);
+        let error = ((target - smoothed) / target).clamp(-1.0, 1.0);
+        // anti-windup: while the ratio is pinned at its limit, an error pushing further
+        // that way isn't integrated, so a long drain or overfill isn't paid back later
+        let output = error + self.integral;
+        if output.abs() < 1.0 || output.signum() != error.signum() {
+            self.integral = (self.integral + self.integral_gain * error).clamp(-1.0, 1.0);
+        }
+        self.ratio = 1.0 + self.max_deviation * (error + self.integral).clamp(-1.0, 1.0);
+        self.ratio
+    }
+
+    /// Forgets the smoothed fill level and the integral, e.g. after a seek or an underrun.
+    pub fn reset(&mut self) {
+        self.smoothed_fill = None;
+        self.integral = 0.0;
+        self.ratio = 1.0;
+    }
+}
+
+/// Linear resampler for a continuous stream of interleaved offset-binary blocks. Unlike
+/// `resample` it carries its position and the last frame over from block to block, so
+/// the ratio can change every block without clicks. Output lags the input by one frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamResampler {
+    channels: usize,
+    // read position in input frames, relative to the first frame of the next block;
+    // -1.0 is `last`
+    pos: f64,
+    last: Option<Vec<i32>>,
+}
+
+impl StreamResampler {
+    pub fn new(channels: u8) -> Self {
+        Self {
+            channels: (channels as usize).max(1),
+            pos: 0.0,
+            last: None,
+        }
+    }
+
+    /// Appends `input` resampled by `ratio` (output per input sample) to `out`. The
+    /// ratio is clamped to 0.5..=2.0; a trailing partial frame is ignored.
+    pub fn process(&mut self, input: &[u16], ratio: f64, out: &mut Vec<u16>) {
+        let channels = self.channels;
+        let frames = input.len() / channels;
+        if frames == 0 {
+            return;
+        }
+        let step = 1.0 / ratio.clamp(0.5, 2.0);
+        let last = self.last.get_or_insert_with(|| input[..channels].iter().map(|s| s.to_i32()).collect());
+        let frame = |index: isize, c: usize| -> f64 {
+            if index < 0 {
+                last[c] as f64
+            } else {
+                input[index as usize * channels + c].to_i32() as f64
+            }
+        };
+        while self.pos < (frames - 1) as f64 {
+            let index = self.pos.floor();
+            let frac = self.pos - index;
+            let index = index as isize;
+            for c in 0..channels {
+                let a = frame(index, c);
+                let b = frame(index + 1, c);
+                out.push(u16::from_i32((a + (b - a) * frac).round() as i32));
+            }
+            self.pos += step;
+        }
+        self.pos -= frames as f64;
+        for (c, dst) in last.iter_mut().enumerate() {
+            *dst = input[(frames - 1) * channels + c].to_i32();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = 0.0;
+        self.last = None;
+    }
+}
+
+/// Keeps audio locked to a master clock, usually the video's, by resampling the decoded
+/// audio a little faster or slower. Each tick, pass the master time and the device's
+/// playback position to `update`, then run the decoded samples through `process` and
+/// write the result.
+///
+/// The position is the device's (`Control::position_ms`, or `current` over the obtained
+/// rate); `AvSync` takes off the frames its own stretching has added, so the error is
+/// the audio content's distance from the master clock. Positive errors mean the audio is
+/// ahead and get stretched, negative ones squeezed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvSync {
+    sample_rate: u32,
+    resampler: StreamResampler,
+    // frames fed to and produced by `process`
+    consumed: u64,
+    produced: u64,
+    error_ms: Option<f64>,
+    correcting: bool,
+    ratio: f64,
+}
+
+impl AvSync {
+    pub fn new(sample_rate: u32, channels: u8) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1),
+            resampler: StreamResampler::new(channels),
+            consumed: 0,
+            produced: 0,
+            error_ms: None,
+            correcting: false,
+            ratio: 1.0,
+        }
+    }
+
+    /// Takes the master clock and the device's playback position and returns the ratio
+    /// for the next `process`.
+    pub fn update(&mut self, master: Duration, audio: Duration) -> f64 {
+        let stretched = (self.produced as f64 - self.consumed as f64) / self.sample_rate as f64;
+        let error = (audio.as_secs_f64() - stretched - master.as_secs_f64()) * 1000.0;
+        let smoothed = match self.error_ms {
+            Some(smoothed) => smoothed + (error - smoothed) * DEFAULT_SMOOTHING,
+            None => error,
+        };
+        self.error_ms = Some(smoothed);
+        if self.correcting {
+            self.correcting = smoothed.abs() > AV_STOP_MS;
+        } else {
+            self.correcting = smoothed.abs() > AV_START_MS;
+        }
+        self.ratio = if self.correcting {
+            1.0 + AV_MAX_DEVIATION * (smoothed / AV_FULL_SCALE_MS).clamp(-1.0, 1.0)
+        } else {
+            1.0
+        };
+        self.ratio
+    }
+
+    /// `update` with the position in frames played, e.g. from `Control::position_frames`.
+    pub fn update_frames(&mut self, master: Duration, audio: Frames) -> f64 {
+        self.update(master, audio.to_duration(SampleRate(self.sample_rate)))
+    }
+
+    /// The ratio returned by the last `update`.
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// The smoothed error in milliseconds, audio ahead of the master clock positive.
+    pub fn error_ms(&self) -> f64 {
+        self.error_ms.unwrap_or(0.0)
+    }
+
+    /// Whether the error has gone past the start threshold and not yet settled.
+    pub fn is_correcting(&self) -> bool {
+        self.correcting
+    }
+
+    /// Appends `input` resampled by the current ratio to `out`.
+    pub fn process(&mut self, input: &[u16], out: &mut Vec<u16>) {
+        let before = out.len();
+        self.resampler.process(input, self.ratio, out);
+        let channels = self.resampler.channels;
+        self.consumed += (input.len() / channels) as u64;
+        self.produced += ((out.len() - before) / channels) as u64;
+    }
+
+    /// Starts over, e.g. after a seek: the error is measured afresh and nothing counts
+    /// as stretched any more.
+    pub fn reset(&mut self) {
+        self.resampler.reset();
+        self.consumed = 0;
+        self.produced = 0;
+        self.error_ms = None;
+        self.correcting = false;
+        self.ratio = 1.0;
+    }
+}
+
+/// Estimates the rate a device really plays at from when its callbacks run. Each
+/// callback's start time is jittered by scheduling, so a least-squares line through a
+/// window of observations gives the rate rather than any two of them. After a pause it
+/// starts over, as the observations from before don't line up with those after.
+#[derive(Debug, Clone)]
+pub(crate) struct DriftEstimator {
+    nominal: f64,
+    origin: Option<Instant>,
+    // output frames handed out before the callback now running
+    frames: u64,
+    // the previous callback's (seconds, frames), to tell a pause from a late block
+    last_block: Option<(f64, usize)>,
+    // seconds since `origin` of the first callback since starting over
+    start: f64,
+    // (seconds since `start`, `frames`), oldest overwritten first
+    window: Box<[(f64, u64)]>,
+    len: usize,
+    next: usize,
+}
+
+impl DriftEstimator {
+    pub(crate) fn new(nominal: i32) -> Self {
+        Self {
+            nominal: nominal.max(1) as f64,
+            origin: None,
+            frames: 0,
+            last_block: None,
+            start: 0.0,
+            window: vec![(0.0, 0); DRIFT_OBSERVATIONS].into_boxed_slice(),
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Called by the callback at the start of a block of `frames` output frames.
+    pub(crate) fn observe_block(&mut self, now: Instant, frames: usize) {
+        let origin = *self.origin.get_or_insert(now);
+        self.observe(now.duration_since(origin).as_secs_f64(), frames);
+    }
+
+    fn observe(&mut self, secs: f64, frames: usize) {
+        if let Some((last_secs, last_frames)) = self.last_block {
+            let expected = last_frames as f64 / self.nominal;
+            if secs - last_secs > (expected * DRIFT_GAP_BLOCKS).max(DRIFT_INTERVAL) {
+                self.start = secs;
+                self.frames = 0;
+                self.len = 0;
+                self.next = 0;
+            }
+        }
+        self.last_block = Some((secs, frames));
+        let secs = secs - self.start;
+        let last = (self.next + self.window.len() - 1) % self.window.len();
+        if self.len == 0 || secs - self.window[last].0 >= DRIFT_INTERVAL {
+            self.window[self.next] = (secs, self.frames);
+            self.next = (self.next + 1) % self.window.len();
+            self.len = (self.len + 1).min(self.window.len());
+        }
+        self.frames += frames as u64;
+    }
+
+    fn observations(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        let start = (self.next + self.window.len() - self.len) % self.window.len();
+        (0..self.len).map(move |i| self.window[(start + i) % self.window.len()])
+    }
+
+    /// Frames per second over the window, `None` until it has enough observations.
+    pub(crate) fn measured_rate(&self) -> Option<f64> {
+        if self.len < DRIFT_MIN_OBSERVATIONS {
+            return None;
+        }
+        let n = self.len as f64;
+        // relative to the first frame count, so the sums stay precise in long sessions
+        let base = self.observations().next()?.1;
+        let (sum_t, sum_f) = self.observations()
+            .fold((0.0, 0.0), |(t, f), (secs, frames)| (t + secs, f + (frames - base) as f64));
+        let (mean_t, mean_f) = (sum_t / n, sum_f / n);
+        let (cov, var) = self.observations().fold((0.0, 0.0), |(cov, var), (secs, frames)| {
+            let (dt, df) = (secs - mean_t, (frames - base) as f64 - mean_f);
+            (cov + dt * df, var + dt * dt)
+        });
+        (var > 0.0).then(|| cov / var)
+    }
+
+    /// Seconds the device's clock, counted in frames at the nominal rate, is ahead of
+    /// wall time since the first observation after the last pause; negative when it
+    /// runs slow.
+    pub(crate) fn clock_drift(&self) -> Option<f64> {
+        let (secs, frames) = self.observations().last()?;
+        (self.len >= DRIFT_MIN_OBSERVATIONS).then(|| frames as f64 / self.nominal - secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::Rng;
+
+    #[test]
+    fn ratio_follows_the_fill_level() {
+        let mut controller = RateController::new(1000);
+        assert_eq!(controller.update(1000), 1.0);
+        let mut low = RateController::new(1000);
+        assert!((low.update(0) - 1.005).abs() < 1e-9);
+        let mut high = RateController::new(1000);
+        assert!((high.update(5000) - 0.995).abs() < 1e-9);
+        // one wild reading only nudges a settled controller
+        let before = controller.update(1000);
+        let after = controller.update(0);
+        assert!(after > before && after - before < 0.0005);
+    }
+
+    #[test]
+    fn drifting_producer_settles_on_the_target_fill() {
+        // the producer runs 0.3% fast against the device for ten minutes of 60 Hz ticks
+        let mut controller = RateController::new(4000);
+        let mut fill = 4000.0;
+        for tick in 0..60 * 600 {
+            let ratio = controller.update(fill as usize);
+            fill += 802.4 * ratio - 800.0;
+            assert!(fill > 1000.0 && fill < 7000.0);
+            // after the first few minutes the fill stays within 2% of the target
+            if tick > 60 * 180 {
+                assert!((fill - 4000.0f64).abs() < 80.0, "fill {} at tick {}", fill, tick);
+            }
+        }
+        assert!((controller.ratio() - 800.0 / 802.4).abs() < 1e-4);
+    }
+
+    #[test]
+    fn a_long_drain_does_not_wind_up_the_integral() {
+        let mut controller = RateController::new(4000);
+        for _ in 0..60 * 300 {
+            assert!((controller.update(0) - 1.005).abs() < 1e-9);
+        }
+        // back at the target, the ratio returns to 1.0 instead of overfilling for minutes
+        for _ in 0..300 {
+            controller.update(4000);
+        }
+        assert!((controller.ratio() - 1.0).abs() < 1e-4, "{}", controller.ratio());
+        controller.reset();
+        assert_eq!(controller.update(4000), 1.0);
+    }
+
+    #[test]
+    fn unity_ratio_passes_blocks_through_one_frame_late() {
+        let mut resampler = StreamResampler::new(2);
+        let mut out = Vec::new();
+        resampler.process(&[1, 2, 3, 4, 5, 6], 1.0, &mut out);
+        resampler.process(&[7, 8, 9, 10], 1.0, &mut out);
+        assert_eq!(out, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn ratio_changes_the_output_length_smoothly() {
+        let mut resampler = StreamResampler::new(1);
+        let ramp: Vec<u16> = (0..1000).map(|i| 0x4000 + i * 16).collect();
+        let mut out = Vec::new();
+        for block in ramp.chunks(100) {
+            resampler.process(block, 1.005, &mut out);
+        }
+        assert!((out.len() as i32 - 1004).abs() <= 1);
+        // a ramp stays a ramp across block boundaries
+        assert!(out.windows(2).all(|w| w[1] >= w[0] && w[1] - w[0] <= 16));
+    }
+
+    #[test]
+    fn av_sync_converges_against_a_skewed_clock() {
+        // the sound card runs 0.1% fast against the video clock and starts 20 ms ahead
+        let mut sync = AvSync::new(48000, 1);
+        let block = vec![0x8000; 480];
+        let (mut played, mut out) = (960.0, Vec::new());
+        for tick in 0..100 * 600 {
+            let master = Duration::from_millis(tick * 10);
+            sync.update(master, Duration::from_secs_f64(played / 48000.0));
+            assert!((sync.ratio() - 1.0).abs() <= AV_MAX_DEVIATION + 1e-12);
+            sync.process(&block, &mut out);
+            out.clear();
+            played += 480.0 * 1.001;
+            if tick > 100 * 60 {
+                assert!(sync.error_ms().abs() < 4.0, "tick {}: {} ms", tick, sync.error_ms());
+            }
+        }
+        assert!(sync.is_correcting());
+    }
+
+    #[test]
+    fn av_sync_ignores_small_jittery_errors() {
+        let mut sync = AvSync::new(48000, 2);
+        for tick in 0..1000u64 {
+            // readings a block apart, so up to a millisecond either side
+            let jitter = [0.0, 0.9, -0.7, 0.4][tick as usize % 4];
+            let audio = Duration::from_secs_f64((tick as f64 * 10.0 + 5.0 + jitter) / 1000.0);
+            assert_eq!(sync.update(Duration::from_millis(tick * 10 + 5), audio), 1.0);
+        }
+        assert!(!sync.is_correcting());
+    }
+
+    #[test]
+    fn drift_estimate_sees_through_scheduling_jitter() {
+        // a card 0.1% fast, its 512-frame callbacks starting up to 3 ms late
+        let true_rate = 48048.0;
+        let mut drift = DriftEstimator::new(48000);
+        let mut rng = Rng(7);
+        assert_eq!(drift.measured_rate(), None);
+        let blocks = 60 * 48048 / 512;
+        for block in 0..blocks {
+            let ideal = block as f64 * 512.0 / true_rate;
+            drift.observe(ideal + (rng.next_signed() + 1.0) * 0.0015, 512);
+        }
+        let measured = drift.measured_rate().unwrap();
+        assert!((measured - true_rate).abs() < 2.0, "{}", measured);
+        // a minute at 0.1% fast is 60 ms ahead, give or take the last block's jitter
+        let ahead = drift.clock_drift().unwrap();
+        assert!((ahead - 0.06).abs() < 0.004, "{}", ahead);
+        // any two observations alone are off by far more
+        let pair: Vec<_> = drift.observations().skip(drift.len - 2).collect();
+        let two_point = (pair[1].1 - pair[0].1) as f64 / (pair[1].0 - pair[0].0);
+        assert!((two_point - true_rate).abs() > (measured - true_rate).abs());
+    }
+
+    #[test]
+    fn drift_estimate_starts_over_after_a_pause() {
+        // as above, but the device is paused for five seconds half way through
+        let true_rate = 48048.0;
+        let mut drift = DriftEstimator::new(48000);
+        let mut rng = Rng(7);
+        let blocks = 60 * 48048 / 512;
+        let mut observe = |drift: &mut DriftEstimator, block: usize| {
+            let pause = if block < blocks / 2 { 0.0 } else { 5.0 };
+            let ideal = block as f64 * 512.0 / true_rate + pause;
+            drift.observe(ideal + (rng.next_signed() + 1.0) * 0.0015, 512);
+        };
+        (0..blocks / 2).for_each(|block| observe(&mut drift, block));
+        assert!(drift.measured_rate().is_some());
+        // the first callback after it starts over, so there's no estimate for a while
+        observe(&mut drift, blocks / 2);
+        assert_eq!(drift.measured_rate(), None);
+        // ten seconds on, the pause isn't read as the card running slow
+        let ten_seconds = 10 * 48048 / 512;
+        (blocks / 2 + 1..blocks / 2 + ten_seconds).for_each(|block| observe(&mut drift, block));
+        let measured = drift.measured_rate().unwrap();
+        assert!((measured - true_rate).abs() < 10.0, "{}", measured);
+        (blocks / 2 + ten_seconds..blocks).for_each(|block| observe(&mut drift, block));
+        let measured = drift.measured_rate().unwrap();
+        assert!((measured - true_rate).abs() < 2.0, "{}", measured);
+        // thirty seconds since the pause at 0.1% fast
+        let ahead = drift.clock_drift().unwrap();
+        assert!((ahead - 0.03).abs() < 0.004, "{}", ahead);
+    }
+}
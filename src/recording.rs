@@ -0,0 +1,158 @@
+//! `Control::start_recording`: the callback copies every output block into a
+//! pre-allocated ring, and a writer thread moves it on to the sink. The callback never
+//! waits on the writer; a block that doesn't fit in the ring is dropped and counted.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::queue::{Counter, SampleRing};
+use crate::wav::wav_header;
+use crate::{AudioSpecInfo, Sample, SoundData16, WavError};
+
+// how much output the ring holds, so the writer can fall this far behind
+const RING_MS: usize = 500;
+// samples the writer moves per pass, and how long it sleeps once the ring is empty
+const WRITE_CHUNK: usize = 4096;
+const WRITER_IDLE: Duration = Duration::from_millis(5);
+
+/// Where `Control::start_recording` sends the output.
+#[derive(Debug, Clone)]
+pub enum RecordingSink {
+    /// A 16-bit PCM WAV file at the device's rate and channels, created or truncated.
+    Wav(PathBuf),
+    /// Appended to the vector as offset-binary samples, e.g. for golden-output tests.
+    Memory(Arc<Mutex<SoundData16>>),
+}
+
+/// Returned by `Control::stop_recording`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RecordingStats {
+    /// Interleaved samples that reached the sink.
+    pub samples_written: u64,
+    /// Output blocks left out because the writer had fallen too far behind.
+    pub dropped_blocks: u64,
+}
+
+struct Shared {
+    // only the callback adds to it
+    dropped: Counter,
+    stop: AtomicBool,
+}
+
+/// The callback's end of a recording, and the writer thread behind it.
+pub(crate) struct RecordingTap {
+    ring: Arc<SampleRing>,
+    shared: Arc<Shared>,
+    writer: Option<JoinHandle<Result<u64, WavError>>>,
+}
+
+enum Output {
+    Wav { file: BufWriter<File>, bytes: u64, spec: AudioSpecInfo },
+    Memory(Arc<Mutex<SoundData16>>),
+}
+
+impl Output {
+    fn write<T: Sample>(&mut self, samples: &[T]) -> Result<(), WavError> {
+        match self {
+            Output::Wav { file, bytes, .. } => {
+                for sample in samples {
+                    file.write_all(&i16::from_i32(sample.to_i32()).to_le_bytes())?;
+                }
+                *bytes += samples.len() as u64 * 2;
+            }
+            Output::Memory(data) => {
+                let mut data = data.lock().unwrap_or_else(PoisonError::into_inner);
+                data.extend(samples.iter().map(|sample| u16::from_i32(sample.to_i32())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts the final length into the WAV header.
+    fn finish(self) -> Result<(), WavError> {
+        if let Output::Wav { mut file, bytes, spec } = self {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&wav_header(bytes.min(u32::MAX as u64 - 36) as u32, spec.freq as u32, spec.channels))?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl RecordingTap {
+    pub(crate) fn start<T: Sample>(sink: RecordingSink, spec: AudioSpecInfo) -> Result<Self, WavError> {
+        let mut output = match sink {
+            RecordingSink::Wav(path) => {
+                let mut file = BufWriter::new(File::create(path)?);
+                // rewritten with the real length at the end
+                file.write_all(&wav_header(0, spec.freq as u32, spec.channels))?;
+                Output::Wav { file, bytes: 0, spec }
+            }
+            RecordingSink::Memory(data) => Output::Memory(data),
+        };
+        let frame = (spec.channels as usize).max(1);
+        let block = spec.samples as usize * frame;
+        let len = (spec.freq.max(0) as usize * frame * RING_MS / 1000).max(block * 4);
+        let ring = Arc::new(SampleRing::new(len));
+        let shared = Arc::new(Shared { dropped: Counter::default(), stop: AtomicBool::new(false) });
+        let writer = {
+            let (ring, shared) = (ring.clone(), shared.clone());
+            std::thread::spawn(move || {
+                let mut chunk: Vec<T> = Vec::with_capacity(WRITE_CHUNK);
+                let mut written = 0;
+                loop {
+                    // read before draining, so nothing pushed before the stop is missed
+                    let stopping = shared.stop.load(Ordering::Acquire);
+                    ring.pop::<T>(WRITE_CHUNK, |sample| chunk.push(sample));
+                    if chunk.is_empty() {
+                        if stopping {
+                            break;
+                        }
+                        std::thread::sleep(WRITER_IDLE);
+                        continue;
+                    }
+                    output.write(&chunk)?;
+                    written += chunk.len() as u64;
+                    chunk.clear();
+                }
+                output.finish()?;
+                Ok(written)
+            })
+        };
+        Ok(Self { ring, shared, writer: Some(writer) })
+    }
+
+    /// Called from the audio callback with each finished block; never blocks.
+    pub(crate) fn push<T: Sample>(&self, block: &[T]) {
+        if self.ring.free() < block.len() {
+            let dropped = &self.shared.dropped;
+            dropped.store(dropped.load() + 1);
+        } else {
+            self.ring.push(block);
+        }
+    }
+
+    /// Waits for the writer to save everything pushed so far.
+    pub(crate) fn finish(mut self) -> Result<RecordingStats, WavError> {
+        self.shared.stop.store(true, Ordering::Release);
+        let written = match self.writer.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result?,
+            Some(Err(payload)) => std::panic::resume_unwind(payload),
+            None => 0,
+        };
+        let dropped_blocks = self.shared.dropped.load();
+        Ok(RecordingStats { samples_written: written, dropped_blocks })
+    }
+}
+
+/// A recording dropped with its device still finishes in the background.
+impl Drop for RecordingTap {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+    }
+}
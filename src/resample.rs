@@ -0,0 +1,83 @@
+//! Offline sample-rate conversion.
+//!
+//! Linear interpolation is cheap (two multiplies per output sample) and fine for
+//! upsampling game assets, but it attenuates the top octave slightly and does no
+//! anti-aliasing when downsampling: content above the new Nyquist frequency folds back.
+//! Low-pass the data first (e.g. `effects::LowPass`) when downsampling bright material.
+
+use crate::{Sample, SoundData16};
+
+/// Resamples interleaved offset-binary `data` with `channels` channels from `from_hz`
+/// to `to_hz`, each channel on its own. A trailing partial frame is dropped.
+pub fn resample(data: &[u16], from_hz: u32, to_hz: u32, channels: u8) -> SoundData16 {
+    let channels = (channels as usize).max(1);
+    let frames = data.len() / channels;
+    if from_hz == 0 || to_hz == 0 || frames == 0 {
+        return Vec::new();
+    }
+    if from_hz == to_hz {
+        return data[..frames * channels].to_vec();
+    }
+    let out_frames = (frames as u64 * to_hz as u64).div_ceil(from_hz as u64) as usize;
+    let step = from_hz as f64 / to_hz as f64;
+    let mut out = Vec::with_capacity(out_frames * channels);
+    for j in 0..out_frames {
+        let pos = j as f64 * step;
+        let i = (pos as usize).min(frames - 1);
+        let next = (i + 1).min(frames - 1);
+        let frac = pos - i as f64;
+        for c in 0..channels {
+            let a = data[i * channels + c].to_i32() as f64;
+            let b = data[next * channels + c].to_i32() as f64;
+            out.push(u16::from_i32((a + (b - a) * frac).round() as i32));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generators, SETUP_U16};
+
+    fn rising_crossings(data: &[u16]) -> Vec<usize> {
+        data.windows(2)
+            .enumerate()
+            .filter(|(_, w)| (w[0] as i32) < SETUP_U16 && w[1] as i32 >= SETUP_U16)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    #[test]
+    fn upsampling_a_sine_keeps_its_frequency() {
+        // 441 Hz is a 50-sample period at 22050 Hz and 100 samples at 44100 Hz
+        let data = generators::sine(441.0, 22050, 2205, 10000);
+        let up = resample(&data, 22050, 44100, 1);
+        assert_eq!(up.len(), 4410);
+        let crossings = rising_crossings(&up);
+        assert_eq!(crossings.len(), rising_crossings(&data).len());
+        assert!(crossings.windows(2).all(|w| (99..=101).contains(&(w[1] - w[0]))));
+        // the last output frame holds the final input sample rather than extrapolating
+        let reference = generators::sine(441.0, 44100, 4409, 10000);
+        assert!(up.iter().zip(&reference).all(|(a, b)| (*a as i32 - *b as i32).abs() < 200));
+    }
+
+    #[test]
+    fn downsampling_halves_and_keeps_the_midpoint() {
+        let data = vec![SETUP_U16 as u16; 480];
+        let down = resample(&data, 48000, 24000, 1);
+        assert_eq!(down, vec![SETUP_U16 as u16; 240]);
+        let ramp: Vec<u16> = (0..8).map(|i| i * 1000).collect();
+        assert_eq!(resample(&ramp, 4, 2, 1), [0, 2000, 4000, 6000]);
+    }
+
+    #[test]
+    fn channels_are_resampled_separately() {
+        let stereo = [0, 0xffff, 0, 0xffff, 0, 0xffff];
+        let up = resample(&stereo, 1, 2, 2);
+        assert_eq!(up.len(), 12);
+        assert!(up.chunks(2).all(|frame| frame == [0, 0xffff]));
+        assert_eq!(resample(&[1, 2, 3], 1, 1, 2), [1, 2]);
+        assert!(resample(&[1, 2, 3], 0, 1, 1).is_empty());
+    }
+}
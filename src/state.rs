@@ -0,0 +1,284 @@
+use crate::{read_wrapping, AudioError, LoopCount, PlayMode, PlaybackStatus, Sample, Sound, MAX_VOLUME};
+
+/// The sound of a locked device, handed out by `Control::with_locked`. The callback
+/// can't run while it exists, so it sees either none or all of the changes made.
+///
+/// The methods behave like their `Control` namesakes. The buffer is only reachable
+/// through them, so its length always matches `buf_samples`.
+pub struct SoundState<'a, T: Sample = u16> {
+    sound: &'a mut Sound<T>,
+}
+
+impl<T: Sample + std::fmt::Debug> std::fmt::Debug for SoundState<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.sound.fmt(f)
+    }
+}
+
+impl<'a, T: Sample> SoundState<'a, T> {
+    pub(crate) fn new(sound: &'a mut Sound<T>) -> Self {
+        Self { sound }
+    }
+
+    pub fn set_mute(&mut self, specifier: bool) {
+        self.sound.mute = specifier;
+    }
+
+    pub fn mute(&self) -> bool {
+        self.sound.mute
+    }
+
+    pub fn set_volume(&mut self, volume: u16) {
+        self.sound.set_volume(volume);
+    }
+
+    pub fn volume(&self) -> u16 {
+        self.sound.volume()
+    }
+
+    pub fn set_gain(&mut self, gain: u16) {
+        self.sound.set_gain(gain);
+    }
+
+    pub fn gain(&self) -> u16 {
+        self.sound.gain()
+    }
+
+    pub fn set_master_volume(&mut self, volume: u16) {
+        self.sound.master_volume = volume.min(MAX_VOLUME);
+    }
+
+    pub fn master_volume(&self) -> u16 {
+        self.sound.master_volume
+    }
+
+    pub fn set_mode(&mut self, mode: PlayMode) {
+        self.sound.mode = mode;
+    }
+
+    pub fn mode(&self) -> PlayMode {
+        self.sound.mode
+    }
+
+    pub fn set_pan(&mut self, pan: f32) {
+        self.sound.set_pan(pan);
+    }
+
+    pub fn clear_pan(&mut self) {
+        self.sound.pan = None;
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        self.sound.set_rate(rate);
+    }
+
+    pub fn set_reversed(&mut self, reversed: bool) {
+        self.sound.reversed = reversed;
+    }
+
+    pub fn set_data(&mut self, offset: usize, sound: &[T]) {
+        self.sound.set_data_wrapping(offset, sound);
+    }
+
+    pub fn get_data(&self, offset: usize, out: &mut [T]) {
+        read_wrapping(&self.sound.buffer, offset, out);
+    }
+
+    pub fn fill(&mut self, value: T) {
+        self.sound.buffer.fill(value);
+        self.sound.generation += 1;
+    }
+
+    pub fn write(&mut self, samples: &[T]) -> usize {
+        self.sound.write(samples)
+    }
+
+    pub fn write_available(&self) -> usize {
+        self.sound.write_available()
+    }
+
+    pub fn set_current(&mut self, pos: usize) {
+        self.sound.set_current(pos);
+    }
+
+    pub fn rewind(&mut self) {
+        self.sound.set_current(0);
+    }
+
+    pub fn restart(&mut self) {
+        self.sound.restart();
+    }
+
+    pub fn current(&self) -> u64 {
+        self.sound.current
+    }
+
+    pub fn remain(&self) -> usize {
+        self.sound.remain
+    }
+
+    pub fn buf_samples(&self) -> usize {
+        self.sound.buf_size
+    }
+
+    #[deprecated(note = "counts samples, not frames; use `buf_samples`")]
+    pub fn buf_size(&self) -> usize {
+        self.buf_samples()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.sound.channels
+    }
+
+    pub fn finished(&self) -> bool {
+        self.sound.finished
+    }
+
+    pub fn set_loop_count(&mut self, count: LoopCount) {
+        self.sound.set_loop_count(count);
+    }
+
+    pub fn set_loop_region(&mut self, start: usize, end: usize) -> Result<(), AudioError> {
+        self.sound.set_loop_region(start, end)
+    }
+
+    pub fn clear_loop_region(&mut self) {
+        self.sound.loop_region = None;
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        self.sound.status()
+    }
+}
+
+/// Where a device is, from `Control::state`. Derived from the device and its sound in
+/// one place, so exactly one applies; when several flags are set the earlier variant
+/// wins, e.g. a lost device is `DeviceLost` whatever else it was doing.
+///
+/// `pause`, `resume`, `restart` and `stop` move between the first four as follows;
+/// anything not listed is a no-op:
+///
+/// | from       | pause  | resume  | restart           | stop    |
+/// |------------|--------|---------|-------------------|---------|
+/// | `Playing`  | Paused |         | Playing, rewound  | Stopped |
+/// | `Paused`   |        | Playing | Paused, rewound   | Stopped |
+/// | `Finished` |        |         | Playing, rewound  | Stopped |
+/// | `Stopped`  |        | Playing | Playing, rewound  |         |
+///
+/// A one-shot reaching its end, or a loop its `LoopCount`, goes from `Playing` to
+/// `Finished`. `DeviceLost` and `Poisoned` are final: reopen the device.
+///
+/// The table applies to the `Control` methods. A device's own inherent `pause` and
+/// `resume` (`AudioDevice`'s, `MockDevice`'s) only halt or run the callback, so on a
+/// concrete device call them as `Control::pause(&mut device)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    DeviceLost,
+    Poisoned,
+    /// Paused and rewound by `stop`.
+    Stopped,
+    Finished,
+    Paused,
+    Playing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Request {
+    Pause,
+    Resume,
+    Restart,
+    Stop,
+}
+
+/// What taking a request from a state does: run or halt the device (`None` leaves it),
+/// rewind, and whether the sound counts as stopped afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Step {
+    pub(crate) run: Option<bool>,
+    pub(crate) rewind: bool,
+    pub(crate) stopped: bool,
+}
+
+impl PlaybackState {
+    pub(crate) fn derive(alive: bool, paused: bool, sound: &Sound<impl Sample>) -> Self {
+        if !alive {
+            PlaybackState::DeviceLost
+        } else if sound.poisoned.is_some() {
+            PlaybackState::Poisoned
+        } else if sound.stopped && paused {
+            // a device resumed behind `Control`'s back isn't stopped any more
+            PlaybackState::Stopped
+        } else if sound.finished {
+            PlaybackState::Finished
+        } else if paused {
+            PlaybackState::Paused
+        } else {
+            PlaybackState::Playing
+        }
+    }
+
+    /// The transition table above; `None` for a no-op.
+    pub(crate) fn step(self, request: Request) -> Option<Step> {
+        use PlaybackState::*;
+        let step = |run, rewind, stopped| Some(Step { run, rewind, stopped });
+        match (self, request) {
+            (Playing, Request::Pause) => step(Some(false), false, false),
+            (Paused | Stopped, Request::Resume) => step(Some(true), false, false),
+            (Finished | Stopped, Request::Restart) => step(Some(true), true, false),
+            (Playing | Paused, Request::Restart) => step(None, true, false),
+            (Playing | Paused | Finished, Request::Stop) => step(Some(false), true, true),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use sdl2::audio::AudioCallback;
+
+    use super::*;
+    use crate::AudioSpecInfo;
+
+    #[test]
+    fn the_callback_never_sees_a_half_applied_batch() {
+        // the mutex stands in for the device lock `with_locked` takes
+        let mut sound = Sound::<u16>::new(64, AudioSpecInfo { freq: 48000, channels: 1, samples: 64 });
+        sound.ramp_samples = 0;
+        sound.mode = PlayMode::Loop;
+        let sound = Arc::new(Mutex::new(sound));
+        let stop = Arc::new(AtomicBool::new(false));
+        let player = {
+            let (sound, stop) = (sound.clone(), stop.clone());
+            std::thread::spawn(move || {
+                let mut out = [0u16; 64];
+                let mut blocks = 0;
+                while !stop.load(Ordering::Relaxed) || blocks == 0 {
+                    sound.lock().unwrap().callback(&mut out);
+                    // either the silent setup or a whole block of the data at full volume
+                    assert!(out.iter().all(|s| *s == out[0]));
+                    assert!(out[0] == 0x8000 || out[0] == 0xc000, "{:#x}", out[0]);
+                    blocks += 1;
+                }
+            })
+        };
+        for i in 0..2000 {
+            let mut locked = sound.lock().unwrap();
+            let mut state = SoundState::new(&mut locked);
+            if i % 2 == 0 {
+                state.set_volume(MAX_VOLUME);
+                state.set_mute(false);
+                state.fill(0xc000);
+                state.rewind();
+            } else {
+                state.set_volume(0);
+                state.fill(0x9000);
+                state.set_mute(true);
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        player.join().unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::pcm::{self, PcmFormat};
+use crate::{Control, PlaybackStatus, SoundData16};
+
+const READ_CHUNK: usize = 4096;
+
+/// What one `Streamer::pump` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pumped {
+    /// Samples handed to the device.
+    pub written: usize,
+    /// The device starved at least once since the previous pump, i.e. pump came too late.
+    pub underrun: bool,
+    /// The source is exhausted and everything read from it has been written.
+    pub end_of_stream: bool,
+}
+
+/// Feeds raw PCM from a reader into a `PlayMode::Stream` device, keeping `write_ahead`
+/// samples queued in front of the play cursor. Call `pump` regularly from the game loop.
+pub struct Streamer<R: Read> {
+    source: R,
+    format: PcmFormat,
+    write_ahead: usize,
+    rewind: Option<fn(&mut R) -> io::Result<()>>,
+    // raw bytes read but not yet converted, and converted samples the device didn't take
+    pending: Vec<u8>,
+    queued: SoundData16,
+    eof: bool,
+    read_since_rewind: bool,
+    last_underruns: Option<u64>,
+}
+
+impl<R: Read> Streamer<R> {
+    pub fn new(source: R, format: PcmFormat, write_ahead: usize) -> Self {
+        Self {
+            source,
+            format,
+            write_ahead,
+            rewind: None,
+            pending: Vec::new(),
+            queued: Vec::new(),
+            eof: false,
+            read_since_rewind: false,
+            last_underruns: None,
+        }
+    }
+
+    pub fn write_ahead(&self) -> usize {
+        self.write_ahead
+    }
+
+    pub fn set_write_ahead(&mut self, samples: usize) {
+        self.write_ahead = samples;
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.rewind.is_some()
+    }
+
+    /// True once `pump` has reported `end_of_stream`.
+    pub fn finished(&self) -> bool {
+        self.eof && self.queued.is_empty() && self.pending.len() < self.format.frame_bytes()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    /// Tops the device up to `write_ahead` queued samples.
+    pub fn pump<C: Control>(&mut self, device: &mut C) -> io::Result<Pumped> {
+        let status = device.playback_status();
+        self.fill(&status, |samples| device.write(samples))
+    }
+
+    fn fill(&mut self, status: &PlaybackStatus, mut write: impl FnMut(&[u16]) -> usize) -> io::Result<Pumped> {
+        let underrun = self.last_underruns.is_some_and(|last| status.underruns > last);
+        self.last_underruns = Some(status.underruns);
+        let channels = (self.format.channels as usize).max(1);
+        let room = self.write_ahead.saturating_sub(status.remain)
+            // `set_data` adds to `remain` without capping it outside `Stream` mode
+            .min(status.buf_size.saturating_sub(status.remain));
+        let wanted = room - room % channels;
+        let mut written = 0;
+        if wanted > self.queued.len() {
+            let more = self.read_samples(wanted - self.queued.len())?;
+            self.queued.extend_from_slice(&more);
+        }
+        if wanted > 0 && !self.queued.is_empty() {
+            let count = wanted.min(self.queued.len());
+            written = write(&self.queued[..count]);
+            self.queued.drain(..written);
+        }
+        Ok(Pumped { written, underrun, end_of_stream: self.finished() })
+    }
+
+    fn read_samples(&mut self, samples: usize) -> io::Result<SoundData16> {
+        let frame_bytes = self.format.frame_bytes();
+        let need = samples / (self.format.channels as usize).max(1) * frame_bytes;
+        let mut chunk = [0u8; READ_CHUNK];
+        while self.pending.len() < need && !self.eof {
+            let n = match self.source.read(&mut chunk) {
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if n > 0 {
+                self.pending.extend_from_slice(&chunk[..n]);
+                self.read_since_rewind = true;
+                continue;
+            }
+            match self.rewind {
+                // a source that is empty from the start would loop forever
+                Some(rewind) if self.read_since_rewind => {
+                    // a partial frame at the end would shift every channel after the loop
+                    let whole = self.pending.len() - self.pending.len() % frame_bytes;
+                    self.pending.truncate(whole);
+                    rewind(&mut self.source)?;
+                    self.read_since_rewind = false;
+                }
+                _ => self.eof = true,
+            }
+        }
+        let usable = self.pending.len().min(need);
+        let usable = usable - usable % frame_bytes;
+        let data = pcm::load_raw(&self.pending[..usable], self.format)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.pending.drain(..usable);
+        Ok(data)
+    }
+}
+
+impl<R: Read + Seek> Streamer<R> {
+    /// Seeks back to the start at the end of the source instead of ending the stream.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.rewind = if looping {
+            Some(|source: &mut R| source.seek(SeekFrom::Start(0)).map(|_| ()))
+        } else {
+            None
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use sdl2::audio::AudioCallback;
+
+    use super::*;
+    use crate::pcm::{Endian, PcmSample};
+    use crate::{AudioSpecInfo, Sound};
+
+    fn device(len: usize) -> Sound<u16> {
+        let mut sound = Sound::new(len, AudioSpecInfo { freq: 8000, channels: 1, samples: 4 });
+        sound.ramp_samples = 0;
+        sound.set_volume(crate::MAX_VOLUME);
+        sound
+    }
+
+    fn source(samples: std::ops::Range<i16>) -> Cursor<Vec<u8>> {
+        Cursor::new(samples.flat_map(|s| s.to_le_bytes()).collect())
+    }
+
+    fn pump(streamer: &mut Streamer<Cursor<Vec<u8>>>, sound: &mut Sound<u16>) -> Pumped {
+        let status = sound.status();
+        streamer.fill(&status, |samples| sound.write(samples)).unwrap()
+    }
+
+    fn i16_mono() -> PcmFormat {
+        PcmFormat::new(PcmSample::I16, Endian::Little, 1)
+    }
+
+    #[test]
+    fn stays_write_ahead_and_reports_the_end() {
+        let mut sound = device(16);
+        let mut streamer = Streamer::new(source(0..10), i16_mono(), 6);
+        assert_eq!(pump(&mut streamer, &mut sound), Pumped { written: 6, ..Default::default() });
+        assert_eq!(pump(&mut streamer, &mut sound).written, 0);
+        let mut out = [0u16; 4];
+        sound.callback(&mut out);
+        assert_eq!(out.map(|s| s as i32 - 0x8000), [0, 1, 2, 3]);
+        assert_eq!(pump(&mut streamer, &mut sound), Pumped { written: 4, ..Default::default() });
+        assert_eq!(sound.remain, 6);
+        // the end is only noticed when a read comes back empty
+        sound.callback(&mut out);
+        assert_eq!(pump(&mut streamer, &mut sound), Pumped { written: 0, underrun: false, end_of_stream: true });
+        assert!(streamer.finished());
+    }
+
+    #[test]
+    fn detects_late_pumps() {
+        let mut sound = device(16);
+        let mut streamer = Streamer::new(source(0..100), i16_mono(), 4);
+        pump(&mut streamer, &mut sound);
+        let mut out = [0u16; 8];
+        sound.callback(&mut out);
+        let pumped = pump(&mut streamer, &mut sound);
+        assert!(pumped.underrun);
+        assert!(!pump(&mut streamer, &mut sound).underrun);
+    }
+
+    #[test]
+    fn looping_restarts_at_the_beginning() {
+        let mut sound = device(16);
+        let mut streamer = Streamer::new(source(0..3), i16_mono(), 8);
+        streamer.set_looping(true);
+        let pumped = pump(&mut streamer, &mut sound);
+        assert_eq!(pumped.written, 8);
+        assert!(!pumped.end_of_stream);
+        let mut out = [0u16; 8];
+        sound.callback(&mut out);
+        assert_eq!(out.map(|s| s as i32 - 0x8000), [0, 1, 2, 0, 1, 2, 0, 1]);
+        let mut empty = Streamer::new(Cursor::new(Vec::new()), i16_mono(), 8);
+        empty.set_looping(true);
+        assert!(pump(&mut empty, &mut sound).end_of_stream);
+    }
+
+    #[test]
+    fn an_overfull_buffer_takes_nothing() {
+        let mut sound = device(16);
+        // three overlapping set_data calls count more than the buffer holds
+        for _ in 0..3 {
+            sound.set_data_wrapping(0, &[0x9000; 8]);
+        }
+        assert!(sound.remain > sound.buf_size);
+        let mut streamer = Streamer::new(source(0..100), i16_mono(), 64);
+        assert_eq!(pump(&mut streamer, &mut sound), Pumped::default());
+    }
+}
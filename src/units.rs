@@ -0,0 +1,113 @@
+//! Newtypes for the units positions and lengths come in, so a count of interleaved
+//! samples can't be passed where frames are meant. A frame holds one sample per
+//! channel: one second of 48 kHz stereo is `Frames(48000)` or `Samples(96000)`.
+//!
+//! The raw `usize`/`u64` methods of `Control` count interleaved samples; the typed ones
+//! (`set_data_at`, `position`, `set_position`) take and return these.
+
+use std::ops::{Add, Sub};
+use std::time::Duration;
+
+/// Interleaved samples, counting every channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Samples(pub u64);
+
+/// Frames of one sample per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Frames(pub u64);
+
+/// Frames per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SampleRate(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Channels(pub u8);
+
+impl Channels {
+    /// Samples per frame, treating 0 channels as 1 like the devices do.
+    fn per_frame(self) -> u64 {
+        (self.0 as u64).max(1)
+    }
+}
+
+impl Samples {
+    /// The frames these samples make up, leaving out a trailing partial frame.
+    pub fn to_frames(self, channels: Channels) -> Frames {
+        Frames(self.0 / channels.per_frame())
+    }
+
+    pub fn to_duration(self, rate: SampleRate, channels: Channels) -> Duration {
+        self.to_frames(channels).to_duration(rate)
+    }
+}
+
+impl Frames {
+    pub fn to_samples(self, channels: Channels) -> Samples {
+        Samples(self.0 * channels.per_frame())
+    }
+
+    /// How long the frames play at `rate`; zero at a rate of 0.
+    pub fn to_duration(self, rate: SampleRate) -> Duration {
+        if rate.0 == 0 {
+            return Duration::ZERO;
+        }
+        let rate = rate.0 as u64;
+        Duration::new(self.0 / rate, ((self.0 % rate) * 1_000_000_000 / rate) as u32)
+    }
+
+    /// The whole frames that fit in `duration` at `rate`, rounded down.
+    pub fn from_duration(duration: Duration, rate: SampleRate) -> Frames {
+        Frames((duration.as_nanos() * rate.0 as u128 / 1_000_000_000) as u64)
+    }
+}
+
+macro_rules! impl_arithmetic {
+    ($($unit:ident),*) => {
+        $(
+            impl Add for $unit {
+                type Output = $unit;
+
+                fn add(self, other: $unit) -> $unit {
+                    $unit(self.0 + other.0)
+                }
+            }
+
+            impl Sub for $unit {
+                type Output = $unit;
+
+                fn sub(self, other: $unit) -> $unit {
+                    $unit(self.0 - other.0)
+                }
+            }
+        )*
+    };
+}
+
+impl_arithmetic!(Samples, Frames);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEREO: Channels = Channels(2);
+
+    #[test]
+    fn stereo_frames_hold_two_samples() {
+        assert_eq!(Frames(48000).to_samples(STEREO), Samples(96000));
+        assert_eq!(Samples(96001).to_frames(STEREO), Frames(48000));
+        assert_eq!(Samples(96000).to_duration(SampleRate(48000), STEREO), Duration::from_secs(1));
+        assert_eq!(Samples(5).to_frames(Channels(0)), Frames(5));
+    }
+
+    #[test]
+    fn durations_convert_both_ways() {
+        let rate = SampleRate(44100);
+        assert_eq!(Frames(22050).to_duration(rate), Duration::from_millis(500));
+        assert_eq!(Frames(44101).to_duration(rate), Duration::new(1, 22675));
+        assert_eq!(Frames::from_duration(Duration::from_millis(500), rate), Frames(22050));
+        // a little short of a whole frame rounds down
+        assert_eq!(Frames::from_duration(Duration::from_micros(22), rate), Frames(0));
+        assert_eq!(Frames(10).to_duration(SampleRate(0)), Duration::ZERO);
+        assert_eq!(Frames(3) + Frames(4) - Frames(2), Frames(5));
+    }
+}
@@ -0,0 +1,247 @@
+//! RIFF/WAVE reading and writing without extra dependencies. Every supported encoding
+//! is converted to the crate's offset-binary `u16`, optionally dithered for float and
+//! 24-bit sources (`WavOptions`); files are written as 16-bit PCM.
+
+use std::path::Path;
+
+use crate::generators::Rng;
+use crate::pcm::dither_to_u16;
+use crate::{ChannelError, Sample, SoundData16, WavError};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Interleaved samples as stored in the file, with the layout needed to play them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedSound {
+    pub data: SoundData16,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl LoadedSound {
+    pub fn frames(&self) -> usize {
+        self.data.len() / (self.channels as usize).max(1)
+    }
+
+    /// The data for a device with `channels` channels, see `channels::convert`.
+    pub fn into_channels(self, channels: u8) -> Result<SoundData16, ChannelError> {
+        let from = u8::try_from(self.channels).map_err(|_| ChannelError::TooManyChannels(self.channels as usize))?;
+        if from == channels {
+            return Ok(self.data);
+        }
+        crate::channels::convert(&self.data, from, channels)
+    }
+}
+
+/// How `load_wav_with` converts sources finer than 16 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WavOptions {
+    /// TPDF dither seeded with this value for float and 24-bit data, see
+    /// `pcm::from_f32_dithered`. `None` rounds to the nearest step.
+    pub dither: Option<u64>,
+}
+
+pub fn load_wav(path: &Path) -> Result<LoadedSound, WavError> {
+    decode_wav(&std::fs::read(path)?)
+}
+
+pub fn load_wav_with(path: &Path, options: WavOptions) -> Result<LoadedSound, WavError> {
+    decode_wav_with(&std::fs::read(path)?, options)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// `load_wav` on a file already in memory.
+pub fn decode_wav(bytes: &[u8]) -> Result<LoadedSound, WavError> {
+    decode_wav_with(bytes, WavOptions::default())
+}
+
+/// `load_wav_with` on a file already in memory.
+pub fn decode_wav_with(bytes: &[u8], options: WavOptions) -> Result<LoadedSound, WavError> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(WavError::Malformed("missing RIFF/WAVE header"));
+    }
+    let mut format = None;
+    let mut data = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = u32_at(bytes, pos + 4) as usize;
+        let body = bytes.get(pos + 8..pos + 8 + size).ok_or(WavError::Malformed("chunk runs past the end of the file"))?;
+        match id {
+            b"fmt " => format = Some(body),
+            b"data" => data = Some(body),
+            _ => (),
+        }
+        // chunks are padded to an even length
+        pos += 8 + size + size % 2;
+    }
+    let format = format.ok_or(WavError::Malformed("no fmt chunk"))?;
+    let data = data.ok_or(WavError::Malformed("no data chunk"))?;
+    if format.len() < 16 {
+        return Err(WavError::Malformed("fmt chunk too short"));
+    }
+    let mut format_tag = u16_at(format, 0);
+    let channels = u16_at(format, 2);
+    let sample_rate = u32_at(format, 4);
+    let bits_per_sample = u16_at(format, 14);
+    if format_tag == FORMAT_EXTENSIBLE {
+        // the real format is the first two bytes of the sub-format GUID
+        if format.len() < 26 {
+            return Err(WavError::Malformed("extensible fmt chunk too short"));
+        }
+        format_tag = u16_at(format, 24);
+    }
+    if channels == 0 {
+        return Err(WavError::Malformed("zero channels"));
+    }
+    // `value` is in 16-bit steps
+    let mut rng = options.dither.map(Rng);
+    let mut quantize = |value: f64| match rng.as_mut() {
+        Some(rng) => dither_to_u16(value, rng),
+        None => u16::from_i32(value.round() as i32),
+    };
+    let data = match (format_tag, bits_per_sample) {
+        (FORMAT_PCM, 8) => data.iter().map(|b| (*b as u16) << 8).collect(),
+        (FORMAT_PCM, 16) => data.chunks_exact(2).map(|b| u16::from_i16(i16::from_le_bytes([b[0], b[1]]))).collect(),
+        (FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            .map(|b| quantize((i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 256.0))
+            .collect(),
+        (FORMAT_IEEE_FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|b| quantize(f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 * 32768.0))
+            .collect(),
+        _ => return Err(WavError::Unsupported { format_tag, bits_per_sample }),
+    };
+    Ok(LoadedSound { data, sample_rate, channels })
+}
+
+/// Writes interleaved offset-binary `data` as a 16-bit PCM WAV file.
+pub fn save_wav(path: &Path, data: &[u16], sample_rate: u32, channels: u8) -> Result<(), WavError> {
+    std::fs::write(path, encode_wav(data, sample_rate, channels)?)?;
+    Ok(())
+}
+
+/// `save_wav` into memory.
+pub fn encode_wav(data: &[u16], sample_rate: u32, channels: u8) -> Result<Vec<u8>, WavError> {
+    if channels == 0 || !data.len().is_multiple_of(channels as usize) {
+        return Err(WavError::InvalidLayout { len: data.len(), channels });
+    }
+    let mut bytes = Vec::with_capacity(44 + data.len() * 2);
+    bytes.extend_from_slice(&wav_header(data.len() as u32 * 2, sample_rate, channels));
+    for sample in data {
+        bytes.extend_from_slice(&(sample.to_i32() as i16).to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+/// The 44 bytes in front of `data_len` bytes of 16-bit PCM.
+pub(crate) fn wav_header(data_len: u32, sample_rate: u32, channels: u8) -> Vec<u8> {
+    let block_align = channels as u16 * 2;
+    let mut bytes = Vec::with_capacity(44);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&36u32.saturating_add(data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+    bytes.extend_from_slice(&(channels as u16).to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Result<LoadedSound, WavError> {
+        load_wav(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name))
+    }
+
+    #[test]
+    fn loads_unsigned_8_bit_past_other_chunks() {
+        let sound = fixture("u8_mono.wav").unwrap();
+        assert_eq!((sound.sample_rate, sound.channels), (8000, 1));
+        assert_eq!(sound.data, [0x0000, 0x4000, 0x8000, 0xc000, 0xff00]);
+    }
+
+    #[test]
+    fn loads_interleaved_16_bit_stereo() {
+        let sound = fixture("i16_stereo.wav").unwrap();
+        assert_eq!((sound.sample_rate, sound.channels, sound.frames()), (44100, 2, 3));
+        assert_eq!(sound.data, [0x0000, 0xffff, 0x8000, 0x7fff, 0x8000 + 1000, 0x8000 - 1000]);
+    }
+
+    #[test]
+    fn converts_to_the_device_layout() {
+        let stereo = fixture("i16_stereo.wav").unwrap();
+        let mono = stereo.clone().into_channels(1).unwrap();
+        assert_eq!(mono.len(), stereo.frames());
+        assert_eq!(mono, crate::channels::downmix_to_mono(&stereo.data, 2).unwrap());
+        assert_eq!(stereo.clone().into_channels(2).unwrap(), stereo.data);
+        assert_eq!(stereo.into_channels(4), Err(ChannelError::Unsupported { from: 2, to: 4 }));
+    }
+
+    #[test]
+    fn loads_extensible_float() {
+        let sound = fixture("f32_mono.wav").unwrap();
+        assert_eq!(sound.sample_rate, 48000);
+        assert_eq!(sound.data, [0x0000, 0x8000, 0xc000, 0xffff]);
+    }
+
+    #[test]
+    fn loads_24_bit_with_optional_dither() {
+        // a 24-bit file's header is the 16-bit one with wider blocks
+        let samples: [i32; 4] = [-0x80_0000, 0x7f_ffff, 0x80, 0x200];
+        let mut bytes = wav_header(samples.len() as u32 * 3, 48000, 1);
+        bytes[28..32].copy_from_slice(&(48000u32 * 3).to_le_bytes());
+        bytes[32..36].copy_from_slice(&[3, 0, 24, 0]);
+        bytes.extend(samples.iter().flat_map(|s| s.to_le_bytes()[..3].to_vec()));
+        assert_eq!(decode_wav(&bytes).unwrap().data, [0x0000, 0xffff, 0x8001, 0x8002]);
+        let dithered = decode_wav_with(&bytes, WavOptions { dither: Some(5) }).unwrap();
+        assert_eq!(dithered, decode_wav_with(&bytes, WavOptions { dither: Some(5) }).unwrap());
+        assert_eq!(dithered.data[..2], [0x0000, 0xffff]);
+        assert!(dithered.data[2..].iter().all(|s| (0x7fff..=0x8003).contains(s)));
+    }
+
+    #[test]
+    fn rejects_compressed_and_broken_files() {
+        assert!(matches!(
+            fixture("adpcm.wav"),
+            Err(WavError::Unsupported { format_tag: 2, bits_per_sample: 4 })
+        ));
+        assert!(matches!(decode_wav(b"RIFF\0\0\0\0WAVEfmt "), Err(WavError::Malformed(_))));
+        assert!(matches!(decode_wav(b"not a wav file"), Err(WavError::Malformed(_))));
+        assert!(matches!(fixture("missing.wav"), Err(WavError::Io(_))));
+    }
+
+    #[test]
+    fn save_then_load_round_trips() {
+        let data: Vec<u16> = (0..64).map(|i| (i * 1021) as u16).collect();
+        let path = std::env::temp_dir().join(format!("audio-lib3-round-trip-{}.wav", std::process::id()));
+        save_wav(&path, &data, 22050, 2).unwrap();
+        let sound = load_wav(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sound, LoadedSound { data, sample_rate: 22050, channels: 2 });
+    }
+
+    #[test]
+    fn save_rejects_partial_frames() {
+        assert!(matches!(encode_wav(&[0; 5], 8000, 2), Err(WavError::InvalidLayout { len: 5, channels: 2 })));
+        assert!(matches!(encode_wav(&[0; 4], 8000, 0), Err(WavError::InvalidLayout { .. })));
+        assert_eq!(encode_wav(&[], 8000, 1).unwrap().len(), 44);
+    }
+}
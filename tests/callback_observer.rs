@@ -0,0 +1,67 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+
+use audio_lib3::{CallbackInfo, Control, PlayMode};
+
+mod common;
+
+use common::TIMEOUT;
+
+// an observer that forwards everything it is told
+fn forwarding(device: &mut impl Control) -> mpsc::Receiver<CallbackInfo> {
+    let (sender, receiver) = mpsc::channel();
+    device.on_callback(move |info| sender.send(info).unwrap());
+    receiver
+}
+
+// dropping the observer drops its sender, so the channel disconnects once it stopped
+fn assert_stopped<T>(receiver: &mpsc::Receiver<T>) {
+    loop {
+        match receiver.recv_timeout(TIMEOUT) {
+            Ok(_) => continue,
+            Err(error) => return assert_eq!(error, RecvTimeoutError::Disconnected),
+        }
+    }
+}
+
+// one test, as the dummy driver can only be brought up once per process
+#[test]
+fn the_observer_runs_once_per_callback_off_the_audio_thread() {
+    let mut mock = common::mock_device(4096);
+    mock.set_mode(PlayMode::Loop);
+    mock.resume();
+    let receiver = forwarding(&mut mock);
+    let audio_thread = std::thread::current().id();
+    for called in 1..=20 {
+        mock.drive_callback(512);
+        let info = receiver.recv_timeout(TIMEOUT).expect("a call for every callback");
+        assert_eq!((info.called, info.consumed), (called, called * 1024));
+    }
+    // a counter reset isn't a callback
+    mock.reset_counters();
+    assert_eq!(receiver.recv_timeout(TIMEOUT / 50), Err(RecvTimeoutError::Timeout));
+
+    // a new observer replaces the old, which stops
+    let (sender, on_thread) = mpsc::channel();
+    mock.on_callback(move |_| sender.send(std::thread::current().id()).unwrap());
+    assert_stopped(&receiver);
+    mock.drive_callback(512);
+    assert_ne!(on_thread.recv_timeout(TIMEOUT).unwrap(), audio_thread);
+    mock.clear_on_callback();
+    assert_stopped(&on_thread);
+
+    let Some(context) = common::dummy_context() else { return };
+    let mut device = context.open_device(4096).expect("dummy playback device");
+    device.set_mode(PlayMode::Loop);
+    let receiver = forwarding(&mut device);
+    device.resume();
+    let mut last = receiver.recv_timeout(TIMEOUT).expect("the dummy driver's callbacks");
+    for _ in 0..10 {
+        let info = receiver.recv_timeout(TIMEOUT).expect("the dummy driver's callbacks");
+        assert!(info.called > last.called && info.consumed > last.consumed, "{:?} after {:?}", info, last);
+        assert!(info.at >= last.at);
+        last = info;
+    }
+    // dropping a playing device with its observer attached neither hangs nor leaks it
+    drop(device);
+    assert_stopped(&receiver);
+}
@@ -0,0 +1,22 @@
+use std::time::{Duration, Instant};
+
+use audio_lib3::{AudioContext, CaptureControl};
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn dummy_driver_records_silence() {
+    std::env::set_var("SDL_AUDIODRIVER", "dummy");
+    let context = AudioContext::try_new().expect("SDL with the dummy audio driver");
+    let mut device = context.open_capture_device(4096).expect("dummy capture device");
+    device.resume();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while device.available() == 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    device.pause();
+    assert!(device.called() > 0);
+    let mut out = vec![0u16; device.available()];
+    assert_eq!(device.read(&mut out), out.len());
+    assert!(!out.is_empty());
+    assert_eq!(device.available(), 0);
+}
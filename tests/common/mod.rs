@@ -0,0 +1,56 @@
+//! Shared setup for the integration tests that run on SDL's dummy driver, which needs no
+//! audio hardware: it calls back on its own thread at about the real rate and throws the
+//! output away.
+//!
+//! Each test binary may only initialize SDL once, so a file using this holds a single test.
+
+// not every test binary uses every helper
+#![allow(dead_code)]
+
+use std::time::{Duration, Instant};
+
+use audio_lib3::{AudioContext, AudioSpecInfo, Control, MockDevice};
+
+/// How long to wait for something the dummy driver's thread should do in a few
+/// milliseconds; generous, so a loaded CI machine doesn't fail the test.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A context on the dummy driver, or `None` when this SDL was built without it.
+pub fn dummy_context() -> Option<AudioContext> {
+    if !AudioContext::available_drivers().iter().any(|driver| driver == "dummy") {
+        eprintln!("SDL has no dummy audio driver, testing with MockDevice only");
+        return None;
+    }
+    Some(AudioContext::with_driver("dummy").expect("SDL with the dummy audio driver"))
+}
+
+/// A mock with the spec the dummy driver usually obtains, for the paths it can't
+/// guarantee; like a real device it starts paused.
+pub fn mock_device(len: usize) -> MockDevice {
+    MockDevice::new(len, AudioSpecInfo { freq: 48000, channels: 2, samples: 512 })
+}
+
+/// Polls `done` every millisecond until it holds or `TIMEOUT` passes.
+pub fn poll_until(mut done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+    while !done() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    true
+}
+
+/// Waits for `n` more callbacks on a resumed device; false when they didn't come.
+pub fn pump(device: &mut impl Control, n: u64) -> bool {
+    let target = device.called() + n;
+    let deadline = Instant::now() + TIMEOUT;
+    while device.called() < target {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || device.wait_for_callback(left).is_err() {
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,39 @@
+use std::time::{Duration, Instant};
+
+use audio_lib3::{AudioContext, Control, PlayMode};
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn a_handle_controls_the_device_from_another_thread() {
+    let context = AudioContext::with_driver("dummy").expect("SDL with the dummy audio driver");
+    let mut device = context.open_device(8192).expect("dummy playback device");
+    device.set_mode(PlayMode::Stream);
+    let handle = device.control_handle();
+    device.resume();
+    let controller = std::thread::spawn(move || {
+        assert!(handle.set_volume(5));
+        let data = [0x9000u16; 1024];
+        let mut written = 0;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while written < 4096 && Instant::now() < deadline {
+            written += handle.write(&data[..(4096 - written).min(data.len())]);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(written, 4096);
+        // the published counters move on as the callback plays what was written
+        while handle.current() == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(handle.current() > 0);
+        handle.called()
+    });
+    let called = controller.join().expect("the controlling thread");
+    assert!(device.wait_for_callback(Duration::from_secs(5)).is_ok());
+    device.pause();
+    assert!(called > 0);
+    assert_eq!(device.volume(), 5);
+    assert!(device.take_consumed() > 0);
+    // every call hands out the same queues
+    let again = device.control_handle();
+    assert_eq!(again.called(), device.called());
+}
@@ -0,0 +1,17 @@
+use std::time::Duration;
+
+use audio_lib3::{AudioContext, Control, PlaybackState};
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn drain_blocking_plays_everything_written_then_pauses() {
+    let context = AudioContext::with_driver("dummy").expect("SDL with the dummy audio driver");
+    let mut device = context.open_device(8192).expect("dummy playback device");
+    device.write(&[0x9000; 4096]);
+    device.resume();
+    assert_eq!(device.drain_blocking(Duration::from_secs(5)), Ok(()));
+    assert_eq!(device.state(), PlaybackState::Finished);
+    assert!(device.is_paused());
+    assert_eq!(device.take_consumed(), 4096);
+    assert_eq!((device.remain(), device.underruns()), (0, 0));
+}
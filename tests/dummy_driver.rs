@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use audio_lib3::{AudioContext, AudioError, Control};
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn dummy_driver_runs_the_callback() {
+    assert!(AudioContext::available_drivers().iter().any(|driver| driver == "dummy"));
+    let err = AudioContext::with_driver("no-such-driver").err();
+    assert_eq!(err, Some(AudioError::UnknownDriver("no-such-driver".to_string())));
+
+    let context = AudioContext::with_driver("dummy").expect("SDL with the dummy audio driver");
+    assert_eq!(context.current_driver(), "dummy");
+    assert!(format!("{:?}", context).starts_with("AudioContext { driver: \"dummy\""));
+    // the driver hint is back as it was: unset, and open to a normal-priority set
+    assert_eq!(sdl2::hint::get("SDL_AUDIODRIVER"), None);
+    assert!(sdl2::hint::set("SDL_AUDIODRIVER", "disk"));
+    assert_eq!(sdl2::hint::get("SDL_AUDIODRIVER").as_deref(), Some("disk"));
+    let mut device = context.device().buffer_len(8192).open().expect("dummy playback device");
+    device.write(&[0x9000; 4096]);
+    device.resume();
+    let called = device.wait_for_callback(Duration::from_secs(5)).expect("a callback");
+    device.pause();
+    assert!(called > 0);
+    assert!(device.take_consumed() > 0);
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use audio_lib3::{Control, DeviceEventKind, PlayMode, MAX_VOLUME};
+
+mod common;
+
+// what every device, real or mock, has to get right through the lock
+fn round_trips(device: &mut impl Control) {
+    for volume in 0..=MAX_VOLUME {
+        device.set_volume(volume);
+        assert_eq!(device.volume(), volume);
+    }
+    device.set_mute(true);
+    assert!(device.mute());
+    device.set_mute(false);
+    assert!(!device.mute());
+
+    let data: Vec<u16> = (0..device.buf_samples()).map(|i| (i * 7) as u16).collect();
+    device.set_data(0, &data);
+    assert_eq!(device.snapshot(), data);
+}
+
+// counters only move forwards while a loop plays
+fn assert_monotonic(mut status: impl FnMut() -> (u64, u64, u64)) {
+    let mut last = status();
+    for _ in 0..5 {
+        std::thread::sleep(Duration::from_millis(10));
+        let now = status();
+        assert!(now.0 >= last.0 && now.1 >= last.1 && now.2 >= last.2, "{:?} after {:?}", now, last);
+        last = now;
+    }
+}
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn devices_round_trip_through_the_lock_and_keep_counting() {
+    let mut mock = common::mock_device(4096);
+    round_trips(&mut mock);
+    mock.set_mode(PlayMode::Loop);
+    Control::resume(&mut mock);
+    assert_monotonic(|| {
+        mock.drive_callback(512);
+        (mock.called(), mock.current(), mock.position_frames())
+    });
+    assert_eq!(mock.called(), 6);
+
+    let Some(context) = common::dummy_context() else {
+        return;
+    };
+    // opening and closing again and again leaves nothing behind
+    for _ in 0..3 {
+        let device = context.open_device(4096).expect("dummy playback device");
+        drop(device);
+    }
+    let mut device = context.device().buffer_len(4096).label("harness").open().expect("dummy playback device");
+    let events = device.poll_device_events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].label.as_deref(), Some("harness"));
+    assert_eq!(events[0].kind, DeviceEventKind::Opened(Control::spec(&mut device)));
+    round_trips(&mut device);
+    device.set_mode(PlayMode::Loop);
+    Control::resume(&mut device);
+    if !common::pump(&mut device, 2) {
+        // the mock above already covered the counters
+        eprintln!("the dummy driver never called back");
+        return;
+    }
+    assert_monotonic(|| (device.called(), device.current(), device.position_frames()));
+    assert!(common::poll_until(|| device.position_frames() > 0));
+    Control::pause(&mut device);
+    assert!(device.is_paused());
+    // a loop never runs dry, and the device is still there
+    assert_eq!(device.poll_device_events(), Vec::new());
+}
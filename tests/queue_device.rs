@@ -0,0 +1,53 @@
+use std::time::{Duration, Instant};
+
+use audio_lib3::{generators, AudioContext, AudioError, QueuePlayer, SETUP_U16};
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn queue_device_plays_what_was_queued_at_the_set_volume() {
+    let raw = std::env::temp_dir().join(format!("audio-lib3-queue-{}.raw", std::process::id()));
+    std::env::set_var("SDL_DISKAUDIOFILE", &raw);
+    let context = AudioContext::with_driver("disk").expect("SDL with the disk audio driver");
+    let mut device = context.open_queue().expect("queue device");
+    let channels = device.spec().channels as usize;
+    if channels > 1 {
+        assert!(matches!(device.queue(&[0x8000]), Err(AudioError::InvalidParam { .. })));
+    }
+
+    // queued while paused, then dropped again
+    device.pause();
+    device.queue(&vec![0x9000; 1024 * channels]).unwrap();
+    assert_eq!(device.queued_bytes(), 2048 * channels as u32);
+    device.clear();
+    assert_eq!(device.queued_bytes(), 0);
+
+    // one volume step down halves the tone; muted, the rest turns to silence
+    let tone: Vec<u16> = generators::sine(500.0, 48000, 4800, 0x4000)
+        .iter()
+        .flat_map(|s| std::iter::repeat_n(*s, channels))
+        .collect();
+    device.set_volume(6);
+    assert_eq!(device.volume(), 6);
+    device.queue(&tone).unwrap();
+    device.set_mute(true);
+    device.queue(&tone).unwrap();
+    device.resume();
+    let start = Instant::now();
+    while device.queued_bytes() > 0 {
+        assert!(start.elapsed() < Duration::from_secs(5), "the queue never drained");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    // the driver may still be writing the last block
+    std::thread::sleep(Duration::from_millis(100));
+    device.pause();
+    drop(device);
+
+    let bytes = std::fs::read(&raw).unwrap();
+    let _ = std::fs::remove_file(&raw);
+    let played: Vec<i32> = bytes.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]) as i32 - SETUP_U16).collect();
+    let halved: Vec<i32> = tone.iter().map(|s| (*s as i32 - SETUP_U16) >> 1).collect();
+    // SDL's own silence may come first, from before the device was paused
+    let start = played.windows(halved.len()).position(|w| w == halved).expect("the tone was played");
+    // after the muted copy SDL fills in its own silence, which is 0x8080 for u16
+    assert!(played[start + tone.len()..start + 2 * tone.len()].iter().all(|s| *s == 0));
+}
@@ -0,0 +1,34 @@
+use audio_lib3::{AudioContext, Control, PlayMode};
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn reopen_carries_the_sound_over() {
+    std::env::set_var("SDL_AUDIODRIVER", "dummy");
+    let context = AudioContext::builder().freq(22050).channels(2).build().expect("SDL with the dummy audio driver");
+    let mut device = context.device().buffer_len(1024).open().expect("dummy playback device");
+    assert!(device.is_alive());
+    device.write(&[0x9000; 100]);
+    device.set_gain(200);
+    device.set_mute(true);
+    device.set_mode(PlayMode::Loop);
+    let spec = Control::spec(&mut device);
+    let mut device = context.reopen(device).expect("reopened device");
+    assert!(device.is_alive());
+    assert!(device.is_paused());
+    assert_eq!(Control::spec(&mut device), spec);
+    assert_eq!((device.buf_samples(), device.remain(), device.gain()), (1024, 100, 200));
+    assert!(device.mute());
+    assert_eq!(device.mode(), PlayMode::Loop);
+    let mut data = [0u16; 100];
+    device.get_data(0, &mut data);
+    assert!(data.iter().all(|s| *s == 0x9000));
+    assert_eq!(device.device_name(), None);
+
+    // a device opened by name is reopened by the same name; the dummy driver's only
+    // device goes by SDL's name for the default one
+    let name = "System audio output device";
+    drop(device);
+    let device = context.device().buffer_len(1024).device_name(name).open().expect("dummy playback device by name");
+    let mut device = context.reopen(device).expect("reopened device");
+    assert_eq!(device.device_name().as_deref(), Some(name));
+}
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use audio_lib3::{AudioContext, MixerControl, PlayOptions, SoundBank, TriggerError};
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn triggers_share_the_banked_sound() {
+    let context = AudioContext::with_driver("dummy").expect("SDL with the dummy audio driver");
+    let mut mixer = context.open_mixer_device(2, 1024).expect("dummy mixer device");
+    mixer.pause();
+    let mut bank = SoundBank::new();
+    bank.insert("explosion".to_string(), vec![0x9000; 4096]);
+
+    let options = PlayOptions { volume: 5, ..PlayOptions::default() };
+    let a = bank.trigger("explosion", &mut mixer, options).unwrap();
+    let b = bank.trigger("explosion", &mut mixer, PlayOptions::default()).unwrap();
+    assert_ne!(a.voice(), b.voice());
+    assert_eq!(Arc::strong_count(bank.get("explosion").unwrap()), 3);
+    assert!(!mixer.handle_finished(a));
+
+    let err = bank.trigger("laser", &mut mixer, PlayOptions::default()).unwrap_err();
+    assert_eq!(err, TriggerError::UnknownKey("laser".to_string()));
+}
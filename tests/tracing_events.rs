@@ -0,0 +1,86 @@
+#![cfg(feature = "tracing")]
+
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use audio_lib3::{Control, PlayMode, RecordingSink};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+mod common;
+
+// a subscriber that keeps every event as "LEVEL target field=value ..."
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<String>>>);
+
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        write!(self.0, " {}={:?}", field.name(), value).unwrap();
+    }
+}
+
+impl Subscriber for Captured {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields(format!("{} {}", event.metadata().level(), event.metadata().target()));
+        event.record(&mut fields);
+        self.0.lock().unwrap().push(fields.0);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+impl Captured {
+    // the one event containing every part, taken out so the next check can't see it
+    fn take(&self, parts: &[&str]) -> String {
+        let mut events = self.0.lock().unwrap();
+        let found = events.iter().position(|event| parts.iter().all(|part| event.contains(part)));
+        let found = found.unwrap_or_else(|| panic!("no event with {:?} in {:#?}", parts, events));
+        events.remove(found)
+    }
+}
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn polled_events_reach_the_subscriber_labelled() {
+    let captured = Captured::default();
+    tracing::subscriber::with_default(captured.clone(), || {
+        let mut mock = common::mock_device(4096);
+        mock.set_label("sfx");
+        mock.set_mode(PlayMode::Stream);
+        mock.resume();
+        mock.start_recording(RecordingSink::Memory(Arc::default())).unwrap();
+        mock.write(&[0x9000; 512]);
+        mock.drive_callback(512);
+        mock.stop_recording().unwrap();
+        // nothing is emitted until the events are polled
+        assert!(captured.0.lock().unwrap().is_empty());
+        assert_eq!(mock.poll_device_events().len(), 3);
+        captured.take(&["INFO audio_lib3", "recording started", "label=\"sfx\"", "age_us="]);
+        captured.take(&["WARN audio_lib3", "underrun", "label=\"sfx\"", "position=512"]);
+        captured.take(&["INFO audio_lib3", "recording stopped", "label=\"sfx\""]);
+
+        let Some(context) = common::dummy_context() else { return };
+        let mut device = context.device().buffer_len(4096).label("music").open().expect("dummy playback device");
+        let spec = Control::spec(&mut device);
+        device.poll_device_events();
+        captured.take(&["INFO audio_lib3", "device opened", "label=\"music\"", &format!("freq={}", spec.freq)]);
+        assert_eq!(*captured.0.lock().unwrap(), Vec::<String>::new());
+    });
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use audio_lib3::{AudioContext, AudioError, Control, DeviceGroup};
+
+// SDL can only be initialized from one thread per process, so this file holds a single test.
+#[test]
+fn two_devices_play_at_once() {
+    {
+        // drivers with a single device refuse the second one with an error
+        let context = AudioContext::with_driver("dummy").expect("SDL with the dummy audio driver");
+        let _main = context.open_device(4096).expect("main device");
+        let err = context.open_device(4096).err();
+        assert!(matches!(err, Some(AudioError::SingleDeviceDriver(_))));
+    }
+
+    let raw = std::env::temp_dir().join("audio-lib3-two-devices.raw");
+    std::env::set_var("SDL_DISKAUDIOFILE", &raw);
+    let context = AudioContext::with_driver("disk").expect("SDL with the disk audio driver");
+    let main = context.open_device(4096).expect("main device");
+    let cue = context.open_device(4096).expect("cue device");
+    let mut group = DeviceGroup::from(vec![main, cue]);
+    group.set_volume(5);
+    for device in group.iter_mut() {
+        device.write(&[0x9000; 2048]);
+        assert_eq!(device.volume(), 5);
+    }
+    group.resume();
+    for device in group.iter_mut() {
+        assert!(device.wait_for_callback(Duration::from_secs(5)).is_ok());
+    }
+    group.pause();
+    assert!(group.into_devices().iter_mut().all(|device| device.is_paused() && device.take_consumed() > 0));
+    let _ = std::fs::remove_file(raw);
+}